@@ -0,0 +1,166 @@
+/*!
+Derive macro for the `ToMemcacheValue` and `FromMemcacheValue` traits of the
+[memcache](https://crates.io/crates/memcache) crate. Use it through the `derive` feature of
+`memcache` instead of depending on this crate directly.
+
+Newtype structs are stored like the wrapped value:
+
+```rust,ignore
+#[derive(memcache::MemcacheValue)]
+struct UserId(u64);
+```
+
+Other types are serialized with serde, in the format selected by the `memcache` attribute,
+which requires the matching feature of `memcache`:
+
+```rust,ignore
+#[derive(serde::Serialize, serde::Deserialize, memcache::MemcacheValue)]
+#[memcache(json)] // or bincode, msgpack
+struct User {
+    name: String,
+    age: u8,
+}
+```
+!*/
+
+extern crate proc_macro;
+
+use proc_macro2::{Span, TokenStream};
+use quote::quote;
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Fields, Ident};
+
+#[proc_macro_derive(MemcacheValue, attributes(memcache))]
+pub fn derive_memcache_value(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+enum Format {
+    Newtype,
+    Serde(Ident),
+}
+
+fn format(input: &DeriveInput) -> syn::Result<Format> {
+    let mut format = None;
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("memcache")) {
+        attr.parse_nested_meta(|meta| {
+            let wrapper = if meta.path.is_ident("json") {
+                "Json"
+            } else if meta.path.is_ident("bincode") {
+                "Bincode"
+            } else if meta.path.is_ident("msgpack") {
+                "MsgPack"
+            } else {
+                return Err(meta.error("expected `json`, `bincode` or `msgpack`"));
+            };
+            format = Some(Format::Serde(Ident::new(wrapper, Span::call_site())));
+            Ok(())
+        })?;
+    }
+    if let Some(format) = format {
+        return Ok(format);
+    }
+    match input.data {
+        Data::Struct(ref data) if matches!(data.fields, Fields::Unnamed(ref fields) if fields.unnamed.len() == 1) => {
+            Ok(Format::Newtype)
+        }
+        _ => Err(syn::Error::new_spanned(
+            &input.ident,
+            "`MemcacheValue` requires a newtype struct or a `#[memcache(json|bincode|msgpack)]` attribute",
+        )),
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let mut to_generics = input.generics.clone();
+    to_generics.params.push(parse_quote!(__W: ::std::io::Write));
+    let (to_impl_generics, _, _) = to_generics.split_for_impl();
+    let mut ref_generics = to_generics.clone();
+    ref_generics.params.insert(0, parse_quote!('__a));
+    let (ref_impl_generics, _, _) = ref_generics.split_for_impl();
+
+    let (to_impl, from_impl) = match format(input)? {
+        Format::Newtype => (
+            quote! {
+                fn get_flags(&self) -> u32 {
+                    ::memcache::ToMemcacheValue::<__W>::get_flags(&self.0)
+                }
+
+                fn get_length(&self) -> usize {
+                    ::memcache::ToMemcacheValue::<__W>::get_length(&self.0)
+                }
+
+                fn write_to(&self, stream: &mut __W) -> ::std::io::Result<()> {
+                    ::memcache::ToMemcacheValue::<__W>::write_to(&self.0, stream)
+                }
+
+                fn get_kind(&self) -> ::memcache::ValueKind {
+                    ::memcache::ToMemcacheValue::<__W>::get_kind(&self.0)
+                }
+            },
+            quote! {
+                fn from_memcache_value(value: Vec<u8>, flags: u32) -> Result<Self, ::memcache::MemcacheError> {
+                    Ok(#name(::memcache::FromMemcacheValue::from_memcache_value(value, flags)?))
+                }
+            },
+        ),
+        Format::Serde(wrapper) => (
+            quote! {
+                fn get_flags(&self) -> u32 {
+                    ::memcache::ToMemcacheValue::<__W>::get_flags(&::memcache::#wrapper(self))
+                }
+
+                fn get_length(&self) -> usize {
+                    ::memcache::ToMemcacheValue::<__W>::get_length(&::memcache::#wrapper(self))
+                }
+
+                fn write_to(&self, stream: &mut __W) -> ::std::io::Result<()> {
+                    ::memcache::ToMemcacheValue::<__W>::write_to(&::memcache::#wrapper(self), stream)
+                }
+
+                fn get_kind(&self) -> ::memcache::ValueKind {
+                    ::memcache::ToMemcacheValue::<__W>::get_kind(&::memcache::#wrapper(self))
+                }
+            },
+            quote! {
+                fn from_memcache_value(value: Vec<u8>, flags: u32) -> Result<Self, ::memcache::MemcacheError> {
+                    let ::memcache::#wrapper(value) = ::memcache::FromMemcacheValue::from_memcache_value(value, flags)?;
+                    Ok(value)
+                }
+            },
+        ),
+    };
+
+    Ok(quote! {
+        impl #to_impl_generics ::memcache::ToMemcacheValue<__W> for #name #ty_generics #where_clause {
+            #to_impl
+        }
+
+        impl #ref_impl_generics ::memcache::ToMemcacheValue<__W> for &'__a #name #ty_generics #where_clause {
+            fn get_flags(&self) -> u32 {
+                ::memcache::ToMemcacheValue::<__W>::get_flags(*self)
+            }
+
+            fn get_length(&self) -> usize {
+                ::memcache::ToMemcacheValue::<__W>::get_length(*self)
+            }
+
+            fn write_to(&self, stream: &mut __W) -> ::std::io::Result<()> {
+                ::memcache::ToMemcacheValue::<__W>::write_to(*self, stream)
+            }
+
+            fn get_kind(&self) -> ::memcache::ValueKind {
+                ::memcache::ToMemcacheValue::<__W>::get_kind(*self)
+            }
+        }
+
+        impl #impl_generics ::memcache::FromMemcacheValue for #name #ty_generics #where_clause {
+            #from_impl
+        }
+    })
+}
@@ -0,0 +1,70 @@
+use std::cell::RefCell;
+
+thread_local! {
+    static BUFFERS: RefCell<Vec<Vec<u8>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Keeps the buffers of decoded values for the next values read by the same thread, see
+/// `ClientBuilder::with_value_buffer_pool`. A connection is only used by one thread at a time, so
+/// the requests on a connection reuse the buffers of the previous ones without locking.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BufferPool {
+    pub(crate) max_buffers: usize,
+    pub(crate) max_buffer_size: usize,
+}
+
+impl BufferPool {
+    /// Keep `buffer` for a later `take`, unless it's too large or the pool is full.
+    pub(crate) fn recycle(&self, buffer: Vec<u8>) {
+        if buffer.capacity() == 0 || buffer.capacity() > self.max_buffer_size {
+            return;
+        }
+        BUFFERS.with(|buffers| {
+            let mut buffers = buffers.borrow_mut();
+            if buffers.len() < self.max_buffers {
+                buffers.push(buffer);
+            }
+        });
+    }
+}
+
+/// A zeroed buffer of `length` bytes to read a value into, from the pool if it has one.
+pub(crate) fn take(length: usize) -> Vec<u8> {
+    if length == 0 {
+        return Vec::new();
+    }
+    match BUFFERS.with(|buffers| buffers.borrow_mut().pop()) {
+        Some(mut buffer) => {
+            buffer.clear();
+            buffer.resize(length, 0);
+            buffer
+        }
+        None => vec![0; length],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{take, BufferPool};
+
+    #[test]
+    fn test_recycle() {
+        let pool = BufferPool {
+            max_buffers: 1,
+            max_buffer_size: 1024,
+        };
+        let buffer = take(100);
+        let pointer = buffer.as_ptr();
+        pool.recycle(buffer);
+        pool.recycle(vec![1; 100]);
+        let buffer = take(10);
+        assert_eq!(buffer, vec![0; 10]);
+        assert_eq!(buffer.as_ptr(), pointer);
+        // the second buffer was dropped as the pool was full
+        let buffer = take(10);
+        assert_ne!(buffer.as_ptr(), pointer);
+
+        pool.recycle(vec![0; 2048]);
+        assert_eq!(take(10).capacity(), 10);
+    }
+}
@@ -0,0 +1,153 @@
+use crate::error::{MemcacheError, ServerError};
+use crate::value::Transcoder;
+
+/// The checksum algorithms supported by `Checksum`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    /// CRC32 (IEEE), stored as a 4 bytes trailer.
+    Crc32,
+    /// 64 bits XXH3, stored as a 8 bytes trailer. Faster than CRC32 on large values without
+    /// hardware CRC support.
+    XxHash3,
+}
+
+/// A `Transcoder` which appends a checksum to stored values, and verifies it when they are
+/// fetched, to detect values corrupted by flaky proxies or partial overwrites. Values with a
+/// checksum are marked with a flag bit, `0x4000_0000` by default, values without it are returned
+/// as is. Only available with the `checksum` feature.
+///
+/// Fetching a value which doesn't match its checksum returns a `ServerError::ChecksumMismatch`
+/// error. Add it after other transcoders like `Compression` to checksum the stored bytes.
+///
+/// Example:
+///
+/// ```rust
+/// # #[cfg(feature = "checksum")]
+/// # {
+/// let client = memcache::Client::builder()
+///     .add_server("memcache://localhost:12345")
+///     .with_transcoder(memcache::Checksum::crc32())
+///     .build()
+///     .unwrap();
+/// client.set("checksummed", "bar", 0).unwrap();
+/// let value: String = client.get("checksummed").unwrap().unwrap();
+/// assert_eq!(value, "bar");
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Checksum {
+    algorithm: ChecksumAlgorithm,
+    flag: u32,
+}
+
+impl Checksum {
+    const DEFAULT_FLAG: u32 = 0x4000_0000;
+
+    pub fn new(algorithm: ChecksumAlgorithm) -> Self {
+        Checksum {
+            algorithm,
+            flag: Self::DEFAULT_FLAG,
+        }
+    }
+
+    /// Checksum values with CRC32.
+    pub fn crc32() -> Self {
+        Self::new(ChecksumAlgorithm::Crc32)
+    }
+
+    /// Checksum values with XXH3.
+    pub fn xxhash3() -> Self {
+        Self::new(ChecksumAlgorithm::XxHash3)
+    }
+
+    /// Set the flag bit which marks values with a checksum.
+    ///
+    /// Panics if `flag` doesn't have exactly one bit set.
+    pub fn with_flag(mut self, flag: u32) -> Self {
+        assert_eq!(flag.count_ones(), 1, "the checksum flag must be a single bit");
+        self.flag = flag;
+        self
+    }
+
+    fn digest(&self, value: &[u8]) -> Vec<u8> {
+        match self.algorithm {
+            ChecksumAlgorithm::Crc32 => crc32fast::hash(value).to_be_bytes().to_vec(),
+            ChecksumAlgorithm::XxHash3 => xxhash_rust::xxh3::xxh3_64(value).to_be_bytes().to_vec(),
+        }
+    }
+
+    fn digest_length(&self) -> usize {
+        match self.algorithm {
+            ChecksumAlgorithm::Crc32 => 4,
+            ChecksumAlgorithm::XxHash3 => 8,
+        }
+    }
+}
+
+impl Transcoder for Checksum {
+    fn encode(&self, mut value: Vec<u8>, flags: u32) -> Result<(Vec<u8>, u32), MemcacheError> {
+        let digest = self.digest(&value);
+        value.extend_from_slice(&digest);
+        Ok((value, flags | self.flag))
+    }
+
+    fn decode(&self, mut value: Vec<u8>, flags: u32) -> Result<(Vec<u8>, u32), MemcacheError> {
+        if flags & self.flag == 0 {
+            return Ok((value, flags));
+        }
+        if value.len() < self.digest_length() {
+            return Err(ServerError::ChecksumMismatch.into());
+        }
+        let digest = value.split_off(value.len() - self.digest_length());
+        if digest != self.digest(&value) {
+            return Err(ServerError::ChecksumMismatch.into());
+        }
+        Ok((value, flags & !self.flag))
+    }
+
+    fn reserved_flags(&self) -> u32 {
+        self.flag
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Checksum;
+    use crate::error::{MemcacheError, ServerError};
+    use crate::value::Transcoder;
+
+    #[test]
+    fn test_roundtrip() {
+        for checksum in &[Checksum::crc32(), Checksum::xxhash3()] {
+            let (encoded, flags) = checksum.encode(b"foo".to_vec(), 0x2).unwrap();
+            assert_eq!(encoded.len(), 3 + checksum.digest_length());
+            assert_eq!(flags, 0x4000_0002);
+            assert_eq!(checksum.decode(encoded, flags).unwrap(), (b"foo".to_vec(), 0x2));
+        }
+    }
+
+    #[test]
+    fn test_crc32() {
+        let (encoded, _) = Checksum::crc32().encode(b"123456789".to_vec(), 0).unwrap();
+        assert_eq!(&encoded[9..], &0xcbf4_3926u32.to_be_bytes());
+    }
+
+    #[test]
+    fn test_mismatch() {
+        for checksum in &[Checksum::crc32(), Checksum::xxhash3()] {
+            let (mut encoded, flags) = checksum.encode(b"foo".to_vec(), 0).unwrap();
+            encoded[0] = b'g';
+            match checksum.decode(encoded, flags) {
+                Err(MemcacheError::ServerError(ServerError::ChecksumMismatch)) => {}
+                result => panic!("unexpected result: {:?}", result),
+            }
+            assert!(checksum.decode(b"f".to_vec(), flags).is_err());
+        }
+    }
+
+    #[test]
+    fn test_unmarked() {
+        let checksum = Checksum::crc32().with_flag(0x1000);
+        assert_eq!(checksum.decode(b"foo".to_vec(), 0x2).unwrap(), (b"foo".to_vec(), 0x2));
+    }
+}
@@ -0,0 +1,97 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use crate::error::{MemcacheError, ServerError};
+
+/// Marks a manifest stored in place of a value which was split into chunks.
+pub(crate) const CHUNKED_FLAG: u32 = 0x8000_0000;
+
+/// Describes a value split into chunks, which are stored under keys derived from the value's key
+/// and a random id, so chunks of different writes of the same key never mix.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Manifest {
+    pub(crate) id: u32,
+    pub(crate) chunks: usize,
+    pub(crate) length: usize,
+    pub(crate) flags: u32,
+}
+
+impl Manifest {
+    pub(crate) fn new(length: usize, chunk_size: usize, flags: u32) -> Self {
+        Manifest {
+            id: rand::random(),
+            chunks: length.div_ceil(chunk_size),
+            length,
+            flags,
+        }
+    }
+
+    pub(crate) fn chunk_keys(&self, key: &str) -> Vec<String> {
+        (0..self.chunks)
+            .map(|i| format!("{}:chunk:{:08x}:{}", key, self.id, i))
+            .collect()
+    }
+
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        format!("{} {} {} {}", self.id, self.chunks, self.length, self.flags).into_bytes()
+    }
+
+    pub(crate) fn parse(value: &[u8]) -> Result<Self, MemcacheError> {
+        let text = std::str::from_utf8(value)?;
+        let bad_manifest = || ServerError::BadResponse(Cow::Owned(format!("bad chunk manifest: {}", text)));
+        let mut parts = text.split(' ');
+        let mut next = || parts.next().ok_or_else(bad_manifest);
+        Ok(Manifest {
+            id: next()?.parse()?,
+            chunks: next()?.parse()?,
+            length: next()?.parse()?,
+            flags: next()?.parse()?,
+        })
+    }
+
+    /// Concatenate the chunks fetched for `key`, or return `None` if any chunk is missing.
+    pub(crate) fn assemble(&self, key: &str, mut chunks: HashMap<String, Vec<u8>>) -> Option<Vec<u8>> {
+        let mut value = Vec::with_capacity(self.length);
+        for chunk_key in self.chunk_keys(key) {
+            value.extend_from_slice(&chunks.remove(&chunk_key)?);
+        }
+        if value.len() != self.length {
+            return None;
+        }
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Manifest;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_manifest() {
+        let manifest = Manifest::new(10, 4, 0x2);
+        assert_eq!(manifest.chunks, 3);
+        let keys = manifest.chunk_keys("foo");
+        assert_eq!(keys.len(), 3);
+        assert_eq!(keys[2], format!("foo:chunk:{:08x}:2", manifest.id));
+        assert_eq!(Manifest::parse(&manifest.to_bytes()).unwrap(), manifest);
+        assert!(Manifest::parse(b"1 2 3").is_err());
+        assert!(Manifest::parse(b"1 2 3 x").is_err());
+    }
+
+    #[test]
+    fn test_assemble() {
+        let manifest = Manifest::new(10, 4, 0);
+        let value = b"0123456789";
+        let mut chunks: HashMap<String, Vec<u8>> = manifest
+            .chunk_keys("foo")
+            .into_iter()
+            .zip(value.chunks(4))
+            .map(|(key, chunk)| (key, chunk.to_vec()))
+            .collect();
+        assert_eq!(manifest.assemble("foo", chunks.clone()).unwrap(), value.to_vec());
+
+        chunks.remove(&manifest.chunk_keys("foo")[1]);
+        assert_eq!(manifest.assemble("foo", chunks), None);
+    }
+}
@@ -1,19 +1,134 @@
+use std::borrow::Cow;
 use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::convert::TryInto;
 use std::hash::{Hash, Hasher};
-use std::time::Duration;
+use std::io::{self, Read, Write};
+use std::ops::{Deref, DerefMut};
+use std::panic;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use url::Url;
 
-use crate::connection::ConnectionManager;
-use crate::error::{ClientError, MemcacheError};
-use crate::protocol::{Protocol, ProtocolTrait};
+use crate::buffer_pool::BufferPool;
+use crate::chunk::{Manifest, CHUNKED_FLAG};
+use crate::coalesce::Coalescer;
+use crate::connection::{
+    is_ascii, AuthProvider, Connection, ConnectionManager, ConnectionOptions, Feature, LastConnectError,
+};
+use crate::deferred::DeferredWrites;
+use crate::error::{ClientError, CommandError, ErrorContext, MemcacheError, PartialError};
+use crate::expiration::{expires_at, Expiration};
+use crate::failover::{OnError, ServerHealth};
+use crate::fallback::{MaybeStale, StaleFallback};
+use crate::metrics::{Command, Counters, LatencyHistogram, Metrics, ServerCounters, SlowOperation};
+use crate::mirror::{CopyingReader, Mirror, Mirrored};
+use crate::protocol::{encode_quiet, GetsCallback, Protocol, ProtocolTrait, QuietCommand, StoreCommand};
+use crate::retry::RetryPolicy;
+use crate::scan::KeyScan;
+use crate::stream::socket::Keepalive;
 use crate::stream::Stream;
-use crate::value::{FromMemcacheValueExt, ToMemcacheValue};
-use r2d2::Pool;
+use crate::value::{
+    EncodedValue, FlagLayout, FlaggedValue, Flags, FromMemcacheValueExt, ReaderValue, StoreValue, ToMemcacheValue,
+    Transcoder, TranscoderChain, Value,
+};
+use crate::worker_pool::WorkerPool;
+use r2d2::{Pool, PooledConnection};
 
 pub type Stats = HashMap<String, String>;
 
+/// Statistics of the slab classes of a server, as returned by `stats items` or `stats slabs`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SlabStats {
+    /// Statistics of each slab class, keyed by the slab class id.
+    pub classes: HashMap<u32, Stats>,
+    /// Statistics which are not specific to a slab class, like `active_slabs`.
+    pub totals: Stats,
+}
+
+impl SlabStats {
+    /// Group statistics named like `[<prefix>:]<slab class id>:<name>` by their slab class.
+    fn from_stats(stats: Stats, prefix: &str) -> Self {
+        let mut result = SlabStats::default();
+        for (key, value) in stats {
+            let name = key.strip_prefix(prefix).unwrap_or(&key);
+            let mut parts = name.splitn(2, ':');
+            match (parts.next().map(str::parse::<u32>), parts.next()) {
+                (Some(Ok(class)), Some(name)) => {
+                    result.classes.entry(class).or_default().insert(name.to_string(), value);
+                }
+                _ => {
+                    result.totals.insert(key, value);
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Item size distribution of a server, as a map from item size to the number of items.
+pub type SizeStats = HashMap<u32, u64>;
+
+/// Parse the `stats sizes` output to a map from item size to the number of items with this size.
+fn parse_size_stats(stats: Stats) -> SizeStats {
+    stats
+        .into_iter()
+        .filter_map(|(size, count)| Some((size.parse().ok()?, count.parse().ok()?)))
+        .collect()
+}
+
+/// The outcome of `Client::try_add` and `Client::try_replace`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreResult {
+    /// The value was stored.
+    Stored,
+    /// The value was not stored, because the key already exists for `try_add`, or is missing for
+    /// `try_replace`.
+    NotStored,
+}
+
+/// The outcome of `Client::gets_partial`: the values fetched from the servers which answered, and
+/// the errors of the servers which didn't.
+#[derive(Debug)]
+pub struct PartialGets<V> {
+    /// The values found, keyed by their keys.
+    pub values: HashMap<String, V>,
+    /// The url of every server which failed, along with its error.
+    pub errors: Vec<(String, MemcacheError)>,
+}
+
+impl<V> PartialGets<V> {
+    /// Whether every server answered, so the values are all the keys found.
+    pub fn is_complete(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// The outcome of `Client::deletes_partial`: whether the keys which were deleted existed, and the
+/// keys which failed.
+#[derive(Debug)]
+pub struct PartialDeletes {
+    /// Whether each key existed, for the keys whose delete succeeded.
+    pub deleted: HashMap<String, bool>,
+    /// The keys which failed, grouped by their error: all the keys of a server which failed
+    /// share its error. The groups and their keys are sorted.
+    pub errors: Vec<(Vec<String>, MemcacheError)>,
+}
+
+impl PartialDeletes {
+    /// Whether every delete succeeded.
+    pub fn is_complete(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// The keys which failed, to retry their deletes.
+    pub fn failed_keys(&self) -> impl Iterator<Item = &str> {
+        self.errors.iter().flat_map(|(keys, _)| keys.iter().map(String::as_str))
+    }
+}
+
 pub trait Connectable {
     fn get_urls(self) -> Vec<String>;
 }
@@ -49,17 +164,241 @@ impl Connectable for Vec<&str> {
 #[derive(Clone)]
 pub struct Client {
     connections: Vec<Pool<ConnectionManager>>,
+    connect_errors: Vec<LastConnectError>,
     pub hash_function: fn(&str) -> u64,
+    transcoder: Option<Arc<dyn Transcoder>>,
+    chunk_size: Option<usize>,
+    flag_layout: FlagLayout,
+    decode_failures_as_misses: bool,
+    cas_retries: u32,
+    multi_get_batch_size: usize,
+    multi_get_concurrency: Option<usize>,
+    percent_encode_keys: bool,
+    negative_ttl: Option<Expiration>,
+    buffer_pool: Option<BufferPool>,
+    get_coalescer: Option<Arc<Coalescer<Option<RawValue>>>>,
+    deferred_writes: Option<Arc<DeferredWrites>>,
+    worker_pool: Option<Arc<WorkerPool>>,
+    metrics: Option<Arc<Metrics>>,
+    slow_operation_hook: Option<(Duration, Arc<SlowOperationHook>)>,
+    counters: Arc<ServerCounters>,
+    retry_policy: Option<Arc<RetryPolicy>>,
+    on_error: OnError,
+    /// Which servers are dead, unless `on_error` is `OnError::FailFast`.
+    server_health: Option<Arc<ServerHealth>>,
+    stale_fallback: Option<Arc<StaleFallback<RawValue>>>,
+    mirror: Option<Arc<Mirror>>,
+    /// Whether errors get the context of their command, with its key if true.
+    error_context: Option<bool>,
+    #[cfg(feature = "key-hashing")]
+    long_key_prefix: Option<String>,
 }
 
+type RawValue = (Vec<u8>, u32, Option<u64>);
+
+type SlowOperationHook = dyn Fn(&SlowOperation) + Send + Sync;
+
+/// The flag of the empty values stored for cached misses, see `ClientBuilder::with_negative_caching`.
+const MISSING_FLAG: u32 = 0x1000_0000;
+
+/// The key sent to the servers and the encoded value of an entry of a batched store.
+type EncodedEntry<'a> = Result<(Cow<'a, str>, EncodedValue), MemcacheError>;
+
+/// The keys sent to the servers for the keys of a multi-get, see `Client::server_keys`.
+type ServerKeys<'a> = (Vec<Cow<'a, str>>, HashMap<String, &'a str>);
+
+/// The index of every server which failed, and its error.
+type ServerErrors = Vec<(usize, MemcacheError)>;
+
+/// The caller's keys of a batch of entries sent to a server, and the entries with the keys sent to
+/// the server.
+type Batch<'a, T> = (Vec<&'a str>, Vec<T>);
+
 unsafe impl Send for Client {}
 
+/// A connection checked out to send a command, which records the latency of the command when it's
+/// dropped if metrics are enabled, and reports it if it's slow.
+struct ServerConnection<'a> {
+    connection: PooledConnection<ConnectionManager>,
+    client: &'a Client,
+    connection_index: usize,
+    command: Command,
+    key: Option<&'a str>,
+    started: Option<Instant>,
+    /// The read and write timeouts of the socket to restore when the connection is returned to the
+    /// pool, after they were overridden for this checkout.
+    timeouts: Option<(Option<Duration>, Option<Duration>)>,
+}
+
+impl Deref for ServerConnection<'_> {
+    type Target = Connection;
+    fn deref(&self) -> &Connection {
+        &self.connection
+    }
+}
+
+impl DerefMut for ServerConnection<'_> {
+    fn deref_mut(&mut self) -> &mut Connection {
+        &mut self.connection
+    }
+}
+
+impl ServerConnection<'_> {
+    /// Send a command with `f`, adding its context to the error if enabled.
+    fn run<T, F>(&mut self, f: F) -> Result<T, MemcacheError>
+    where
+        F: FnOnce(&mut Connection) -> Result<T, MemcacheError>,
+    {
+        f(&mut self.connection).map_err(|e| {
+            self.client.record_failure(self.connection_index, &e);
+            // like for fetches, the connection is in an unknown state, so a retry needs a new one
+            if let MemcacheError::IOError(_) = e {
+                self.connection.mark_broken();
+            }
+            self.context(e)
+        })
+    }
+
+    fn context(&self, error: MemcacheError) -> MemcacheError {
+        self.client
+            .error_context(error, self.connection_index, self.command, self.key)
+    }
+
+    /// Fetch a key, counting whether it was found or the error.
+    fn fetch<T, F>(&mut self, fetch: F) -> Result<Option<T>, MemcacheError>
+    where
+        F: FnOnce(&mut Connection) -> Result<Option<T>, MemcacheError>,
+    {
+        let result = fetch(&mut self.connection);
+        match result {
+            Ok(ref value) => {
+                let hits = value.is_some() as usize;
+                self.client.counters.record_fetch(self.connection_index, hits, 1 - hits)
+            }
+            Err(ref e) => self.record_error(e),
+        }
+        result.map_err(|e| self.context(e))
+    }
+
+    /// Fetch `keys`, counting how many of them were found or the error.
+    fn fetch_many<T, F>(&mut self, keys: usize, fetch: F) -> Result<HashMap<String, T>, MemcacheError>
+    where
+        F: FnOnce(&mut Connection) -> Result<HashMap<String, T>, MemcacheError>,
+    {
+        let result = fetch(&mut self.connection);
+        match result {
+            Ok(ref values) => {
+                let hits = values.len().min(keys);
+                self.client
+                    .counters
+                    .record_fetch(self.connection_index, hits, keys - hits)
+            }
+            Err(ref e) => self.record_error(e),
+        }
+        result.map_err(|e| self.context(e))
+    }
+
+    /// Fetch `keys`, passing the values found to `f`, and count how many of them were found or the
+    /// error.
+    fn fetch_each(&mut self, keys: &[&str], f: &mut GetsCallback<RawValue>) -> Result<(), MemcacheError> {
+        match self.connection.gets_foreach(keys, f) {
+            Ok(found) => {
+                let hits = found.min(keys.len());
+                self.client
+                    .counters
+                    .record_fetch(self.connection_index, hits, keys.len() - hits);
+                Ok(())
+            }
+            Err(e) => {
+                self.record_error(&e);
+                Err(self.context(e))
+            }
+        }
+    }
+
+    /// Count the error of a fetch, and close the connection if it was an IO error, like a timeout,
+    /// since the rest of the response may still be read by the next request.
+    fn record_error(&mut self, error: &MemcacheError) {
+        self.client.counters.record_error(self.connection_index);
+        self.client.record_failure(self.connection_index, error);
+        if let MemcacheError::IOError(_) = error {
+            self.connection.mark_broken();
+        }
+    }
+}
+
+impl Drop for ServerConnection<'_> {
+    fn drop(&mut self) {
+        if let Some((read, write)) = self.timeouts.take() {
+            if self.connection.set_timeouts(read, write).is_err() {
+                self.connection.mark_broken();
+            }
+        }
+        let elapsed = match self.started {
+            Some(started) => started.elapsed(),
+            None => return,
+        };
+        if let Some(ref metrics) = self.client.metrics {
+            metrics.record(self.connection_index, self.command, elapsed);
+        }
+        if let Some((threshold, ref hook)) = self.client.slow_operation_hook {
+            if elapsed >= threshold {
+                hook(&SlowOperation {
+                    command: self.command,
+                    key: self.key,
+                    server: &self.connection.url,
+                    elapsed,
+                });
+            }
+        }
+    }
+}
+
+/// The time left until `deadline`, or `ClientError::DeadlineExceeded` if it was reached.
+fn time_left(deadline: Instant) -> Result<Duration, MemcacheError> {
+    match deadline.checked_duration_since(Instant::now()) {
+        Some(left) if left > Duration::from_secs(0) => Ok(left),
+        _ => Err(ClientError::DeadlineExceeded.into()),
+    }
+}
+
 fn default_hash_function(key: &str) -> u64 {
     let mut hasher = DefaultHasher::new();
     key.hash(&mut hasher);
     return hasher.finish();
 }
 
+/// Replace an over-length key by `prefix` followed by the hex SHA-256 digest of the key.
+#[cfg(feature = "key-hashing")]
+fn hash_key(prefix: &str, key: &str) -> String {
+    use sha2::{Digest, Sha256};
+    use std::fmt::Write;
+
+    let mut hashed = String::with_capacity(prefix.len() + 64);
+    hashed.push_str(prefix);
+    for byte in Sha256::digest(key.as_bytes()) {
+        write!(hashed, "{:02x}", byte).unwrap();
+    }
+    hashed
+}
+
+/// Replace whitespace and control characters, and `%` itself, by `%` followed by their hex value.
+fn percent_encode_key(key: &str) -> Cow<'_, str> {
+    let needs_encoding = |byte: u8| byte.is_ascii_control() || byte == b' ' || byte == b'%';
+    if !key.bytes().any(needs_encoding) {
+        return Cow::Borrowed(key);
+    }
+    let mut encoded = String::with_capacity(key.len() + 8);
+    for c in key.chars() {
+        if c.is_ascii() && needs_encoding(c as u8) {
+            encoded.push_str(&format!("%{:02X}", c as u8));
+        } else {
+            encoded.push(c);
+        }
+    }
+    Cow::Owned(encoded)
+}
+
 pub(crate) fn check_key_len(key: &str) -> Result<(), MemcacheError> {
     if key.len() > 250 {
         Err(ClientError::KeyTooLong)?
@@ -67,6 +406,27 @@ pub(crate) fn check_key_len(key: &str) -> Result<(), MemcacheError> {
     Ok(())
 }
 
+/// Serialize `value` in memory, as it would be streamed to a server.
+fn serialize<V: ToMemcacheValue<Stream>>(value: &V) -> io::Result<Vec<u8>> {
+    // not using `get_length` as capacity, which may have to serialize the value on its own
+    let mut stream = Stream::Memory(Vec::new());
+    value.write_to(&mut stream)?;
+    Ok(stream.into_memory().unwrap_or_default())
+}
+
+/// Replace the keys rewritten by `Client::key` in the results of a multi-get by the caller's keys.
+fn restore_keys<V>(mut values: HashMap<String, V>, rewritten_keys: &HashMap<String, &str>) -> HashMap<String, V> {
+    if rewritten_keys.is_empty() {
+        return values;
+    }
+    for (server_key, key) in rewritten_keys {
+        if let Some(value) = values.remove(server_key) {
+            values.insert(key.to_string(), value);
+        }
+    }
+    values
+}
+
 impl Client {
     #[deprecated(since = "0.10.0", note = "please use `connect` instead")]
     pub fn new<C: Connectable>(target: C) -> Result<Self, MemcacheError> {
@@ -74,28 +434,555 @@ impl Client {
     }
 
     pub fn with_pool_size<C: Connectable>(target: C, size: u32) -> Result<Self, MemcacheError> {
-        let urls = target.get_urls();
-        let mut connections = vec![];
-        for url in urls {
-            let parsed = Url::parse(url.as_str())?;
-            let pool = r2d2::Pool::builder()
-                .max_size(size)
-                .build(ConnectionManager::new(parsed))?;
-            connections.push(pool);
-        }
-        Ok(Client {
-            connections,
-            hash_function: default_hash_function,
-        })
+        Self::builder().add_server(target).with_pool_size(size).build()
+    }
+
+    /// Create a `ClientBuilder`, for settings which can't be expressed by the server urls.
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
     }
 
     pub fn connect<C: Connectable>(target: C) -> Result<Self, MemcacheError> {
         Self::with_pool_size(target, 1)
     }
 
-    fn get_connection(&self, key: &str) -> Pool<ConnectionManager> {
+    /// Whether the values are transformed, split into chunks or mirrored by the client, so they
+    /// are encoded before being stored, instead of streamed as serialized by `ToMemcacheValue`.
+    fn encodes_values(&self) -> bool {
+        self.transcoder.is_some() || self.chunk_size.is_some() || self.mirror.is_some()
+    }
+
+    fn encode<V: ToMemcacheValue<Stream>>(&self, value: &V) -> Result<EncodedValue, MemcacheError> {
+        let data = serialize(value)?;
+        let (data, flags) = match self.transcoder {
+            Some(ref transcoder) => transcoder.encode_kind(data, value.get_flags(), value.get_kind())?,
+            None => (data, value.get_flags()),
+        };
+        Ok(EncodedValue { data, flags })
+    }
+
+    /// Split a value larger than the chunk size into chunks stored under their own keys, and return
+    /// the manifest to store under `key` instead.
+    fn store_chunks(&self, key: &str, value: EncodedValue, expiration: u32) -> Result<EncodedValue, MemcacheError> {
+        let chunk_size = match self.chunk_size {
+            Some(chunk_size) if value.data.len() > chunk_size => chunk_size,
+            _ => return Ok(value),
+        };
+        let manifest = Manifest::new(value.data.len(), chunk_size, value.flags);
+        let chunk_keys = manifest.chunk_keys(key);
+        let chunk_keys = chunk_keys
+            .iter()
+            .map(|chunk_key| self.key(chunk_key))
+            .collect::<Result<Vec<_>, _>>()?;
+        for (chunk_key, chunk) in chunk_keys.iter().zip(value.data.chunks(chunk_size)) {
+            let mut connection = self.connection(Command::Set, chunk_key)?;
+            connection.check_item_size(chunk.len())?;
+            connection.run(|connection| connection.set(chunk_key, chunk, expiration))?;
+        }
+        Ok(EncodedValue {
+            data: manifest.to_bytes(),
+            flags: CHUNKED_FLAG,
+        })
+    }
+
+    /// Replace a fetched manifest by the value reassembled from its chunks, or `None` if any chunk
+    /// is missing. `server_key` is the key sent to the servers, from which the chunk keys are
+    /// derived like in `store_chunks`.
+    fn fetch_chunks(&self, server_key: &str, value: RawValue) -> Result<Option<RawValue>, MemcacheError> {
+        let (data, flags, cas) = value;
+        if self.chunk_size.is_none() || flags & CHUNKED_FLAG == 0 {
+            return Ok(Some((data, flags, cas)));
+        }
+        let manifest = Manifest::parse(&data)?;
+        // `gets_raw` rewrites the chunk keys like `store_chunks` did
+        let chunk_keys = manifest.chunk_keys(server_key);
+        let chunk_keys: Vec<&str> = chunk_keys.iter().map(String::as_str).collect();
+        let chunks = self
+            .gets_raw(&chunk_keys)?
+            .into_iter()
+            .map(|(chunk_key, (chunk, _, _))| (chunk_key, chunk))
+            .collect();
+        Ok(manifest
+            .assemble(server_key, chunks)
+            .map(|data| (data, manifest.flags, cas)))
+    }
+
+    /// Decode a fetched value, or return `None` if it can't be decoded and decode failures are
+    /// treated as misses.
+    fn decode<V: FromMemcacheValueExt>(&self, value: RawValue) -> Result<Option<V>, MemcacheError> {
+        let (data, flags, cas) = value;
+        if flags & self.flag_layout.negative_caching != 0 {
+            return Ok(None);
+        }
+        let decoded = match self.transcoder {
+            Some(ref transcoder) => transcoder.decode(data, flags),
+            None => Ok((data, flags)),
+        }
+        .and_then(|(data, flags)| match self.buffer_pool {
+            Some(ref pool) => match V::from_memcache_slice(&data, flags, cas) {
+                Some(decoded) => {
+                    pool.recycle(data);
+                    decoded
+                }
+                None => V::from_memcache_value(data, flags, cas),
+            },
+            None => V::from_memcache_value(data, flags, cas),
+        });
+        match decoded {
+            Err(_) if self.decode_failures_as_misses => Ok(None),
+            decoded => decoded.map(Some),
+        }
+    }
+
+    fn decode_value<V: FromMemcacheValueExt>(
+        &self,
+        key: String,
+        value: RawValue,
+    ) -> Result<Option<Value<V>>, MemcacheError> {
+        let value = match self.fetch_chunks(&self.key(&key)?, value)? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let (flags, cas) = (value.1, value.2);
+        Ok(self.decode(value)?.map(|value| Value { value, flags, cas, key }))
+    }
+
+    /// Which flag bits are reserved by the client's transcoders, chunking and serialization
+    /// features, and which are declared by the application.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::builder()
+    ///     .add_server("memcache://localhost:12345")
+    ///     .with_transcoder(memcache::PythonCompat::python_memcached())
+    ///     .with_application_flags(0xf000)
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!(client.flag_layout().transcoders, 0x17);
+    /// assert_eq!(client.flag_layout().reserved() & 0xf000, 0);
+    /// ```
+    pub fn flag_layout(&self) -> FlagLayout {
+        self.flag_layout
+    }
+
+    /// The key sent to the servers for `key`. Over-length keys are replaced by their hash if
+    /// enabled with `ClientBuilder::with_long_key_hashing`, or rejected.
+    fn key<'a>(&self, key: &'a str) -> Result<Cow<'a, str>, MemcacheError> {
+        let key = if self.percent_encode_keys {
+            percent_encode_key(key)
+        } else {
+            Cow::Borrowed(key)
+        };
+        #[cfg(feature = "key-hashing")]
+        {
+            if let Some(ref prefix) = self.long_key_prefix {
+                if key.len() > 250 {
+                    return Ok(Cow::Owned(hash_key(prefix, &key)));
+                }
+            }
+        }
+        check_key_len(&key)?;
+        Ok(key)
+    }
+
+    /// The index of the server storing `key` in `self.connections`. A single server is used without
+    /// hashing the key.
+    fn connection_index(&self, key: &str) -> usize {
         let connections_count = self.connections.len();
-        return self.connections[(self.hash_function)(key) as usize % connections_count].clone();
+        if connections_count == 1 {
+            return 0;
+        }
+        let hash = (self.hash_function)(key);
+        let connection_index = hash as usize % connections_count;
+        if let (OnError::RehashToLive, Some(ref health)) = (self.on_error, &self.server_health) {
+            if health.is_dead(connection_index) {
+                return health.rehash(hash).unwrap_or(connection_index);
+            }
+        }
+        connection_index
+    }
+
+    /// Mark the server at `connection_index` as dead if `error` is a connection error, unless the
+    /// failures are handled with `OnError::FailFast`.
+    fn record_failure(&self, connection_index: usize, error: &MemcacheError) {
+        if let Some(ref health) = self.server_health {
+            health.record_failure(connection_index, error);
+        }
+    }
+
+    /// Send the command built by `command` to the mirror set with `ClientBuilder::with_mirror`.
+    fn mirror<F: FnOnce() -> Mirrored>(&self, command: F) {
+        if let Some(ref mirror) = self.mirror {
+            mirror.send(command());
+        }
+    }
+
+    /// Send the store of an encoded value to the mirror, before it's split into chunks.
+    fn mirror_store(&self, command: Command, key: &str, data: &[u8], flags: u32, expiration: u32) {
+        self.mirror(|| Mirrored::Store {
+            command,
+            key: key.to_string(),
+            data: data.to_vec(),
+            flags,
+            expiration,
+        })
+    }
+
+    /// The set of `value` to send to the mirror once it's stored, for the stores which may be
+    /// rejected like `cas`, or `None` without a mirror.
+    fn mirrored_set(&self, key: &str, value: &EncodedValue, expiration: u32) -> Option<Mirrored> {
+        self.mirror.as_ref().map(|_| Mirrored::Store {
+            command: Command::Set,
+            key: key.to_string(),
+            data: value.data.clone(),
+            flags: value.flags,
+            expiration,
+        })
+    }
+
+    /// Send the store of `value` under the caller's `key` to the mirror, and split it into chunks
+    /// if it's larger than the chunk size, see `store_chunks`. The values appended or prepended
+    /// are never split.
+    fn prepare_store(
+        &self,
+        command: Command,
+        key: &str,
+        server_key: &str,
+        value: EncodedValue,
+        expiration: u32,
+    ) -> Result<EncodedValue, MemcacheError> {
+        self.mirror_store(command, key, &value.data, value.flags, expiration);
+        match command {
+            Command::Append | Command::Prepend => Ok(value),
+            _ => self.store_chunks(server_key, value, expiration),
+        }
+    }
+
+    /// Encode `value` and prepare its store like `prepare_store` if the client encodes the values,
+    /// or else return `None`, so it's streamed to the server without being copied.
+    fn prepare_value<V: ToMemcacheValue<Stream>>(
+        &self,
+        command: Command,
+        key: &str,
+        server_key: &str,
+        value: &V,
+        expiration: u32,
+    ) -> Result<Option<EncodedValue>, MemcacheError> {
+        if !self.encodes_values() {
+            return Ok(None);
+        }
+        let value = self.encode(value)?;
+        self.prepare_store(command, key, server_key, value, expiration)
+            .map(Some)
+    }
+
+    /// Send a command mirrored by another client, see `ClientBuilder::with_mirror`. The values
+    /// are stored as encoded by the other client.
+    pub(crate) fn replay(&self, command: Mirrored) -> Result<(), MemcacheError> {
+        match command {
+            Mirrored::Store {
+                command,
+                key,
+                data,
+                flags,
+                expiration,
+            } => {
+                let server_key = self.key(&key)?;
+                let key = &*server_key;
+                let value = FlaggedValue { data: &data, flags };
+                let mut connection = self.connection(command, key)?;
+                connection.run(|connection| match command {
+                    Command::Add => connection.add(key, value, expiration),
+                    Command::Replace => connection.replace(key, value, expiration),
+                    Command::Append => connection.append(key, value),
+                    Command::Prepend => connection.prepend(key, value),
+                    _ => connection.set(key, value, expiration),
+                })
+            }
+            Mirrored::SetBytesKey {
+                key,
+                data,
+                flags,
+                expiration,
+            } => {
+                let value = FlaggedValue { data: &data, flags };
+                self.checkout(Command::Set, self.bytes_key_connection_index(&key)?, None)?
+                    .run(|connection| connection.set_bytes_key(&key, value, expiration))
+            }
+            Mirrored::Delete(key) => self.delete(&key).map(|_| ()),
+            Mirrored::DeleteBytesKey(key) => self.delete_bytes_key(&key).map(|_| ()),
+            Mirrored::Touch(key, expiration) => self.touch(&key, expiration).map(|_| ()),
+            Mirrored::Increment(key, amount) => self.increment(&key, amount).map(|_| ()),
+            Mirrored::Decrement(key, amount) => self.decrement(&key, amount).map(|_| ()),
+            Mirrored::Get(key) => self.get_raw(&key).map(|_| ()),
+            Mirrored::Flush => self.flush(),
+            Mirrored::FlushWithDelay(delay) => self.flush_with_delay(delay),
+        }
+    }
+
+    /// Check that every server supports fetching the remaining TTLs of the keys copied by
+    /// `copy_key`, see `migrate`.
+    pub(crate) fn check_copy_source(&self) -> Result<(), MemcacheError> {
+        self.broadcast_unit(|connection| connection.check_get_with_ttl())
+    }
+
+    /// Copy the value of `key` to `dst` with its flags and remaining TTL, and return whether it
+    /// was found, see `migrate`. `key` is sent as is if it was listed by the server, else the key
+    /// encoding of each client is applied. A value split into chunks is reassembled, and stored
+    /// through the chunking of `dst`.
+    pub(crate) fn copy_key(&self, dst: &Client, key: &str, listed: bool) -> Result<bool, MemcacheError> {
+        let (src_key, dst_key) = if listed {
+            (Cow::Borrowed(key), Cow::Borrowed(key))
+        } else {
+            (self.key(key)?, dst.key(key)?)
+        };
+        let src_key = &*src_key;
+        let stored = self
+            .connection(Command::Get, src_key)?
+            .run(|connection| connection.get_with_ttl(src_key))?;
+        let (data, flags, expiration) = match stored {
+            // the item is about to expire
+            Some((_, _, Some(0))) | None => return Ok(false),
+            Some((data, flags, ttl)) => (
+                data,
+                flags,
+                ttl.map_or(0, |ttl| {
+                    Expiration::After(Duration::from_secs(u64::from(ttl))).exptime()
+                }),
+            ),
+        };
+        let (data, flags) = match self.fetch_chunks(src_key, (data, flags, None))? {
+            Some((data, flags, _)) => (data, flags),
+            // a chunk expired or was evicted
+            None => return Ok(false),
+        };
+        let dst_key = &*dst_key;
+        let value = dst.store_chunks(dst_key, EncodedValue { data, flags }, expiration)?;
+        let mut connection = dst.connection(Command::Set, dst_key)?;
+        connection.check_item_size(value.data.len())?;
+        connection.run(|connection| connection.set(dst_key, value, expiration))?;
+        Ok(true)
+    }
+
+    /// Whether a multi-get treats the keys of a server which failed with `error` as misses.
+    fn skips_failure(&self, error: &MemcacheError) -> bool {
+        self.on_error == OnError::SkipDeadServers && error.is_connection()
+    }
+
+    /// The index of the server storing a key which isn't valid UTF-8, selected by hashing its
+    /// lossy UTF-8 conversion.
+    fn bytes_key_connection_index(&self, key: &[u8]) -> Result<usize, MemcacheError> {
+        if key.len() > 250 {
+            return Err(ClientError::KeyTooLong.into());
+        }
+        Ok(self.connection_index(&String::from_utf8_lossy(key)))
+    }
+
+    /// Check out a connection of the server at `connection_index` to send `command`, for `key`
+    /// unless the command is sent for several keys.
+    fn checkout<'a>(
+        &'a self,
+        command: Command,
+        connection_index: usize,
+        key: Option<&'a str>,
+    ) -> Result<ServerConnection<'a>, MemcacheError> {
+        self.checkout_with_timeout(command, connection_index, key, None)
+    }
+
+    /// Check out a connection like `checkout`, waiting at most `timeout` for it and then for each
+    /// read and write on its socket, instead of the timeouts of the pool and of the connection.
+    fn checkout_with_timeout<'a>(
+        &'a self,
+        command: Command,
+        connection_index: usize,
+        key: Option<&'a str>,
+        timeout: Option<Duration>,
+    ) -> Result<ServerConnection<'a>, MemcacheError> {
+        if let (OnError::SkipDeadServers, Some(ref health)) = (self.on_error, &self.server_health) {
+            if health.is_dead(connection_index) {
+                let e = ClientError::ServerDead.into();
+                return Err(self.error_context(e, connection_index, command, key));
+            }
+        }
+        let pool = &self.connections[connection_index];
+        let connection = match timeout.map_or_else(|| pool.get(), |timeout| pool.get_timeout(timeout)) {
+            Ok(connection) => connection,
+            Err(e) => {
+                if let Command::Get | Command::Gets = command {
+                    self.counters.record_error(connection_index);
+                }
+                let e = self.connect_errors[connection_index].pool_error(e);
+                self.record_failure(connection_index, &e);
+                return Err(self.error_context(e, connection_index, command, key));
+            }
+        };
+        let timed = self.metrics.is_some() || self.slow_operation_hook.is_some();
+        let mut connection = ServerConnection {
+            connection,
+            client: self,
+            connection_index,
+            command,
+            key,
+            started: if timed { Some(Instant::now()) } else { None },
+            timeouts: None,
+        };
+        if let Some(timeout) = timeout {
+            let timeouts = connection.run(|connection| connection.timeouts())?;
+            // restored when the connection is dropped, even if setting them partially failed
+            connection.timeouts = Some(timeouts);
+            connection.run(|connection| connection.set_timeouts(Some(timeout), Some(timeout)))?;
+        }
+        Ok(connection)
+    }
+
+    /// Run `f`, which sends `command`, again after the transient errors allowed by the retry policy.
+    fn retrying<T, F>(&self, command: Command, mut f: F) -> Result<T, MemcacheError>
+    where
+        F: FnMut() -> Result<T, MemcacheError>,
+    {
+        self.retrying_until(command, None, |_| f())
+    }
+
+    /// Run `f` like `retrying`, but fail with `ClientError::DeadlineExceeded` once `deadline` is
+    /// reached instead of attempting `command` again, or when a transient error happened after it.
+    /// Every attempt is passed the time left until the deadline.
+    fn retrying_until<T, F>(&self, command: Command, deadline: Option<Instant>, mut f: F) -> Result<T, MemcacheError>
+    where
+        F: FnMut(Option<Duration>) -> Result<T, MemcacheError>,
+    {
+        let mut attempt = 1;
+        loop {
+            let left = match deadline {
+                Some(deadline) => Some(time_left(deadline)?),
+                None => None,
+            };
+            let error = match f(left) {
+                Err(e) => e,
+                result => return result,
+            };
+            if let Some(deadline) = deadline {
+                if error.class().is_some() && Instant::now() >= deadline {
+                    return Err(ClientError::DeadlineExceeded.into());
+                }
+            }
+            let backoff = match self.retry_policy {
+                Some(ref policy) => policy.backoff(command, &error, attempt),
+                None => None,
+            };
+            let backoff = match backoff {
+                Some(backoff) => backoff,
+                None => return Err(error),
+            };
+            if let Some(deadline) = deadline {
+                if Instant::now() + backoff >= deadline {
+                    return Err(ClientError::DeadlineExceeded.into());
+                }
+            }
+            thread::sleep(backoff);
+            attempt += 1;
+        }
+    }
+
+    /// Add the server and `command` to `error` if enabled with `ClientBuilder::with_error_context`.
+    fn error_context(
+        &self,
+        error: MemcacheError,
+        connection_index: usize,
+        command: Command,
+        key: Option<&str>,
+    ) -> MemcacheError {
+        match (self.error_context, error) {
+            (None, error) | (_, error @ MemcacheError::ContextError(_)) => error,
+            (Some(include_keys), error) => MemcacheError::ContextError(Box::new(ErrorContext {
+                error,
+                server: self.counters.url(connection_index).to_string(),
+                command,
+                key: if include_keys { key.map(String::from) } else { None },
+            })),
+        }
+    }
+
+    /// Check out a connection of the server storing `key` to send `command`.
+    fn connection<'a>(&'a self, command: Command, key: &'a str) -> Result<ServerConnection<'a>, MemcacheError> {
+        self.checkout(command, self.connection_index(key), Some(key))
+    }
+
+    /// Check out a connection for every server, in the same order as the urls passed to `connect`.
+    /// This gives access to server specific functionality which is not exposed by `Client`.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// let connections = client.get_connections().unwrap();
+    /// assert_eq!(connections.len(), 1);
+    /// ```
+    pub fn get_connections(&self) -> Result<Vec<PooledConnection<ConnectionManager>>, MemcacheError> {
+        let mut result = Vec::with_capacity(self.connections.len());
+        for connection in self.connections.iter() {
+            result.push(connection.get()?);
+        }
+        Ok(result)
+    }
+
+    /// The latency histograms of the commands sent to each server since the client was built, by
+    /// server url and command, for the commands sent at least once. Empty unless enabled with
+    /// `ClientBuilder::with_metrics`.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::builder()
+    ///     .add_server("memcache://localhost:12345")
+    ///     .with_metrics()
+    ///     .build()
+    ///     .unwrap();
+    /// client.set("foo", "bar", 10).unwrap();
+    /// let metrics = client.metrics();
+    /// let set = &metrics[&("memcache://localhost:12345".to_string(), memcache::Command::Set)];
+    /// assert_eq!(set.count(), 1);
+    /// println!("p99: {:?}", set.quantile(0.99));
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn metrics(&self) -> HashMap<(String, Command), LatencyHistogram> {
+        match self.metrics {
+            Some(ref metrics) => metrics.snapshot(),
+            None => HashMap::new(),
+        }
+    }
+
+    /// The hits, misses and errors of the commands fetching keys sent by the client since it was
+    /// built, summed over every server. See `server_counters` for those of each server.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// client.set("foo", "bar", 10).unwrap();
+    /// let _: Option<String> = client.get("foo").unwrap();
+    /// let _: Option<String> = client.get("missing").unwrap();
+    /// let counters = client.counters();
+    /// assert_eq!((counters.hits, counters.misses), (1, 1));
+    /// assert_eq!(counters.hit_rate(), 0.5);
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn counters(&self) -> Counters {
+        self.counters.total()
+    }
+
+    /// The hits, misses and errors of the commands fetching keys sent to each server since the
+    /// client was built, by server url.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// let _: Option<String> = client.get("missing").unwrap();
+    /// assert_eq!(client.server_counters()["memcache://localhost:12345"].misses, 1);
+    /// ```
+    pub fn server_counters(&self) -> HashMap<String, Counters> {
+        self.counters.snapshot()
     }
 
     /// Set the socket read timeout for TCP connections.
@@ -145,13 +1032,89 @@ impl Client {
     /// client.version().unwrap();
     /// ```
     pub fn version(&self) -> Result<Vec<(String, String)>, MemcacheError> {
-        let mut result = Vec::with_capacity(self.connections.len());
-        for connection in self.connections.iter() {
-            let mut connection = connection.get()?;
-            let url = connection.get_url();
-            result.push((url, connection.version()?));
+        self.broadcast(|connection| Ok((connection.get_url(), connection.version()?)))
+            .into_iter()
+            .collect()
+    }
+
+    /// Get the version of every server like `version`, but return the result of each server along
+    /// with its url, so the versions of the servers which answered are known even if others are
+    /// unreachable.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// for (url, version) in client.version_each() {
+    ///     match version {
+    ///         Ok(version) => println!("{} runs memcached {}", url, version),
+    ///         Err(e) => println!("{} is unreachable: {}", url, e),
+    ///     }
+    /// }
+    /// ```
+    pub fn version_each(&self) -> Vec<(String, Result<String, MemcacheError>)> {
+        self.broadcast_each(|connection| connection.version())
+    }
+
+    /// Run `f` on a connection to every server, concurrently if there are several, and return the
+    /// results in the order of the servers. Every server is reached even if some fail.
+    fn broadcast<T, F>(&self, f: F) -> Vec<Result<T, MemcacheError>>
+    where
+        T: Send,
+        F: Fn(&mut Connection) -> Result<T, MemcacheError> + Sync,
+    {
+        let run = |index: usize| {
+            let mut connection = self.connections[index]
+                .get()
+                .map_err(|e| self.connect_errors[index].pool_error(e))?;
+            f(&mut connection)
+        };
+        if self.connections.len() <= 1 {
+            return (0..self.connections.len()).map(run).collect();
         }
-        Ok(result)
+        let run = &run;
+        self.run_concurrently(
+            (0..self.connections.len())
+                .map(|index| -> Box<dyn FnOnce() -> _ + Send> { Box::new(move || run(index)) })
+                .collect(),
+        )
+    }
+
+    /// Run `jobs` concurrently, on the worker pool set with `ClientBuilder::with_worker_pool` or
+    /// else on a thread each, and return their results in order.
+    fn run_concurrently<'a, T: Send + 'a>(&self, jobs: Vec<Box<dyn FnOnce() -> T + Send + 'a>>) -> Vec<T> {
+        if let Some(ref worker_pool) = self.worker_pool {
+            return worker_pool.run(jobs);
+        }
+        thread::scope(|scope| {
+            let workers: Vec<_> = jobs.into_iter().map(|job| scope.spawn(job)).collect();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+                .collect()
+        })
+    }
+
+    /// Run `f` on every server with `broadcast`, and return the results along with the urls of
+    /// the servers.
+    fn broadcast_each<T, F>(&self, f: F) -> Vec<(String, Result<T, MemcacheError>)>
+    where
+        T: Send,
+        F: Fn(&mut Connection) -> Result<T, MemcacheError> + Sync,
+    {
+        self.broadcast(f)
+            .into_iter()
+            .enumerate()
+            .map(|(index, result)| (self.counters.url(index).to_string(), result))
+            .collect()
+    }
+
+    /// Run `f` on every server with `broadcast`, and return the first error if any failed.
+    fn broadcast_unit<F>(&self, f: F) -> Result<(), MemcacheError>
+    where
+        F: Fn(&mut Connection) -> Result<(), MemcacheError> + Sync,
+    {
+        self.broadcast(f).into_iter().collect()
     }
 
     /// Flush all cache on memcached server immediately.
@@ -163,10 +1126,8 @@ impl Client {
     /// client.flush().unwrap();
     /// ```
     pub fn flush(&self) -> Result<(), MemcacheError> {
-        for connection in self.connections.iter() {
-            connection.get()?.flush()?;
-        }
-        return Ok(());
+        self.mirror(|| Mirrored::Flush);
+        self.broadcast_unit(|connection| connection.flush())
     }
 
     /// Flush all cache on memcached server with a delay seconds.
@@ -178,10 +1139,24 @@ impl Client {
     /// client.flush_with_delay(10).unwrap();
     /// ```
     pub fn flush_with_delay(&self, delay: u32) -> Result<(), MemcacheError> {
-        for connection in self.connections.iter() {
-            connection.get()?.flush_with_delay(delay)?;
-        }
-        return Ok(());
+        self.mirror(|| Mirrored::FlushWithDelay(delay));
+        self.broadcast_unit(|connection| {
+            connection.check_feature(Feature::FlushWithDelay)?;
+            connection.flush_with_delay(delay)
+        })
+    }
+
+    /// Set the logging verbosity level of all servers.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// client.verbosity(1).unwrap();
+    /// client.verbosity(0).unwrap();
+    /// ```
+    pub fn verbosity(&self, level: u32) -> Result<(), MemcacheError> {
+        self.broadcast_unit(|connection| connection.verbosity(level))
     }
 
     /// Get a key from memcached server.
@@ -192,234 +1167,2914 @@ impl Client {
     /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
     /// let _: Option<String> = client.get("foo").unwrap();
     /// ```
-    pub fn get<V: FromMemcacheValueExt>(&self, key: &str) -> Result<Option<V>, MemcacheError> {
-        check_key_len(key)?;
-        return self.get_connection(key).get()?.get(key);
+    pub fn get<V: FromMemcacheValueExt>(&self, key: impl AsRef<str>) -> Result<Option<V>, MemcacheError> {
+        let key = key.as_ref();
+        if let Some(ref mirror) = self.mirror {
+            mirror.sample_read(key);
+        }
+        let key = &*self.key(key)?;
+        match self.get_assembled(key)? {
+            Some(value) => self.decode(value),
+            None => Ok(None),
+        }
     }
 
-    /// Get multiple keys from memcached server. Using this function instead of calling `get` multiple times can reduce network workloads.
+    /// Get a key from memcached server like `get`, but if its server is down, return the value
+    /// last fetched by `get` or `get_or_stale` if it's kept by the stale fallback enabled with
+    /// `ClientBuilder::with_stale_fallback`, marked as stale, instead of the connection error.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::builder()
+    ///     .add_server("memcache://localhost:12345")
+    ///     .with_stale_fallback(1000)
+    ///     .build()
+    ///     .unwrap();
+    /// client.set("foo", "bar", 10).unwrap();
+    /// match client.get_or_stale::<String>("foo").unwrap() {
+    ///     Some(memcache::MaybeStale::Fresh(value)) => assert_eq!(value, "bar"),
+    ///     Some(memcache::MaybeStale::Stale { value, age }) => println!("{} is {:?} old", value, age),
+    ///     None => panic!("foo is missing"),
+    /// }
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn get_or_stale<V: FromMemcacheValueExt>(
+        &self,
+        key: impl AsRef<str>,
+    ) -> Result<Option<MaybeStale<V>>, MemcacheError> {
+        let key = &*self.key(key.as_ref())?;
+        let (value, age) = match (self.get_assembled(key), &self.stale_fallback) {
+            (Ok(Some(value)), _) => (value, None),
+            (Ok(None), _) => return Ok(None),
+            (Err(e), Some(fallback)) if e.is_connection() => match fallback.get(key) {
+                Some((value, age)) => (value, Some(age)),
+                None => return Err(e),
+            },
+            (Err(e), _) => return Err(e),
+        };
+        Ok(self.decode(value)?.map(|value| match age {
+            Some(age) => MaybeStale::Stale { value, age },
+            None => MaybeStale::Fresh(value),
+        }))
+    }
+
+    /// Fetch `key` and the chunks of its value, and keep a copy of the value for the stale
+    /// fallback if enabled.
+    fn get_assembled(&self, key: &str) -> Result<Option<RawValue>, MemcacheError> {
+        let value = match self.get_raw_coalesced(key)? {
+            Some(value) => self.fetch_chunks(key, value)?,
+            None => None,
+        };
+        if let Some(ref fallback) = self.stale_fallback {
+            match value {
+                Some(ref value) => fallback.insert(key, value.clone()),
+                None => fallback.remove(key),
+            }
+        }
+        Ok(value)
+    }
+
+    /// Get a key from memcached server like `get`, within `timeout`: the pooled connection is
+    /// waited for, and the request sent and read, with the time left instead of the timeouts of
+    /// the client, and retries allowed by the retry policy are only attempted before the deadline.
+    /// Fails with `ClientError::DeadlineExceeded` when the deadline is reached. The timeouts of
+    /// the connection are restored when it's returned to the pool, or it is closed if the request
+    /// failed, so a late response isn't read by the next request.
+    ///
+    /// The fetch isn't shared with the concurrent gets of the key, as they may wait longer, and
+    /// the chunks of a value stored in chunks are fetched with the timeouts of the client.
     ///
     /// Example:
     ///
     /// ```rust
+    /// use std::time::Duration;
+    ///
     /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
-    /// client.set("foo", "42", 0).unwrap();
-    /// let result: std::collections::HashMap<String, String> = client.gets(&["foo", "bar", "baz"]).unwrap();
-    /// assert_eq!(result.len(), 1);
-    /// assert_eq!(result["foo"], "42");
+    /// client.set("foo", "bar", 10).unwrap();
+    /// let value: Option<String> = client.get_with_timeout("foo", Duration::from_millis(20)).unwrap();
+    /// assert_eq!(value, Some("bar".to_string()));
+    /// # client.flush().unwrap();
     /// ```
-    pub fn gets<V: FromMemcacheValueExt>(&self, keys: &[&str]) -> Result<HashMap<String, V>, MemcacheError> {
-        for key in keys {
-            check_key_len(key)?;
+    pub fn get_with_timeout<V: FromMemcacheValueExt>(
+        &self,
+        key: impl AsRef<str>,
+        timeout: Duration,
+    ) -> Result<Option<V>, MemcacheError> {
+        let key = &*self.key(key.as_ref())?;
+        let deadline = Instant::now() + timeout;
+        let value = self.retrying_until(Command::Get, Some(deadline), |left| {
+            self.checkout_with_timeout(Command::Get, self.connection_index(key), Some(key), left)?
+                .fetch(|connection| connection.get(key))
+        })?;
+        let value = match value {
+            Some(value) => self.fetch_chunks(key, value)?,
+            None => None,
+        };
+        match value {
+            Some(value) => self.decode(value),
+            None => Ok(None),
         }
-        let mut con_keys: HashMap<usize, Vec<&str>> = HashMap::new();
-        let mut result: HashMap<String, V> = HashMap::new();
-        let connections_count = self.connections.len();
+    }
 
-        for key in keys {
-            let connection_index = (self.hash_function)(key) as usize % connections_count;
-            let array = con_keys.entry(connection_index).or_insert_with(Vec::new);
-            array.push(key);
+    /// Fetch the raw value of a key sent to the servers as is, sharing the request with the
+    /// concurrent fetches of the key if enabled with `ClientBuilder::with_get_coalescing`.
+    fn get_raw_coalesced(&self, key: &str) -> Result<Option<RawValue>, MemcacheError> {
+        let fetch = || {
+            self.retrying(Command::Get, || {
+                self.connection(Command::Get, key)?
+                    .fetch(|connection| connection.get(key))
+            })
+        };
+        match self.get_coalescer {
+            Some(ref coalescer) => coalescer.fetch(key, fetch),
+            None => fetch(),
+        }
+    }
+
+    /// Get a key of arbitrary bytes, like a raw digest, from memcached server. Keys which are valid
+    /// UTF-8 are handled like by `get`, others are sent as is, without key encoding or hashing.
+    /// Only the binary protocol can carry them, the ascii protocol rejects them with
+    /// `ClientError::InvalidKey`.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// client.set_bytes_key(b"\xde\xad\xbe\xef", "bar", 10).unwrap();
+    /// let value: Option<String> = client.get_bytes_key(b"\xde\xad\xbe\xef").unwrap();
+    /// assert_eq!(value, Some("bar".to_string()));
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn get_bytes_key<V: FromMemcacheValueExt>(&self, key: &[u8]) -> Result<Option<V>, MemcacheError> {
+        if let Ok(key) = std::str::from_utf8(key) {
+            return self.get(key);
         }
-        for (&connection_index, keys) in con_keys.iter() {
-            let connection = self.connections[connection_index].clone();
-            result.extend(connection.get()?.gets(keys)?);
+        let connection_index = self.bytes_key_connection_index(key)?;
+        let value = self.retrying(Command::Get, || {
+            self.checkout(Command::Get, connection_index, None)?
+                .fetch(|connection| connection.get_bytes_key(key))
+        })?;
+        match value {
+            Some(value) => self.decode(value),
+            None => Ok(None),
         }
-        return Ok(result);
     }
 
-    /// Set a key with associate value into memcached server with expiration seconds.
+    /// Get a key from memcached server with its flags and cas unique, for check and set workflows.
     ///
     /// Example:
     ///
     /// ```rust
     /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
     /// client.set("foo", "bar", 10).unwrap();
+    /// let value: memcache::Value<String> = client.get_value("foo").unwrap().unwrap();
+    /// assert_eq!(value.value, "bar");
+    /// assert!(client.cas("foo", "baz", 10, value.cas.unwrap()).unwrap());
     /// # client.flush().unwrap();
     /// ```
-    pub fn set<V: ToMemcacheValue<Stream>>(&self, key: &str, value: V, expiration: u32) -> Result<(), MemcacheError> {
-        check_key_len(key)?;
-        return self.get_connection(key).get()?.set(key, value, expiration);
+    pub fn get_value<V: FromMemcacheValueExt>(&self, key: &str) -> Result<Option<Value<V>>, MemcacheError> {
+        let server_key = &*self.key(key)?;
+        let value = self.retrying(Command::Get, || {
+            self.connection(Command::Get, server_key)?
+                .fetch(|connection| connection.get_with_cas::<RawValue>(server_key))
+        })?;
+        match value {
+            Some((value, _)) => self.decode_value(key.to_string(), value),
+            None => Ok(None),
+        }
     }
 
-    /// Compare and swap a key with the associate value into memcached server with expiration seconds.
-    /// `cas_id` should be obtained from a previous `gets` call.
+    /// Get a key from memcached server with its cas unique, for `cas`.
     ///
     /// Example:
     ///
     /// ```rust
-    /// use std::collections::HashMap;
     /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
     /// client.set("foo", "bar", 10).unwrap();
-    /// let result: HashMap<String, (Vec<u8>, u32, Option<u64>)> = client.gets(&["foo"]).unwrap();
-    /// let (_, _, cas) = result.get("foo").unwrap();
-    /// let cas = cas.unwrap();
-    /// assert_eq!(true, client.cas("foo", "bar2", 10, cas).unwrap());
+    /// let (value, cas): (String, u64) = client.get_with_cas("foo").unwrap().unwrap();
+    /// assert_eq!(value, "bar");
+    /// assert!(client.cas("foo", "baz", 10, cas).unwrap());
     /// # client.flush().unwrap();
     /// ```
-    pub fn cas<V: ToMemcacheValue<Stream>>(
+    pub fn get_with_cas<V: FromMemcacheValueExt>(&self, key: &str) -> Result<Option<(V, u64)>, MemcacheError> {
+        let key = &*self.key(key)?;
+        let value = self.retrying(Command::Get, || {
+            self.connection(Command::Get, key)?
+                .fetch(|connection| connection.get_with_cas::<RawValue>(key))
+        })?;
+        let (value, cas) = match value {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let value = match self.fetch_chunks(key, value)? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        Ok(self.decode(value)?.map(|value| (value, cas)))
+    }
+
+    /// Get multiple keys from memcached server with their flags and cas uniques, see `get_value`.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// client.set("foo", "42", 10).unwrap();
+    /// let values: std::collections::HashMap<String, memcache::Value<u64>> =
+    ///     client.gets_values(&["foo", "bar"]).unwrap();
+    /// assert_eq!(values["foo"].value, 42);
+    /// assert!(values["foo"].cas.is_some());
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn gets_values<V: FromMemcacheValueExt>(
         &self,
-        key: &str,
-        value: V,
-        expiration: u32,
-        cas_id: u64,
-    ) -> Result<bool, MemcacheError> {
-        check_key_len(key)?;
-        self.get_connection(key).get()?.cas(key, value, expiration, cas_id)
+        keys: &[&str],
+    ) -> Result<HashMap<String, Value<V>>, MemcacheError> {
+        for key in keys {
+            self.key(key)?;
+        }
+        let mut result = HashMap::new();
+        for (key, value) in self.gets_raw(keys)? {
+            if let Some(value) = self.decode_value(key.clone(), value)? {
+                result.insert(key, value);
+            }
+        }
+        Ok(result)
     }
 
-    /// Add a key with associate value into memcached server with expiration seconds.
+    /// Get a key from memcached server as bytes with its flags, as stored by the client's
+    /// transcoder, for values which fail to decode. Unlike `get_with_flags`, chunked values are
+    /// reassembled.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::builder()
+    ///     .add_server("memcache://localhost:12345")
+    ///     .with_decode_failures_as_misses(true)
+    ///     .build()
+    ///     .unwrap();
+    /// client.set("foo", "bar", 10).unwrap();
+    /// let value: Option<u64> = client.get("foo").unwrap();
+    /// assert_eq!(value, None);
+    /// assert_eq!(client.get_raw("foo").unwrap(), Some((b"bar".to_vec(), 0)));
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn get_raw(&self, key: &str) -> Result<Option<(Vec<u8>, u32)>, MemcacheError> {
+        let key = &*self.key(key)?;
+        let value = match self.get_raw_coalesced(key)? {
+            Some(value) => self.fetch_chunks(key, value)?,
+            None => None,
+        };
+        Ok(value.map(|(data, flags, _)| (data, flags)))
+    }
+
+    /// Get a key from memcached server as bytes, with its flags as stored. The value is not decoded
+    /// by the client's transcoder or reassembled from chunks, see `set_with_flags`.
     ///
     /// Example:
     ///
     /// ```rust
     /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
-    /// let key = "add_test";
-    /// client.delete(key).unwrap();
-    /// client.add(key, "bar", 100000000).unwrap();
+    /// client.set_with_flags("foo", b"bar", 10, 0x42).unwrap();
+    /// assert_eq!(client.get_with_flags("foo").unwrap(), Some((b"bar".to_vec(), 0x42)));
     /// # client.flush().unwrap();
     /// ```
-    pub fn add<V: ToMemcacheValue<Stream>>(&self, key: &str, value: V, expiration: u32) -> Result<(), MemcacheError> {
-        check_key_len(key)?;
-        return self.get_connection(key).get()?.add(key, value, expiration);
+    pub fn get_with_flags(&self, key: &str) -> Result<Option<(Vec<u8>, u32)>, MemcacheError> {
+        let key = &*self.key(key)?;
+        self.retrying(Command::Get, || {
+            self.connection(Command::Get, key)?
+                .fetch(|connection| connection.get(key))
+        })
     }
 
-    /// Replace a key with associate value into memcached server with expiration seconds.
+    /// Get a key from memcached server, copying the value to `writer` as it's received instead of
+    /// allocating it in memory first. Returns the flags of the value, or `None` if the key doesn't
+    /// exist. The value is not decoded by the client's transcoder or reassembled from chunks.
     ///
     /// Example:
     ///
     /// ```rust
     /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
-    /// let key = "replace_test";
-    /// client.set(key, "bar", 0).unwrap();
-    /// client.replace(key, "baz", 100000000).unwrap();
+    /// client.set("foo", "bar", 10).unwrap();
+    /// let mut buf = Vec::new();
+    /// assert_eq!(client.get_into_writer("foo", &mut buf).unwrap(), Some(0));
+    /// assert_eq!(buf, b"bar");
     /// # client.flush().unwrap();
     /// ```
-    pub fn replace<V: ToMemcacheValue<Stream>>(
-        &self,
-        key: &str,
-        value: V,
-        expiration: u32,
-    ) -> Result<(), MemcacheError> {
-        check_key_len(key)?;
-        return self.get_connection(key).get()?.replace(key, value, expiration);
+    pub fn get_into_writer<W: Write>(&self, key: &str, mut writer: W) -> Result<Option<u32>, MemcacheError> {
+        let key = &*self.key(key)?;
+        let mut connection = self.connection(Command::Get, key)?;
+        connection.fetch(|connection| connection.get_into(key, &mut writer))
     }
 
-    /// Append value to the key.
+    /// Get multiple keys from memcached server. Using this function instead of calling `get` multiple times can reduce network workloads.
     ///
     /// Example:
     ///
     /// ```rust
     /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
-    /// let key = "key_to_append";
-    /// client.set(key, "hello", 0).unwrap();
-    /// client.append(key, ", world!").unwrap();
-    /// let result: String = client.get(key).unwrap().unwrap();
-    /// assert_eq!(result, "hello, world!");
+    /// client.set("foo", "42", 0).unwrap();
+    /// let result: std::collections::HashMap<String, String> = client.gets(&["foo", "bar", "baz"]).unwrap();
+    /// assert_eq!(result.len(), 1);
+    /// assert_eq!(result["foo"], "42");
+    /// ```
+    pub fn gets<V: FromMemcacheValueExt>(&self, keys: &[impl AsRef<str>]) -> Result<HashMap<String, V>, MemcacheError> {
+        let keys: Vec<&str> = keys.iter().map(AsRef::as_ref).collect();
+        for key in keys.iter() {
+            self.key(key)?;
+        }
+        let mut result: HashMap<String, V> = HashMap::new();
+        for (key, value) in self.gets_raw(&keys)? {
+            if let Some(value) = self.fetch_chunks(&self.key(&key)?, value)? {
+                if let Some(value) = self.decode(value)? {
+                    result.insert(key, value);
+                }
+            }
+        }
+        return Ok(result);
+    }
+
+    /// Get multiple keys from memcached server like `gets`, but return the values keyed by the
+    /// caller's own keys, without copying the keys of the hits.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// client.set("foo", "42", 0).unwrap();
+    /// let keys = vec!["foo".to_string(), "bar".to_string()];
+    /// let result: std::collections::HashMap<&String, String> = client.gets_keyed(&keys).unwrap();
+    /// assert_eq!(result.len(), 1);
+    /// assert_eq!(result[&keys[0]], "42");
     /// # client.flush().unwrap();
     /// ```
-    pub fn append<V: ToMemcacheValue<Stream>>(&self, key: &str, value: V) -> Result<(), MemcacheError> {
-        check_key_len(key)?;
-        return self.get_connection(key).get()?.append(key, value);
+    pub fn gets_keyed<'a, K, V>(&self, keys: &'a [K]) -> Result<HashMap<&'a K, V>, MemcacheError>
+    where
+        K: AsRef<str> + Eq + Hash,
+        V: FromMemcacheValueExt,
+    {
+        let raw_keys: Vec<&str> = keys.iter().map(AsRef::as_ref).collect();
+        for key in raw_keys.iter() {
+            self.key(key)?;
+        }
+        let mut values = self.gets_raw(&raw_keys)?;
+        let mut result = HashMap::with_capacity(values.len());
+        for key in keys {
+            let value = match values.remove(key.as_ref()) {
+                Some(value) => self.fetch_chunks(&self.key(key.as_ref())?, value)?,
+                None => continue,
+            };
+            if let Some(value) = value {
+                if let Some(value) = self.decode(value)? {
+                    result.insert(key, value);
+                }
+            }
+        }
+        Ok(result)
     }
 
-    /// Prepend value to the key.
+    /// Get multiple keys from memcached server like `gets`, but return the values in the order of
+    /// `keys`, with `None` for the missing keys.
     ///
     /// Example:
     ///
     /// ```rust
     /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
-    /// let key = "key_to_append";
-    /// client.set(key, "world!", 0).unwrap();
-    /// client.prepend(key, "hello, ").unwrap();
-    /// let result: String = client.get(key).unwrap().unwrap();
-    /// assert_eq!(result, "hello, world!");
+    /// client.set("foo", "42", 0).unwrap();
+    /// # client.delete("bar").unwrap();
+    /// let result: Vec<Option<String>> = client.get_multi_ordered(&["bar", "foo"]).unwrap();
+    /// assert_eq!(result, vec![None, Some("42".to_string())]);
     /// # client.flush().unwrap();
     /// ```
-    pub fn prepend<V: ToMemcacheValue<Stream>>(&self, key: &str, value: V) -> Result<(), MemcacheError> {
-        check_key_len(key)?;
-        return self.get_connection(key).get()?.prepend(key, value);
+    pub fn get_multi_ordered<V: FromMemcacheValueExt>(&self, keys: &[&str]) -> Result<Vec<Option<V>>, MemcacheError> {
+        for key in keys {
+            self.key(key)?;
+        }
+        let mut values = self.gets_raw(keys)?;
+        // the number of remaining occurrences of each key, to only copy the values of repeated keys
+        let mut occurrences: HashMap<&str, usize> = HashMap::new();
+        for key in keys {
+            *occurrences.entry(key).or_default() += 1;
+        }
+        let mut result = Vec::with_capacity(keys.len());
+        for key in keys {
+            let remaining = occurrences.get_mut(key).unwrap();
+            *remaining -= 1;
+            let value = if *remaining == 0 {
+                values.remove(*key)
+            } else {
+                values.get(*key).cloned()
+            };
+            let value = match value {
+                Some(value) => self.fetch_chunks(&self.key(key)?, value)?,
+                None => None,
+            };
+            result.push(match value {
+                Some(value) => self.decode(value)?,
+                None => None,
+            });
+        }
+        Ok(result)
     }
 
-    /// Delete a key from memcached server.
+    /// Get multiple keys from memcached server like `gets`, but pass each value to `f` as it's
+    /// received instead of collecting them in a map, to fetch thousands of keys without the
+    /// intermediate allocation, like when warming up a cache. The servers are queried one by one,
+    /// and the order of the values is unspecified. If decoding a value fails, the following values
+    /// of the same request are discarded and the error is returned.
     ///
     /// Example:
     ///
     /// ```rust
     /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
-    /// client.delete("foo").unwrap();
+    /// client.set("foo", "42", 0).unwrap();
+    /// let mut values = Vec::new();
+    /// client
+    ///     .gets_foreach(&["foo", "bar"], |key, value: String| values.push((key.to_string(), value)))
+    ///     .unwrap();
+    /// assert_eq!(values, vec![("foo".to_string(), "42".to_string())]);
     /// # client.flush().unwrap();
     /// ```
-    pub fn delete(&self, key: &str) -> Result<bool, MemcacheError> {
-        check_key_len(key)?;
-        return self.get_connection(key).get()?.delete(key);
+    pub fn gets_foreach<V, F>(&self, keys: &[impl AsRef<str>], mut f: F) -> Result<(), MemcacheError>
+    where
+        V: FromMemcacheValueExt,
+        F: FnMut(&str, V),
+    {
+        let keys: Vec<&str> = keys.iter().map(AsRef::as_ref).collect();
+        let (server_keys, rewritten_keys) = self.server_keys(&keys)?;
+        let mut con_keys: HashMap<usize, Vec<&str>> = HashMap::new();
+        for key in server_keys.iter() {
+            con_keys.entry(self.connection_index(key)).or_default().push(key);
+        }
+        // the chunks of chunked values are fetched once the connection is released, as they may
+        // be stored on the same server
+        let mut manifests = Vec::new();
+        for (connection_index, keys) in con_keys {
+            for keys in keys.chunks(self.multi_get_batch_size) {
+                let mut connection = match self.checkout(Command::Gets, connection_index, None) {
+                    Err(ref e) if self.skips_failure(e) => break,
+                    connection => connection?,
+                };
+                let fetched = connection.fetch_each(keys, &mut |server_key, value: RawValue| {
+                    let key = rewritten_keys.get(&server_key).copied().unwrap_or(server_key.as_str());
+                    if self.chunk_size.is_some() && value.1 & CHUNKED_FLAG != 0 {
+                        manifests.push((key.to_string(), server_key.clone(), value));
+                    } else if let Some(value) = self.decode(value)? {
+                        f(key, value);
+                    }
+                    Ok(())
+                });
+                match fetched {
+                    // the remaining keys of the server are misses
+                    Err(ref e) if self.skips_failure(e) => break,
+                    fetched => fetched?,
+                }
+            }
+        }
+        for (key, server_key, value) in manifests {
+            if let Some(value) = self.fetch_chunks(&server_key, value)? {
+                if let Some(value) = self.decode(value)? {
+                    f(&key, value);
+                }
+            }
+        }
+        Ok(())
     }
 
-    /// Increment the value with amount.
+    /// The keys sent to the servers for `keys`, and the keys they replace if they are encoded or
+    /// hashed.
+    fn server_keys<'a>(&self, keys: &[&'a str]) -> Result<ServerKeys<'a>, MemcacheError> {
+        let mut server_keys = Vec::with_capacity(keys.len());
+        let mut rewritten_keys: HashMap<String, &str> = HashMap::new();
+        for &key in keys {
+            let server_key = self.key(key)?;
+            if let Cow::Owned(ref server_key) = server_key {
+                rewritten_keys.insert(server_key.clone(), key);
+            }
+            server_keys.push(server_key);
+        }
+        Ok((server_keys, rewritten_keys))
+    }
+
+    fn gets_raw(&self, keys: &[&str]) -> Result<HashMap<String, RawValue>, MemcacheError> {
+        match self.gets_raw_until(keys, None) {
+            (values, None) => Ok(values),
+            (_, Some(e)) => Err(e),
+        }
+    }
+
+    /// Fetch `keys` like `gets_raw`, with the requests to the servers and their retries sharing
+    /// the time left until `deadline`. Returns the values fetched before the first error if any
+    /// failed, along with the error.
+    fn gets_raw_until(
+        &self,
+        keys: &[&str],
+        deadline: Option<Instant>,
+    ) -> (HashMap<String, RawValue>, Option<MemcacheError>) {
+        match self.gets_raw_from(keys, deadline, false) {
+            Ok((values, errors)) => (values, errors.into_iter().next().map(|(_, e)| e)),
+            Err(e) => (HashMap::new(), Some(e)),
+        }
+    }
+
+    /// Fetch `keys` from their servers, and return the values along with the index and the error
+    /// of the servers which failed. Unless `partial`, the requests stop at the first error, else
+    /// only the requests to the failed servers are skipped.
+    fn gets_raw_from(
+        &self,
+        keys: &[&str],
+        deadline: Option<Instant>,
+        partial: bool,
+    ) -> Result<(HashMap<String, RawValue>, ServerErrors), MemcacheError> {
+        let mut con_keys: HashMap<usize, Vec<&str>> = HashMap::new();
+        let mut result = HashMap::new();
+        let (server_keys, rewritten_keys) = self.server_keys(keys)?;
+        for key in server_keys.iter() {
+            let key = key.as_ref();
+            let connection_index = self.connection_index(key);
+            let array = con_keys.entry(connection_index).or_insert_with(Vec::new);
+            array.push(key);
+        }
+        // split huge key lists, so the requests and responses stay reasonably sized, and interleave
+        // the servers, so concurrent requests go to different servers first
+        let mut server_batches: Vec<_> = con_keys
+            .iter()
+            .map(|(&connection_index, keys)| (connection_index, keys.chunks(self.multi_get_batch_size)))
+            .collect();
+        let mut batches: Vec<(usize, &[&str])> = Vec::new();
+        while !server_batches.is_empty() {
+            server_batches.retain_mut(|(connection_index, chunks)| match chunks.next() {
+                Some(keys) => {
+                    batches.push((*connection_index, keys));
+                    true
+                }
+                None => false,
+            });
+        }
+        // by default the servers are queried concurrently, and the batches of a server one by one
+        let concurrency = self.multi_get_concurrency.unwrap_or(con_keys.len());
+        // the servers which failed, whose remaining batches are skipped
+        let errors: Mutex<ServerErrors> = Mutex::new(Vec::new());
+        // fetch a batch into `values`, and return whether to go on with the next batches
+        let fetch = |connection_index: usize, keys: &[&str], values: &mut HashMap<String, RawValue>| {
+            if errors
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|&(index, _)| index == connection_index)
+            {
+                return true;
+            }
+            let fetched = self.retrying_until(Command::Gets, deadline, |left| {
+                self.checkout_with_timeout(Command::Gets, connection_index, None, left)?
+                    .fetch_many(keys.len(), |connection| connection.gets(keys))
+            });
+            match fetched {
+                Ok(fetched) => {
+                    values.extend(fetched);
+                    true
+                }
+                // the keys of the server are misses
+                Err(ref e) if !partial && self.skips_failure(e) => true,
+                Err(e) => {
+                    errors.lock().unwrap().push((connection_index, e));
+                    partial
+                }
+            }
+        };
+        if concurrency <= 1 || batches.len() <= 1 {
+            for (connection_index, keys) in batches {
+                if !fetch(connection_index, keys, &mut result) {
+                    break;
+                }
+            }
+        } else {
+            let workers_count = concurrency.min(batches.len());
+            let batches = Mutex::new(batches.into_iter());
+            let work = || -> HashMap<String, RawValue> {
+                let mut result = HashMap::new();
+                loop {
+                    let batch = batches.lock().unwrap().next();
+                    match batch {
+                        Some((connection_index, keys)) if fetch(connection_index, keys, &mut result) => (),
+                        _ => return result,
+                    }
+                }
+            };
+            let work = &work;
+            let jobs = (0..workers_count)
+                .map(|_| -> Box<dyn FnOnce() -> _ + Send> { Box::new(work) })
+                .collect();
+            for values in self.run_concurrently(jobs) {
+                result.extend(values);
+            }
+        }
+        Ok((restore_keys(result, &rewritten_keys), errors.into_inner().unwrap()))
+    }
+
+    /// Get multiple keys from memcached server like `gets`, but if some servers fail, return the
+    /// values fetched from the others along with the error of every failed server, instead of
+    /// failing the whole call, so a server outage only turns its keys into misses. The values
+    /// stored in chunks whose chunks can't be fetched are skipped, and reported as an error of
+    /// the server of their key. Only invalid keys and values which can't be decoded fail the call.
     ///
     /// Example:
     ///
     /// ```rust
     /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
-    /// client.increment("counter", 42).unwrap();
+    /// client.set("foo", "42", 0).unwrap();
+    /// let result = client.gets_partial::<String>(&["foo", "bar"]).unwrap();
+    /// for (server, error) in result.errors.iter() {
+    ///     println!("failed to get keys from {}: {}", server, error);
+    /// }
+    /// assert_eq!(result.values["foo"], "42");
     /// # client.flush().unwrap();
     /// ```
-    pub fn increment(&self, key: &str, amount: u64) -> Result<u64, MemcacheError> {
-        check_key_len(key)?;
-        return self.get_connection(key).get()?.increment(key, amount);
+    pub fn gets_partial<V: FromMemcacheValueExt>(
+        &self,
+        keys: &[impl AsRef<str>],
+    ) -> Result<PartialGets<V>, MemcacheError> {
+        let keys: Vec<&str> = keys.iter().map(AsRef::as_ref).collect();
+        let (values, mut errors) = self.gets_raw_from(&keys, None, true)?;
+        let mut result = HashMap::new();
+        for (key, value) in values {
+            let server_key = self.key(&key)?;
+            let value = match self.fetch_chunks(&server_key, value) {
+                Ok(value) => value,
+                Err(e) => {
+                    let connection_index = self.connection_index(&server_key);
+                    if !errors.iter().any(|&(index, _)| index == connection_index) {
+                        errors.push((connection_index, e));
+                    }
+                    continue;
+                }
+            };
+            if let Some(value) = value {
+                if let Some(value) = self.decode(value)? {
+                    result.insert(key, value);
+                }
+            }
+        }
+        Ok(PartialGets {
+            values: result,
+            errors: errors
+                .into_iter()
+                .map(|(connection_index, e)| (self.counters.url(connection_index).to_string(), e))
+                .collect(),
+        })
     }
 
-    /// Decrement the value with amount.
+    /// Get multiple keys from memcached server like `gets`, within `timeout`: the requests to the
+    /// servers, and their retries allowed by the retry policy, share the time left until the
+    /// deadline, like `get_with_timeout`. If a request fails or the deadline is reached, the
+    /// error, `ClientError::DeadlineExceeded` for the latter, comes with the values fetched until
+    /// then.
+    ///
+    /// The chunks of values stored in chunks are fetched with the timeouts of the client.
     ///
     /// Example:
     ///
     /// ```rust
+    /// use std::time::Duration;
+    ///
     /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
-    /// client.decrement("counter", 42).unwrap();
+    /// client.set("foo", "42", 0).unwrap();
+    /// let timeout = Duration::from_millis(50);
+    /// let result: std::collections::HashMap<String, String> = match client.gets_with_timeout(&["foo", "bar"], timeout) {
+    ///     Ok(result) => result,
+    ///     Err(memcache::PartialError { error, partial }) => {
+    ///         println!("partial result: {}", error);
+    ///         partial
+    ///     }
+    /// };
+    /// assert_eq!(result["foo"], "42");
     /// # client.flush().unwrap();
     /// ```
-    pub fn decrement(&self, key: &str, amount: u64) -> Result<u64, MemcacheError> {
-        check_key_len(key)?;
-        return self.get_connection(key).get()?.decrement(key, amount);
+    pub fn gets_with_timeout<V: FromMemcacheValueExt>(
+        &self,
+        keys: &[impl AsRef<str>],
+        timeout: Duration,
+    ) -> Result<HashMap<String, V>, PartialError<HashMap<String, V>>> {
+        let deadline = Instant::now() + timeout;
+        let keys: Vec<&str> = keys.iter().map(AsRef::as_ref).collect();
+        let (values, mut error) = self.gets_raw_until(&keys, Some(deadline));
+        let mut result = HashMap::new();
+        for (key, value) in values {
+            let value = match self
+                .key(&key)
+                .and_then(|server_key| self.fetch_chunks(&server_key, value))
+            {
+                Ok(Some(value)) => self.decode(value),
+                Ok(None) => Ok(None),
+                Err(e) => Err(e),
+            };
+            match value {
+                Ok(Some(value)) => {
+                    result.insert(key, value);
+                }
+                Ok(None) => (),
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        match error {
+            Some(error) => Err(PartialError { error, partial: result }),
+            None => Ok(result),
+        }
     }
 
-    /// Set a new expiration time for a exist key.
+    /// Set a key with associate value into memcached server with expiration seconds.
     ///
     /// Example:
     ///
     /// ```rust
     /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
-    /// assert_eq!(client.touch("not_exists_key", 12345).unwrap(), false);
-    /// client.set("foo", "bar", 123).unwrap();
-    /// assert_eq!(client.touch("foo", 12345).unwrap(), true);
+    /// client.set("foo", "bar", 10).unwrap();
     /// # client.flush().unwrap();
     /// ```
-    pub fn touch(&self, key: &str, expiration: u32) -> Result<bool, MemcacheError> {
-        check_key_len(key)?;
-        return self.get_connection(key).get()?.touch(key, expiration);
+    pub fn set<V: ToMemcacheValue<Stream>>(
+        &self,
+        key: impl AsRef<str>,
+        value: V,
+        expiration: impl Into<Expiration>,
+    ) -> Result<(), MemcacheError> {
+        let expiration = expiration.into().exptime();
+        let caller_key = key.as_ref();
+        let key = &*self.key(caller_key)?;
+        let encoded = self.prepare_value(Command::Set, caller_key, key, &value, expiration)?;
+        self.retrying(Command::Set, || {
+            let mut connection = self.connection(Command::Set, key)?;
+            let value = StoreValue::new(&value, encoded.as_ref());
+            connection.check_item_size(ToMemcacheValue::<Stream>::get_length(&value))?;
+            connection.run(|connection| connection.set(key, value, expiration))
+        })
     }
 
-    /// Get all servers' statistics.
+    /// Set a key of arbitrary bytes, see `get_bytes_key`. Values stored under keys which aren't
+    /// valid UTF-8 are never split into chunks.
     ///
     /// Example:
+    ///
     /// ```rust
     /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
-    /// let stats = client.stats().unwrap();
+    /// client.set_bytes_key(&[0xff, 0x00, 0x20], "bar", 10).unwrap();
+    /// # client.flush().unwrap();
     /// ```
-    pub fn stats(&self) -> Result<Vec<(String, Stats)>, MemcacheError> {
-        let mut result: Vec<(String, HashMap<String, String>)> = vec![];
-        for connection in self.connections.iter() {
-            let mut connection = connection.get()?;
-            let stats_info = connection.stats()?;
-            let url = connection.get_url();
-            result.push((url, stats_info));
+    pub fn set_bytes_key<V: ToMemcacheValue<Stream>>(
+        &self,
+        key: &[u8],
+        value: V,
+        expiration: impl Into<Expiration>,
+    ) -> Result<(), MemcacheError> {
+        let expiration = expiration.into().exptime();
+        if let Ok(key) = std::str::from_utf8(key) {
+            return self.set(key, value, expiration);
         }
-        return Ok(result);
+        let value = self.encode(&value)?;
+        let connection_index = self.bytes_key_connection_index(key)?;
+        self.mirror(|| Mirrored::SetBytesKey {
+            key: key.to_vec(),
+            data: value.data.clone(),
+            flags: value.flags,
+            expiration,
+        });
+        let mut connection = self.checkout(Command::Set, connection_index, None)?;
+        connection.check_item_size(value.data.len())?;
+        connection.run(|connection| connection.set_bytes_key(key, value, expiration))
+    }
+
+    /// Set multiple keys with their values and expiration seconds, see `Expiration::Raw`. Using
+    /// this function instead of calling `set` multiple times can reduce network workloads, as the
+    /// keys stored on the same server are sent in one batch. Returns the outcome of each key, so
+    /// the keys which failed to be stored, e.g. because their value is too large or their server
+    /// is down, can be retried or logged.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// let results = client.sets(&[("foo", "bar", 10), ("baz", "qux", 0)]).unwrap();
+    /// assert!(results.values().all(|result| result.is_ok()));
+    /// let value: Option<String> = client.get("baz").unwrap();
+    /// assert_eq!(value, Some("qux".to_string()));
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn sets<V: ToMemcacheValue<Stream>>(
+        &self,
+        entries: &[(&str, V, u32)],
+    ) -> Result<HashMap<String, Result<StoreResult, MemcacheError>>, MemcacheError> {
+        let entries = entries.iter().map(|(key, value, expiration)| {
+            let expiration = Expiration::Raw(*expiration).exptime();
+            (*key, self.encode_entry(key, value), expiration)
+        });
+        self.stores(StoreCommand::Set, entries)
+    }
+
+    /// Add multiple keys with their values and the same expiration seconds, in one batch per
+    /// server like `sets`. Keys which already exist are reported as `StoreResult::NotStored`,
+    /// for populating a cache without overwriting fresher values.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// use memcache::StoreResult;
+    ///
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// client.set("foo", "bar", 0).unwrap();
+    /// # client.delete("baz").unwrap();
+    /// let results = client.adds(&[("foo", "new"), ("baz", "qux")], 10).unwrap();
+    /// assert_eq!(results["foo"].as_ref().unwrap(), &StoreResult::NotStored);
+    /// assert_eq!(results["baz"].as_ref().unwrap(), &StoreResult::Stored);
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn adds<V: ToMemcacheValue<Stream>>(
+        &self,
+        entries: &[(&str, V)],
+        expiration: impl Into<Expiration>,
+    ) -> Result<HashMap<String, Result<StoreResult, MemcacheError>>, MemcacheError> {
+        let expiration = expiration.into().exptime();
+        let entries = entries
+            .iter()
+            .map(|(key, value)| (*key, self.encode_entry(key, value), expiration));
+        self.stores(StoreCommand::Add, entries)
+    }
+
+    /// Replace multiple keys with their values and the same expiration seconds, in one batch per
+    /// server like `sets`. Missing keys are reported as `StoreResult::NotStored`.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// use memcache::StoreResult;
+    ///
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// client.set("foo", "bar", 0).unwrap();
+    /// # client.delete("baz").unwrap();
+    /// let results = client.replaces(&[("foo", "new"), ("baz", "qux")], 10).unwrap();
+    /// assert_eq!(results["foo"].as_ref().unwrap(), &StoreResult::Stored);
+    /// assert_eq!(results["baz"].as_ref().unwrap(), &StoreResult::NotStored);
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn replaces<V: ToMemcacheValue<Stream>>(
+        &self,
+        entries: &[(&str, V)],
+        expiration: impl Into<Expiration>,
+    ) -> Result<HashMap<String, Result<StoreResult, MemcacheError>>, MemcacheError> {
+        let expiration = expiration.into().exptime();
+        let entries = entries
+            .iter()
+            .map(|(key, value)| (*key, self.encode_entry(key, value), expiration));
+        self.stores(StoreCommand::Replace, entries)
+    }
+
+    /// Append values to multiple keys, in one batch per server like `sets`. Missing keys are
+    /// reported as `StoreResult::NotStored`. The values are not encoded by the client's transcoder.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// client.set("foo", "hello", 0).unwrap();
+    /// client.appends(&[("foo", ", world!")]).unwrap();
+    /// let result: String = client.get("foo").unwrap().unwrap();
+    /// assert_eq!(result, "hello, world!");
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn appends<V: ToMemcacheValue<Stream>>(
+        &self,
+        entries: &[(&str, V)],
+    ) -> Result<HashMap<String, Result<StoreResult, MemcacheError>>, MemcacheError> {
+        let entries = entries
+            .iter()
+            .map(|(key, value)| (*key, self.encode_raw(key, value), 0));
+        self.stores(StoreCommand::Append, entries)
+    }
+
+    /// Prepend values to multiple keys, in one batch per server like `sets`. Missing keys are
+    /// reported as `StoreResult::NotStored`. The values are not encoded by the client's transcoder.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// client.set("foo", "world!", 0).unwrap();
+    /// client.prepends(&[("foo", "hello, ")]).unwrap();
+    /// let result: String = client.get("foo").unwrap().unwrap();
+    /// assert_eq!(result, "hello, world!");
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn prepends<V: ToMemcacheValue<Stream>>(
+        &self,
+        entries: &[(&str, V)],
+    ) -> Result<HashMap<String, Result<StoreResult, MemcacheError>>, MemcacheError> {
+        let entries = entries
+            .iter()
+            .map(|(key, value)| (*key, self.encode_raw(key, value), 0));
+        self.stores(StoreCommand::Prepend, entries)
+    }
+
+    /// Check the key and encode the value of an entry of a batched store.
+    fn encode_entry<'a, V: ToMemcacheValue<Stream>>(&self, key: &'a str, value: &V) -> EncodedEntry<'a> {
+        Ok((self.key(key)?, self.encode(value)?))
+    }
+
+    /// Check the key and serialize the value of an entry of a batched append or prepend, which
+    /// isn't encoded by the transcoder.
+    fn encode_raw<'a, V: ToMemcacheValue<Stream>>(&self, key: &'a str, value: &V) -> EncodedEntry<'a> {
+        let key = self.key(key)?;
+        Ok((
+            key,
+            EncodedValue {
+                data: serialize(value)?,
+                flags: 0,
+            },
+        ))
+    }
+
+    /// Store multiple encoded entries with `command`, pipelined per server, and return the outcome
+    /// of each key. The entries are sent to the mirror and split into chunks like the single
+    /// stores. Entries which failed to be encoded or are too large are reported without being
+    /// sent, and a server which fails fails all of its keys.
+    fn stores<'a, I>(
+        &self,
+        command: StoreCommand,
+        entries: I,
+    ) -> Result<HashMap<String, Result<StoreResult, MemcacheError>>, MemcacheError>
+    where
+        I: IntoIterator<Item = (&'a str, EncodedEntry<'a>, u32)>,
+    {
+        let mut results = HashMap::new();
+        let mut con_entries: HashMap<usize, Batch<(Cow<str>, EncodedValue, u32)>> = HashMap::new();
+
+        for (key, encoded, expiration) in entries {
+            let encoded = encoded.and_then(|(server_key, value)| {
+                let value = self.prepare_store(command.into(), key, &server_key, value, expiration)?;
+                Ok((server_key, value))
+            });
+            match encoded {
+                Ok((server_key, value)) => {
+                    let connection_index = self.connection_index(&server_key);
+                    let (keys, entries) = con_entries.entry(connection_index).or_default();
+                    keys.push(key);
+                    entries.push((server_key, value, expiration));
+                }
+                Err(e) => {
+                    results.insert(key.to_string(), Err(e));
+                }
+            }
+        }
+        for (connection_index, (con_keys, entries)) in con_entries {
+            let mut connection = match self.checkout(command.into(), connection_index, None) {
+                Ok(connection) => connection,
+                Err(e) => {
+                    for key in con_keys {
+                        results.insert(key.to_string(), Err(e.duplicate()));
+                    }
+                    continue;
+                }
+            };
+            let mut keys = Vec::with_capacity(entries.len());
+            let mut server_keys = Vec::with_capacity(entries.len());
+            let mut values = Vec::with_capacity(entries.len());
+            for (key, (server_key, value, expiration)) in con_keys.into_iter().zip(entries) {
+                match connection.check_item_size(value.data.len()) {
+                    Ok(()) => {
+                        keys.push(key);
+                        server_keys.push(server_key);
+                        values.push((value, expiration));
+                    }
+                    Err(e) => {
+                        results.insert(key.to_string(), Err(e));
+                    }
+                }
+            }
+            let batch = server_keys
+                .iter()
+                .zip(values)
+                .map(|(server_key, (value, expiration))| (server_key.as_ref(), value, expiration))
+                .collect();
+            match connection.run(|connection| connection.stores(command, batch)) {
+                Ok(stored) => {
+                    for (key, result) in keys.into_iter().zip(stored) {
+                        results.insert(key.to_string(), result);
+                    }
+                }
+                Err(e) => {
+                    for key in keys {
+                        results.insert(key.to_string(), Err(e.duplicate()));
+                    }
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    /// Set a key to bytes stored with exactly the given flags, for applications with their own flag
+    /// conventions. The value is not encoded by the client's transcoder or chunked.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// client.set_with_flags("foo", b"bar", 10, 0x42).unwrap();
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn set_with_flags(
+        &self,
+        key: &str,
+        value: &[u8],
+        expiration: impl Into<Expiration>,
+        flags: u32,
+    ) -> Result<(), MemcacheError> {
+        let expiration = expiration.into().exptime();
+        let caller_key = key;
+        let key = &*self.key(key)?;
+        self.mirror_store(Command::Set, caller_key, value, flags, expiration);
+        let mut connection = self.connection(Command::Set, key)?;
+        connection.check_item_size(value.len())?;
+        connection.run(|connection| connection.set(key, FlaggedValue { data: value, flags }, expiration))
+    }
+
+    /// Set a key to a value of `length` bytes read from `reader`, which is copied to the server as
+    /// it's read instead of being buffered in memory first. The value is stored as bytes, without
+    /// the client's transcoder or chunking.
+    ///
+    /// Returns an `UnexpectedEof` IO error if the reader ends before `length` bytes.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// let data = vec![42u8; 500 * 1024];
+    /// client.set_from_reader("streamed", &data[..], data.len(), 10).unwrap();
+    /// let fetched: Vec<u8> = client.get("streamed").unwrap().unwrap();
+    /// assert_eq!(fetched, data);
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn set_from_reader<R: Read>(
+        &self,
+        key: &str,
+        reader: R,
+        length: usize,
+        expiration: impl Into<Expiration>,
+    ) -> Result<(), MemcacheError> {
+        let expiration = expiration.into().exptime();
+        let caller_key = key;
+        let key = &*self.key(key)?;
+        let mut connection = self.connection(Command::Set, key)?;
+        connection.check_item_size(length)?;
+        // the value is only kept in memory for the mirror
+        let mut reader = CopyingReader::new(reader, self.mirror.is_some());
+        let result = connection.set(key, ReaderValue::new(&mut reader, length), expiration);
+        if let Err(MemcacheError::IOError(_)) = result {
+            connection.mark_broken();
+        }
+        // the value is only complete once it was stored
+        if let (Ok(()), Some(data)) = (&result, reader.into_copy()) {
+            self.mirror(|| Mirrored::Store {
+                command: Command::Set,
+                key: caller_key.to_string(),
+                data,
+                flags: Flags::Bytes as u32,
+                expiration,
+            });
+        }
+        result.map_err(|e| connection.context(e))
+    }
+
+    /// Get a key from memcached server, or compute its value with `loader` if it's missing and
+    /// store it with expiration seconds, the cache-aside pattern in one call.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// # client.delete("answer").unwrap();
+    /// let value: u64 = client.get_or_set("answer", 10, || 42).unwrap();
+    /// assert_eq!(value, 42);
+    /// let value: u64 = client.get_or_set("answer", 10, || unreachable!()).unwrap();
+    /// assert_eq!(value, 42);
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn get_or_set<V, F>(&self, key: &str, expiration: impl Into<Expiration>, loader: F) -> Result<V, MemcacheError>
+    where
+        V: FromMemcacheValueExt + ToMemcacheValue<Stream>,
+        F: FnOnce() -> V,
+    {
+        let expiration = expiration.into().exptime();
+        if let Some(value) = self.get(key)? {
+            return Ok(value);
+        }
+        let value = loader();
+        let caller_key = key;
+        let key = &*self.key(key)?;
+        let encoded = self.prepare_store(Command::Set, caller_key, key, self.encode(&value)?, expiration)?;
+        let mut connection = self.connection(Command::Set, key)?;
+        connection.check_item_size(encoded.data.len())?;
+        connection.run(|connection| connection.set(key, encoded, expiration))?;
+        Ok(value)
+    }
+
+    /// Same as `get_or_set`, but for values which may not exist. If `loader` returns `None`, the
+    /// miss is cached for the expiration set with `ClientBuilder::with_negative_caching`, and
+    /// returned without calling `loader` until it expires. Without negative caching, misses are
+    /// not cached.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::builder()
+    ///     .add_server("memcache://localhost:12345")
+    ///     .with_negative_caching(5)
+    ///     .build()
+    ///     .unwrap();
+    /// # client.delete("user:42").unwrap();
+    /// let user: Option<String> = client.get_or_set_optional("user:42", 60, || None).unwrap();
+    /// assert_eq!(user, None);
+    /// let user: Option<String> = client.get_or_set_optional("user:42", 60, || unreachable!()).unwrap();
+    /// assert_eq!(user, None);
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn get_or_set_optional<V, F>(
+        &self,
+        key: &str,
+        expiration: impl Into<Expiration>,
+        loader: F,
+    ) -> Result<Option<V>, MemcacheError>
+    where
+        V: FromMemcacheValueExt + ToMemcacheValue<Stream>,
+        F: FnOnce() -> Option<V>,
+    {
+        let expiration = expiration.into().exptime();
+        let caller_key = key;
+        let key = &*self.key(key)?;
+        let connection_index = self.connection_index(key);
+        let value = match self
+            .checkout(Command::Get, connection_index, Some(key))?
+            .fetch(|connection| connection.get::<RawValue>(key))?
+        {
+            Some(value) => self.fetch_chunks(key, value)?,
+            None => None,
+        };
+        if let Some(value) = value {
+            if value.1 & self.flag_layout.negative_caching != 0 {
+                return Ok(None);
+            }
+            if let Some(value) = self.decode(value)? {
+                return Ok(Some(value));
+            }
+        }
+
+        let value = loader();
+        let (encoded, expiration) = match (&value, self.negative_ttl) {
+            (Some(value), _) => (self.encode(value)?, expiration),
+            (None, Some(negative_ttl)) => {
+                let missing = EncodedValue {
+                    data: Vec::new(),
+                    flags: MISSING_FLAG,
+                };
+                (missing, negative_ttl.exptime())
+            }
+            (None, None) => return Ok(None),
+        };
+        let encoded = self.prepare_store(Command::Set, caller_key, key, encoded, expiration)?;
+        let mut connection = self.checkout(Command::Set, connection_index, Some(key))?;
+        connection.check_item_size(encoded.data.len())?;
+        connection.run(|connection| connection.set(key, encoded, expiration))?;
+        Ok(value)
+    }
+
+    /// Same as `get_or_set`, but with probabilistic early expiration, the XFetch algorithm: the
+    /// value is stored with the time `loader` took to compute it, and each fetch recomputes it
+    /// before it expires with a probability which grows as the expiration gets closer and the
+    /// computation is slower. A hot key is then refreshed by a single caller ahead of time,
+    /// instead of by all callers at once when it expires. `beta` scales how early values are
+    /// refreshed, 1.0 is a good default.
+    ///
+    /// The computation time and expiration are stored in a header before the value, so the key
+    /// should only be fetched with this method.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// let value: String = client.get_or_set_xfetch("report", 60, 1.0, || "expensive".to_string()).unwrap();
+    /// assert_eq!(value, "expensive");
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn get_or_set_xfetch<V, F>(
+        &self,
+        key: &str,
+        expiration: impl Into<Expiration>,
+        beta: f64,
+        loader: F,
+    ) -> Result<V, MemcacheError>
+    where
+        V: FromMemcacheValueExt + ToMemcacheValue<Stream>,
+        F: FnOnce() -> V,
+    {
+        let expiration = expiration.into().exptime();
+        let caller_key = key;
+        let key = &*self.key(key)?;
+        let connection_index = self.connection_index(key);
+        let value = match self
+            .checkout(Command::Get, connection_index, Some(key))?
+            .fetch(|connection| connection.get(key))?
+        {
+            Some(value) => self.fetch_chunks(key, value)?,
+            None => None,
+        };
+        if let Some((mut data, flags, cas)) = value {
+            if data.len() < 8 {
+                return Err(ClientError::Error(Cow::Borrowed("value is missing its early expiration header")).into());
+            }
+            let delta = f64::from(u32::from_be_bytes(data[..4].try_into().unwrap())) / 1000.0;
+            let expires_at = f64::from(u32::from_be_bytes(data[4..8].try_into().unwrap()));
+            data.drain(..8);
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64();
+            // ln of a uniform random number in (0, 1] is negative, and usually small
+            let early = -delta * beta * (1.0 - rand::random::<f64>()).ln();
+            if expires_at == 0.0 || now + early < expires_at {
+                if let Some(value) = self.decode((data, flags, cas))? {
+                    return Ok(value);
+                }
+            }
+        }
+
+        let started = Instant::now();
+        let value = loader();
+        let delta = started.elapsed().as_millis().min(u128::from(u32::MAX)) as u32;
+        let encoded = self.encode(&value)?;
+        let mut data = Vec::with_capacity(8 + encoded.data.len());
+        data.extend_from_slice(&delta.to_be_bytes());
+        let expires_at = expires_at(expiration).map_or(0, |timestamp| timestamp.min(u64::from(u32::MAX)) as u32);
+        data.extend_from_slice(&expires_at.to_be_bytes());
+        data.extend_from_slice(&encoded.data);
+        let encoded = self.prepare_store(
+            Command::Set,
+            caller_key,
+            key,
+            EncodedValue {
+                data,
+                flags: encoded.flags,
+            },
+            expiration,
+        )?;
+        let mut connection = self.checkout(Command::Set, connection_index, Some(key))?;
+        connection.check_item_size(encoded.data.len())?;
+        connection.run(|connection| connection.set(key, encoded, expiration))?;
+        Ok(value)
+    }
+
+    /// Same as `get_or_set`, but stores the computed value with `add`, so a value stored by a
+    /// concurrent caller in the meantime is kept instead of being overwritten.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// let value: String = client.get_or_add("greeting", 10, || "hello".to_string()).unwrap();
+    /// assert_eq!(value, "hello");
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn get_or_add<V, F>(&self, key: &str, expiration: impl Into<Expiration>, loader: F) -> Result<V, MemcacheError>
+    where
+        V: FromMemcacheValueExt + ToMemcacheValue<Stream>,
+        F: FnOnce() -> V,
+    {
+        let expiration = expiration.into().exptime();
+        if let Some(value) = self.get(key)? {
+            return Ok(value);
+        }
+        let value = loader();
+        let caller_key = key;
+        let key = &*self.key(key)?;
+        let encoded = self.prepare_store(Command::Add, caller_key, key, self.encode(&value)?, expiration)?;
+        let mut connection = self.connection(Command::Add, key)?;
+        connection.check_item_size(encoded.data.len())?;
+        match connection.add(key, encoded, expiration) {
+            Ok(()) | Err(MemcacheError::CommandError(CommandError::KeyExists)) => Ok(value),
+            Err(e) => Err(connection.context(e)),
+        }
+    }
+
+    /// Update a key with the value computed by `f` from its current value, or `None` if it's
+    /// missing, and store it with expiration seconds. The value is fetched with its cas unique and
+    /// stored with `cas`, or `add` if it was missing, and the update is retried from the fetch
+    /// when the key was modified concurrently in the meantime, so `f` may be called several
+    /// times. Fails with `CommandError::KeyExists` once the retries set with
+    /// `ClientBuilder::with_cas_retries` are exhausted.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// # client.delete("counter").unwrap();
+    /// let value: u64 = client.update("counter", 10, |current: Option<u64>| current.unwrap_or(0) + 1).unwrap();
+    /// assert_eq!(value, 1);
+    /// let value: u64 = client.update("counter", 10, |current: Option<u64>| current.unwrap_or(0) + 1).unwrap();
+    /// assert_eq!(value, 2);
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn update<V, F>(&self, key: &str, expiration: impl Into<Expiration>, mut f: F) -> Result<V, MemcacheError>
+    where
+        V: FromMemcacheValueExt + ToMemcacheValue<Stream>,
+        F: FnMut(Option<V>) -> V,
+    {
+        let expiration = expiration.into().exptime();
+        let server_key = &*self.key(key)?;
+        let connection_index = self.connection_index(server_key);
+        for _ in 0..=self.cas_retries {
+            match self.get_value(key)? {
+                Some(current) => {
+                    let cas_id = current.cas.ok_or(ClientError::Error(Cow::Borrowed(
+                        "the server didn't return a cas unique",
+                    )))?;
+                    let value = f(Some(current.value));
+                    let encoded = self.encode(&value)?;
+                    let mirrored = self.mirrored_set(key, &encoded, expiration);
+                    let encoded = self.store_chunks(server_key, encoded, expiration)?;
+                    let mut connection = self.checkout(Command::Cas, connection_index, Some(server_key))?;
+                    connection.check_item_size(encoded.data.len())?;
+                    if connection.run(|connection| connection.cas(server_key, encoded, expiration, cas_id))? {
+                        if let Some(mirrored) = mirrored {
+                            self.mirror(|| mirrored);
+                        }
+                        return Ok(value);
+                    }
+                }
+                None => {
+                    let value = f(None);
+                    let encoded = self.encode(&value)?;
+                    let mirrored = self.mirrored_set(key, &encoded, expiration);
+                    let encoded = self.store_chunks(server_key, encoded, expiration)?;
+                    let mut connection = self.checkout(Command::Add, connection_index, Some(server_key))?;
+                    connection.check_item_size(encoded.data.len())?;
+                    match connection.add(server_key, encoded, expiration) {
+                        Ok(()) => {
+                            if let Some(mirrored) = mirrored {
+                                self.mirror(|| mirrored);
+                            }
+                            return Ok(value);
+                        }
+                        Err(MemcacheError::CommandError(CommandError::KeyExists)) => {}
+                        Err(e) => return Err(connection.context(e)),
+                    }
+                }
+            }
+        }
+        Err(CommandError::KeyExists.into())
+    }
+
+    /// Compare and swap a key with the associate value into memcached server with expiration seconds.
+    /// `cas_id` should be obtained from a previous `get_value` or `gets_values` call.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// client.set("foo", "bar", 10).unwrap();
+    /// let value: memcache::Value<String> = client.get_value("foo").unwrap().unwrap();
+    /// let cas = value.cas.unwrap();
+    /// assert_eq!(true, client.cas("foo", "bar2", 10, cas).unwrap());
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn cas<V: ToMemcacheValue<Stream>>(
+        &self,
+        key: &str,
+        value: V,
+        expiration: impl Into<Expiration>,
+        cas_id: u64,
+    ) -> Result<bool, MemcacheError> {
+        let expiration = expiration.into().exptime();
+        let caller_key = key;
+        let key = &*self.key(key)?;
+        // the cas unique is only valid on this client's servers, the mirror sets the value once
+        // it's stored
+        let (encoded, mirrored) = if self.encodes_values() {
+            let encoded = self.encode(&value)?;
+            let mirrored = self.mirrored_set(caller_key, &encoded, expiration);
+            (Some(self.store_chunks(key, encoded, expiration)?), mirrored)
+        } else {
+            (None, None)
+        };
+        let stored = self.retrying(Command::Cas, || {
+            let mut connection = self.connection(Command::Cas, key)?;
+            let value = StoreValue::new(&value, encoded.as_ref());
+            connection.check_item_size(ToMemcacheValue::<Stream>::get_length(&value))?;
+            connection.run(|connection| connection.cas(key, value, expiration, cas_id))
+        })?;
+        if let (true, Some(mirrored)) = (stored, mirrored) {
+            self.mirror(|| mirrored);
+        }
+        Ok(stored)
+    }
+
+    /// Add a key with associate value into memcached server with expiration seconds.
+    ///
+    /// Fails with `CommandError::KeyExists` if the key already exists, with both the binary and
+    /// the ascii protocols.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// let key = "add_test";
+    /// client.delete(key).unwrap();
+    /// client.add(key, "bar", 100000000).unwrap();
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn add<V: ToMemcacheValue<Stream>>(
+        &self,
+        key: &str,
+        value: V,
+        expiration: impl Into<Expiration>,
+    ) -> Result<(), MemcacheError> {
+        let expiration = expiration.into().exptime();
+        let caller_key = key;
+        let key = &*self.key(key)?;
+        let encoded = self.prepare_value(Command::Add, caller_key, key, &value, expiration)?;
+        self.retrying(Command::Add, || {
+            let mut connection = self.connection(Command::Add, key)?;
+            let value = StoreValue::new(&value, encoded.as_ref());
+            connection.check_item_size(ToMemcacheValue::<Stream>::get_length(&value))?;
+            connection.run(|connection| connection.add(key, value, expiration))
+        })
+    }
+
+    /// Replace a key with associate value into memcached server with expiration seconds.
+    ///
+    /// Fails with `CommandError::KeyNotFound` if the key doesn't exist, with both the binary and
+    /// the ascii protocols.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// let key = "replace_test";
+    /// client.set(key, "bar", 0).unwrap();
+    /// client.replace(key, "baz", 100000000).unwrap();
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn replace<V: ToMemcacheValue<Stream>>(
+        &self,
+        key: &str,
+        value: V,
+        expiration: impl Into<Expiration>,
+    ) -> Result<(), MemcacheError> {
+        let expiration = expiration.into().exptime();
+        let caller_key = key;
+        let key = &*self.key(key)?;
+        let encoded = self.prepare_value(Command::Replace, caller_key, key, &value, expiration)?;
+        self.retrying(Command::Replace, || {
+            let mut connection = self.connection(Command::Replace, key)?;
+            let value = StoreValue::new(&value, encoded.as_ref());
+            connection.check_item_size(ToMemcacheValue::<Stream>::get_length(&value))?;
+            connection.run(|connection| connection.replace(key, value, expiration))
+        })
+    }
+
+    /// Same as `add`, but returns `StoreResult::NotStored` instead of a `CommandError::KeyExists`
+    /// error if the key already exists, so losing a race to store a key isn't handled as an error.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// use memcache::StoreResult;
+    ///
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// let key = "try_add_test";
+    /// client.delete(key).unwrap();
+    /// assert_eq!(client.try_add(key, "bar", 0).unwrap(), StoreResult::Stored);
+    /// assert_eq!(client.try_add(key, "baz", 0).unwrap(), StoreResult::NotStored);
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn try_add<V: ToMemcacheValue<Stream>>(
+        &self,
+        key: &str,
+        value: V,
+        expiration: impl Into<Expiration>,
+    ) -> Result<StoreResult, MemcacheError> {
+        let expiration = expiration.into().exptime();
+        match self.add(key, value, expiration) {
+            Ok(()) => Ok(StoreResult::Stored),
+            Err(ref e) if matches!(e.inner(), MemcacheError::CommandError(CommandError::KeyExists)) => {
+                Ok(StoreResult::NotStored)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Same as `replace`, but returns `StoreResult::NotStored` instead of a
+    /// `CommandError::KeyNotFound` error if the key is missing.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// use memcache::StoreResult;
+    ///
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// let key = "try_replace_test";
+    /// client.delete(key).unwrap();
+    /// assert_eq!(client.try_replace(key, "bar", 0).unwrap(), StoreResult::NotStored);
+    /// client.set(key, "bar", 0).unwrap();
+    /// assert_eq!(client.try_replace(key, "baz", 0).unwrap(), StoreResult::Stored);
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn try_replace<V: ToMemcacheValue<Stream>>(
+        &self,
+        key: &str,
+        value: V,
+        expiration: impl Into<Expiration>,
+    ) -> Result<StoreResult, MemcacheError> {
+        let expiration = expiration.into().exptime();
+        match self.replace(key, value, expiration) {
+            Ok(()) => Ok(StoreResult::Stored),
+            Err(ref e) if matches!(e.inner(), MemcacheError::CommandError(CommandError::KeyNotFound)) => {
+                Ok(StoreResult::NotStored)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Append value to the key. The value is not encoded by the client's transcoder.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// let key = "key_to_append";
+    /// client.set(key, "hello", 0).unwrap();
+    /// client.append(key, ", world!").unwrap();
+    /// let result: String = client.get(key).unwrap().unwrap();
+    /// assert_eq!(result, "hello, world!");
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn append<V: ToMemcacheValue<Stream>>(&self, key: &str, value: V) -> Result<(), MemcacheError> {
+        let server_key = &*self.key(key)?;
+        // the value is only serialized in memory for the mirror
+        if self.mirror.is_some() {
+            self.mirror_store(Command::Append, key, &serialize(&value)?, 0, 0);
+        }
+        return self
+            .connection(Command::Append, server_key)?
+            .run(|connection| connection.append(server_key, value));
+    }
+
+    /// Prepend value to the key. The value is not encoded by the client's transcoder.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// let key = "key_to_append";
+    /// client.set(key, "world!", 0).unwrap();
+    /// client.prepend(key, "hello, ").unwrap();
+    /// let result: String = client.get(key).unwrap().unwrap();
+    /// assert_eq!(result, "hello, world!");
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn prepend<V: ToMemcacheValue<Stream>>(&self, key: &str, value: V) -> Result<(), MemcacheError> {
+        let server_key = &*self.key(key)?;
+        // the value is only serialized in memory for the mirror
+        if self.mirror.is_some() {
+            self.mirror_store(Command::Prepend, key, &serialize(&value)?, 0, 0);
+        }
+        return self
+            .connection(Command::Prepend, server_key)?
+            .run(|connection| connection.prepend(server_key, value));
+    }
+
+    /// Delete a key from memcached server.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// client.delete("foo").unwrap();
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn delete(&self, key: &str) -> Result<bool, MemcacheError> {
+        let caller_key = key;
+        let key = &*self.key(key)?;
+        self.mirror(|| Mirrored::Delete(caller_key.to_string()));
+        if let Some(ref fallback) = self.stale_fallback {
+            fallback.remove(key);
+        }
+        self.retrying(Command::Delete, || {
+            self.connection(Command::Delete, key)?
+                .run(|connection| connection.delete(key))
+        })
+    }
+
+    /// Delete a key of arbitrary bytes, see `get_bytes_key`.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// client.delete_bytes_key(&[0xff, 0x00, 0x20]).unwrap();
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn delete_bytes_key(&self, key: &[u8]) -> Result<bool, MemcacheError> {
+        if let Ok(key) = std::str::from_utf8(key) {
+            return self.delete(key);
+        }
+        let connection_index = self.bytes_key_connection_index(key)?;
+        self.mirror(|| Mirrored::DeleteBytesKey(key.to_vec()));
+        self.checkout(Command::Delete, connection_index, None)?
+            .run(|connection| connection.delete_bytes_key(key))
+    }
+
+    /// Delete multiple keys, pipelined per server, and return whether each key existed. If some
+    /// deletes fail, the other keys are still deleted, and the error of the smallest failed key is
+    /// returned, see `deletes_partial` to know which keys failed.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// client.set("foo", "bar", 0).unwrap();
+    /// # client.delete("baz").unwrap();
+    /// let keys = vec!["foo".to_string(), "baz".to_string()];
+    /// let results = client.deletes(keys).unwrap();
+    /// assert!(results["foo"]);
+    /// assert!(!results["baz"]);
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn deletes<K, I>(&self, keys: I) -> Result<HashMap<String, bool>, MemcacheError>
+    where
+        K: AsRef<str>,
+        I: IntoIterator<Item = K>,
+    {
+        let keys: Vec<K> = keys.into_iter().collect();
+        for key in keys.iter() {
+            self.key(key.as_ref())?;
+        }
+        let result = self.deletes_partial(keys);
+        match result.errors.into_iter().next() {
+            Some((_, e)) => Err(e),
+            None => Ok(result.deleted),
+        }
+    }
+
+    /// Delete multiple keys like `deletes`, but return the keys which failed along with their
+    /// errors instead of failing the whole call, so the failed deletes can be retried. A server
+    /// which fails fails all of its keys, while an error reported by the server for a key, or an
+    /// invalid key, only fails this key.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// client.set("foo", "bar", 0).unwrap();
+    /// let too_long = "a".repeat(251);
+    /// let result = client.deletes_partial(&["foo", too_long.as_str()]);
+    /// assert!(result.deleted["foo"]);
+    /// assert_eq!(result.failed_keys().collect::<Vec<_>>(), vec![too_long.as_str()]);
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn deletes_partial<K, I>(&self, keys: I) -> PartialDeletes
+    where
+        K: AsRef<str>,
+        I: IntoIterator<Item = K>,
+    {
+        let keys: Vec<K> = keys.into_iter().collect();
+        let mut result = PartialDeletes {
+            deleted: HashMap::with_capacity(keys.len()),
+            errors: Vec::new(),
+        };
+        let mut con_keys: HashMap<usize, Batch<Cow<str>>> = HashMap::new();
+
+        for key in keys.iter() {
+            let key = key.as_ref();
+            let server_key = match self.key(key) {
+                Ok(server_key) => server_key,
+                Err(e) => {
+                    result.errors.push((vec![key.to_string()], e));
+                    continue;
+                }
+            };
+            self.mirror(|| Mirrored::Delete(key.to_string()));
+            let connection_index = self.connection_index(&server_key);
+            let (keys, server_keys) = con_keys.entry(connection_index).or_default();
+            keys.push(key);
+            server_keys.push(server_key);
+        }
+        for (connection_index, (keys, server_keys)) in con_keys {
+            let server_keys: Vec<&str> = server_keys.iter().map(AsRef::as_ref).collect();
+            let deleted = self
+                .checkout(Command::Delete, connection_index, None)
+                .and_then(|mut connection| connection.run(|connection| connection.deletes(&server_keys)));
+            match deleted {
+                Ok(deleted) => {
+                    for (key, deleted) in keys.into_iter().zip(deleted) {
+                        match deleted {
+                            Ok(deleted) => {
+                                result.deleted.insert(key.to_string(), deleted);
+                            }
+                            Err(e) => result.errors.push((vec![key.to_string()], e)),
+                        }
+                    }
+                }
+                Err(e) => result.errors.push((keys.into_iter().map(String::from).collect(), e)),
+            }
+        }
+        // the servers are visited in no particular order
+        for (keys, _) in result.errors.iter_mut() {
+            keys.sort();
+        }
+        result.errors.sort_by(|(a, _), (b, _)| a.cmp(b));
+        result
+    }
+
+    /// Delete multiple keys like `deletes`, without waiting for the result of each key: the
+    /// deletes are sent as quiet requests with the binary protocol, and all but the last one with
+    /// `noreply` with the ascii protocol, so invalidating many keys takes a single round trip per
+    /// server. Missing keys are not errors. With the ascii protocol, the server reports no error
+    /// for the `noreply` deletes.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// client.set("foo", "bar", 0).unwrap();
+    /// client.deletes_noreply(&["foo", "baz"]).unwrap();
+    /// assert_eq!(client.get::<String>("foo").unwrap(), None);
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn deletes_noreply<K: AsRef<str>>(&self, keys: &[K]) -> Result<(), MemcacheError> {
+        let mut con_keys: HashMap<usize, Vec<Cow<str>>> = HashMap::new();
+        for key in keys {
+            let server_key = self.key(key.as_ref())?;
+            let connection_index = self.connection_index(&server_key);
+            con_keys.entry(connection_index).or_default().push(server_key);
+        }
+        for key in keys {
+            self.mirror(|| Mirrored::Delete(key.as_ref().to_string()));
+        }
+        for (connection_index, server_keys) in con_keys {
+            let server_keys: Vec<&str> = server_keys.iter().map(AsRef::as_ref).collect();
+            for batch in server_keys.chunks(self.multi_get_batch_size) {
+                self.checkout(Command::Delete, connection_index, None)?
+                    .run(|connection| connection.deletes_noreply(batch))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Send a quiet command, or buffer it if deferred writes are enabled.
+    fn write_quiet(&self, key: &str, command: &QuietCommand) -> Result<(), MemcacheError> {
+        let connection_index = self.connection_index(key);
+        if let Some(ref deferred_writes) = self.deferred_writes {
+            return deferred_writes.push(connection_index, command);
+        }
+        let metrics_command = match *command {
+            QuietCommand::Set { .. } => Command::Set,
+            QuietCommand::Delete { .. } => Command::Delete,
+        };
+        let mut connection = self.checkout(metrics_command, connection_index, Some(key))?;
+        let ascii = matches!(connection.protocol, Protocol::Ascii(_));
+        let mut commands = Vec::new();
+        encode_quiet(&mut commands, ascii, command)?;
+        connection.run(|connection| connection.write_quiet(&commands, 1))
+    }
+
+    /// Set a key with `noreply` with the ascii protocol, or a quiet request with the binary
+    /// protocol. With `ClientBuilder::with_deferred_writes`, the set is buffered and written
+    /// along with the following mutations of the same server, otherwise it's written right away.
+    /// Values are not split into chunks, see `ClientBuilder::with_chunking`.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// client.set_noreply("foo", "bar", 10).unwrap();
+    /// assert_eq!(client.get::<String>("foo").unwrap(), Some("bar".into()));
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn set_noreply<V: ToMemcacheValue<Stream>>(
+        &self,
+        key: &str,
+        value: V,
+        expiration: impl Into<Expiration>,
+    ) -> Result<(), MemcacheError> {
+        let expiration = expiration.into().exptime();
+        let caller_key = key;
+        let key = &*self.key(key)?;
+        let encoded = self.encode(&value)?;
+        self.mirror_store(Command::Set, caller_key, &encoded.data, encoded.flags, expiration);
+        self.write_quiet(
+            key,
+            &QuietCommand::Set {
+                key,
+                data: &encoded.data,
+                flags: encoded.flags,
+                expiration,
+            },
+        )
+    }
+
+    /// Delete a key like `set_noreply` sets one. Missing keys are not errors.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// client.set("foo", "bar", 10).unwrap();
+    /// client.delete_noreply("foo").unwrap();
+    /// assert_eq!(client.get::<String>("foo").unwrap(), None);
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn delete_noreply(&self, key: &str) -> Result<(), MemcacheError> {
+        let caller_key = key;
+        let key = &*self.key(key)?;
+        self.mirror(|| Mirrored::Delete(caller_key.to_string()));
+        self.write_quiet(key, &QuietCommand::Delete { key })
+    }
+
+    /// Write the mutations buffered by `ClientBuilder::with_deferred_writes` without waiting for
+    /// their delay, and return the first error reported by the servers. Does nothing if deferred
+    /// writes are not enabled.
+    pub fn flush_deferred_writes(&self) -> Result<(), MemcacheError> {
+        match self.deferred_writes {
+            Some(ref deferred_writes) => deferred_writes.write_all(),
+            None => Ok(()),
+        }
+    }
+
+    /// Increment the value with amount.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// client.increment("counter", 42).unwrap();
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn increment(&self, key: &str, amount: u64) -> Result<u64, MemcacheError> {
+        let caller_key = key;
+        let key = &*self.key(key)?;
+        self.mirror(|| Mirrored::Increment(caller_key.to_string(), amount));
+        self.retrying(Command::Increment, || {
+            self.connection(Command::Increment, key)?
+                .run(|connection| connection.increment(key, amount))
+        })
+    }
+
+    /// Increment multiple counters with their amount, pipelined per server, and return the new
+    /// value of each key. Missing keys are reported as a `CommandError::KeyNotFound` error, and a
+    /// server which fails fails all of its keys.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// client.set("hits", 1, 0).unwrap();
+    /// # client.delete("misses").unwrap();
+    /// let results = client.increments(&[("hits", 2), ("misses", 1)]).unwrap();
+    /// assert_eq!(results["hits"].as_ref().unwrap(), &3);
+    /// assert!(results["misses"].is_err());
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn increments(
+        &self,
+        entries: &[(&str, u64)],
+    ) -> Result<HashMap<String, Result<u64, MemcacheError>>, MemcacheError> {
+        let mut results = HashMap::new();
+        let mut con_entries: HashMap<usize, Batch<(Cow<str>, u64)>> = HashMap::new();
+
+        for &(key, amount) in entries {
+            let server_key = match self.key(key) {
+                Ok(server_key) => server_key,
+                Err(e) => {
+                    results.insert(key.to_string(), Err(e));
+                    continue;
+                }
+            };
+            self.mirror(|| Mirrored::Increment(key.to_string(), amount));
+            let connection_index = self.connection_index(&server_key);
+            let (keys, entries) = con_entries.entry(connection_index).or_default();
+            keys.push(key);
+            entries.push((server_key, amount));
+        }
+        for (connection_index, (keys, entries)) in con_entries {
+            let entries: Vec<(&str, u64)> = entries.iter().map(|(key, amount)| (key.as_ref(), *amount)).collect();
+            let values = self
+                .checkout(Command::Increment, connection_index, None)
+                .and_then(|mut connection| connection.run(|connection| connection.increments(&entries)));
+            match values {
+                Ok(values) => {
+                    for (key, value) in keys.into_iter().zip(values) {
+                        results.insert(key.to_string(), value);
+                    }
+                }
+                Err(e) => {
+                    for key in keys {
+                        results.insert(key.to_string(), Err(e.duplicate()));
+                    }
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    /// Decrement the value with amount.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// client.decrement("counter", 42).unwrap();
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn decrement(&self, key: &str, amount: u64) -> Result<u64, MemcacheError> {
+        let caller_key = key;
+        let key = &*self.key(key)?;
+        self.mirror(|| Mirrored::Decrement(caller_key.to_string(), amount));
+        self.retrying(Command::Decrement, || {
+            self.connection(Command::Decrement, key)?
+                .run(|connection| connection.decrement(key, amount))
+        })
+    }
+
+    /// Set a new expiration time for a exist key.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// assert_eq!(client.touch("not_exists_key", 12345).unwrap(), false);
+    /// client.set("foo", "bar", 123).unwrap();
+    /// assert_eq!(client.touch("foo", 12345).unwrap(), true);
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn touch(&self, key: &str, expiration: impl Into<Expiration>) -> Result<bool, MemcacheError> {
+        let expiration = expiration.into().exptime();
+        let caller_key = key;
+        let key = &*self.key(key)?;
+        self.mirror(|| Mirrored::Touch(caller_key.to_string(), expiration));
+        self.retrying(Command::Touch, || {
+            let mut connection = self.connection(Command::Touch, key)?;
+            connection.check_feature(Feature::Touch)?;
+            connection.run(|connection| connection.touch(key, expiration))
+        })
+    }
+
+    /// Check whether a key exists, without transferring its value when the server supports meta
+    /// commands on the ascii protocol. The binary protocol and older servers fetch the value and
+    /// discard it. Only the key itself is checked, the chunks of a chunked value may be missing.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345?protocol=ascii").unwrap();
+    /// client.set("foo", "bar", 10).unwrap();
+    /// assert!(client.exists("foo").unwrap());
+    /// assert!(!client.exists("not_exists_key").unwrap());
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn exists(&self, key: &str) -> Result<bool, MemcacheError> {
+        let key = &*self.key(key)?;
+        let mut connection = self.connection(Command::Get, key)?;
+        match connection.check_feature(Feature::MetaCommands) {
+            Ok(()) => connection.run(|connection| connection.exists(key)),
+            Err(_) => Ok(connection
+                .fetch(|connection| connection.get::<RawValue>(key))?
+                .is_some()),
+        }
+    }
+
+    /// Get all servers' statistics.
+    ///
+    /// Example:
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// let stats = client.stats().unwrap();
+    /// ```
+    pub fn stats(&self) -> Result<Vec<(String, Stats)>, MemcacheError> {
+        self.broadcast(|connection| Ok((connection.get_url(), connection.stats()?)))
+            .into_iter()
+            .collect()
+    }
+
+    /// Get all servers' statistics like `stats`, but return the result of each server along with
+    /// its url, so the statistics of the servers which answered are available even if others are
+    /// unreachable.
+    ///
+    /// Example:
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// for (url, stats) in client.stats_each() {
+    ///     if let Ok(stats) = stats {
+    ///         println!("{} has {} items", url, stats["curr_items"]);
+    ///     }
+    /// }
+    /// ```
+    pub fn stats_each(&self) -> Vec<(String, Result<Stats, MemcacheError>)> {
+        self.broadcast_each(|connection| connection.stats())
+    }
+
+    /// Get all servers' item statistics of each slab class, like the number of items and evictions.
+    ///
+    /// Example:
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// client.set("foo", "bar", 0).unwrap();
+    /// let stats = client.stats_items().unwrap();
+    /// assert!(!stats[0].1.classes.is_empty());
+    /// ```
+    pub fn stats_items(&self) -> Result<Vec<(String, SlabStats)>, MemcacheError> {
+        self.broadcast(|connection| {
+            let stats = connection.stats_with_args("items")?;
+            Ok((connection.get_url(), SlabStats::from_stats(stats, "items:")))
+        })
+        .into_iter()
+        .collect()
+    }
+
+    /// Get all servers' memory statistics of each slab class, like the chunk size and the number
+    /// of used chunks.
+    ///
+    /// Example:
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// client.set("foo", "bar", 0).unwrap();
+    /// let stats = client.stats_slabs().unwrap();
+    /// assert!(stats[0].1.totals.contains_key("active_slabs"));
+    /// ```
+    pub fn stats_slabs(&self) -> Result<Vec<(String, SlabStats)>, MemcacheError> {
+        self.broadcast(|connection| {
+            let stats = connection.stats_with_args("slabs")?;
+            Ok((connection.get_url(), SlabStats::from_stats(stats, "")))
+        })
+        .into_iter()
+        .collect()
+    }
+
+    /// Get all servers' item size distribution, as a map from item size (rounded up to 32 bytes)
+    /// to the number of items of this size. The server only tracks this when started with
+    /// `-o track_sizes`, otherwise the maps are empty.
+    ///
+    /// Example:
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// let stats = client.stats_sizes().unwrap();
+    /// ```
+    pub fn stats_sizes(&self) -> Result<Vec<(String, SizeStats)>, MemcacheError> {
+        self.broadcast(|connection| {
+            let stats = connection.stats_with_args("sizes")?;
+            Ok((connection.get_url(), parse_size_stats(stats)))
+        })
+        .into_iter()
+        .collect()
+    }
+
+    /// Iterate over the metadata of all keys stored on all servers, using `lru_crawler metadump`.
+    /// The keys are streamed from one server after another while iterating, without buffering
+    /// the whole dump. Only available for connections using the ascii protocol.
+    ///
+    /// Example:
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345?protocol=ascii").unwrap();
+    /// client.set("foo", "bar", 0).unwrap();
+    /// for metadata in client.scan_keys() {
+    ///     let metadata = metadata.unwrap();
+    ///     println!("{} expires at {:?}", metadata.key, metadata.expiration);
+    /// }
+    /// ```
+    pub fn scan_keys(&self) -> KeyScan<'_> {
+        KeyScan::new(&self.connections)
+    }
+
+    /// Reset the statistics counters of all servers.
+    ///
+    /// Example:
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// client.stats_reset().unwrap();
+    /// ```
+    pub fn stats_reset(&self) -> Result<(), MemcacheError> {
+        self.broadcast_unit(|connection| connection.stats_reset())
+    }
+}
+
+/// Builder for a `Client`.
+///
+/// Example:
+///
+/// ```rust
+/// let client = memcache::Client::builder()
+///     .add_server("memcache://localhost:12345")
+///     .with_pool_size(4)
+///     .build()
+///     .unwrap();
+/// ```
+pub struct ClientBuilder {
+    urls: Vec<String>,
+    pool_size: u32,
+    hash_function: fn(&str) -> u64,
+    connection_options: ConnectionOptions,
+    transcoders: Vec<Arc<dyn Transcoder>>,
+    chunk_size: Option<usize>,
+    application_flags: u32,
+    decode_failures_as_misses: bool,
+    cas_retries: u32,
+    multi_get_batch_size: usize,
+    multi_get_concurrency: Option<usize>,
+    percent_encode_keys: bool,
+    negative_ttl: Option<Expiration>,
+    buffer_pool: Option<BufferPool>,
+    coalesce_gets: bool,
+    deferred_writes: Option<(Duration, usize)>,
+    worker_threads: Option<usize>,
+    metrics: bool,
+    slow_operation_hook: Option<(Duration, Arc<SlowOperationHook>)>,
+    retry_policy: Option<RetryPolicy>,
+    on_error: OnError,
+    dead_server_timeout: Duration,
+    stale_fallback: Option<usize>,
+    mirror: Option<(Client, f64)>,
+    error_context: Option<bool>,
+    #[cfg(feature = "key-hashing")]
+    long_key_prefix: Option<String>,
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClientBuilder {
+    pub fn new() -> Self {
+        ClientBuilder {
+            urls: vec![],
+            pool_size: 1,
+            hash_function: default_hash_function,
+            connection_options: ConnectionOptions::default(),
+            transcoders: vec![],
+            chunk_size: None,
+            application_flags: 0,
+            decode_failures_as_misses: false,
+            cas_retries: 10,
+            multi_get_batch_size: 1000,
+            multi_get_concurrency: None,
+            percent_encode_keys: false,
+            negative_ttl: None,
+            buffer_pool: None,
+            coalesce_gets: false,
+            deferred_writes: None,
+            worker_threads: None,
+            metrics: false,
+            slow_operation_hook: None,
+            retry_policy: None,
+            on_error: OnError::FailFast,
+            dead_server_timeout: Duration::from_secs(10),
+            stale_fallback: None,
+            mirror: None,
+            error_context: None,
+            #[cfg(feature = "key-hashing")]
+            long_key_prefix: None,
+        }
+    }
+
+    /// Add one or more memcached servers by their urls.
+    pub fn add_server<C: Connectable>(mut self, target: C) -> Self {
+        self.urls.extend(target.get_urls());
+        self
+    }
+
+    /// Set the maximum number of connections for each server. Default is 1.
+    pub fn with_pool_size(mut self, size: u32) -> Self {
+        self.pool_size = size;
+        self
+    }
+
+    /// Set the function used to select the server for a key.
+    pub fn with_hash_function(mut self, hash_function: fn(&str) -> u64) -> Self {
+        self.hash_function = hash_function;
+        self
+    }
+
+    /// Set the SASL mechanism used to authenticate binary protocol connections, like `PLAIN` or
+    /// `SCRAM-SHA-256`. Same as the `sasl_mech` url parameter. By default the most secure
+    /// mechanism offered by the server is used.
+    pub fn with_sasl_mechanism(mut self, mechanism: &str) -> Self {
+        self.connection_options.sasl_mechanism = Some(mechanism.to_string());
+        self
+    }
+
+    /// Set the capacity in bytes of the buffer responses are read through, 8 KiB by default. A larger
+    /// buffer reads responses with many small values, like large multi-gets, with fewer syscalls.
+    /// Same as the `read_buffer_size` url parameter.
+    pub fn with_read_buffer_size(mut self, size: usize) -> Self {
+        self.connection_options.read_buffer_size = Some(size);
+        self
+    }
+
+    /// Set the capacity in bytes of the buffer commands are written through before being sent,
+    /// 8 KiB by default. Same as the `write_buffer_size` url parameter.
+    pub fn with_write_buffer_size(mut self, size: usize) -> Self {
+        self.connection_options.write_buffer_size = Some(size);
+        self
+    }
+
+    /// Set the size in bytes of the kernel receive buffer of TCP connections, `SO_RCVBUF`, for
+    /// deployments reading very large values or at very high rates where the system default limits
+    /// throughput. The kernel may round or cap it, and the system default is used if it's not set.
+    /// Same as the `recv_buffer_size` url parameter.
+    pub fn with_recv_buffer_size(mut self, size: usize) -> Self {
+        self.connection_options.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Set the size in bytes of the kernel send buffer of TCP connections, `SO_SNDBUF`, like
+    /// `with_recv_buffer_size`. Same as the `send_buffer_size` url parameter.
+    pub fn with_send_buffer_size(mut self, size: usize) -> Self {
+        self.connection_options.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Enable TCP keepalive probes on TCP connections, sent after a connection has been idle for
+    /// `idle` and then every `interval`, so pooled connections dropped by a NAT or load balancer are
+    /// detected and closed by the kernel instead of by a blocked read. Same as the
+    /// `tcp_keepalive_idle` and `tcp_keepalive_interval` url parameters in seconds, or
+    /// `tcp_keepalive=true` with the system default timings. Only supported on Linux, Android,
+    /// FreeBSD, NetBSD, macOS and iOS.
+    pub fn with_tcp_keepalive(mut self, idle: Duration, interval: Duration) -> Self {
+        self.connection_options.keepalive = Some(Keepalive {
+            idle: Some(idle),
+            interval: Some(interval),
+        });
+        self
+    }
+
+    /// Set `TCP_USER_TIMEOUT` on TCP connections, how long sent data may stay unacknowledged
+    /// before the kernel closes the connection, so writes to a server which stopped answering fail
+    /// after `timeout` instead of after the retransmission timeouts, which may take many minutes.
+    /// Same as the `tcp_user_timeout` url parameter in seconds. Only supported on Linux.
+    pub fn with_tcp_user_timeout(mut self, timeout: Duration) -> Self {
+        self.connection_options.user_timeout = Some(timeout);
+        self
+    }
+
+    /// Return the errors of the commands sent to a server as `MemcacheError::ContextError`, with
+    /// the url of the server, the command, and its key unless `include_keys` is false, like for
+    /// keys holding personal data which shouldn't end up in logs. Use `MemcacheError::inner` to
+    /// match on the error itself.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::builder()
+    ///     .add_server("memcache://localhost:12345")
+    ///     .with_error_context(true)
+    ///     .build()
+    ///     .unwrap();
+    /// client.set("foo", "bar", 10).unwrap();
+    /// let error = client.add("foo", "baz", 10).unwrap_err();
+    /// assert_eq!(error.to_string(), "add foo on memcache://localhost:12345: Key already exists in the server.");
+    /// match error.inner() {
+    ///     memcache::MemcacheError::CommandError(memcache::CommandError::KeyExists) => {}
+    ///     e => panic!("unexpected error: {}", e),
+    /// }
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn with_error_context(mut self, include_keys: bool) -> Self {
+        self.error_context = Some(include_keys);
+        self
+    }
+
+    /// Send the commands which failed with a transient error again, as set by `policy`.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self
+    }
+
+    /// Set how the failures of a single server affect the commands for its keys and the multi-key
+    /// operations, see `OnError`. Defaults to `OnError::FailFast`.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    ///
+    /// let client = memcache::Client::builder()
+    ///     .add_server("memcache://localhost:12345")
+    ///     .with_on_error(memcache::OnError::RehashToLive)
+    ///     .with_dead_server_timeout(Duration::from_secs(30))
+    ///     .build()
+    ///     .unwrap();
+    /// client.set("foo", "bar", 10).unwrap();
+    /// assert_eq!(client.get::<String>("foo").unwrap(), Some("bar".to_string()));
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn with_on_error(mut self, on_error: OnError) -> Self {
+        self.on_error = on_error;
+        self
+    }
+
+    /// How long a server which failed is considered dead before commands are sent to it again,
+    /// unless the failures are handled with `OnError::FailFast`. Defaults to 10 seconds.
+    pub fn with_dead_server_timeout(mut self, timeout: Duration) -> Self {
+        self.dead_server_timeout = timeout;
+        self
+    }
+
+    /// Keep the values of up to `capacity` keys fetched by `Client::get`, to return them marked as
+    /// stale from `Client::get_or_stale` while their servers are down, for read-mostly workloads
+    /// which prefer stale values to errors. The keys fetched the longest ago are evicted first,
+    /// and the keys which are missing or deleted with `Client::delete` are dropped.
+    pub fn with_stale_fallback(mut self, capacity: usize) -> Self {
+        self.stale_fallback = Some(capacity);
+        self
+    }
+
+    /// Send the writes of the client to `secondary` as well, including the batched and `noreply`
+    /// ones and the flushes, and the gets of a `read_sample_rate` fraction of the keys read with
+    /// `Client::get`, to validate a new cluster or memcached version with real traffic before
+    /// switching to it. The mirrored commands are sent from a background thread, their results
+    /// are ignored, and they are dropped if the secondary falls too far behind. The values are
+    /// stored as encoded by this client, before being split into chunks, and the values stored
+    /// with `cas` or `update` are sent as sets once stored.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let secondary = memcache::Client::connect("memcache://localhost:12345?protocol=ascii").unwrap();
+    /// let client = memcache::Client::builder()
+    ///     .add_server("memcache://localhost:12345")
+    ///     .with_mirror(secondary, 0.01)
+    ///     .build()
+    ///     .unwrap();
+    /// client.set("foo", "bar", 10).unwrap();
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn with_mirror(mut self, secondary: Client, read_sample_rate: f64) -> Self {
+        self.mirror = Some((secondary, read_sample_rate));
+        self
+    }
+
+    /// Wait at most `timeout` for the whole response of a request on UDP connections, and send
+    /// the request again up to `retransmits` times when it's lost, before failing with a timeout
+    /// error. Every attempt uses a new request id, so late responses to the previous attempts are
+    /// discarded. Same as the `udp_timeout_ms` and `udp_retransmits` url parameters.
+    ///
+    /// As with any retransmission, a command whose response was lost may be applied more than
+    /// once, which isn't idempotent for `increment`, `append` and the like.
+    pub fn with_udp_retransmission(mut self, timeout: Duration, retransmits: u32) -> Self {
+        self.connection_options.udp_retransmission = Some((timeout, retransmits));
+        self
+    }
+
+    /// Set the largest datagram sent on UDP connections, including the 8 bytes of the UDP frame
+    /// header, like 1400 to avoid IP fragmentation on most networks, or more on jumbo frame
+    /// networks. Same as the `udp_max_datagram_size` url parameter. Requests can't span several
+    /// datagrams, larger ones fail unless `with_udp_tcp_fallback` is enabled. Defaults to the
+    /// largest UDP payload, 65507 bytes.
+    pub fn with_udp_max_datagram_size(mut self, size: usize) -> Self {
+        self.connection_options.udp_max_datagram_size = Some(size);
+        self
+    }
+
+    /// Send the requests which don't fit in a datagram over a TCP connection to the same host and
+    /// port, opened by the first one, instead of failing. Same as the `udp_tcp_fallback=true` url
+    /// parameter.
+    pub fn with_udp_tcp_fallback(mut self) -> Self {
+        self.connection_options.udp_tcp_fallback = true;
+        self
+    }
+
+    /// Set a provider for the credentials used to authenticate new connections, which takes
+    /// precedence over the credentials in the server urls.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// # fn fetch_auth_token() -> String { String::from("token") }
+    /// let builder = memcache::Client::builder()
+    ///     .add_server("memcache://localhost:12345")
+    ///     .with_auth_provider(|_url: &str| Ok((String::from("user"), fetch_auth_token())));
+    /// ```
+    pub fn with_auth_provider<P: AuthProvider + 'static>(mut self, provider: P) -> Self {
+        self.connection_options.auth_provider = Some(Arc::new(provider));
+        self
+    }
+
+    /// Add a transcoder to transform all values stored and fetched by the client, see
+    /// `Transcoder`. By default values are stored as serialized by `ToMemcacheValue`. Multiple
+    /// transcoders are applied in the order they were added when storing values, and in reverse
+    /// order when fetching them.
+    pub fn with_transcoder<T: Transcoder + 'static>(mut self, transcoder: T) -> Self {
+        self.transcoders.push(Arc::new(transcoder));
+        self
+    }
+
+    /// Split values larger than `chunk_size` bytes into chunks, to store values over the item size
+    /// limit of the servers, which is 1MB by default. The chunks are stored under keys derived from
+    /// the value's key, which must be at most 230 bytes long, and a small manifest under the key
+    /// itself. Values are reassembled when fetched, and treated as missing if any chunk was evicted.
+    ///
+    /// `delete` only removes the manifest, the chunks are left to expire or be evicted. Only clients
+    /// with chunking enabled reassemble chunked values, other clients see the manifest.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::builder()
+    ///     .add_server("memcache://localhost:12345")
+    ///     .with_chunking(1000 * 1000)
+    ///     .build()
+    ///     .unwrap();
+    /// let value = vec![42u8; 3 * 1024 * 1024];
+    /// client.set("chunked", &value[..], 0).unwrap();
+    /// let fetched: Vec<u8> = client.get("chunked").unwrap().unwrap();
+    /// assert_eq!(fetched, value);
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn with_chunking(mut self, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "the chunk size must not be zero");
+        self.chunk_size = Some(chunk_size);
+        self
+    }
+
+    /// Declare the flag bits used by the application, e.g. with `set_with_flags`. Building the
+    /// client fails if any of them is reserved by a transcoder, chunking, or the serialization
+    /// features, see `Client::flag_layout`.
+    pub fn with_application_flags(mut self, flags: u32) -> Self {
+        self.application_flags = flags;
+        self
+    }
+
+    /// Treat values which can't be decoded, e.g. stale values written with an older layout, as
+    /// missing in `get`, `gets` and their variants, instead of returning the error. The
+    /// application can then overwrite them with a fresh value. Use `Client::get_raw` to inspect
+    /// such values.
+    pub fn with_decode_failures_as_misses(mut self, enabled: bool) -> Self {
+        self.decode_failures_as_misses = enabled;
+        self
+    }
+
+    /// Set how many times `Client::update` retries after a concurrent modification of the key,
+    /// 10 by default.
+    pub fn with_cas_retries(mut self, retries: u32) -> Self {
+        self.cas_retries = retries;
+        self
+    }
+
+    /// Set the maximum number of keys fetched from a server in one request by `gets` and its
+    /// variants, or deleted by `deletes_noreply`, 1000 by default. Larger key lists are split into
+    /// several requests.
+    ///
+    /// Panics if `size` is zero.
+    pub fn with_multi_get_batch_size(mut self, size: usize) -> Self {
+        assert!(size > 0, "the multi-get batch size must not be zero");
+        self.multi_get_batch_size = size;
+        self
+    }
+
+    /// Set how many requests of a multi-get split by `with_multi_get_batch_size`, or sent to
+    /// different servers, are issued concurrently, each on its own pooled connection. By default
+    /// the servers holding the keys are queried concurrently, so a multi-get takes as long as the
+    /// slowest server rather than the sum of their round trips, and the requests to a server are
+    /// issued one after the other. 1 issues all the requests one after the other.
+    pub fn with_multi_get_concurrency(mut self, concurrency: usize) -> Self {
+        self.multi_get_concurrency = Some(concurrency);
+        self
+    }
+
+    /// Run at most `threads` of the requests sent to several servers at once, by multi-gets and by
+    /// `flush`, `version` and the stats methods, at a time across the clones of the client,
+    /// instead of a thread for each of them. A call needing more threads than the pool has waits
+    /// for the busy ones.
+    pub fn with_worker_pool(mut self, threads: usize) -> Self {
+        self.worker_threads = Some(threads);
+        self
+    }
+
+    /// Record the latency of the commands sent to each server, from the checkout of a pooled
+    /// connection to the end of the response, for `Client::metrics`.
+    pub fn with_metrics(mut self) -> Self {
+        self.metrics = true;
+        self
+    }
+
+    /// Call `hook` with every command which took `threshold` or longer, measured like the
+    /// latencies of `with_metrics`, to find the keys with pathological values or the overloaded
+    /// servers. The hook is called by the thread which sent the command, before the operation
+    /// returns, so it should be quick.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::builder()
+    ///     .add_server("memcache://localhost:12345")
+    ///     .with_slow_operation_hook(std::time::Duration::from_millis(100), |operation| {
+    ///         eprintln!(
+    ///             "slow {} of {:?} on {}: {:?}",
+    ///             operation.command, operation.key, operation.server, operation.elapsed
+    ///         );
+    ///     })
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn with_slow_operation_hook<F>(mut self, threshold: Duration, hook: F) -> Self
+    where
+        F: Fn(&SlowOperation) + Send + Sync + 'static,
+    {
+        self.slow_operation_hook = Some((threshold, Arc::new(hook)));
+        self
+    }
+
+    /// Cache the misses of `Client::get_or_set_optional` for `expiration`, usually shorter than
+    /// the expiration of found values, so keys missing from the backing store don't hit it on
+    /// every call. Misses are stored as empty values marked with the flag bit `0x1000_0000`, which
+    /// every fetch method returns as missing.
+    pub fn with_negative_caching(mut self, expiration: impl Into<Expiration>) -> Self {
+        self.negative_ttl = Some(expiration.into());
+        self
+    }
+
+    /// Reuse the buffers values are read into for the next values, once decoded to types which
+    /// don't keep them, like numbers or `Json`, see `FromMemcacheValue::from_memcache_slice`. Up to
+    /// `max_buffers` buffers of up to `max_buffer_size` bytes are kept for each thread, as a
+    /// connection is only used by one thread at a time. Values decoded to types which keep their
+    /// buffer, like `String` or `Vec<u8>`, are unaffected.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::builder()
+    ///     .add_server("memcache://localhost:12345")
+    ///     .with_value_buffer_pool(16, 64 * 1024)
+    ///     .build()
+    ///     .unwrap();
+    /// client.set("counter", 42, 0).unwrap();
+    /// for _ in 0..3 {
+    ///     let value: u64 = client.get("counter").unwrap().unwrap();
+    ///     assert_eq!(value, 42);
+    /// }
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn with_value_buffer_pool(mut self, max_buffers: usize, max_buffer_size: usize) -> Self {
+        self.buffer_pool = Some(BufferPool {
+            max_buffers,
+            max_buffer_size,
+        });
+        self
+    }
+
+    /// Coalesce concurrent `Client::get` and `Client::get_raw` calls for the same key, from
+    /// threads sharing the client or its clones: only the first caller sends a request, and the
+    /// callers arriving while it is in flight wait for it and share its value, so a hot key which
+    /// just expired doesn't hit the server with a burst of identical requests. If the request
+    /// fails, each waiting caller sends its own.
+    pub fn with_get_coalescing(mut self) -> Self {
+        self.coalesce_gets = true;
+        self
+    }
+
+    /// Buffer the mutations of `Client::set_noreply` and `Client::delete_noreply` for each
+    /// server, and write them together once they waited for `max_delay` or `max_bytes` are
+    /// buffered, so write-heavy workloads send many mutations with a single syscall and round
+    /// trip. The buffered mutations are not visible to reads until they are written, which
+    /// `Client::flush_deferred_writes` does right away, and the errors of mutations written after
+    /// `max_delay` are discarded.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::builder()
+    ///     .add_server("memcache://localhost:12345")
+    ///     .with_deferred_writes(std::time::Duration::from_micros(500), 64 * 1024)
+    ///     .build()
+    ///     .unwrap();
+    /// client.set_noreply("metric:requests", 42, 60).unwrap();
+    /// client.flush_deferred_writes().unwrap();
+    /// assert_eq!(client.get::<u32>("metric:requests").unwrap(), Some(42));
+    /// # client.flush().unwrap();
+    /// ```
+    pub fn with_deferred_writes(mut self, max_delay: Duration, max_bytes: usize) -> Self {
+        self.deferred_writes = Some((max_delay, max_bytes));
+        self
+    }
+
+    /// Percent-encode whitespace and control characters in keys, which the ascii protocol
+    /// rejects with `ClientError::InvalidKey`, and `%` to keep the encoded keys distinct. The
+    /// encoded keys are used by every operation, and multi-key operations return the results under
+    /// the original keys.
+    pub fn with_key_percent_encoding(mut self) -> Self {
+        self.percent_encode_keys = true;
+        self
+    }
+
+    /// Replace keys longer than the 250 bytes allowed by memcached by `prefix` followed by the hex
+    /// SHA-256 digest of the key, instead of rejecting them with `ClientError::KeyTooLong`. The
+    /// hashed keys are used by every operation, and multi-key operations return the results under
+    /// the original keys. Only available with the `key-hashing` feature.
+    ///
+    /// Panics if `prefix` is longer than 186 bytes, which would make the hashed keys over-length.
+    #[cfg(feature = "key-hashing")]
+    pub fn with_long_key_hashing(mut self, prefix: &str) -> Self {
+        assert!(prefix.len() <= 250 - 64, "the prefix of hashed keys is too long");
+        self.long_key_prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// Check that the flag bits claimed by the transcoders, chunking and the application don't
+    /// overlap.
+    fn flag_layout(&self) -> Result<FlagLayout, MemcacheError> {
+        let conflict = |flags: u32, first: &str, second: &str| -> MemcacheError {
+            ClientError::Error(Cow::Owned(format!(
+                "flag bits {:#x} are claimed by both {} and {}",
+                flags, first, second
+            )))
+            .into()
+        };
+        // the first of the transcoders and of the client's features which claimed `flags`
+        let owner = |layout: &FlagLayout, flags: u32| -> String {
+            match self.transcoders.iter().position(|t| t.reserved_flags() & flags != 0) {
+                Some(i) => format!("transcoder #{}", i + 1),
+                None if layout.serialization & flags != 0 => "serialization".to_string(),
+                None if layout.chunking & flags != 0 => "chunking".to_string(),
+                None => "negative caching".to_string(),
+            }
+        };
+        let mut layout = FlagLayout {
+            application: self.application_flags,
+            serialization: Flags::serialization_mask(),
+            ..Default::default()
+        };
+        for (i, transcoder) in self.transcoders.iter().enumerate() {
+            let flags = transcoder.reserved_flags();
+            let claimed = flags & layout.transcoders;
+            if claimed != 0 {
+                let name = format!("transcoder #{}", i + 1);
+                return Err(conflict(claimed, &owner(&layout, claimed), &name));
+            }
+            layout.transcoders |= flags;
+        }
+        if self.chunk_size.is_some() {
+            if CHUNKED_FLAG & layout.transcoders != 0 {
+                return Err(conflict(CHUNKED_FLAG, &owner(&layout, CHUNKED_FLAG), "chunking"));
+            }
+            layout.chunking = CHUNKED_FLAG;
+        }
+        if self.negative_ttl.is_some() {
+            if MISSING_FLAG & (layout.transcoders | layout.chunking) != 0 {
+                return Err(conflict(
+                    MISSING_FLAG,
+                    &owner(&layout, MISSING_FLAG),
+                    "negative caching",
+                ));
+            }
+            layout.negative_caching = MISSING_FLAG;
+        }
+        let claimed = layout.application & layout.reserved();
+        if claimed != 0 {
+            return Err(conflict(claimed, &owner(&layout, claimed), "the application"));
+        }
+        Ok(layout)
+    }
+
+    pub fn build(self) -> Result<Client, MemcacheError> {
+        if self.urls.is_empty() {
+            return Err(ClientError::Error(Cow::Borrowed("at least one server url is required")).into());
+        }
+        let flag_layout = self.flag_layout()?;
+        let mut transcoders = self.transcoders;
+        let transcoder: Option<Arc<dyn Transcoder>> = match transcoders.len() {
+            0 => None,
+            1 => transcoders.pop(),
+            _ => Some(Arc::new(TranscoderChain(transcoders))),
+        };
+        let metrics = if self.metrics {
+            Some(Arc::new(Metrics::new(self.urls.clone())))
+        } else {
+            None
+        };
+        let counters = Arc::new(ServerCounters::new(self.urls.clone()));
+        let urls_count = self.urls.len();
+        let mut connections = vec![];
+        let mut connect_errors = vec![];
+        let mut ascii = vec![];
+        for url in self.urls {
+            let parsed = Url::parse(url.as_str())?;
+            ascii.push(is_ascii(&parsed)?);
+            let manager = ConnectionManager::new(parsed, self.connection_options.clone());
+            let last_error = manager.last_error();
+            let pool = r2d2::Pool::builder()
+                .max_size(self.pool_size)
+                .build(manager)
+                .map_err(|e| last_error.pool_error(e))?;
+            connections.push(pool);
+            connect_errors.push(last_error);
+        }
+        let deferred_writes = match self.deferred_writes {
+            Some((max_delay, max_bytes)) => Some(DeferredWrites::start(
+                connections.iter().cloned().zip(ascii).collect(),
+                max_delay,
+                max_bytes,
+            )?),
+            None => None,
+        };
+        Ok(Client {
+            connections,
+            connect_errors,
+            hash_function: self.hash_function,
+            transcoder,
+            chunk_size: self.chunk_size,
+            flag_layout,
+            decode_failures_as_misses: self.decode_failures_as_misses,
+            cas_retries: self.cas_retries,
+            multi_get_batch_size: self.multi_get_batch_size,
+            multi_get_concurrency: self.multi_get_concurrency,
+            percent_encode_keys: self.percent_encode_keys,
+            negative_ttl: self.negative_ttl,
+            buffer_pool: self.buffer_pool,
+            get_coalescer: if self.coalesce_gets {
+                Some(Arc::new(Coalescer::default()))
+            } else {
+                None
+            },
+            deferred_writes,
+            worker_pool: self.worker_threads.map(|threads| Arc::new(WorkerPool::new(threads))),
+            metrics,
+            slow_operation_hook: self.slow_operation_hook,
+            counters,
+            retry_policy: self.retry_policy.map(Arc::new),
+            on_error: self.on_error,
+            server_health: match self.on_error {
+                OnError::FailFast => None,
+                _ => Some(Arc::new(ServerHealth::new(urls_count, self.dead_server_timeout))),
+            },
+            stale_fallback: self
+                .stale_fallback
+                .map(|capacity| Arc::new(StaleFallback::new(capacity))),
+            mirror: match self.mirror {
+                Some((secondary, read_sample_rate)) => Some(Arc::new(Mirror::start(secondary, read_sample_rate)?)),
+                None => None,
+            },
+            error_context: self.error_context,
+            #[cfg(feature = "key-hashing")]
+            long_key_prefix: self.long_key_prefix,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn slab_stats() {
+        let stats = [
+            ("items:1:number", "5"),
+            ("items:1:evicted", "0"),
+            ("items:12:number", "1"),
+        ];
+        let stats = stats.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let stats = super::SlabStats::from_stats(stats, "items:");
+        assert_eq!(stats.classes.len(), 2);
+        assert_eq!(stats.classes[&1]["number"], "5");
+        assert_eq!(stats.classes[&1]["evicted"], "0");
+        assert_eq!(stats.classes[&12]["number"], "1");
+        assert!(stats.totals.is_empty());
+
+        let stats = [
+            ("1:chunk_size", "96"),
+            ("active_slabs", "1"),
+            ("total_malloced", "1048576"),
+        ];
+        let stats = stats.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let stats = super::SlabStats::from_stats(stats, "");
+        assert_eq!(stats.classes[&1]["chunk_size"], "96");
+        assert_eq!(stats.totals["active_slabs"], "1");
+        assert_eq!(stats.totals["total_malloced"], "1048576");
+    }
+
+    #[test]
+    fn size_stats() {
+        let stats = [("96", "3"), ("128", "1"), ("sizes_status", "enabled")];
+        let stats = stats.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let sizes = super::parse_size_stats(stats);
+        assert_eq!(sizes.len(), 2);
+        assert_eq!(sizes[&96], 3);
+        assert_eq!(sizes[&128], 1);
+    }
+
+    #[test]
+    fn flag_layout() {
+        let builder = super::ClientBuilder::new()
+            .with_transcoder(crate::PythonCompat::pylibmc())
+            .with_chunking(1000)
+            .with_application_flags(0xf000);
+        let layout = builder.flag_layout().unwrap();
+        assert_eq!(layout.transcoders, 0x37);
+        assert_eq!(layout.chunking, 0x8000_0000);
+        assert_eq!(layout.negative_caching, 0);
+
+        let builder = super::ClientBuilder::new().with_negative_caching(5);
+        assert_eq!(builder.flag_layout().unwrap().negative_caching, 0x1000_0000);
+        let builder = super::ClientBuilder::new()
+            .with_negative_caching(5)
+            .with_transcoder(crate::SchemaVersion::new(1).with_flag(0x1000_0000));
+        let error = builder.flag_layout().unwrap_err().to_string();
+        assert!(error.contains("transcoder #1 and negative caching"), "{}", error);
+        let builder = super::ClientBuilder::new()
+            .with_transcoder(crate::PythonCompat::pylibmc())
+            .with_transcoder(crate::SchemaVersion::new(1).with_flag(0x8000_0000))
+            .with_chunking(1000);
+        let error = builder.flag_layout().unwrap_err().to_string();
+        assert!(error.contains("transcoder #2 and chunking"), "{}", error);
+
+        // both use the lowest bit for serialized objects
+        let builder = super::ClientBuilder::new()
+            .with_transcoder(crate::PythonCompat::pylibmc())
+            .with_transcoder(crate::JavaCompat::spymemcached());
+        let error = builder.flag_layout().unwrap_err().to_string();
+        assert!(error.contains("transcoder #1 and transcoder #2"), "{}", error);
+
+        let builder = super::ClientBuilder::new()
+            .with_chunking(1000)
+            .with_application_flags(0xc000_0000);
+        let error = builder.flag_layout().unwrap_err().to_string();
+        assert!(error.contains("chunking and the application"), "{}", error);
+    }
+
+    #[test]
+    fn retry_on_new_connection() {
+        use crate::metrics::Command;
+        use crate::retry::RetryPolicy;
+        use std::io::{BufRead, BufReader, Write};
+        use std::net::TcpListener;
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+        use std::thread;
+        use std::time::Duration;
+
+        // a server which doesn't answer the first set, and notes if its connection is used again
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (timed_out, reused) = (Arc::new(AtomicBool::new(false)), Arc::new(AtomicBool::new(false)));
+        let server_reused = reused.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = stream.unwrap();
+                let (timed_out, reused) = (timed_out.clone(), server_reused.clone());
+                thread::spawn(move || {
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut line = String::new();
+                    let mut silent = false;
+                    while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                        if silent {
+                            reused.store(true, Ordering::SeqCst);
+                        }
+                        if line == "version\r\n" {
+                            (&stream).write_all(b"VERSION 1.6.21\r\n").unwrap();
+                        } else if line.starts_with("stats") {
+                            (&stream).write_all(b"END\r\n").unwrap();
+                        } else if line.starts_with("set") {
+                            reader.read_line(&mut line).unwrap();
+                            if !timed_out.swap(true, Ordering::SeqCst) {
+                                silent = true;
+                            } else {
+                                (&stream).write_all(b"STORED\r\n").unwrap();
+                            }
+                        }
+                        line.clear();
+                    }
+                });
+            }
+        });
+
+        let policy = RetryPolicy::new(2)
+            .with_commands(&[Command::Set])
+            .with_backoff(Duration::from_millis(1), Duration::from_millis(1));
+        let client = super::Client::builder()
+            .add_server(format!("memcache://127.0.0.1:{}?protocol=ascii", port))
+            .with_pool_size(1)
+            .with_retry_policy(policy)
+            .build()
+            .unwrap();
+        client.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+        client.set("foo", "bar", 0).unwrap();
+        assert!(!reused.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn time_left() {
+        use std::time::{Duration, Instant};
+        let left = super::time_left(Instant::now() + Duration::from_secs(1)).unwrap();
+        assert!(left > Duration::from_millis(500) && left <= Duration::from_secs(1));
+        assert!(super::time_left(Instant::now()).is_err());
     }
-}
 
-#[cfg(test)]
-mod tests {
     #[cfg(unix)]
     #[test]
     fn unix() {
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex};
+
+use crate::error::MemcacheError;
+
+/// Shares the result of a fetch with the callers asking for the same key while it is in flight,
+/// see `ClientBuilder::with_get_coalescing`.
+#[derive(Default)]
+pub(crate) struct Coalescer<T> {
+    flights: Mutex<HashMap<String, Arc<Flight<T>>>>,
+}
+
+struct Flight<T> {
+    state: Mutex<State<T>>,
+    landed: Condvar,
+}
+
+enum State<T> {
+    Pending,
+    Done(T),
+    /// The fetch failed or panicked, the waiting callers fetch on their own.
+    Failed,
+}
+
+/// Ends the flight of the leading caller, even if its fetch panicked, so the waiting callers don't
+/// wait forever.
+struct Landing<'a, T> {
+    flights: &'a Mutex<HashMap<String, Arc<Flight<T>>>>,
+    key: &'a str,
+    flight: Arc<Flight<T>>,
+    grounded: bool,
+}
+
+impl<T> Landing<'_, T> {
+    /// Remove the flight, so later callers start a new one, and return whether callers are waiting.
+    fn ground(&mut self) -> bool {
+        if !self.grounded {
+            self.flights.lock().unwrap().remove(self.key);
+            self.grounded = true;
+        }
+        Arc::strong_count(&self.flight) > 1
+    }
+
+    fn land(mut self, state: State<T>) {
+        self.ground();
+        *self.flight.state.lock().unwrap() = state;
+        self.flight.landed.notify_all();
+    }
+}
+
+impl<T> Drop for Landing<'_, T> {
+    fn drop(&mut self) {
+        self.ground();
+        let mut state = self.flight.state.lock().unwrap();
+        if let State::Pending = *state {
+            *state = State::Failed;
+        }
+        self.flight.landed.notify_all();
+    }
+}
+
+impl<T: Clone> Coalescer<T> {
+    /// Run `fetch` for `key`, or wait for the fetch of the same key already in flight and return
+    /// a copy of its result.
+    pub(crate) fn fetch<F>(&self, key: &str, fetch: F) -> Result<T, MemcacheError>
+    where
+        F: FnOnce() -> Result<T, MemcacheError>,
+    {
+        let (flight, leading) = {
+            let mut flights = self.flights.lock().unwrap();
+            match flights.get(key) {
+                Some(flight) => (flight.clone(), false),
+                None => {
+                    let flight = Arc::new(Flight {
+                        state: Mutex::new(State::Pending),
+                        landed: Condvar::new(),
+                    });
+                    flights.insert(key.to_string(), flight.clone());
+                    (flight, true)
+                }
+            }
+        };
+        if !leading {
+            let mut state = flight.state.lock().unwrap();
+            while let State::Pending = *state {
+                state = flight.landed.wait(state).unwrap();
+            }
+            if let State::Done(ref value) = *state {
+                return Ok(value.clone());
+            }
+            drop(state);
+            return fetch();
+        }
+
+        let mut landing = Landing {
+            flights: &self.flights,
+            key,
+            flight,
+            grounded: false,
+        };
+        let result = fetch();
+        // only copy the value if other callers are waiting for it
+        let state = match result {
+            Ok(ref value) if landing.ground() => State::Done(value.clone()),
+            _ => State::Failed,
+        };
+        landing.land(state);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Coalescer;
+    use crate::error::{ClientError, MemcacheError};
+    use std::borrow::Cow;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_coalesce() {
+        let coalescer = Arc::new(Coalescer::default());
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(4));
+        let callers: Vec<_> = (0..4)
+            .map(|_| {
+                let (coalescer, fetches, barrier) = (coalescer.clone(), fetches.clone(), barrier.clone());
+                thread::spawn(move || {
+                    barrier.wait();
+                    coalescer.fetch("foo", || {
+                        fetches.fetch_add(1, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(200));
+                        Ok(42)
+                    })
+                })
+            })
+            .collect();
+        for caller in callers {
+            assert_eq!(caller.join().unwrap().unwrap(), 42);
+        }
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+        // a new fetch is started once the previous one landed
+        assert_eq!(coalescer.fetch("foo", || Ok(43)).unwrap(), 43);
+    }
+
+    #[test]
+    fn test_failed_fetch() {
+        let coalescer: Coalescer<u32> = Coalescer::default();
+        let error = coalescer.fetch("foo", || -> Result<u32, MemcacheError> {
+            Err(ClientError::Error(Cow::Borrowed("failed")).into())
+        });
+        assert!(error.is_err());
+        assert_eq!(coalescer.fetch("foo", || Ok(1)).unwrap(), 1);
+    }
+}
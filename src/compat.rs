@@ -0,0 +1,391 @@
+use std::borrow::Cow;
+
+use crate::error::{ClientError, MemcacheError};
+use crate::value::{Transcoder, ValueKind};
+
+/// The python clients whose flags `PythonCompat` can follow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PythonClient {
+    PythonMemcached,
+    Pylibmc,
+}
+
+const PYTHON_FLAG_PICKLE: u32 = 1 << 0;
+const PYTHON_FLAG_INTEGER: u32 = 1 << 1;
+const PYTHON_FLAG_LONG: u32 = 1 << 2;
+const PYTHON_FLAG_COMPRESSED: u32 = 1 << 3;
+
+/// A `Transcoder` which follows the flags of python-memcached or pylibmc, so values can be
+/// shared with python applications: strings are read as `str`, integers and booleans as `int`
+/// and `bool`, and `str`, `int` and `bool` values written by python can be fetched as `String`,
+/// integers and `bool`. Floats are stored as strings, as python pickles them.
+///
+/// Pickled values can't be decoded and fail with a `ClientError`. Compressed values need a zlib
+/// `Compression` added after this transcoder, which uses the same flag by default.
+///
+/// Example:
+///
+/// ```rust
+/// let client = memcache::Client::builder()
+///     .add_server("memcache://localhost:12345")
+///     .with_transcoder(memcache::PythonCompat::pylibmc())
+///     .build()
+///     .unwrap();
+/// client.set("shared", 42, 0).unwrap();
+/// let value: (Vec<u8>, u32) = memcache::Client::connect("memcache://localhost:12345")
+///     .unwrap()
+///     .get("shared")
+///     .unwrap()
+///     .unwrap();
+/// assert_eq!(value, (b"42".to_vec(), 2));
+/// ```
+#[derive(Debug, Clone)]
+pub struct PythonCompat {
+    client: PythonClient,
+}
+
+impl PythonCompat {
+    pub fn new(client: PythonClient) -> Self {
+        PythonCompat { client }
+    }
+
+    /// Follow the flags of python-memcached.
+    pub fn python_memcached() -> Self {
+        Self::new(PythonClient::PythonMemcached)
+    }
+
+    /// Follow the flags of pylibmc.
+    pub fn pylibmc() -> Self {
+        Self::new(PythonClient::Pylibmc)
+    }
+
+    fn text_flag(&self) -> u32 {
+        match self.client {
+            PythonClient::PythonMemcached => 1 << 4,
+            PythonClient::Pylibmc => 1 << 5,
+        }
+    }
+
+    fn bool_flag(&self) -> Option<u32> {
+        match self.client {
+            // python-memcached stores booleans like any other int
+            PythonClient::PythonMemcached => None,
+            PythonClient::Pylibmc => Some(1 << 4),
+        }
+    }
+}
+
+impl Transcoder for PythonCompat {
+    fn encode(&self, value: Vec<u8>, flags: u32) -> Result<(Vec<u8>, u32), MemcacheError> {
+        Ok((value, flags))
+    }
+
+    fn encode_kind(&self, value: Vec<u8>, flags: u32, kind: ValueKind) -> Result<(Vec<u8>, u32), MemcacheError> {
+        match kind {
+            ValueKind::Bytes => Ok((value, flags)),
+            ValueKind::Text | ValueKind::Float(_) => Ok((value, flags | self.text_flag())),
+            ValueKind::Integer(_) => Ok((value, flags | PYTHON_FLAG_INTEGER)),
+            ValueKind::Bool => {
+                let value = if value == b"true" { b"1".to_vec() } else { b"0".to_vec() };
+                Ok((value, flags | self.bool_flag().unwrap_or(PYTHON_FLAG_INTEGER)))
+            }
+        }
+    }
+
+    fn decode(&self, value: Vec<u8>, flags: u32) -> Result<(Vec<u8>, u32), MemcacheError> {
+        if flags & PYTHON_FLAG_PICKLE != 0 {
+            return Err(ClientError::Error(Cow::Borrowed("pickled python values can't be decoded")).into());
+        }
+        if flags & PYTHON_FLAG_COMPRESSED != 0 {
+            return Err(ClientError::Error(Cow::Borrowed(
+                "compressed python values need a zlib `Compression` transcoder",
+            ))
+            .into());
+        }
+        if let Some(bool_flag) = self.bool_flag() {
+            if flags & bool_flag != 0 {
+                let value = if value == b"1" {
+                    b"true".to_vec()
+                } else {
+                    b"false".to_vec()
+                };
+                return Ok((value, flags & !bool_flag));
+            }
+        }
+        Ok((
+            value,
+            flags & !(PYTHON_FLAG_INTEGER | PYTHON_FLAG_LONG | self.text_flag()),
+        ))
+    }
+
+    /// The compressed flag is left to the zlib `Compression` transcoder.
+    fn reserved_flags(&self) -> u32 {
+        PYTHON_FLAG_PICKLE | PYTHON_FLAG_INTEGER | PYTHON_FLAG_LONG | self.text_flag() | self.bool_flag().unwrap_or(0)
+    }
+}
+
+const JAVA_FLAG_SERIALIZED: u32 = 1;
+const JAVA_FLAG_COMPRESSED: u32 = 2;
+const JAVA_SPECIAL_MASK: u32 = 0xff00;
+const JAVA_SPECIAL_BOOLEAN: u32 = 1 << 8;
+const JAVA_SPECIAL_INT: u32 = 2 << 8;
+const JAVA_SPECIAL_LONG: u32 = 3 << 8;
+const JAVA_SPECIAL_DATE: u32 = 4 << 8;
+const JAVA_SPECIAL_BYTE: u32 = 5 << 8;
+const JAVA_SPECIAL_FLOAT: u32 = 6 << 8;
+const JAVA_SPECIAL_DOUBLE: u32 = 7 << 8;
+const JAVA_SPECIAL_BYTEARRAY: u32 = 8 << 8;
+
+/// A `Transcoder` which follows the flags of spymemcached's `SerializingTranscoder`, which are
+/// also used by xmemcached, so values can be shared with JVM applications. Strings are stored
+/// as is, byte arrays and primitives are marked with their java type, and primitives are stored
+/// as big endian binary numbers like java does. Unsigned numbers which don't fit in the java type
+/// of their size are stored as the next larger one, and as strings if they don't fit in a `long`.
+///
+/// Serialized java objects can't be decoded and fail with a `ClientError`. Compressed values
+/// need a gzip `Compression` added after this transcoder, which uses the same flag by default.
+///
+/// Example:
+///
+/// ```rust
+/// let client = memcache::Client::builder()
+///     .add_server("memcache://localhost:12345")
+///     .with_transcoder(memcache::JavaCompat::spymemcached())
+///     .build()
+///     .unwrap();
+/// client.set("shared", 42i64, 0).unwrap();
+/// let value: i64 = client.get("shared").unwrap().unwrap();
+/// assert_eq!(value, 42);
+/// ```
+#[derive(Debug, Clone)]
+pub struct JavaCompat {
+    primitives: bool,
+}
+
+impl JavaCompat {
+    /// Follow the flags of spymemcached and xmemcached.
+    pub fn spymemcached() -> Self {
+        JavaCompat { primitives: true }
+    }
+
+    /// Set whether numbers and booleans are stored in java's binary representation, which is
+    /// the default. Otherwise they are stored as strings, which java reads as `String`, like
+    /// with the `StringTranscoder` of xmemcached.
+    pub fn with_primitives(mut self, primitives: bool) -> Self {
+        self.primitives = primitives;
+        self
+    }
+
+    fn encode_primitive(value: &[u8], kind: ValueKind) -> Result<Option<(Vec<u8>, u32)>, MemcacheError> {
+        let text = std::str::from_utf8(value)?;
+        let encoded = match kind {
+            ValueKind::Bool => (vec![if text == "true" { b'1' } else { b'0' }], JAVA_SPECIAL_BOOLEAN),
+            // java has no 128 bit primitive, these are stored as strings
+            ValueKind::Integer(16) => return Ok(None),
+            ValueKind::Integer(size) => {
+                // unsigned 64 bit values above i64::MAX don't fit in any java primitive
+                let number = match text.parse::<i64>() {
+                    Ok(number) => number,
+                    Err(_) => return Ok(None),
+                };
+                if size == 1 && number >= i8::MIN as i64 && number <= i8::MAX as i64 {
+                    (vec![number as i8 as u8], JAVA_SPECIAL_BYTE)
+                } else if size <= 4 && number >= i32::MIN as i64 && number <= i32::MAX as i64 {
+                    (encode_java_number(number as i32 as u32 as u64, 4), JAVA_SPECIAL_INT)
+                } else {
+                    (encode_java_number(number as u64, 8), JAVA_SPECIAL_LONG)
+                }
+            }
+            ValueKind::Float(4) => (
+                encode_java_number(text.parse::<f32>()?.to_bits() as u64, 4),
+                JAVA_SPECIAL_FLOAT,
+            ),
+            ValueKind::Float(_) => (
+                encode_java_number(text.parse::<f64>()?.to_bits(), 8),
+                JAVA_SPECIAL_DOUBLE,
+            ),
+            ValueKind::Bytes | ValueKind::Text => return Ok(None),
+        };
+        Ok(Some(encoded))
+    }
+
+    fn decode_primitive(value: &[u8], special: u32) -> Result<Option<Vec<u8>>, MemcacheError> {
+        // the strings don't have a special flag and may be longer than any number
+        if !(JAVA_SPECIAL_BOOLEAN..=JAVA_SPECIAL_DOUBLE).contains(&special) {
+            return Ok(None);
+        }
+        let number = decode_java_number(value)?;
+        let text = match special {
+            JAVA_SPECIAL_BOOLEAN => (value == b"1").to_string(),
+            JAVA_SPECIAL_BYTE => (number as i8).to_string(),
+            JAVA_SPECIAL_INT => (number as u32 as i32).to_string(),
+            // dates are the milliseconds since the epoch
+            JAVA_SPECIAL_LONG | JAVA_SPECIAL_DATE => (number as i64).to_string(),
+            JAVA_SPECIAL_FLOAT => f32::from_bits(number as u32).to_string(),
+            JAVA_SPECIAL_DOUBLE => f64::from_bits(number).to_string(),
+            _ => return Ok(None),
+        };
+        Ok(Some(text.into_bytes()))
+    }
+}
+
+/// Encode a number as big endian bytes without leading zeros, like spymemcached does.
+fn encode_java_number(number: u64, size: usize) -> Vec<u8> {
+    let bytes = number.to_be_bytes();
+    let bytes = &bytes[8 - size..];
+    let leading_zeros = bytes.iter().take_while(|b| **b == 0).count();
+    bytes[leading_zeros..].to_vec()
+}
+
+fn decode_java_number(value: &[u8]) -> Result<u64, MemcacheError> {
+    if value.len() > 8 {
+        return Err(ClientError::Error(Cow::Borrowed("java number with more than 8 bytes")).into());
+    }
+    Ok(value.iter().fold(0, |number, b| (number << 8) | *b as u64))
+}
+
+impl Transcoder for JavaCompat {
+    fn encode(&self, value: Vec<u8>, flags: u32) -> Result<(Vec<u8>, u32), MemcacheError> {
+        Ok((value, flags))
+    }
+
+    fn encode_kind(&self, value: Vec<u8>, flags: u32, kind: ValueKind) -> Result<(Vec<u8>, u32), MemcacheError> {
+        if kind == ValueKind::Bytes {
+            return Ok((value, flags | JAVA_SPECIAL_BYTEARRAY));
+        }
+        if !self.primitives {
+            return Ok((value, flags));
+        }
+        match Self::encode_primitive(&value, kind)? {
+            Some((value, special)) => Ok((value, flags | special)),
+            None => Ok((value, flags)),
+        }
+    }
+
+    fn decode(&self, value: Vec<u8>, flags: u32) -> Result<(Vec<u8>, u32), MemcacheError> {
+        if flags & JAVA_FLAG_SERIALIZED != 0 {
+            return Err(ClientError::Error(Cow::Borrowed("serialized java objects can't be decoded")).into());
+        }
+        if flags & JAVA_FLAG_COMPRESSED != 0 {
+            return Err(ClientError::Error(Cow::Borrowed(
+                "compressed java values need a gzip `Compression` transcoder",
+            ))
+            .into());
+        }
+        let special = flags & JAVA_SPECIAL_MASK;
+        if special == JAVA_SPECIAL_BYTEARRAY {
+            return Ok((value, flags & !JAVA_SPECIAL_MASK));
+        }
+        match Self::decode_primitive(&value, special)? {
+            Some(value) => Ok((value, flags & !JAVA_SPECIAL_MASK)),
+            None => Ok((value, flags)),
+        }
+    }
+
+    /// The compressed flag is left to the gzip `Compression` transcoder.
+    fn reserved_flags(&self) -> u32 {
+        JAVA_FLAG_SERIALIZED | JAVA_SPECIAL_MASK
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{JavaCompat, PythonCompat};
+    use crate::value::{Transcoder, ValueKind};
+
+    #[test]
+    fn test_python_memcached() {
+        let compat = PythonCompat::python_memcached();
+        assert_eq!(
+            compat.encode_kind(b"foo".to_vec(), 0, ValueKind::Text).unwrap(),
+            (b"foo".to_vec(), 16)
+        );
+        assert_eq!(
+            compat.encode_kind(b"42".to_vec(), 0, ValueKind::Integer(8)).unwrap(),
+            (b"42".to_vec(), 2)
+        );
+        assert_eq!(
+            compat.encode_kind(b"true".to_vec(), 0, ValueKind::Bool).unwrap(),
+            (b"1".to_vec(), 2)
+        );
+        assert_eq!(
+            compat.encode_kind(vec![0xff], 0, ValueKind::Bytes).unwrap(),
+            (vec![0xff], 0)
+        );
+
+        assert_eq!(compat.decode(b"foo".to_vec(), 16).unwrap(), (b"foo".to_vec(), 0));
+        assert_eq!(compat.decode(b"42".to_vec(), 2).unwrap(), (b"42".to_vec(), 0));
+        assert_eq!(compat.decode(b"42".to_vec(), 4).unwrap(), (b"42".to_vec(), 0));
+        assert!(compat.decode(b"\x80\x04".to_vec(), 1).is_err());
+        assert!(compat.decode(b"x\x9c".to_vec(), 8 | 16).is_err());
+    }
+
+    #[test]
+    fn test_pylibmc() {
+        let compat = PythonCompat::pylibmc();
+        assert_eq!(
+            compat.encode_kind(b"foo".to_vec(), 0, ValueKind::Text).unwrap(),
+            (b"foo".to_vec(), 32)
+        );
+        assert_eq!(
+            compat.encode_kind(b"false".to_vec(), 0, ValueKind::Bool).unwrap(),
+            (b"0".to_vec(), 16)
+        );
+        assert_eq!(compat.decode(b"1".to_vec(), 16).unwrap(), (b"true".to_vec(), 0));
+        assert_eq!(compat.decode(b"foo".to_vec(), 32).unwrap(), (b"foo".to_vec(), 0));
+    }
+
+    #[test]
+    fn test_java() {
+        let compat = JavaCompat::spymemcached();
+        let cases: &[(&[u8], ValueKind, &[u8], u32)] = &[
+            (b"foo", ValueKind::Text, b"foo", 0),
+            (b"\xff", ValueKind::Bytes, b"\xff", 0x800),
+            (b"true", ValueKind::Bool, b"1", 0x100),
+            (b"42", ValueKind::Integer(4), b"\x2a", 0x200),
+            (b"-1", ValueKind::Integer(4), b"\xff\xff\xff\xff", 0x200),
+            (b"0", ValueKind::Integer(8), b"", 0x300),
+            (b"4294967296", ValueKind::Integer(8), b"\x01\x00\x00\x00\x00", 0x300),
+            (b"-2", ValueKind::Integer(1), b"\xfe", 0x500),
+            (b"1.5", ValueKind::Float(4), b"\x3f\xc0\x00\x00", 0x600),
+            (b"1.5", ValueKind::Float(8), b"\x3f\xf8\x00\x00\x00\x00\x00\x00", 0x700),
+        ];
+        for (value, kind, encoded, flags) in cases {
+            assert_eq!(
+                compat.encode_kind(value.to_vec(), 0, *kind).unwrap(),
+                (encoded.to_vec(), *flags)
+            );
+            assert_eq!(compat.decode(encoded.to_vec(), *flags).unwrap(), (value.to_vec(), 0));
+        }
+
+        // u32 values which don't fit in a java int are stored as long
+        assert_eq!(
+            compat
+                .encode_kind(b"4294967295".to_vec(), 0, ValueKind::Integer(4))
+                .unwrap(),
+            (b"\xff\xff\xff\xff".to_vec(), 0x300)
+        );
+        assert!(compat.decode(b"\xac\xed".to_vec(), 1).is_err());
+
+        // the unsigned numbers round trip, the ones which don't fit in a java long as strings
+        let cases: &[(&[u8], ValueKind, u32)] = &[
+            (b"127", ValueKind::Integer(1), 0x500),
+            (b"-128", ValueKind::Integer(1), 0x500),
+            (b"128", ValueKind::Integer(1), 0x200),
+            (b"255", ValueKind::Integer(1), 0x200),
+            (b"9223372036854775807", ValueKind::Integer(8), 0x300),
+            (b"18446744073709551615", ValueKind::Integer(8), 0),
+        ];
+        for (value, kind, flags) in cases {
+            let (encoded, encoded_flags) = compat.encode_kind(value.to_vec(), 0, *kind).unwrap();
+            assert_eq!(encoded_flags, *flags);
+            assert_eq!(compat.decode(encoded, encoded_flags).unwrap(), (value.to_vec(), 0));
+        }
+
+        let compat = JavaCompat::spymemcached().with_primitives(false);
+        assert_eq!(
+            compat.encode_kind(b"42".to_vec(), 0, ValueKind::Integer(4)).unwrap(),
+            (b"42".to_vec(), 0)
+        );
+    }
+}
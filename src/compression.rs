@@ -0,0 +1,261 @@
+use std::io::{Read, Write};
+
+use flate2::read::{GzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+
+use crate::error::MemcacheError;
+use crate::value::Transcoder;
+
+/// The compression formats supported by `Compression`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionAlgorithm {
+    /// zlib, as used by python-memcached and pylibmc.
+    Zlib,
+    /// gzip, as used by spymemcached.
+    Gzip,
+    /// zstd, which compresses faster and better than zlib, but isn't understood by other
+    /// clients. Only available with the `zstd` feature.
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+/// A `Transcoder` which compresses values above a size threshold, and marks them with a flag bit
+/// so they are decompressed transparently when fetched. Only available with the `compression`
+/// feature.
+///
+/// The defaults follow the flag convention of other clients sharing the cache: `zlib()` marks
+/// compressed values with the flag `0x8` like python-memcached and pylibmc, `gzip()` with the
+/// flag `0x2` like spymemcached. `zstd()` uses the flag `0x40`, which isn't used by these clients.
+/// The threshold, level and flag can be changed to match the settings of other clients.
+///
+/// Example:
+///
+/// ```rust
+/// # #[cfg(feature = "compression")]
+/// # {
+/// let client = memcache::Client::builder()
+///     .add_server("memcache://localhost:12345")
+///     .with_transcoder(memcache::Compression::zlib().with_threshold(512).with_level(9))
+///     .build()
+///     .unwrap();
+/// let value = "foo".repeat(1000);
+/// client.set("compressed", value.as_str(), 0).unwrap();
+/// let fetched: String = client.get("compressed").unwrap().unwrap();
+/// assert_eq!(fetched, value);
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Compression {
+    algorithm: CompressionAlgorithm,
+    threshold: usize,
+    level: Option<i32>,
+    flag: u32,
+}
+
+impl Compression {
+    /// Values shorter than this are stored as is.
+    const DEFAULT_THRESHOLD: usize = 1024;
+
+    /// Compress values with `algorithm`, using the flag convention of its common users.
+    pub fn new(algorithm: CompressionAlgorithm) -> Self {
+        let flag = match algorithm {
+            CompressionAlgorithm::Zlib => 0x8,
+            CompressionAlgorithm::Gzip => 0x2,
+            #[cfg(feature = "zstd")]
+            CompressionAlgorithm::Zstd => 0x40,
+        };
+        Compression {
+            algorithm,
+            threshold: Self::DEFAULT_THRESHOLD,
+            level: None,
+            flag,
+        }
+    }
+
+    /// Compress values with zlib, compatible with python-memcached and pylibmc.
+    pub fn zlib() -> Self {
+        Self::new(CompressionAlgorithm::Zlib)
+    }
+
+    /// Compress values with gzip, compatible with spymemcached.
+    pub fn gzip() -> Self {
+        Self::new(CompressionAlgorithm::Gzip)
+    }
+
+    /// Compress values with zstd. Only available with the `zstd` feature.
+    #[cfg(feature = "zstd")]
+    pub fn zstd() -> Self {
+        Self::new(CompressionAlgorithm::Zstd)
+    }
+
+    /// Set the compression level, from 0 to 9 for zlib and gzip, and from 1 to 22 for zstd, with
+    /// negative levels trading ratio for even more speed. Higher levels compress better, but
+    /// slower. Defaults to the default level of the algorithm: 6 for zlib and gzip, 3 for zstd.
+    pub fn with_level(mut self, level: i32) -> Self {
+        self.level = Some(level);
+        self
+    }
+
+    /// Set the minimum size in bytes of values to compress, smaller values are stored as is.
+    /// Defaults to 1024.
+    pub fn with_threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Set the flag bit which marks compressed values, to match the convention of other clients
+    /// writing to the same servers.
+    ///
+    /// Panics if `flag` doesn't have exactly one bit set.
+    pub fn with_flag(mut self, flag: u32) -> Self {
+        assert_eq!(flag.count_ones(), 1, "the compression flag must be a single bit");
+        self.flag = flag;
+        self
+    }
+
+    fn flate2_level(&self) -> flate2::Compression {
+        match self.level {
+            Some(level) => flate2::Compression::new(level.clamp(0, 9) as u32),
+            None => flate2::Compression::default(),
+        }
+    }
+
+    fn compress(&self, value: &[u8]) -> Result<Vec<u8>, MemcacheError> {
+        let compressed = match self.algorithm {
+            CompressionAlgorithm::Zlib => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), self.flate2_level());
+                encoder.write_all(value)?;
+                encoder.finish()?
+            }
+            CompressionAlgorithm::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), self.flate2_level());
+                encoder.write_all(value)?;
+                encoder.finish()?
+            }
+            #[cfg(feature = "zstd")]
+            CompressionAlgorithm::Zstd => {
+                zstd::bulk::compress(value, self.level.unwrap_or(zstd::DEFAULT_COMPRESSION_LEVEL))?
+            }
+        };
+        Ok(compressed)
+    }
+
+    fn decompress(&self, value: &[u8]) -> Result<Vec<u8>, MemcacheError> {
+        let mut decompressed = Vec::with_capacity(value.len() * 2);
+        match self.algorithm {
+            CompressionAlgorithm::Zlib => ZlibDecoder::new(value).read_to_end(&mut decompressed)?,
+            CompressionAlgorithm::Gzip => GzDecoder::new(value).read_to_end(&mut decompressed)?,
+            #[cfg(feature = "zstd")]
+            CompressionAlgorithm::Zstd => zstd::stream::read::Decoder::new(value)?.read_to_end(&mut decompressed)?,
+        };
+        Ok(decompressed)
+    }
+}
+
+impl Transcoder for Compression {
+    fn encode(&self, value: Vec<u8>, flags: u32) -> Result<(Vec<u8>, u32), MemcacheError> {
+        if value.len() < self.threshold {
+            return Ok((value, flags));
+        }
+        let compressed = self.compress(&value)?;
+        // incompressible values are stored as is to save the decompression on every fetch
+        if compressed.len() >= value.len() {
+            return Ok((value, flags));
+        }
+        Ok((compressed, flags | self.flag))
+    }
+
+    fn decode(&self, value: Vec<u8>, flags: u32) -> Result<(Vec<u8>, u32), MemcacheError> {
+        if flags & self.flag == 0 {
+            return Ok((value, flags));
+        }
+        Ok((self.decompress(&value)?, flags & !self.flag))
+    }
+
+    fn reserved_flags(&self) -> u32 {
+        self.flag
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Compression;
+    use crate::value::Transcoder;
+
+    #[test]
+    fn test_roundtrip() {
+        for compression in &[Compression::zlib(), Compression::gzip()] {
+            let value = b"foo".repeat(1000);
+            let (encoded, flags) = compression.encode(value.clone(), 0x100).unwrap();
+            assert!(encoded.len() < value.len());
+            assert_eq!(flags, 0x100 | compression.flag);
+            let (decoded, flags) = compression.decode(encoded, flags).unwrap();
+            assert_eq!(decoded, value);
+            assert_eq!(flags, 0x100);
+        }
+    }
+
+    #[test]
+    fn test_level() {
+        let value = b"foo bar baz ".repeat(1000);
+        let (fast, _) = Compression::zlib().with_level(1).encode(value.clone(), 0).unwrap();
+        let (best, _) = Compression::zlib().with_level(9).encode(value.clone(), 0).unwrap();
+        assert!(best.len() <= fast.len());
+        // out of range levels are clamped for zlib and gzip
+        let (encoded, flags) = Compression::gzip().with_level(42).encode(value.clone(), 0).unwrap();
+        assert_eq!(Compression::gzip().decode(encoded, flags).unwrap(), (value, 0));
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd() {
+        let value = b"foo".repeat(1000);
+        for compression in &[Compression::zstd(), Compression::zstd().with_level(19)] {
+            let (encoded, flags) = compression.encode(value.clone(), 0).unwrap();
+            assert!(encoded.len() < value.len());
+            assert_eq!(flags, 0x40);
+            assert_eq!(compression.decode(encoded, flags).unwrap(), (value.clone(), 0));
+        }
+    }
+
+    #[test]
+    fn test_threshold() {
+        let compression = Compression::zlib();
+        let value = b"foo".to_vec();
+        assert_eq!(compression.encode(value.clone(), 0).unwrap(), (value.clone(), 0));
+        assert_eq!(compression.decode(value.clone(), 0).unwrap(), (value, 0));
+    }
+
+    #[test]
+    fn test_configuration() {
+        let compression = Compression::zlib().with_threshold(16).with_flag(0x1000);
+        let value = b"foo".repeat(10);
+        let (encoded, flags) = compression.encode(value.clone(), 0x8).unwrap();
+        assert_eq!(flags, 0x1008);
+        assert_eq!(compression.decode(encoded, flags).unwrap(), (value, 0x8));
+        // values flagged with the default bit are left alone
+        assert_eq!(
+            compression.decode(b"foo".to_vec(), 0x8).unwrap(),
+            (b"foo".to_vec(), 0x8)
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_invalid_flag() {
+        Compression::zlib().with_flag(0x3);
+    }
+
+    #[test]
+    fn test_incompressible() {
+        let compression = Compression::zlib();
+        let value: Vec<u8> = (0..4096).map(|_| rand::random()).collect();
+        assert_eq!(compression.encode(value.clone(), 0).unwrap(), (value, 0));
+    }
+
+    #[test]
+    fn test_corrupted() {
+        let compression = Compression::gzip();
+        assert!(compression.decode(b"not gzip".to_vec(), 0x2).is_err());
+    }
+}
@@ -1,16 +1,22 @@
-use std::net::TcpStream;
+use std::borrow::Cow;
+use std::net::{SocketAddr, TcpStream};
 use std::ops::{Deref, DerefMut};
 #[cfg(unix)]
 use std::os::unix::net::UnixStream;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use url::Url;
 
-use crate::error::MemcacheError;
+use crate::error::{ClientError, CommandError, ConnectPhase, ConnectionError, MemcacheError};
 
-use crate::protocol::{AsciiProtocol, BinaryProtocol, Protocol, ProtocolTrait};
-use crate::stream::Stream;
-use crate::stream::UdpStream;
+use crate::protocol::{
+    AsciiProtocol, BinaryProtocol, BinaryRequest, BinaryResponse, Protocol, ProtocolTrait, StoredValue,
+};
+use crate::scan::CachedumpItem;
+use crate::stream::socket::{self, Keepalive};
+use crate::stream::{buffered, Stream, DEFAULT_BUFFER_SIZE};
+use crate::stream::{UdpOptions, UdpStream};
+use crate::watch::{Watch, WatchFilter};
 #[cfg(feature = "tls")]
 use openssl::ssl::{SslConnector, SslFiletype, SslMethod, SslVerifyMode};
 use r2d2::ManageConnection;
@@ -19,6 +25,65 @@ use r2d2::ManageConnection;
 pub struct Connection {
     pub protocol: Protocol,
     pub url: Arc<String>,
+    server_version: Option<ServerVersion>,
+    item_size_max: Option<usize>,
+    watching: bool,
+    broken: bool,
+}
+
+/// The version of a memcached server, as reported by the `version` command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ServerVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl ServerVersion {
+    /// Parse a version string like `1.6.9`, ignoring any suffix such as `1.4.24-tags`.
+    pub fn parse(version: &str) -> Option<Self> {
+        let mut parts = version.trim().splitn(3, '.').map(|part| {
+            let digits = part.find(|c: char| !c.is_ascii_digit()).unwrap_or(part.len());
+            part[..digits].parse::<u32>().ok()
+        });
+        let major = parts.next()??;
+        let minor = parts.next()??;
+        let patch = parts.next().unwrap_or(Some(0))?;
+        Some(ServerVersion { major, minor, patch })
+    }
+}
+
+/// Server side features which are only available since a specific memcached version.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Feature {
+    Touch,
+    FlushWithDelay,
+    MetaCommands,
+    Metadump,
+    Watch,
+}
+
+impl Feature {
+    fn command(self) -> &'static str {
+        match self {
+            Feature::Touch => "touch",
+            Feature::FlushWithDelay => "flush_all with delay",
+            Feature::MetaCommands => "meta",
+            Feature::Metadump => "lru_crawler metadump",
+            Feature::Watch => "watch",
+        }
+    }
+
+    fn min_version(self) -> ServerVersion {
+        let (major, minor, patch) = match self {
+            Feature::Touch => (1, 4, 8),
+            Feature::FlushWithDelay => (1, 4, 0),
+            Feature::MetaCommands => (1, 6, 0),
+            Feature::Metadump => (1, 4, 31),
+            Feature::Watch => (1, 5, 0),
+        };
+        ServerVersion { major, minor, patch }
+    }
 }
 
 impl DerefMut for Connection {
@@ -34,44 +99,198 @@ impl Deref for Connection {
     }
 }
 
-pub(crate) struct ConnectionManager {
+/// Supplies the credentials used to authenticate new connections. It is called every time a
+/// connection is (re)established, so short-lived credentials like ElastiCache IAM auth tokens
+/// can be refreshed without recreating the client.
+///
+/// It is implemented for closures taking the server url.
+pub trait AuthProvider: Send + Sync {
+    /// Return the username and password for the server with the given url.
+    fn credentials(&self, url: &str) -> Result<(String, String), MemcacheError>;
+}
+
+impl<F> AuthProvider for F
+where
+    F: Fn(&str) -> Result<(String, String), MemcacheError> + Send + Sync,
+{
+    fn credentials(&self, url: &str) -> Result<(String, String), MemcacheError> {
+        self(url)
+    }
+}
+
+/// Connection settings configured through `ClientBuilder`, which take precedence over the
+/// query parameters of the server urls.
+#[derive(Clone, Default)]
+pub(crate) struct ConnectionOptions {
+    pub(crate) sasl_mechanism: Option<String>,
+    pub(crate) auth_provider: Option<Arc<dyn AuthProvider>>,
+    pub(crate) read_buffer_size: Option<usize>,
+    pub(crate) write_buffer_size: Option<usize>,
+    pub(crate) recv_buffer_size: Option<usize>,
+    pub(crate) send_buffer_size: Option<usize>,
+    pub(crate) keepalive: Option<Keepalive>,
+    pub(crate) user_timeout: Option<Duration>,
+    pub(crate) udp_retransmission: Option<(Duration, u32)>,
+    pub(crate) udp_max_datagram_size: Option<usize>,
+    pub(crate) udp_tcp_fallback: bool,
+}
+
+/// Manages the connections of a single memcached server for the connection pool.
+pub struct ConnectionManager {
     url: Url,
+    options: ConnectionOptions,
+    last_error: LastConnectError,
+    probed: Mutex<Option<Probed>>,
+}
+
+/// What the first connection to a server detected, reused by the next connections to skip the
+/// probes.
+#[derive(Clone, Copy)]
+struct Probed {
+    server_version: Option<ServerVersion>,
+    item_size_max: Option<usize>,
 }
 
 impl ConnectionManager {
-    pub(crate) fn new(url: Url) -> Self {
-        Self { url }
+    pub(crate) fn new(url: Url, options: ConnectionOptions) -> Self {
+        Self {
+            url,
+            options,
+            last_error: LastConnectError::default(),
+            probed: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn last_error(&self) -> LastConnectError {
+        self.last_error.clone()
+    }
+
+    /// Connect to the server and authenticate.
+    fn open(&self) -> Result<Connection, MemcacheError> {
+        let url = &self.url;
+        let mut connection = Connection::connect(url, &self.options)?;
+        in_phase(ConnectPhase::Auth, url, || {
+            let credentials = match self.options.auth_provider {
+                Some(ref provider) => Some(provider.credentials(url.as_str())?),
+                None if url.has_authority() && !url.username().is_empty() && url.password().is_some() => {
+                    Some((url.username().to_string(), url.password().unwrap().to_string()))
+                }
+                None => None,
+            };
+            if let Some((ref username, ref password)) = credentials {
+                match connection.protocol {
+                    Protocol::Binary(ref mut protocol) => {
+                        let mechanism = self
+                            .options
+                            .sasl_mechanism
+                            .clone()
+                            .or_else(|| get_param(url, "sasl_mech"));
+                        protocol.auth_with_mechanism(username, password, mechanism.as_deref())?
+                    }
+                    Protocol::Ascii(ref mut protocol) => protocol.auth(username, password)?,
+                }
+            }
+            Ok(())
+        })?;
+        Ok(connection)
+    }
+
+    fn establish(&self) -> Result<Connection, MemcacheError> {
+        let mut connection = self.open()?;
+        if let Some(probed) = *self.probed.lock().unwrap() {
+            connection.server_version = probed.server_version;
+            connection.item_size_max = probed.item_size_max;
+            return Ok(connection);
+        }
+        let result = connection
+            .detect_server_version()
+            .and_then(|()| connection.detect_item_size_max());
+        // a failed probe isn't retried either, so that each connection is only opened once
+        *self.probed.lock().unwrap() = Some(Probed {
+            server_version: connection.server_version,
+            item_size_max: connection.item_size_max,
+        });
+        match result {
+            Ok(()) => Ok(connection),
+            // the server version and item size limit are only hints, and some proxies answer the
+            // probes unexpectedly or close the connection, which leaves it in an unknown state
+            Err(_) => {
+                let mut reopened = self.open()?;
+                reopened.server_version = connection.server_version;
+                Ok(reopened)
+            }
+        }
     }
 }
 
+/// The error of the last connection to a server which failed to be established, until one
+/// succeeds, to report it instead of the timeout of the pool.
+#[derive(Clone, Default)]
+pub(crate) struct LastConnectError(Arc<Mutex<Option<ConnectionError>>>);
+
+impl LastConnectError {
+    /// The error to report when no pooled connection could be checked out.
+    pub(crate) fn pool_error(&self, error: r2d2::Error) -> MemcacheError {
+        match *self.0.lock().unwrap() {
+            Some(ref last_error) => last_error.clone().into(),
+            None => error.into(),
+        }
+    }
+}
+
+fn ttl_unsupported() -> MemcacheError {
+    ClientError::Error(Cow::Borrowed(
+        "fetching the remaining TTL is only supported on ascii protocol connections",
+    ))
+    .into()
+}
+
+/// The host and port of the server, or the path of its unix socket.
+fn address(url: &Url) -> String {
+    match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{}:{}", host, port),
+        (Some(host), None) => host.to_string(),
+        (None, _) => url.path().to_string(),
+    }
+}
+
+/// Run `f`, a phase of establishing a connection, and wrap its error in a `ConnectionError`.
+fn in_phase<T, F>(phase: ConnectPhase, url: &Url, f: F) -> Result<T, MemcacheError>
+where
+    F: FnOnce() -> Result<T, MemcacheError>,
+{
+    f().map_err(|e| ConnectionError::new(phase, address(url), e).into())
+}
+
 impl ManageConnection for ConnectionManager {
     type Connection = Connection;
     type Error = MemcacheError;
 
     fn connect(&self) -> Result<Self::Connection, Self::Error> {
-        let url = &self.url;
-        let mut connection = Connection::connect(url)?;
-        if url.has_authority() && !url.username().is_empty() && url.password().is_some() {
-            let username = url.username();
-            let password = url.password().unwrap();
-            connection.auth(username, password)?;
+        let result = self.establish();
+        let mut last_error = self.last_error.0.lock().unwrap();
+        match result {
+            Ok(_) => *last_error = None,
+            Err(MemcacheError::ConnectionError(ref e)) => *last_error = Some(e.clone()),
+            Err(_) => (),
         }
-        Ok(connection)
+        result
     }
 
     fn is_valid(&self, conn: &mut Self::Connection) -> Result<(), Self::Error> {
         conn.version().map(|_| ())
     }
 
-    fn has_broken(&self, _conn: &mut Self::Connection) -> bool {
-        // TODO: fix this
-        false
+    fn has_broken(&self, conn: &mut Self::Connection) -> bool {
+        // a connection in watch mode only streams log lines and can't be used for commands anymore
+        // TODO: detect closed sockets
+        conn.watching || conn.broken
     }
 }
 
 enum Transport {
     Tcp(TcpOptions),
-    Udp,
+    Udp(UdpOptions),
     #[cfg(unix)]
     Unix,
     #[cfg(feature = "tls")]
@@ -90,9 +309,12 @@ struct TlsOptions {
 struct TcpOptions {
     timeout: Option<Duration>,
     nodelay: bool,
+    recv_buffer_size: Option<usize>,
+    send_buffer_size: Option<usize>,
+    keepalive: Option<Keepalive>,
+    user_timeout: Option<Duration>,
 }
 
-#[cfg(feature = "tls")]
 fn get_param(url: &Url, key: &str) -> Option<String> {
     return url
         .query_pairs()
@@ -100,9 +322,136 @@ fn get_param(url: &Url, key: &str) -> Option<String> {
         .map(|(_k, v)| v.to_string());
 }
 
+/// Whether the `protocol` url parameter selects the ascii protocol, instead of the default binary
+/// protocol, which can also be selected explicitly with `protocol=binary`.
+pub(crate) fn is_ascii(url: &Url) -> Result<bool, MemcacheError> {
+    match get_param(url, "protocol").as_deref() {
+        Some("ascii") => Ok(true),
+        Some("binary") | None => Ok(false),
+        Some(_) => Err(MemcacheError::BadURL(
+            "unknown protocol, expected 'ascii' or 'binary'".into(),
+        )),
+    }
+}
+
+/// A size in bytes from the builder, or else the `key` url parameter, if either is set.
+fn size_param(url: &Url, key: &str, configured: Option<usize>) -> Result<Option<usize>, MemcacheError> {
+    if configured.is_some() {
+        return Ok(configured);
+    }
+    match get_param(url, key) {
+        Some(size) => match size.parse::<usize>() {
+            Ok(size) if size > 0 => Ok(Some(size)),
+            _ => Err(MemcacheError::BadURL(format!(
+                "{} should be a positive number of bytes",
+                key
+            ))),
+        },
+        None => Ok(None),
+    }
+}
+
+/// A positive number of seconds from the `key` url parameter, if it's set.
+fn seconds_param(url: &Url, key: &str) -> Result<Option<Duration>, MemcacheError> {
+    match get_param(url, key) {
+        Some(seconds) => match seconds.parse::<u64>() {
+            Ok(seconds) if seconds > 0 => Ok(Some(Duration::from_secs(seconds))),
+            _ => Err(MemcacheError::BadURL(format!(
+                "{} should be a positive number of seconds",
+                key
+            ))),
+        },
+        None => Ok(None),
+    }
+}
+
+/// The TCP keepalive settings from the builder, or else the `tcp_keepalive`, `tcp_keepalive_idle`
+/// and `tcp_keepalive_interval` url parameters. Setting the idle time or interval in the url
+/// enables keepalive, unless `tcp_keepalive` is `false`.
+fn keepalive(url: &Url, configured: Option<Keepalive>) -> Result<Option<Keepalive>, MemcacheError> {
+    if configured.is_some() {
+        return Ok(configured);
+    }
+    let keepalive = Keepalive {
+        idle: seconds_param(url, "tcp_keepalive_idle")?,
+        interval: seconds_param(url, "tcp_keepalive_interval")?,
+    };
+    let enabled = match get_param(url, "tcp_keepalive").as_deref() {
+        Some("true") => true,
+        Some("false") => false,
+        Some(_) => {
+            return Err(MemcacheError::BadURL(
+                "tcp_keepalive should be 'true' or 'false'".into(),
+            ))
+        }
+        None => keepalive != Keepalive::default(),
+    };
+    Ok(if enabled { Some(keepalive) } else { None })
+}
+
+/// The UDP options from the builder, or else the `udp_timeout_ms`, `udp_retransmits`,
+/// `udp_max_datagram_size` and `udp_tcp_fallback` url parameters.
+fn udp_options(url: &Url, options: &ConnectionOptions) -> Result<UdpOptions, MemcacheError> {
+    let max_datagram_size = size_param(url, "udp_max_datagram_size", options.udp_max_datagram_size)?;
+    if matches!(max_datagram_size, Some(size) if size <= 8 || size > 65507) {
+        return Err(MemcacheError::BadURL(
+            "udp_max_datagram_size should be between 9 and 65507 bytes".into(),
+        ));
+    }
+    let tcp_fallback = options.udp_tcp_fallback
+        || match get_param(url, "udp_tcp_fallback").as_deref() {
+            Some("true") => true,
+            Some("false") | None => false,
+            Some(_) => {
+                return Err(MemcacheError::BadURL(
+                    "udp_tcp_fallback should be 'true' or 'false'".into(),
+                ))
+            }
+        };
+    if let Some((request_timeout, retransmits)) = options.udp_retransmission {
+        return Ok(UdpOptions {
+            request_timeout: Some(request_timeout),
+            retransmits,
+            max_datagram_size,
+            tcp_fallback,
+        });
+    }
+    let request_timeout = match get_param(url, "udp_timeout_ms") {
+        Some(millis) => match millis.parse::<u64>() {
+            Ok(millis) if millis > 0 => Some(Duration::from_millis(millis)),
+            _ => {
+                return Err(MemcacheError::BadURL(
+                    "udp_timeout_ms should be a positive number of milliseconds".into(),
+                ))
+            }
+        },
+        None => None,
+    };
+    let retransmits = match get_param(url, "udp_retransmits") {
+        Some(retransmits) => retransmits
+            .parse::<u32>()
+            .map_err(|_| MemcacheError::BadURL("udp_retransmits should be a number".into()))?,
+        None => 0,
+    };
+    if retransmits > 0 && request_timeout.is_none() {
+        return Err(MemcacheError::BadURL("udp_retransmits requires udp_timeout_ms".into()));
+    }
+    Ok(UdpOptions {
+        request_timeout,
+        retransmits,
+        max_datagram_size,
+        tcp_fallback,
+    })
+}
+
+/// The capacity of a stream buffer, from the builder or else the `key` url parameter.
+fn buffer_size(url: &Url, key: &str, configured: Option<usize>) -> Result<usize, MemcacheError> {
+    Ok(size_param(url, key, configured)?.unwrap_or(DEFAULT_BUFFER_SIZE))
+}
+
 #[cfg(feature = "tls")]
 impl TlsOptions {
-    fn from_url(url: &Url) -> Result<Self, MemcacheError> {
+    fn from_url(url: &Url, options: &ConnectionOptions) -> Result<Self, MemcacheError> {
         let verify_mode = match get_param(url, "verify_mode").as_ref().map(String::as_str) {
             Some("none") => SslVerifyMode::NONE,
             Some("peer") => SslVerifyMode::PEER,
@@ -129,7 +478,7 @@ impl TlsOptions {
         }
 
         Ok(TlsOptions {
-            tcp_options: TcpOptions::from_url(url),
+            tcp_options: TcpOptions::from_url(url, options)?,
             ca_path: ca_path,
             key_path: key_path,
             cert_path: cert_path,
@@ -139,7 +488,7 @@ impl TlsOptions {
 }
 
 impl TcpOptions {
-    fn from_url(url: &Url) -> Self {
+    fn from_url(url: &Url, options: &ConnectionOptions) -> Result<Self, MemcacheError> {
         let nodelay = !url
             .query_pairs()
             .any(|(ref k, ref v)| k == "tcp_nodelay" && v == "false");
@@ -148,15 +497,22 @@ impl TcpOptions {
             .find(|&(ref k, ref _v)| k == "timeout")
             .and_then(|(ref _k, ref v)| v.parse::<u64>().ok())
             .map(Duration::from_secs);
-        TcpOptions {
+        Ok(TcpOptions {
             nodelay: nodelay,
             timeout: timeout,
-        }
+            recv_buffer_size: size_param(url, "recv_buffer_size", options.recv_buffer_size)?,
+            send_buffer_size: size_param(url, "send_buffer_size", options.send_buffer_size)?,
+            keepalive: keepalive(url, options.keepalive)?,
+            user_timeout: match options.user_timeout {
+                Some(timeout) => Some(timeout),
+                None => seconds_param(url, "tcp_user_timeout")?,
+            },
+        })
     }
 }
 
 impl Transport {
-    fn from_url(url: &Url) -> Result<Self, MemcacheError> {
+    fn from_url(url: &Url, options: &ConnectionOptions) -> Result<Self, MemcacheError> {
         let mut parts = url.scheme().splitn(2, "+");
         match parts.next() {
             Some(part) if part == "memcache" => (),
@@ -170,12 +526,12 @@ impl Transport {
         // scheme has highest priority
         if let Some(proto) = parts.next() {
             return match proto {
-                "tcp" => Ok(Transport::Tcp(TcpOptions::from_url(url))),
-                "udp" => Ok(Transport::Udp),
+                "tcp" => Ok(Transport::Tcp(TcpOptions::from_url(url, options)?)),
+                "udp" => Ok(Transport::Udp(udp_options(url, options)?)),
                 #[cfg(unix)]
                 "unix" => Ok(Transport::Unix),
                 #[cfg(feature = "tls")]
-                "tls" => Ok(Transport::Tls(TlsOptions::from_url(url)?)),
+                "tls" => Ok(Transport::Tls(TlsOptions::from_url(url, options)?)),
                 _ => Err(MemcacheError::BadURL(
                     "memcache URL's scheme should be 'memcache+tcp' or 'memcache+udp' or 'memcache+unix' or 'memcache+tls'".into(),
                 )),
@@ -184,7 +540,7 @@ impl Transport {
 
         let is_udp = url.query_pairs().any(|(ref k, ref v)| k == "udp" && v == "true");
         if is_udp {
-            return Ok(Transport::Udp);
+            return Ok(Transport::Udp(udp_options(url, options)?));
         }
 
         #[cfg(unix)]
@@ -194,17 +550,34 @@ impl Transport {
             }
         }
 
-        Ok(Transport::Tcp(TcpOptions::from_url(url)))
+        Ok(Transport::Tcp(TcpOptions::from_url(url, options)?))
     }
 }
 
 fn tcp_stream(url: &Url, opts: &TcpOptions) -> Result<TcpStream, MemcacheError> {
-    let tcp_stream = TcpStream::connect(&*url.socket_addrs(|| None)?)?;
+    let addrs = in_phase(ConnectPhase::Resolve, url, || Ok(url.socket_addrs(|| None)?))?;
+    in_phase(ConnectPhase::Connect, url, || connect_tcp(&addrs, opts))
+}
+
+fn connect_tcp(addrs: &[SocketAddr], opts: &TcpOptions) -> Result<TcpStream, MemcacheError> {
+    let tcp_stream = TcpStream::connect(addrs)?;
     if opts.timeout.is_some() {
         tcp_stream.set_read_timeout(opts.timeout)?;
         tcp_stream.set_write_timeout(opts.timeout)?;
     }
     tcp_stream.set_nodelay(opts.nodelay)?;
+    if let Some(size) = opts.recv_buffer_size {
+        socket::set_recv_buffer_size(&tcp_stream, size)?;
+    }
+    if let Some(size) = opts.send_buffer_size {
+        socket::set_send_buffer_size(&tcp_stream, size)?;
+    }
+    if let Some(keepalive) = opts.keepalive {
+        socket::set_keepalive(&tcp_stream, keepalive)?;
+    }
+    if let Some(timeout) = opts.user_timeout {
+        socket::set_user_timeout(&tcp_stream, timeout)?;
+    }
     Ok(tcp_stream)
 }
 
@@ -213,51 +586,325 @@ impl Connection {
         self.url.to_string()
     }
 
-    pub(crate) fn connect(url: &Url) -> Result<Self, MemcacheError> {
-        let transport = Transport::from_url(url)?;
-        let is_ascii = url.query_pairs().any(|(ref k, ref v)| k == "protocol" && v == "ascii");
+    /// The version of the connected server, if it could be detected when connecting.
+    pub fn server_version(&self) -> Option<ServerVersion> {
+        self.server_version
+    }
+
+    /// The read and write timeouts of the socket.
+    pub(crate) fn timeouts(&mut self) -> Result<(Option<Duration>, Option<Duration>), MemcacheError> {
+        self.stream().timeouts()
+    }
+
+    pub(crate) fn set_timeouts(
+        &mut self,
+        read: Option<Duration>,
+        write: Option<Duration>,
+    ) -> Result<(), MemcacheError> {
+        let stream = self.stream();
+        stream.set_read_timeout(read)?;
+        stream.set_write_timeout(write)
+    }
+
+    fn stream(&mut self) -> &mut Stream {
+        match self.protocol {
+            Protocol::Ascii(ref mut protocol) => protocol.stream(),
+            Protocol::Binary(ref mut protocol) => &mut protocol.stream,
+        }
+    }
+
+    /// Drop the connection instead of returning it to the pool, for when a request could only be
+    /// partially written and the server is still waiting for the rest.
+    pub(crate) fn mark_broken(&mut self) {
+        self.broken = true;
+    }
+
+    fn detect_server_version(&mut self) -> Result<(), MemcacheError> {
+        self.server_version = match self.version() {
+            Ok(version) => ServerVersion::parse(&version),
+            // some proxies don't implement the version command, don't gate anything for them
+            Err(MemcacheError::CommandError(_)) => None,
+            Err(e) => return Err(e),
+        };
+        Ok(())
+    }
+
+    /// The maximum size of the values accepted by the connected server, the `-I` option of
+    /// memcached, if it could be detected when connecting.
+    pub fn item_size_max(&self) -> Option<usize> {
+        self.item_size_max
+    }
+
+    fn detect_item_size_max(&mut self) -> Result<(), MemcacheError> {
+        self.item_size_max = match self.stats_with_args("settings") {
+            Ok(settings) => settings.get("item_size_max").and_then(|size| size.parse().ok()),
+            // some proxies don't implement the stats settings command
+            Err(MemcacheError::CommandError(_)) | Err(MemcacheError::ServerError(_)) => None,
+            Err(e) => return Err(e),
+        };
+        Ok(())
+    }
+
+    /// Return a `ValueTooLarge` error if the server is known to reject values of `length` bytes,
+    /// before sending them.
+    pub(crate) fn check_item_size(&self, length: usize) -> Result<(), MemcacheError> {
+        match self.item_size_max {
+            Some(limit) if length > limit => Err(ClientError::ValueTooLarge { length, limit }.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Return an `UnsupportedCommand` error if the server is known to be too old for `feature`.
+    pub(crate) fn check_feature(&self, feature: Feature) -> Result<(), MemcacheError> {
+        match self.server_version {
+            Some(version) if version < feature.min_version() => {
+                Err(CommandError::UnsupportedCommand(feature.command()))?
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Send a raw ascii protocol command to the server, for commands not modelled by this crate.
+    /// The command is terminated with `\r\n` automatically, storage commands should include the
+    /// `\r\n` separated data block in `command`. Returns the response lines without line endings.
+    ///
+    /// Only available for connections using the ascii protocol.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345?protocol=ascii").unwrap();
+    /// let mut connection = client.get_connections().unwrap().remove(0);
+    /// let response = connection.run_ascii_command("version").unwrap();
+    /// assert!(response[0].starts_with("VERSION"));
+    /// ```
+    pub fn run_ascii_command(&mut self, command: &str) -> Result<Vec<String>, MemcacheError> {
+        let is_meta_command = matches!(
+            command.split(' ').next(),
+            Some("mg") | Some("ms") | Some("md") | Some("ma") | Some("mn") | Some("me")
+        );
+        if is_meta_command {
+            self.check_feature(Feature::MetaCommands)?;
+        }
+        match self.protocol {
+            Protocol::Ascii(ref mut protocol) => protocol.run_command(command),
+            Protocol::Binary(_) => Err(ClientError::Error(Cow::Borrowed(
+                "ascii commands can only be sent on ascii protocol connections",
+            )))?,
+        }
+    }
+
+    /// Check that the remaining TTLs of the keys can be fetched with `get_with_ttl`.
+    pub(crate) fn check_get_with_ttl(&self) -> Result<(), MemcacheError> {
+        self.check_feature(Feature::MetaCommands)?;
+        match self.protocol {
+            Protocol::Ascii(_) => Ok(()),
+            Protocol::Binary(_) => Err(ttl_unsupported()),
+        }
+    }
+
+    /// Fetch the value of `key` with its flags and remaining TTL, see `migrate`.
+    pub(crate) fn get_with_ttl(&mut self, key: &str) -> Result<Option<StoredValue>, MemcacheError> {
+        self.check_feature(Feature::MetaCommands)?;
+        match self.protocol {
+            Protocol::Ascii(ref mut protocol) => protocol.get_with_ttl(key),
+            Protocol::Binary(_) => Err(ttl_unsupported()),
+        }
+    }
+
+    pub(crate) fn start_metadump(&mut self) -> Result<(), MemcacheError> {
+        self.check_feature(Feature::Metadump)?;
+        match self.protocol {
+            Protocol::Ascii(ref mut protocol) => protocol.start_metadump(),
+            Protocol::Binary(_) => Err(ClientError::Error(Cow::Borrowed(
+                "key scanning is only supported on ascii protocol connections",
+            )))?,
+        }
+    }
+
+    pub(crate) fn read_metadump_line(&mut self) -> Result<Option<String>, MemcacheError> {
+        match self.protocol {
+            Protocol::Ascii(ref mut protocol) => protocol.read_metadump_line(),
+            Protocol::Binary(_) => Ok(None),
+        }
+    }
+
+    /// List up to `limit` keys stored in the slab class `slab` with `stats cachedump`, for
+    /// debugging servers too old for `lru_crawler metadump`. A `limit` of 0 lists all keys of the
+    /// slab class, but the server truncates the response at 2MB.
+    ///
+    /// Only available for connections using the ascii protocol.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345?protocol=ascii").unwrap();
+    /// client.set("foo", "bar", 0).unwrap();
+    /// let mut connection = client.get_connections().unwrap().remove(0);
+    /// for item in connection.stats_cachedump(1, 100).unwrap() {
+    ///     println!("{}: {} bytes", item.key, item.size);
+    /// }
+    /// ```
+    pub fn stats_cachedump(&mut self, slab: u32, limit: u32) -> Result<Vec<CachedumpItem>, MemcacheError> {
+        match self.protocol {
+            Protocol::Ascii(ref mut protocol) => protocol
+                .run_command(&format!("stats cachedump {} {}", slab, limit))?
+                .iter()
+                .take_while(|line| line.as_str() != "END")
+                .map(|line| CachedumpItem::parse(line))
+                .collect(),
+            Protocol::Binary(_) => Err(ClientError::Error(Cow::Borrowed(
+                "stats cachedump is only supported on ascii protocol connections",
+            )))?,
+        }
+    }
+
+    /// Stream the server's log lines for the given kinds of events, for live debugging of the
+    /// cache traffic. The connection can't be used for other commands afterwards, a pooled
+    /// connection is closed instead of being returned to the pool.
+    ///
+    /// Only available for connections using the ascii protocol.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// use memcache::WatchFilter;
+    ///
+    /// fn print_traffic(connection: &mut memcache::Connection) {
+    ///     let events = connection.watch(&[WatchFilter::Fetchers, WatchFilter::Mutations]).unwrap();
+    ///     for event in events {
+    ///         let event = event.unwrap();
+    ///         println!("{:?} {:?}", event.kind, event.key);
+    ///     }
+    /// }
+    /// ```
+    pub fn watch(&mut self, filters: &[WatchFilter]) -> Result<Watch<'_>, MemcacheError> {
+        self.check_feature(Feature::Watch)?;
+        let filters: Vec<_> = filters.iter().map(|filter| filter.as_str()).collect();
+        match self.protocol {
+            Protocol::Ascii(ref mut protocol) => {
+                self.watching = true;
+                protocol.start_watch(&filters.join(" "))?;
+                Ok(Watch::new(protocol))
+            }
+            Protocol::Binary(_) => Err(ClientError::Error(Cow::Borrowed(
+                "watch is only supported on ascii protocol connections",
+            )))?,
+        }
+    }
+
+    /// Shut down the memcached server, which must have been started with `-A` to allow it.
+    /// A graceful shutdown lets a server with a restartable cache save its memory first.
+    ///
+    /// Only available for connections using the ascii protocol.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// fn stop_server(connection: &mut memcache::Connection) {
+    ///     connection.shutdown(true).unwrap();
+    /// }
+    /// ```
+    pub fn shutdown(&mut self, graceful: bool) -> Result<(), MemcacheError> {
+        match self.protocol {
+            Protocol::Ascii(ref mut protocol) => protocol.shutdown(graceful),
+            Protocol::Binary(_) => Err(ClientError::Error(Cow::Borrowed(
+                "shutdown can only be sent on ascii protocol connections",
+            )))?,
+        }
+    }
+
+    /// Send a raw binary protocol packet to the server and read the response packet, for
+    /// experimenting with opcodes not modelled by this crate. The response status is returned
+    /// as is instead of being converted to an error. Quiet opcodes which the server doesn't
+    /// answer will block until the read timeout.
+    ///
+    /// Only available for connections using the binary protocol.
+    ///
+    /// Example:
+    ///
+    /// ```rust
+    /// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    /// let mut connection = client.get_connections().unwrap().remove(0);
+    /// let request = memcache::BinaryRequest {
+    ///     opcode: 0x0b, // version
+    ///     ..Default::default()
+    /// };
+    /// let response = connection.run_binary_command(&request).unwrap();
+    /// assert_eq!(response.status, 0);
+    /// ```
+    pub fn run_binary_command(&mut self, request: &BinaryRequest) -> Result<BinaryResponse, MemcacheError> {
+        match self.protocol {
+            Protocol::Binary(ref mut protocol) => protocol.run_command(request),
+            Protocol::Ascii(_) => Err(ClientError::Error(Cow::Borrowed(
+                "binary commands can only be sent on binary protocol connections",
+            )))?,
+        }
+    }
+
+    pub(crate) fn connect(url: &Url, options: &ConnectionOptions) -> Result<Self, MemcacheError> {
+        let transport = Transport::from_url(url, options)?;
+        let read_buffer_size = buffer_size(url, "read_buffer_size", options.read_buffer_size)?;
+        let write_buffer_size = buffer_size(url, "write_buffer_size", options.write_buffer_size)?;
+        let is_ascii = is_ascii(url)?;
         let stream: Stream = match transport {
-            Transport::Tcp(options) => Stream::Tcp(tcp_stream(url, &options)?),
-            Transport::Udp => Stream::Udp(UdpStream::new(url)?),
+            Transport::Tcp(options) => Stream::Tcp(buffered(
+                tcp_stream(url, &options)?,
+                read_buffer_size,
+                write_buffer_size,
+            )),
+            Transport::Udp(options) => {
+                Stream::Udp(in_phase(ConnectPhase::Connect, url, || UdpStream::new(url, options))?)
+            }
             #[cfg(unix)]
-            Transport::Unix => Stream::Unix(UnixStream::connect(url.path())?),
+            Transport::Unix => Stream::Unix(buffered(
+                in_phase(ConnectPhase::Connect, url, || Ok(UnixStream::connect(url.path())?))?,
+                read_buffer_size,
+                write_buffer_size,
+            )),
             #[cfg(feature = "tls")]
             Transport::Tls(options) => {
                 let host = url
                     .host_str()
                     .ok_or(MemcacheError::BadURL("host required for TLS connection".into()))?;
 
-                let mut builder = SslConnector::builder(SslMethod::tls())?;
-                builder.set_verify(options.verify_mode);
+                let tls_conn = in_phase(ConnectPhase::Tls, url, || {
+                    let mut builder = SslConnector::builder(SslMethod::tls())?;
+                    builder.set_verify(options.verify_mode);
 
-                if options.ca_path.is_some() {
-                    builder.set_ca_file(&options.ca_path.unwrap())?;
-                }
+                    if let Some(ref ca_path) = options.ca_path {
+                        builder.set_ca_file(ca_path)?;
+                    }
 
-                if options.key_path.is_some() {
-                    builder.set_private_key_file(options.key_path.unwrap(), SslFiletype::PEM)?;
-                }
+                    if let Some(ref key_path) = options.key_path {
+                        builder.set_private_key_file(key_path, SslFiletype::PEM)?;
+                    }
 
-                if options.cert_path.is_some() {
-                    builder.set_certificate_chain_file(options.cert_path.unwrap())?;
-                }
+                    if let Some(ref cert_path) = options.cert_path {
+                        builder.set_certificate_chain_file(cert_path)?;
+                    }
 
-                let tls_conn = builder.build();
+                    Ok(builder.build())
+                })?;
                 let tcp_stream = tcp_stream(url, &options.tcp_options)?;
-                let tls_stream = tls_conn.connect(host, tcp_stream)?;
-                Stream::Tls(tls_stream)
+                let tls_stream = in_phase(ConnectPhase::Tls, url, || Ok(tls_conn.connect(host, tcp_stream)?))?;
+                Stream::Tls(buffered(tls_stream, read_buffer_size, write_buffer_size))
             }
         };
 
         let protocol = if is_ascii {
             Protocol::Ascii(AsciiProtocol::new(stream))
         } else {
-            Protocol::Binary(BinaryProtocol { stream: stream })
+            Protocol::Binary(BinaryProtocol { stream })
         };
 
         Ok(Connection {
             url: Arc::new(url.to_string()),
-            protocol: protocol,
+            protocol,
+            server_version: None,
+            item_size_max: None,
+            watching: false,
+            broken: false,
         })
     }
 }
@@ -267,11 +914,269 @@ mod tests {
     #[cfg(unix)]
     #[test]
     fn test_transport_url() {
-        use super::Transport;
+        use super::{ConnectionOptions, Transport};
         use url::Url;
-        match Transport::from_url(&Url::parse("memcache:///tmp/memcached.sock").unwrap()).unwrap() {
+        let url = Url::parse("memcache:///tmp/memcached.sock").unwrap();
+        match Transport::from_url(&url, &ConnectionOptions::default()).unwrap() {
             Transport::Unix => (),
-            _ => assert!(false, "transport is not unix"),
+            _ => panic!("transport is not unix"),
+        }
+    }
+
+    #[test]
+    fn test_udp_protocol() {
+        use super::{Connection, ConnectionOptions};
+        use crate::protocol::Protocol;
+        use url::Url;
+        // binding an UDP socket doesn't need a server
+        let connect = |url: &str| Connection::connect(&Url::parse(url).unwrap(), &ConnectionOptions::default());
+        for url in &[
+            "memcache+udp://localhost:22345",
+            "memcache+udp://localhost:22345?protocol=binary",
+        ] {
+            assert!(matches!(connect(url).unwrap().protocol, Protocol::Binary(_)));
         }
+        let connection = connect("memcache+udp://localhost:22345?protocol=ascii").unwrap();
+        assert!(matches!(connection.protocol, Protocol::Ascii(_)));
+        assert!(connect("memcache+udp://localhost:22345?protocol=text").is_err());
+    }
+
+    #[test]
+    fn test_udp_options() {
+        use super::{ConnectionOptions, Transport};
+        use crate::stream::UdpOptions;
+        use std::time::Duration;
+        use url::Url;
+        let udp_options =
+            |url: &str, options: &ConnectionOptions| match Transport::from_url(&Url::parse(url).unwrap(), options)
+                .unwrap()
+            {
+                Transport::Udp(options) => options,
+                _ => panic!("transport is not udp"),
+            };
+        let options = ConnectionOptions::default();
+        assert_eq!(
+            udp_options("memcache+udp://localhost:22345", &options),
+            UdpOptions::default()
+        );
+        assert_eq!(
+            udp_options(
+                "memcache+udp://localhost:22345?udp_timeout_ms=200&udp_retransmits=2",
+                &options
+            ),
+            UdpOptions {
+                request_timeout: Some(Duration::from_millis(200)),
+                retransmits: 2,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            udp_options(
+                "memcache+udp://localhost:22345?udp_max_datagram_size=8972&udp_tcp_fallback=true",
+                &options
+            ),
+            UdpOptions {
+                max_datagram_size: Some(8972),
+                tcp_fallback: true,
+                ..Default::default()
+            }
+        );
+        let url = Url::parse("memcache+udp://localhost:22345?udp_max_datagram_size=8").unwrap();
+        assert!(Transport::from_url(&url, &options).is_err());
+        let url = Url::parse("memcache+udp://localhost:22345?udp_retransmits=2").unwrap();
+        assert!(Transport::from_url(&url, &options).is_err());
+
+        let options = ConnectionOptions {
+            udp_retransmission: Some((Duration::from_millis(50), 3)),
+            ..Default::default()
+        };
+        assert_eq!(
+            udp_options("memcache://localhost:22345?udp=true&udp_timeout_ms=200", &options),
+            UdpOptions {
+                request_timeout: Some(Duration::from_millis(50)),
+                retransmits: 3,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_buffer_size() {
+        use super::buffer_size;
+        use crate::stream::DEFAULT_BUFFER_SIZE;
+        use url::Url;
+        let url = Url::parse("memcache://localhost:12345?read_buffer_size=65536&write_buffer_size=0").unwrap();
+        assert_eq!(buffer_size(&url, "read_buffer_size", None).unwrap(), 65536);
+        assert_eq!(buffer_size(&url, "read_buffer_size", Some(1024)).unwrap(), 1024);
+        assert!(buffer_size(&url, "write_buffer_size", None).is_err());
+        let url = Url::parse("memcache://localhost:12345").unwrap();
+        assert_eq!(
+            buffer_size(&url, "read_buffer_size", None).unwrap(),
+            DEFAULT_BUFFER_SIZE
+        );
+    }
+
+    #[test]
+    fn test_socket_buffer_size() {
+        use super::{ConnectionOptions, TcpOptions};
+        use url::Url;
+        let url = Url::parse("memcache://localhost:12345?recv_buffer_size=1048576").unwrap();
+        let options = ConnectionOptions {
+            send_buffer_size: Some(65536),
+            ..Default::default()
+        };
+        let tcp_options = TcpOptions::from_url(&url, &options).unwrap();
+        assert_eq!(tcp_options.recv_buffer_size, Some(1048576));
+        assert_eq!(tcp_options.send_buffer_size, Some(65536));
+        assert_eq!(tcp_options.user_timeout, None);
+        let url = Url::parse("memcache://localhost:12345?tcp_user_timeout=30").unwrap();
+        let tcp_options = TcpOptions::from_url(&url, &ConnectionOptions::default()).unwrap();
+        assert_eq!(tcp_options.user_timeout, Some(std::time::Duration::from_secs(30)));
+        let url = Url::parse("memcache://localhost:12345?send_buffer_size=big").unwrap();
+        assert!(TcpOptions::from_url(&url, &ConnectionOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_keepalive() {
+        use super::{keepalive, Keepalive};
+        use std::time::Duration;
+        use url::Url;
+        let url = Url::parse("memcache://localhost:12345").unwrap();
+        assert_eq!(keepalive(&url, None).unwrap(), None);
+        let url = Url::parse("memcache://localhost:12345?tcp_keepalive=true").unwrap();
+        assert_eq!(keepalive(&url, None).unwrap(), Some(Keepalive::default()));
+        let url = Url::parse("memcache://localhost:12345?tcp_keepalive_idle=60&tcp_keepalive_interval=10").unwrap();
+        let expected = Keepalive {
+            idle: Some(Duration::from_secs(60)),
+            interval: Some(Duration::from_secs(10)),
+        };
+        assert_eq!(keepalive(&url, None).unwrap(), Some(expected));
+        assert_eq!(
+            keepalive(&url, Some(Keepalive::default())).unwrap(),
+            Some(Keepalive::default())
+        );
+        let url = Url::parse("memcache://localhost:12345?tcp_keepalive=false&tcp_keepalive_idle=60").unwrap();
+        assert_eq!(keepalive(&url, None).unwrap(), None);
+        let url = Url::parse("memcache://localhost:12345?tcp_keepalive_idle=0").unwrap();
+        assert!(keepalive(&url, None).is_err());
+    }
+
+    #[test]
+    fn test_parse_server_version() {
+        use super::ServerVersion;
+        let version = |major, minor, patch| ServerVersion { major, minor, patch };
+        assert_eq!(ServerVersion::parse("1.6.9"), Some(version(1, 6, 9)));
+        assert_eq!(ServerVersion::parse("1.4.24-tags\r\n"), Some(version(1, 4, 24)));
+        assert_eq!(ServerVersion::parse("1.5"), Some(version(1, 5, 0)));
+        assert_eq!(ServerVersion::parse("unknown"), None);
+        assert!(version(1, 4, 7) < version(1, 4, 8));
+        assert!(version(1, 10, 0) > version(1, 6, 21));
+    }
+
+    #[test]
+    fn test_connection_error() {
+        use super::{ConnectionManager, ConnectionOptions};
+        use crate::error::{ConnectPhase, MemcacheError};
+        use r2d2::ManageConnection;
+        use std::net::TcpListener;
+        use url::Url;
+
+        // a port which was just free
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let url = Url::parse(&format!("memcache://127.0.0.1:{}", port)).unwrap();
+        let manager = ConnectionManager::new(url, ConnectionOptions::default());
+        match manager.connect() {
+            Err(MemcacheError::ConnectionError(e)) => {
+                assert_eq!(e.phase, ConnectPhase::Connect);
+                assert_eq!(e.address, format!("127.0.0.1:{}", port));
+                assert!(matches!(e.error(), MemcacheError::IOError(_)));
+            }
+            _ => panic!("connecting to a closed port succeeded"),
+        }
+        assert!(manager.last_error.0.lock().unwrap().is_some());
+
+        let url = Url::parse("memcache://memcached.invalid:12345").unwrap();
+        match ConnectionManager::new(url, ConnectionOptions::default()).connect() {
+            Err(MemcacheError::ConnectionError(e)) => {
+                assert_eq!(e.phase, ConnectPhase::Resolve);
+                assert_eq!(e.address, "memcached.invalid:12345");
+                assert!(e.to_string().starts_with("resolving memcached.invalid:12345 failed: "));
+            }
+            _ => panic!("resolving an invalid host succeeded"),
+        }
+    }
+
+    #[test]
+    fn test_cached_probes() {
+        use super::{ConnectionManager, ConnectionOptions, ServerVersion};
+        use r2d2::ManageConnection;
+        use std::io::{BufRead, BufReader, Read, Write};
+        use std::net::TcpListener;
+        use std::thread;
+        use std::time::Duration;
+        use url::Url;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(line, "version\r\n");
+            (&stream).write_all(b"VERSION 1.6.21\r\n").unwrap();
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(line, "stats settings\r\n");
+            (&stream).write_all(b"STAT item_size_max 1024\r\nEND\r\n").unwrap();
+            // the next connection isn't probed again
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+            assert!(stream.read(&mut [0; 1]).is_err());
+        });
+        let url = Url::parse(&format!("memcache://127.0.0.1:{}?protocol=ascii", port)).unwrap();
+        let manager = ConnectionManager::new(url, ConnectionOptions::default());
+        let first = manager.connect().unwrap();
+        let second = manager.connect().unwrap();
+        server.join().unwrap();
+        for connection in &[first, second] {
+            assert_eq!(connection.server_version(), ServerVersion::parse("1.6.21"));
+            assert_eq!(connection.item_size_max(), Some(1024));
+        }
+    }
+
+    #[test]
+    fn test_failed_probe() {
+        use super::{ConnectionManager, ConnectionOptions, ServerVersion};
+        use r2d2::ManageConnection;
+        use std::io::{BufRead, BufReader, Write};
+        use std::net::TcpListener;
+        use std::thread;
+        use url::Url;
+
+        // a proxy which closes the connection on the stats settings command
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let proxy = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(line, "version\r\n");
+            (&stream).write_all(b"VERSION 1.6.21\r\n").unwrap();
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(line, "stats settings\r\n");
+            drop((reader, stream));
+            // the connection opened again without the probes
+            listener.accept().unwrap().0
+        });
+        let url = Url::parse(&format!("memcache://127.0.0.1:{}?protocol=ascii", port)).unwrap();
+        let connection = ConnectionManager::new(url, ConnectionOptions::default())
+            .connect()
+            .unwrap();
+        assert_eq!(connection.server_version(), ServerVersion::parse("1.6.21"));
+        assert_eq!(connection.item_size_max(), None);
+        proxy.join().unwrap();
     }
 }
@@ -0,0 +1,159 @@
+use std::sync::{Arc, Mutex, Weak};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+use r2d2::Pool;
+
+use crate::connection::ConnectionManager;
+use crate::error::MemcacheError;
+use crate::protocol::{encode_quiet, ProtocolTrait, QuietCommand};
+
+/// Buffers the noreply mutations of each server and writes them together, see
+/// `ClientBuilder::with_deferred_writes`. A background thread writes the commands which waited
+/// for `max_delay`, and the callers write them as soon as `max_bytes` are buffered.
+pub(crate) struct DeferredWrites {
+    max_delay: Duration,
+    max_bytes: usize,
+    servers: Vec<Server>,
+    writer: Thread,
+}
+
+struct Server {
+    pool: Pool<ConnectionManager>,
+    ascii: bool,
+    batch: Mutex<Batch>,
+    /// Held while a batch is taken and written, so the batches of a server are written in order.
+    writing: Mutex<()>,
+}
+
+#[derive(Default)]
+struct Batch {
+    commands: Vec<u8>,
+    count: usize,
+    since: Option<Instant>,
+}
+
+impl DeferredWrites {
+    /// Start buffering the writes to the servers of `pools`, with whether each one uses the ascii
+    /// protocol.
+    pub(crate) fn start(
+        pools: Vec<(Pool<ConnectionManager>, bool)>,
+        max_delay: Duration,
+        max_bytes: usize,
+    ) -> Result<Arc<Self>, MemcacheError> {
+        let (started, writer) = std::sync::mpsc::channel::<Weak<DeferredWrites>>();
+        let handle = thread::Builder::new()
+            .name("memcache-deferred-writer".into())
+            .spawn(move || {
+                if let Ok(writes) = writer.recv() {
+                    write_expired(writes);
+                }
+            })?;
+        let writes = Arc::new(DeferredWrites {
+            max_delay,
+            max_bytes,
+            servers: pools
+                .into_iter()
+                .map(|(pool, ascii)| Server {
+                    pool,
+                    ascii,
+                    batch: Mutex::new(Batch::default()),
+                    writing: Mutex::new(()),
+                })
+                .collect(),
+            writer: handle.thread().clone(),
+        });
+        let _ = started.send(Arc::downgrade(&writes));
+        Ok(writes)
+    }
+
+    /// Buffer `command` for the server at `index`, and write the buffered commands if they reached
+    /// `max_bytes`.
+    pub(crate) fn push(&self, index: usize, command: &QuietCommand) -> Result<(), MemcacheError> {
+        let server = &self.servers[index];
+        let full = {
+            let mut batch = server.batch.lock().unwrap();
+            let length = batch.commands.len();
+            if let Err(e) = encode_quiet(&mut batch.commands, server.ascii, command) {
+                batch.commands.truncate(length);
+                return Err(e);
+            }
+            batch.count += 1;
+            if batch.since.is_none() {
+                batch.since = Some(Instant::now());
+                self.writer.unpark();
+            }
+            batch.commands.len() >= self.max_bytes
+        };
+        if full {
+            self.write(index)?;
+        }
+        Ok(())
+    }
+
+    /// Write the commands buffered for the server at `index`.
+    fn write(&self, index: usize) -> Result<(), MemcacheError> {
+        let server = &self.servers[index];
+        let _writing = server.writing.lock().unwrap();
+        let batch = std::mem::take(&mut *server.batch.lock().unwrap());
+        if batch.count == 0 {
+            return Ok(());
+        }
+        server.pool.get()?.write_quiet(&batch.commands, batch.count)
+    }
+
+    /// Write the commands buffered for every server, and return the first error.
+    pub(crate) fn write_all(&self) -> Result<(), MemcacheError> {
+        let mut result = Ok(());
+        for index in 0..self.servers.len() {
+            if let Err(e) = self.write(index) {
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+
+    /// Write the batches which waited for `max_delay`, and return how long until the next one
+    /// does, or `None` if no commands are buffered.
+    fn write_expired(&self) -> Option<Duration> {
+        let mut next = None;
+        for (index, server) in self.servers.iter().enumerate() {
+            let since = server.batch.lock().unwrap().since;
+            if let Some(since) = since {
+                let waited = since.elapsed();
+                if waited >= self.max_delay {
+                    // the caller isn't waiting for these writes, they can only fail silently
+                    let _ = self.write(index);
+                } else {
+                    let remaining = self.max_delay - waited;
+                    next = Some(next.map_or(remaining, |next: Duration| next.min(remaining)));
+                }
+            }
+        }
+        next
+    }
+}
+
+impl Drop for DeferredWrites {
+    fn drop(&mut self) {
+        let _ = self.write_all();
+        // let the writer thread see that the client is gone
+        self.writer.unpark();
+    }
+}
+
+/// The loop of the writer thread, until the client is dropped.
+fn write_expired(writes: Weak<DeferredWrites>) {
+    loop {
+        let next = match writes.upgrade() {
+            Some(writes) => writes.write_expired(),
+            None => return,
+        };
+        match next {
+            Some(wait) => thread::park_timeout(wait),
+            None => thread::park(),
+        }
+    }
+}
@@ -6,26 +6,50 @@ use std::io;
 use std::num;
 use std::str;
 use std::string;
+use std::sync::Arc;
 use url;
 
+use crate::metrics::Command;
+
 /// Client-side errors
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
 pub enum ClientError {
     /// The key provided was longer than 250 bytes.
     KeyTooLong,
+    /// The key provided contains a whitespace or control character, which the ascii protocol
+    /// doesn't allow.
+    InvalidKey(u8),
     /// The server returned an error prefixed with CLIENT_ERROR in response to a command.
     Error(Cow<'static, str>),
+    /// The value is larger than the item size limit of the server, and was not sent.
+    ValueTooLarge { length: usize, limit: usize },
+    /// The deadline of a call, like `Client::get_with_timeout`, was reached before it completed.
+    DeadlineExceeded,
+    /// The server of the key is marked as dead after it failed, and the command was not sent, see
+    /// `OnError::SkipDeadServers`.
+    ServerDead,
 }
 
 impl fmt::Display for ClientError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             ClientError::KeyTooLong => write!(f, "The provided key was too long."),
+            ClientError::InvalidKey(byte) => write!(f, "The provided key contains the invalid byte {:#04x}.", byte),
             ClientError::Error(s) => write!(f, "{}", s),
+            ClientError::ValueTooLarge { length, limit } => write!(
+                f,
+                "The value of {} bytes is larger than the server item size limit of {} bytes.",
+                length, limit
+            ),
+            ClientError::DeadlineExceeded => write!(f, "The deadline was exceeded."),
+            ClientError::ServerDead => write!(f, "The server is marked as dead."),
         }
     }
 }
 
+impl error::Error for ClientError {}
+
 impl From<ClientError> for MemcacheError {
     fn from(err: ClientError) -> Self {
         MemcacheError::ClientError(err)
@@ -33,7 +57,8 @@ impl From<ClientError> for MemcacheError {
 }
 
 /// Server-side errors
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum ServerError {
     /// When using binary protocol, the server returned magic byte other
     /// than 0x81 in the response packet.
@@ -42,6 +67,8 @@ pub enum ServerError {
     BadResponse(Cow<'static, str>),
     /// The server returned an error prefixed with SERVER_ERROR in response to a command.
     Error(String),
+    /// A value fetched from the server didn't match the checksum stored with it, see `Checksum`.
+    ChecksumMismatch,
 }
 
 impl fmt::Display for ServerError {
@@ -50,12 +77,14 @@ impl fmt::Display for ServerError {
             ServerError::BadMagic(e) => write!(f, "Expected 0x81 as magic in response header, but found: {:x}", e),
             ServerError::BadResponse(s) => write!(f, "Unexpected: {} in response", s),
             ServerError::Error(s) => write!(f, "{}", s),
+            ServerError::ChecksumMismatch => write!(f, "The value does not match its checksum."),
         }
     }
 }
 
 /// Command specific errors.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
 pub enum CommandError {
     /// The client tried to set a key which already existed in the server.
     KeyExists,
@@ -65,12 +94,15 @@ pub enum CommandError {
     ValueTooLarge,
     /// Invalid arguments were passed to the command.
     InvalidArguments,
-    /// The server requires authentication.
-    AuthenticationRequired,
+    /// The server requires authentication, or the authentication failed. Contains the message
+    /// returned by the server if there is one.
+    AuthenticationRequired(Option<String>),
     /// When using binary protocol, the server returned an unknown response status.
     Unknown(u16),
     /// The client sent an invalid command to the server.
     InvalidCommand,
+    /// The server version is too old to support the command.
+    UnsupportedCommand(&'static str),
 }
 
 impl MemcacheError {
@@ -97,6 +129,8 @@ impl From<String> for ClientError {
     }
 }
 
+impl error::Error for ServerError {}
+
 impl From<String> for ServerError {
     fn from(s: String) -> Self {
         ServerError::Error(s)
@@ -110,9 +144,13 @@ impl fmt::Display for CommandError {
             CommandError::KeyNotFound => write!(f, "Key was not found in the server."),
             CommandError::ValueTooLarge => write!(f, "Value was too large."),
             CommandError::InvalidArguments => write!(f, "Invalid arguments provided."),
-            CommandError::AuthenticationRequired => write!(f, "Authentication required."),
+            CommandError::AuthenticationRequired(None) => write!(f, "Authentication required."),
+            CommandError::AuthenticationRequired(Some(message)) => write!(f, "Authentication required: {}", message),
             CommandError::Unknown(code) => write!(f, "Unknown error occurred with code: {}.", code),
             CommandError::InvalidCommand => write!(f, "Invalid command sent to the server."),
+            CommandError::UnsupportedCommand(command) => {
+                write!(f, "The server version does not support the {} command.", command)
+            }
         }
     }
 }
@@ -124,12 +162,14 @@ impl From<u16> for CommandError {
             0x2 => CommandError::KeyExists,
             0x3 => CommandError::ValueTooLarge,
             0x4 => CommandError::InvalidArguments,
-            0x20 => CommandError::AuthenticationRequired,
+            0x20 => CommandError::AuthenticationRequired(None),
             e => CommandError::Unknown(e),
         }
     }
 }
 
+impl error::Error for CommandError {}
+
 impl From<CommandError> for MemcacheError {
     fn from(err: CommandError) -> Self {
         MemcacheError::CommandError(err)
@@ -143,6 +183,7 @@ impl From<ServerError> for MemcacheError {
 }
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum ParseError {
     Bool(str::ParseBoolError),
     Int(num::ParseIntError),
@@ -150,17 +191,29 @@ pub enum ParseError {
     String(string::FromUtf8Error),
     Str(std::str::Utf8Error),
     Url(url::ParseError),
+    #[cfg(feature = "serde_json")]
+    Json(serde_json::Error),
+    #[cfg(feature = "bincode")]
+    Bincode(bincode::Error),
+    #[cfg(feature = "msgpack")]
+    MsgPack(rmp_serde::decode::Error),
 }
 
 impl error::Error for ParseError {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self {
-            ParseError::Bool(ref e) => e.source(),
-            ParseError::Int(ref e) => e.source(),
-            ParseError::Float(ref e) => e.source(),
-            ParseError::String(ref e) => e.source(),
-            ParseError::Str(ref e) => e.source(),
-            ParseError::Url(ref e) => e.source(),
+            ParseError::Bool(ref e) => Some(e),
+            ParseError::Int(ref e) => Some(e),
+            ParseError::Float(ref e) => Some(e),
+            ParseError::String(ref e) => Some(e),
+            ParseError::Str(ref e) => Some(e),
+            ParseError::Url(ref e) => Some(e),
+            #[cfg(feature = "serde_json")]
+            ParseError::Json(ref e) => Some(e),
+            #[cfg(feature = "bincode")]
+            ParseError::Bincode(ref e) => Some(e),
+            #[cfg(feature = "msgpack")]
+            ParseError::MsgPack(ref e) => Some(e),
         }
     }
 }
@@ -174,6 +227,12 @@ impl fmt::Display for ParseError {
             ParseError::String(ref e) => e.fmt(f),
             ParseError::Str(ref e) => e.fmt(f),
             ParseError::Url(ref e) => e.fmt(f),
+            #[cfg(feature = "serde_json")]
+            ParseError::Json(ref e) => e.fmt(f),
+            #[cfg(feature = "bincode")]
+            ParseError::Bincode(ref e) => e.fmt(f),
+            #[cfg(feature = "msgpack")]
+            ParseError::MsgPack(ref e) => e.fmt(f),
         }
     }
 }
@@ -220,11 +279,35 @@ impl From<str::ParseBoolError> for MemcacheError {
     }
 }
 
+#[cfg(feature = "serde_json")]
+impl From<serde_json::Error> for MemcacheError {
+    fn from(err: serde_json::Error) -> MemcacheError {
+        ParseError::Json(err).into()
+    }
+}
+
+#[cfg(feature = "bincode")]
+impl From<bincode::Error> for MemcacheError {
+    fn from(err: bincode::Error) -> MemcacheError {
+        ParseError::Bincode(err).into()
+    }
+}
+
+#[cfg(feature = "msgpack")]
+impl From<rmp_serde::decode::Error> for MemcacheError {
+    fn from(err: rmp_serde::decode::Error) -> MemcacheError {
+        ParseError::MsgPack(err).into()
+    }
+}
+
 /// Stands for errors raised from rust-memcache
+///
+/// The error wrapped by a variant, whose message is the message of the variant, is its `source`,
+/// so the underlying errors can be walked and downcast.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum MemcacheError {
     /// Error raised when the provided memcache URL doesn't have a host name
-    #[cfg(feature = "tls")]
     BadURL(String),
     /// `std::io` related errors.
     IOError(io::Error),
@@ -240,6 +323,11 @@ pub enum MemcacheError {
     ParseError(ParseError),
     /// ConnectionPool errors
     PoolError(r2d2::Error),
+    /// A connection to a server couldn't be established
+    ConnectionError(ConnectionError),
+    /// An error with the server and the command it happened on, see
+    /// `ClientBuilder::with_error_context`.
+    ContextError(Box<ErrorContext>),
 }
 
 impl fmt::Display for MemcacheError {
@@ -254,6 +342,8 @@ impl fmt::Display for MemcacheError {
             MemcacheError::ServerError(ref err) => err.fmt(f),
             MemcacheError::CommandError(ref err) => err.fmt(f),
             MemcacheError::PoolError(ref err) => err.fmt(f),
+            MemcacheError::ConnectionError(ref err) => err.fmt(f),
+            MemcacheError::ContextError(ref context) => context.fmt(f),
         }
     }
 }
@@ -261,20 +351,263 @@ impl fmt::Display for MemcacheError {
 impl error::Error for MemcacheError {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match *self {
-            #[cfg(feature = "tls")]
             MemcacheError::BadURL(_) => None,
-            MemcacheError::IOError(ref err) => err.source(),
+            MemcacheError::IOError(ref err) => Some(err),
             #[cfg(feature = "tls")]
-            MemcacheError::OpensslError(ref err) => err.source(),
-            MemcacheError::ParseError(ref p) => p.source(),
-            MemcacheError::ClientError(_) => None,
-            MemcacheError::ServerError(_) => None,
-            MemcacheError::CommandError(_) => None,
-            MemcacheError::PoolError(ref p) => p.source(),
+            MemcacheError::OpensslError(ref err) => Some(err),
+            MemcacheError::ParseError(ref p) => Some(p),
+            MemcacheError::ClientError(ref err) => Some(err),
+            MemcacheError::ServerError(ref err) => Some(err),
+            MemcacheError::CommandError(ref err) => Some(err),
+            MemcacheError::PoolError(ref p) => Some(p),
+            MemcacheError::ConnectionError(ref err) => Some(err.error()),
+            MemcacheError::ContextError(ref context) => Some(&context.error),
         }
     }
 }
 
+/// The phase of establishing a connection which failed, see `ConnectionError`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ConnectPhase {
+    /// Resolving the host name of the server.
+    Resolve,
+    /// Connecting the socket and setting its options.
+    Connect,
+    /// Loading the certificates and the TLS handshake.
+    Tls,
+    /// Fetching the credentials and authenticating.
+    Auth,
+}
+
+impl fmt::Display for ConnectPhase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let phase = match *self {
+            ConnectPhase::Resolve => "resolving",
+            ConnectPhase::Connect => "connecting to",
+            ConnectPhase::Tls => "TLS handshake with",
+            ConnectPhase::Auth => "authenticating to",
+        };
+        f.write_str(phase)
+    }
+}
+
+/// The failure to establish a connection to a server, and the phase it failed in. When no pooled
+/// connection can be checked out because new connections fail, this is the error returned
+/// instead of the timeout of the pool.
+#[derive(Debug, Clone)]
+pub struct ConnectionError {
+    pub phase: ConnectPhase,
+    /// The host and port of the server, or the path of its unix socket.
+    pub address: String,
+    error: Arc<MemcacheError>,
+}
+
+impl ConnectionError {
+    pub(crate) fn new(phase: ConnectPhase, address: String, error: MemcacheError) -> Self {
+        ConnectionError {
+            phase,
+            address,
+            error: Arc::new(error),
+        }
+    }
+
+    /// The error the phase failed with.
+    pub fn error(&self) -> &MemcacheError {
+        &self.error
+    }
+}
+
+impl fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} failed: {}", self.phase, self.address, self.error)
+    }
+}
+
+impl From<ConnectionError> for MemcacheError {
+    fn from(err: ConnectionError) -> Self {
+        MemcacheError::ConnectionError(err)
+    }
+}
+
+/// The server and the command an error happened on, see `ClientBuilder::with_error_context`.
+#[derive(Debug)]
+pub struct ErrorContext {
+    pub error: MemcacheError,
+    /// The url of the server.
+    pub server: String,
+    pub command: Command,
+    /// The key sent to the server, `None` for the commands sent for several keys or if keys are
+    /// redacted.
+    pub key: Option<String>,
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.key {
+            Some(ref key) => write!(f, "{} {} on {}: {}", self.command, key, self.server, self.error),
+            None => write!(f, "{} on {}: {}", self.command, self.server, self.error),
+        }
+    }
+}
+
+/// The error of a call fetching several keys, like `Client::gets_with_timeout`, with the values
+/// fetched before it failed. It converts to its `MemcacheError` with `?`.
+#[derive(Debug)]
+pub struct PartialError<T> {
+    pub error: MemcacheError,
+    pub partial: T,
+}
+
+impl<T> fmt::Display for PartialError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl<T: fmt::Debug> error::Error for PartialError<T> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl<T> From<PartialError<T>> for MemcacheError {
+    fn from(err: PartialError<T>) -> Self {
+        err.error
+    }
+}
+
+/// The kind of a `MemcacheError`, the same whichever features are enabled, see
+/// `MemcacheError::kind`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// An invalid server url or url parameter.
+    BadUrl,
+    /// An IO error, like a timeout or a connection reset by the server.
+    Io,
+    /// A client side error, like an invalid key, see `ClientError`.
+    Client,
+    /// The server failed or sent an invalid response, see `ServerError`.
+    Server,
+    /// The server rejected the command, like a missing key, see `CommandError`.
+    Command,
+    /// The TLS configuration or handshake failed.
+    Tls,
+    /// A response or value couldn't be parsed, see `ParseError`.
+    Parse,
+    /// No pooled connection could be checked out in time.
+    Pool,
+    /// A connection to a server couldn't be established, see `ConnectionError`.
+    Connection,
+}
+
+/// The classes of transient errors, which may not happen again when the command is retried, see
+/// `MemcacheError::class` and `RetryPolicy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorClass {
+    /// The connection to the server failed or was closed, or no connection could be checked out
+    /// of the pool in time.
+    Connection,
+    /// The server didn't answer in time.
+    Timeout,
+    /// The server failed to run the command, like when it's out of memory.
+    Server,
+}
+
+impl MemcacheError {
+    /// The class of a transient error, `None` for the errors which would happen again, like
+    /// command errors or invalid keys.
+    pub fn class(&self) -> Option<ErrorClass> {
+        match *self.inner() {
+            MemcacheError::IOError(ref e) => match e.kind() {
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => Some(ErrorClass::Timeout),
+                io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::NotConnected
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::UnexpectedEof => Some(ErrorClass::Connection),
+                _ => None,
+            },
+            MemcacheError::ClientError(ClientError::DeadlineExceeded) => Some(ErrorClass::Timeout),
+            MemcacheError::ClientError(ClientError::ServerDead) => Some(ErrorClass::Connection),
+            // a rejected authentication would be rejected again
+            MemcacheError::ConnectionError(ref err) if err.phase == ConnectPhase::Auth => err.error().class(),
+            MemcacheError::ConnectionError(_) => Some(ErrorClass::Connection),
+            MemcacheError::PoolError(_) => Some(ErrorClass::Connection),
+            MemcacheError::ServerError(ServerError::Error(_)) => Some(ErrorClass::Server),
+            _ => None,
+        }
+    }
+
+    /// The kind of the error, or of the error in its context, to tell errors apart without
+    /// matching on the variants, some of which depend on the enabled features.
+    pub fn kind(&self) -> ErrorKind {
+        match *self.inner() {
+            MemcacheError::BadURL(_) => ErrorKind::BadUrl,
+            MemcacheError::IOError(_) => ErrorKind::Io,
+            MemcacheError::ClientError(_) => ErrorKind::Client,
+            MemcacheError::ServerError(_) => ErrorKind::Server,
+            MemcacheError::CommandError(_) => ErrorKind::Command,
+            #[cfg(feature = "tls")]
+            MemcacheError::OpensslError(_) => ErrorKind::Tls,
+            MemcacheError::ParseError(_) => ErrorKind::Parse,
+            MemcacheError::PoolError(_) => ErrorKind::Pool,
+            MemcacheError::ConnectionError(_) => ErrorKind::Connection,
+            // not returned by `inner`
+            MemcacheError::ContextError(ref context) => context.error.kind(),
+        }
+    }
+
+    /// A copy of the error, to report the failure of a server for each of its keys. The IO errors
+    /// keep their kind and message, and the errors which can't be copied, like pool errors, are
+    /// reported as a `ClientError::Error` with their message.
+    pub(crate) fn duplicate(&self) -> MemcacheError {
+        match *self {
+            MemcacheError::BadURL(ref url) => MemcacheError::BadURL(url.clone()),
+            MemcacheError::IOError(ref e) => io::Error::new(e.kind(), e.to_string()).into(),
+            MemcacheError::ClientError(ref e) => e.clone().into(),
+            MemcacheError::ServerError(ref e) => e.clone().into(),
+            MemcacheError::CommandError(ref e) => e.clone().into(),
+            MemcacheError::ConnectionError(ref e) => e.clone().into(),
+            MemcacheError::ContextError(ref context) => MemcacheError::ContextError(Box::new(ErrorContext {
+                error: context.error.duplicate(),
+                server: context.server.clone(),
+                command: context.command,
+                key: context.key.clone(),
+            })),
+            ref error => ClientError::Error(Cow::Owned(error.to_string())).into(),
+        }
+    }
+
+    /// The error without its context, if it has one, to match on its kind.
+    pub fn inner(&self) -> &MemcacheError {
+        match *self {
+            MemcacheError::ContextError(ref context) => context.error.inner(),
+            ref error => error,
+        }
+    }
+
+    /// Whether the error is transient, so the command may succeed if it's sent again, see
+    /// `RetryPolicy`.
+    pub fn is_retriable(&self) -> bool {
+        self.class().is_some()
+    }
+
+    /// Whether the server didn't answer in time, including `ClientError::DeadlineExceeded`.
+    pub fn is_timeout(&self) -> bool {
+        self.class() == Some(ErrorClass::Timeout)
+    }
+
+    /// Whether the connection to the server failed or was closed, or no pooled connection could
+    /// be checked out.
+    pub fn is_connection(&self) -> bool {
+        self.class() == Some(ErrorClass::Connection)
+    }
+}
+
 impl From<io::Error> for MemcacheError {
     fn from(err: io::Error) -> MemcacheError {
         MemcacheError::IOError(err)
@@ -300,3 +633,95 @@ impl From<r2d2::Error> for MemcacheError {
         MemcacheError::PoolError(err)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{ClientError, CommandError, ErrorClass, ErrorContext, ErrorKind, MemcacheError, ServerError};
+    use crate::metrics::Command;
+    use std::io;
+
+    #[test]
+    fn test_class() {
+        let timeout = MemcacheError::from(io::Error::from(io::ErrorKind::WouldBlock));
+        assert!(timeout.is_timeout() && timeout.is_retriable() && !timeout.is_connection());
+        let reset = MemcacheError::from(io::Error::from(io::ErrorKind::ConnectionReset));
+        assert!(reset.is_connection() && reset.is_retriable() && !reset.is_timeout());
+        assert!(MemcacheError::from(ClientError::DeadlineExceeded).is_timeout());
+        assert!(MemcacheError::from(ClientError::ServerDead).is_connection());
+        let server_error = MemcacheError::from(ServerError::Error("SERVER_ERROR out of memory".into()));
+        assert_eq!(server_error.class(), Some(ErrorClass::Server));
+        assert!(server_error.is_retriable());
+
+        assert!(!MemcacheError::from(CommandError::KeyNotFound).is_retriable());
+        assert!(!MemcacheError::from(ClientError::KeyTooLong).is_retriable());
+        assert!(!MemcacheError::from(io::Error::from(io::ErrorKind::InvalidData)).is_retriable());
+    }
+
+    #[test]
+    fn test_kind() {
+        assert_eq!(MemcacheError::BadURL("invalid".into()).kind(), ErrorKind::BadUrl);
+        assert_eq!(MemcacheError::from(CommandError::KeyExists).kind(), ErrorKind::Command);
+        let context = MemcacheError::ContextError(Box::new(ErrorContext {
+            error: ClientError::KeyTooLong.into(),
+            server: "memcache://localhost:12345".into(),
+            command: Command::Set,
+            key: None,
+        }));
+        assert_eq!(context.kind(), ErrorKind::Client);
+    }
+
+    #[test]
+    fn test_source() {
+        use std::error::Error;
+        let error = MemcacheError::from("x".parse::<u32>().unwrap_err());
+        let parse_error = error.source().unwrap();
+        assert!(parse_error.downcast_ref::<super::ParseError>().is_some());
+        assert!(parse_error.source().unwrap().is::<std::num::ParseIntError>());
+
+        let error = MemcacheError::from(ServerError::Error("SERVER_ERROR out of memory".into()));
+        let server_error = error.source().unwrap().downcast_ref::<ServerError>().unwrap();
+        assert_eq!(server_error.to_string(), "SERVER_ERROR out of memory");
+        assert!(MemcacheError::from(CommandError::KeyNotFound)
+            .source()
+            .unwrap()
+            .is::<CommandError>());
+        let error = MemcacheError::from(io::Error::from(io::ErrorKind::ConnectionReset));
+        assert!(error.source().unwrap().is::<io::Error>());
+    }
+
+    #[test]
+    fn test_context() {
+        let error = MemcacheError::ContextError(Box::new(ErrorContext {
+            error: io::Error::from(io::ErrorKind::ConnectionReset).into(),
+            server: "memcache://localhost:12345".into(),
+            command: Command::Get,
+            key: Some("foo".into()),
+        }));
+        assert!(error.is_connection());
+        assert!(matches!(error.inner(), MemcacheError::IOError(_)));
+        assert!(error.to_string().starts_with("get foo on memcache://localhost:12345: "));
+        assert!(std::error::Error::source(&error).is_some());
+    }
+
+    #[test]
+    fn test_duplicate() {
+        let error = MemcacheError::ContextError(Box::new(ErrorContext {
+            error: io::Error::from(io::ErrorKind::ConnectionReset).into(),
+            server: "memcache://localhost:12345".into(),
+            command: Command::Set,
+            key: None,
+        }));
+        let duplicate = error.duplicate();
+        assert!(duplicate.is_connection());
+        assert_eq!(duplicate.to_string(), error.to_string());
+
+        let error = MemcacheError::from(CommandError::KeyExists);
+        assert!(matches!(
+            error.duplicate(),
+            MemcacheError::CommandError(CommandError::KeyExists)
+        ));
+        let error = MemcacheError::from("x".parse::<u32>().unwrap_err());
+        assert_eq!(error.duplicate().kind(), ErrorKind::Client);
+        assert_eq!(error.duplicate().to_string(), error.to_string());
+    }
+}
@@ -0,0 +1,126 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Expirations up to this many seconds are relative to the current time, longer ones are
+/// interpreted by memcached as unix timestamps.
+pub(crate) const MAX_RELATIVE_EXPIRATION: u32 = 60 * 60 * 24 * 30;
+
+/// Raw expirations longer than 30 days but up to this many seconds, 10 years, are relative TTLs
+/// rather than timestamps, which would be before 1980.
+const MAX_CONVERTED_EXPIRATION: u32 = 60 * 60 * 24 * 365 * 10;
+
+/// The expiration of an item, accepted by the methods of `Client` storing a single key. It
+/// converts from the raw `u32` seconds of the memcached protocol, a `Duration` from now, or the
+/// `SystemTime` at which the item expires.
+///
+/// Example:
+///
+/// ```rust
+/// use std::time::{Duration, SystemTime};
+///
+/// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+/// client.set("foo", "bar", 10).unwrap();
+/// client.set("foo", "bar", Duration::from_secs(60 * 60 * 24 * 60)).unwrap();
+/// client.set("foo", "bar", SystemTime::now() + Duration::from_secs(60)).unwrap();
+/// # client.flush().unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expiration {
+    /// Seconds in the form of the memcached protocol: 0 never expires, up to 30 days is relative
+    /// to the current time, and longer is a unix timestamp. As memcached would take relative
+    /// expirations over 30 days for timestamps in 1970 and expire the item immediately, seconds
+    /// over 30 days and up to 10 years are converted to the timestamp of the current time plus
+    /// the seconds.
+    Raw(u32),
+    /// Expire after the duration, rounded up to whole seconds. Durations longer than 30 days are
+    /// converted to a unix timestamp, and a zero duration never expires, like `Raw(0)`.
+    After(Duration),
+    /// Expire at the given time. Times in the past expire the item immediately.
+    At(SystemTime),
+}
+
+impl Expiration {
+    /// The expiration in the form sent to the server.
+    pub fn exptime(&self) -> u32 {
+        match *self {
+            Expiration::Raw(seconds) if seconds > MAX_RELATIVE_EXPIRATION && seconds <= MAX_CONVERTED_EXPIRATION => {
+                Expiration::After(Duration::from_secs(u64::from(seconds))).exptime()
+            }
+            Expiration::Raw(seconds) => seconds,
+            Expiration::After(duration) => {
+                let seconds = duration.as_secs() + u64::from(duration.subsec_nanos() > 0);
+                if seconds <= u64::from(MAX_RELATIVE_EXPIRATION) {
+                    seconds as u32
+                } else {
+                    Expiration::At(SystemTime::now() + duration).exptime()
+                }
+            }
+            Expiration::At(time) => {
+                let timestamp = time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+                // timestamps up to 30 days would be taken as relative, they are all in the past anyway
+                timestamp.clamp(u64::from(MAX_RELATIVE_EXPIRATION) + 1, u64::from(u32::MAX)) as u32
+            }
+        }
+    }
+}
+
+/// The unix timestamp at which an item stored with `exptime` expires, or `None` if it never does.
+pub(crate) fn expires_at(exptime: u32) -> Option<u64> {
+    match exptime {
+        0 => None,
+        seconds if seconds <= MAX_RELATIVE_EXPIRATION => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+            Some(now.as_secs() + u64::from(seconds))
+        }
+        timestamp => Some(u64::from(timestamp)),
+    }
+}
+
+impl From<u32> for Expiration {
+    fn from(seconds: u32) -> Self {
+        Expiration::Raw(seconds)
+    }
+}
+
+impl From<Duration> for Expiration {
+    fn from(duration: Duration) -> Self {
+        Expiration::After(duration)
+    }
+}
+
+impl From<SystemTime> for Expiration {
+    fn from(time: SystemTime) -> Self {
+        Expiration::At(time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Expiration, MAX_RELATIVE_EXPIRATION};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn test_relative() {
+        assert_eq!(Expiration::from(10).exptime(), 10);
+        assert_eq!(Expiration::from(Duration::from_secs(10)).exptime(), 10);
+        assert_eq!(Expiration::from(Duration::from_millis(1500)).exptime(), 2);
+        assert_eq!(Expiration::from(Duration::from_secs(0)).exptime(), 0);
+    }
+
+    #[test]
+    fn test_absolute() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(Expiration::from(time).exptime(), 1_700_000_000);
+        assert_eq!(Expiration::from(UNIX_EPOCH).exptime(), MAX_RELATIVE_EXPIRATION + 1);
+
+        let sixty_days = Duration::from_secs(60 * 60 * 24 * 60);
+        let expected = (SystemTime::now() + sixty_days)
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as u32;
+        let exptime = Expiration::from(sixty_days).exptime();
+        assert!(exptime >= expected && exptime <= expected + 1);
+        let exptime = Expiration::from(sixty_days.as_secs() as u32).exptime();
+        assert!(exptime >= expected && exptime <= expected + 1);
+        assert_eq!(Expiration::from(1_700_000_000).exptime(), 1_700_000_000);
+    }
+}
@@ -0,0 +1,106 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::error::{ClientError, MemcacheError};
+
+/// How `Client` handles a server which fails, see `ClientBuilder::with_on_error`.
+///
+/// With `SkipDeadServers` and `RehashToLive`, a server whose command or connection fails with a
+/// connection error is marked as dead for the dead server timeout, after which it's tried again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum OnError {
+    /// Send every command to the server of its key, and fail the multi-key operations if one of
+    /// their servers fails. The default.
+    FailFast,
+    /// Fail the commands for the keys of a dead server with `ClientError::ServerDead` without
+    /// connecting to it, and treat the keys of the servers which are dead or fail as misses in
+    /// multi-gets like `Client::gets`.
+    SkipDeadServers,
+    /// Send the commands for the keys of a dead server to the live servers, like if it was
+    /// removed from the client, until it's tried again.
+    RehashToLive,
+}
+
+/// Which servers of a `Client` are dead, see `OnError`.
+pub(crate) struct ServerHealth {
+    dead_timeout: Duration,
+    /// When each server which failed is tried again.
+    dead_until: Vec<Mutex<Option<Instant>>>,
+}
+
+impl ServerHealth {
+    pub(crate) fn new(servers: usize, dead_timeout: Duration) -> Self {
+        ServerHealth {
+            dead_timeout,
+            dead_until: (0..servers).map(|_| Mutex::new(None)).collect(),
+        }
+    }
+
+    pub(crate) fn is_dead(&self, server: usize) -> bool {
+        let mut dead_until = self.dead_until[server].lock().unwrap();
+        match *dead_until {
+            Some(until) if Instant::now() < until => true,
+            Some(_) => {
+                *dead_until = None;
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Mark `server` as dead if `error` is a connection error.
+    pub(crate) fn record_failure(&self, server: usize, error: &MemcacheError) {
+        // the commands skipped for a dead server don't keep it dead
+        if let MemcacheError::ClientError(ClientError::ServerDead) = *error.inner() {
+            return;
+        }
+        if error.is_connection() {
+            *self.dead_until[server].lock().unwrap() = Some(Instant::now() + self.dead_timeout);
+        }
+    }
+
+    /// The live server for a key whose hash is `hash`, or `None` if every server is dead.
+    pub(crate) fn rehash(&self, hash: u64) -> Option<usize> {
+        let live: Vec<usize> = (0..self.dead_until.len())
+            .filter(|&server| !self.is_dead(server))
+            .collect();
+        match live.len() {
+            0 => None,
+            count => Some(live[(hash % count as u64) as usize]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ServerHealth;
+    use crate::error::{ClientError, CommandError, MemcacheError};
+    use std::io;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_server_health() {
+        let health = ServerHealth::new(3, Duration::from_millis(50));
+        let reset = MemcacheError::from(io::Error::from(io::ErrorKind::ConnectionReset));
+        health.record_failure(1, &MemcacheError::from(CommandError::KeyNotFound));
+        health.record_failure(2, &ClientError::ServerDead.into());
+        assert!(!health.is_dead(1) && !health.is_dead(2));
+        assert_eq!(health.rehash(4), Some(1));
+
+        health.record_failure(1, &reset);
+        assert!(health.is_dead(1));
+        // the keys are spread over the servers 0 and 2
+        assert_eq!(health.rehash(4), Some(0));
+        assert_eq!(health.rehash(5), Some(2));
+        health.record_failure(0, &reset);
+        health.record_failure(2, &reset);
+        assert_eq!(health.rehash(4), None);
+
+        // the servers are tried again after the dead server timeout
+        thread::sleep(Duration::from_millis(60));
+        assert!(!health.is_dead(1));
+        assert_eq!(health.rehash(4), Some(1));
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A value fetched by `Client::get_or_stale`, which is stale if its server is down.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MaybeStale<V> {
+    /// The value was fetched from its server.
+    Fresh(V),
+    /// The server of the key is down, and this value was fetched from it `age` ago.
+    Stale { value: V, age: Duration },
+}
+
+impl<V> MaybeStale<V> {
+    /// Whether the value was kept by the client while its server is down.
+    pub fn is_stale(&self) -> bool {
+        match self {
+            MaybeStale::Fresh(_) => false,
+            MaybeStale::Stale { .. } => true,
+        }
+    }
+
+    /// The value, fresh or stale.
+    pub fn into_inner(self) -> V {
+        match self {
+            MaybeStale::Fresh(value) | MaybeStale::Stale { value, .. } => value,
+        }
+    }
+}
+
+/// The values last fetched by `Client::get`, kept to be served by `Client::get_or_stale` while
+/// their servers are down, see `ClientBuilder::with_stale_fallback`.
+pub(crate) struct StaleFallback<T> {
+    capacity: usize,
+    state: Mutex<State<T>>,
+}
+
+struct State<T> {
+    /// The values by key, with when they were fetched and the generation of their entry in `order`.
+    values: HashMap<String, (T, Instant, u64)>,
+    /// The keys in the order they were fetched, the oldest are evicted first. Entries whose
+    /// generation doesn't match the value anymore are outdated.
+    order: VecDeque<(String, u64)>,
+    generation: u64,
+}
+
+impl<T> State<T> {
+    fn is_current(&self, key: &str, generation: u64) -> bool {
+        self.values.get(key).map(|&(_, _, current)| current) == Some(generation)
+    }
+}
+
+impl<T: Clone> StaleFallback<T> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        StaleFallback {
+            capacity,
+            state: Mutex::new(State {
+                values: HashMap::new(),
+                order: VecDeque::new(),
+                generation: 0,
+            }),
+        }
+    }
+
+    pub(crate) fn insert(&self, key: &str, value: T) {
+        let mut state = self.state.lock().unwrap();
+        state.generation += 1;
+        let generation = state.generation;
+        state
+            .values
+            .insert(key.to_string(), (value, Instant::now(), generation));
+        state.order.push_back((key.to_string(), generation));
+        while state.values.len() > self.capacity {
+            let (key, generation) = match state.order.pop_front() {
+                Some(entry) => entry,
+                None => break,
+            };
+            if state.is_current(&key, generation) {
+                state.values.remove(&key);
+            }
+        }
+        // drop the outdated entries, so refetching the same keys doesn't grow `order`
+        if state.order.len() > self.capacity.saturating_mul(2) {
+            let State {
+                ref values,
+                ref mut order,
+                ..
+            } = *state;
+            order.retain(|(key, generation)| values.get(key).map(|&(_, _, current)| current) == Some(*generation));
+        }
+    }
+
+    pub(crate) fn remove(&self, key: &str) {
+        self.state.lock().unwrap().values.remove(key);
+    }
+
+    /// The value of `key` and how long ago it was fetched.
+    pub(crate) fn get(&self, key: &str) -> Option<(T, Duration)> {
+        let state = self.state.lock().unwrap();
+        state
+            .values
+            .get(key)
+            .map(|(value, fetched, _)| (value.clone(), fetched.elapsed()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StaleFallback;
+
+    #[test]
+    fn test_eviction() {
+        let fallback = StaleFallback::new(2);
+        fallback.insert("foo", 1);
+        fallback.insert("bar", 2);
+        // refetching a key keeps it
+        for _ in 0..10 {
+            fallback.insert("foo", 3);
+        }
+        assert_eq!(fallback.get("foo").unwrap().0, 3);
+        assert_eq!(fallback.get("bar").unwrap().0, 2);
+        fallback.insert("baz", 4);
+        assert!(fallback.get("bar").is_none());
+        assert_eq!(fallback.get("baz").unwrap().0, 4);
+
+        fallback.remove("baz");
+        assert!(fallback.get("baz").is_none());
+        assert_eq!(fallback.get("foo").unwrap().0, 3);
+    }
+}
@@ -22,11 +22,13 @@ memcache = "*"
   - <input type="checkbox"  disabled checked/> TLS connection
 - <input type="checkbox"  disabled /> Encodings
   - <input type="checkbox"  disabled checked /> Typed interface
-  - <input type="checkbox"  disabled /> Automatically compress
+  - <input type="checkbox"  disabled checked /> Automatically compress (requires the `compression` feature, or `zstd` for zstd)
+  - <input type="checkbox"  disabled checked /> Checksum verification (requires the `checksum` feature)
   - <input type="checkbox"  disabled /> Automatically serialize to JSON / msgpack etc
 - <input type="checkbox"  disabled checked /> Mutiple server support with custom key hash algorithm
+- <input type="checkbox"  disabled checked /> Hashing of over-length keys (requires the `key-hashing` feature)
 - <input type="checkbox"  disabled checked /> Authority
-  - <input type="checkbox"  disabled checked /> Binary protocol (plain SASL authority)
+  - <input type="checkbox"  disabled checked /> Binary protocol (SASL PLAIN and SCRAM-SHA authority)
   - <input type="checkbox"  disabled checked /> ASCII protocol
 
 # Basic usage:
@@ -73,16 +75,73 @@ extern crate r2d2;
 extern crate rand;
 extern crate url;
 
+mod buffer_pool;
+#[cfg(feature = "checksum")]
+mod checksum;
+mod chunk;
 mod client;
+mod coalesce;
+mod compat;
+#[cfg(feature = "compression")]
+mod compression;
 mod connection;
+mod deferred;
 mod error;
+mod expiration;
+mod failover;
+mod fallback;
+mod lock;
+mod metrics;
+mod migrate;
+mod mirror;
+mod multiplex;
 mod protocol;
+mod rate_limit;
+mod retry;
+mod scan;
+mod schema;
 mod stream;
 mod value;
-
-pub use crate::client::{Client, Connectable};
-pub use crate::error::{ClientError, CommandError, MemcacheError, ServerError};
-pub use crate::value::{FromMemcacheValue, FromMemcacheValueExt, ToMemcacheValue};
+mod watch;
+mod worker_pool;
+
+#[cfg(feature = "checksum")]
+pub use crate::checksum::{Checksum, ChecksumAlgorithm};
+pub use crate::client::{
+    Client, ClientBuilder, Connectable, PartialDeletes, PartialGets, SizeStats, SlabStats, Stats, StoreResult,
+};
+pub use crate::compat::{JavaCompat, PythonClient, PythonCompat};
+#[cfg(feature = "compression")]
+pub use crate::compression::{Compression, CompressionAlgorithm};
+pub use crate::connection::{AuthProvider, Connection, ConnectionManager, ServerVersion};
+pub use crate::error::{
+    ClientError, CommandError, ConnectPhase, ConnectionError, ErrorClass, ErrorContext, ErrorKind, MemcacheError,
+    PartialError, ServerError,
+};
+pub use crate::expiration::Expiration;
+pub use crate::failover::OnError;
+pub use crate::fallback::MaybeStale;
+pub use crate::lock::{LockGuard, MemcacheLock};
+pub use crate::metrics::{Command, Counters, LatencyHistogram, SlowOperation};
+pub use crate::migrate::{migrate, migrate_all};
+pub use crate::multiplex::MultiplexedConnection;
+pub use crate::protocol::{BinaryRequest, BinaryResponse};
+pub use crate::rate_limit::RateLimiter;
+pub use crate::retry::RetryPolicy;
+pub use crate::scan::{CachedumpItem, KeyMetadata, KeyScan};
+pub use crate::schema::SchemaVersion;
+#[cfg(feature = "bincode")]
+pub use crate::value::Bincode;
+#[cfg(feature = "serde_json")]
+pub use crate::value::Json;
+#[cfg(feature = "msgpack")]
+pub use crate::value::MsgPack;
+pub use crate::value::{
+    BinaryNumber, FlagLayout, FromMemcacheValue, FromMemcacheValueExt, ToMemcacheValue, Transcoder, Value, ValueKind,
+};
+pub use crate::watch::{Watch, WatchEvent, WatchEventKind, WatchFilter};
+#[cfg(feature = "derive")]
+pub use memcache_derive::MemcacheValue;
 pub use r2d2::Error;
 
 /// Create a memcached client instance and connect to memcached server.
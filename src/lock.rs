@@ -0,0 +1,146 @@
+use std::fmt;
+use std::ops::Deref;
+use std::time::UNIX_EPOCH;
+
+use crate::client::Client;
+use crate::error::{CommandError, MemcacheError};
+use crate::expiration::Expiration;
+
+/// A lock held in memcached, as a lease which expires after its TTL unless it is extended, so a
+/// crashed owner can't hold it forever. The lock is acquired with `add` and stores a random owner
+/// token, and extending or releasing it is a check and set against the token, so an owner whose
+/// lease expired can't release the lock acquired by someone else in the meantime.
+///
+/// memcached doesn't replicate or persist items, so this is only suited for locks which can
+/// tolerate a lost lock after a server failure or eviction, like avoiding duplicated work.
+///
+/// Example:
+///
+/// ```rust
+/// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+/// if let Some(lock) = memcache::MemcacheLock::try_lock(&client, "lock:report", 30).unwrap() {
+///     assert!(memcache::MemcacheLock::try_lock(&client, "lock:report", 30).unwrap().is_none());
+///     // work which must not run concurrently
+///     assert!(lock.extend().unwrap());
+///     assert!(lock.unlock().unwrap());
+/// }
+/// # client.flush().unwrap();
+/// ```
+pub struct MemcacheLock<'a> {
+    client: &'a Client,
+    key: String,
+    token: String,
+    ttl: Expiration,
+}
+
+impl fmt::Debug for MemcacheLock<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MemcacheLock")
+            .field("key", &self.key)
+            .field("token", &self.token)
+            .field("ttl", &self.ttl)
+            .finish()
+    }
+}
+
+impl<'a> MemcacheLock<'a> {
+    /// Acquire the lock stored under `key` for `ttl`, or return `None` if it is held.
+    pub fn try_lock(
+        client: &'a Client,
+        key: &str,
+        ttl: impl Into<Expiration>,
+    ) -> Result<Option<MemcacheLock<'a>>, MemcacheError> {
+        let lock = MemcacheLock {
+            client,
+            key: key.to_string(),
+            token: format!("{:032x}", rand::random::<u128>()),
+            ttl: ttl.into(),
+        };
+        match client.add(&lock.key, lock.token.as_str(), lock.ttl) {
+            Ok(()) => Ok(Some(lock)),
+            Err(ref e) if matches!(e.inner(), MemcacheError::CommandError(CommandError::KeyExists)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The key the lock is stored under.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// The random token identifying this owner of the lock.
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// Renew the lease for another TTL. Returns `false` if the lock expired and is not held by this
+    /// owner anymore.
+    pub fn extend(&self) -> Result<bool, MemcacheError> {
+        self.store_if_owner(self.ttl)
+    }
+
+    /// Release the lock. Returns `false` if it expired and is not held by this owner anymore.
+    pub fn unlock(self) -> Result<bool, MemcacheError> {
+        self.release()
+    }
+
+    /// Wrap the lock in a guard which releases it when dropped.
+    pub fn guard(self) -> LockGuard<'a> {
+        LockGuard { lock: Some(self) }
+    }
+
+    fn release(&self) -> Result<bool, MemcacheError> {
+        // a timestamp in the past expires the item immediately, as a delete guarded by the cas
+        self.store_if_owner(Expiration::At(UNIX_EPOCH))
+    }
+
+    fn store_if_owner(&self, expiration: Expiration) -> Result<bool, MemcacheError> {
+        let cas = match self.client.get_with_cas::<String>(&self.key)? {
+            Some((token, cas)) if token == self.token => cas,
+            _ => return Ok(false),
+        };
+        self.client.cas(&self.key, self.token.as_str(), expiration, cas)
+    }
+}
+
+/// A `MemcacheLock` which is released when the guard is dropped, see `MemcacheLock::guard`.
+/// Errors while releasing the lock on drop are ignored, the lock expires after its TTL then.
+///
+/// Example:
+///
+/// ```rust
+/// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+/// {
+///     let _guard = memcache::MemcacheLock::try_lock(&client, "lock:job", 30).unwrap().unwrap().guard();
+///     assert!(memcache::MemcacheLock::try_lock(&client, "lock:job", 30).unwrap().is_none());
+/// }
+/// assert!(memcache::MemcacheLock::try_lock(&client, "lock:job", 30).unwrap().is_some());
+/// # client.flush().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct LockGuard<'a> {
+    lock: Option<MemcacheLock<'a>>,
+}
+
+impl<'a> LockGuard<'a> {
+    /// Release the lock now, and report whether it was still held, unlike dropping the guard.
+    pub fn unlock(mut self) -> Result<bool, MemcacheError> {
+        self.lock.take().unwrap().unlock()
+    }
+}
+
+impl<'a> Deref for LockGuard<'a> {
+    type Target = MemcacheLock<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        self.lock.as_ref().unwrap()
+    }
+}
+
+impl<'a> Drop for LockGuard<'a> {
+    fn drop(&mut self) {
+        if let Some(lock) = self.lock.take() {
+            let _ = lock.release();
+        }
+    }
+}
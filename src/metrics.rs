@@ -0,0 +1,384 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::protocol::StoreCommand;
+
+/// The commands whose latency is recorded by `Client::metrics`. The commands of batched
+/// operations, like `Client::gets` or `Client::deletes`, are recorded once per request sent to a
+/// server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Command {
+    Get,
+    Gets,
+    Set,
+    Add,
+    Replace,
+    Cas,
+    Append,
+    Prepend,
+    Delete,
+    Increment,
+    Decrement,
+    Touch,
+}
+
+const COMMANDS: [Command; 12] = [
+    Command::Get,
+    Command::Gets,
+    Command::Set,
+    Command::Add,
+    Command::Replace,
+    Command::Cas,
+    Command::Append,
+    Command::Prepend,
+    Command::Delete,
+    Command::Increment,
+    Command::Decrement,
+    Command::Touch,
+];
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Command::Get => "get",
+            Command::Gets => "gets",
+            Command::Set => "set",
+            Command::Add => "add",
+            Command::Replace => "replace",
+            Command::Cas => "cas",
+            Command::Append => "append",
+            Command::Prepend => "prepend",
+            Command::Delete => "delete",
+            Command::Increment => "incr",
+            Command::Decrement => "decr",
+            Command::Touch => "touch",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A command which took longer than the threshold of `ClientBuilder::with_slow_operation_hook`.
+#[derive(Debug)]
+pub struct SlowOperation<'a> {
+    pub command: Command,
+    /// The key sent to the server, or `None` for the commands sent for several keys.
+    pub key: Option<&'a str>,
+    /// The url of the server.
+    pub server: &'a str,
+    /// How long the command took, from the checkout of a pooled connection.
+    pub elapsed: Duration,
+}
+
+impl From<StoreCommand> for Command {
+    fn from(command: StoreCommand) -> Self {
+        match command {
+            StoreCommand::Cas => Command::Cas,
+            StoreCommand::Set => Command::Set,
+            StoreCommand::Add => Command::Add,
+            StoreCommand::Replace => Command::Replace,
+            StoreCommand::Append => Command::Append,
+            StoreCommand::Prepend => Command::Prepend,
+        }
+    }
+}
+
+/// The number of buckets of a histogram: bucket 0 counts the latencies under 1µs, and bucket `i`
+/// those from 2^(i-1) up to 2^i µs, the last one also counting longer latencies.
+const BUCKETS: usize = 32;
+
+fn bucket(latency: Duration) -> usize {
+    let micros = latency.as_micros().min(u128::from(u64::MAX)) as u64;
+    ((64 - micros.leading_zeros()) as usize).min(BUCKETS - 1)
+}
+
+/// A snapshot of the latencies recorded for a command, see `Client::metrics`. Latencies are
+/// counted in buckets whose bounds double, so percentiles are approximate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    buckets: [u64; BUCKETS],
+    count: u64,
+    total: Duration,
+    max: Duration,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        LatencyHistogram {
+            buckets: [0; BUCKETS],
+            count: 0,
+            total: Duration::from_secs(0),
+            max: Duration::from_secs(0),
+        }
+    }
+}
+
+impl LatencyHistogram {
+    /// The number of recorded latencies.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The mean latency, zero if none was recorded.
+    pub fn mean(&self) -> Duration {
+        match self.count {
+            0 => Duration::from_secs(0),
+            count => Duration::from_nanos((self.total.as_nanos() / u128::from(count)) as u64),
+        }
+    }
+
+    /// The longest recorded latency.
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// The latency under which the `quantile` of the latencies are, between 0.0 and 1.0, like 0.99
+    /// for the p99. It is the upper bound of the bucket holding the quantile, at most `max`.
+    pub fn quantile(&self, quantile: f64) -> Duration {
+        if self.count == 0 {
+            return Duration::from_secs(0);
+        }
+        let rank = ((quantile.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_micros(1 << i).min(self.max);
+            }
+        }
+        self.max
+    }
+
+    /// Add the latencies of `other`, like those of another server.
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        for (bucket, count) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *bucket += count;
+        }
+        self.count += other.count;
+        self.total += other.total;
+        self.max = self.max.max(other.max);
+    }
+}
+
+/// The latencies of a command on a server, recorded with relaxed atomics.
+struct AtomicHistogram {
+    buckets: [AtomicU64; BUCKETS],
+    total_nanos: AtomicU64,
+    max_nanos: AtomicU64,
+}
+
+impl AtomicHistogram {
+    fn new() -> Self {
+        AtomicHistogram {
+            buckets: Default::default(),
+            total_nanos: AtomicU64::new(0),
+            max_nanos: AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self, latency: Duration) {
+        let nanos = latency.as_nanos().min(u128::from(u64::MAX)) as u64;
+        self.buckets[bucket(latency)].fetch_add(1, Ordering::Relaxed);
+        self.total_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.max_nanos.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> LatencyHistogram {
+        let mut histogram = LatencyHistogram::default();
+        for (bucket, count) in histogram.buckets.iter_mut().zip(self.buckets.iter()) {
+            *bucket = count.load(Ordering::Relaxed);
+        }
+        histogram.count = histogram.buckets.iter().sum();
+        histogram.total = Duration::from_nanos(self.total_nanos.load(Ordering::Relaxed));
+        histogram.max = Duration::from_nanos(self.max_nanos.load(Ordering::Relaxed));
+        histogram
+    }
+}
+
+/// The hits, misses and errors of the commands fetching keys, like `Client::get` or
+/// `Client::gets`, see `Client::counters`. Hits are the keys found on the servers, even if their
+/// value couldn't be decoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counters {
+    pub hits: u64,
+    pub misses: u64,
+    /// The failed requests, including the connections which couldn't be checked out.
+    pub errors: u64,
+}
+
+impl Counters {
+    /// The ratio of hits among the fetched keys, zero if none was fetched.
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            fetched => self.hits as f64 / fetched as f64,
+        }
+    }
+
+    fn add(&mut self, other: &Counters) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.errors += other.errors;
+    }
+}
+
+#[derive(Default)]
+struct AtomicCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    errors: AtomicU64,
+}
+
+/// The counters of every server, always recorded since they only cost a few relaxed atomic adds.
+pub(crate) struct ServerCounters {
+    urls: Vec<String>,
+    servers: Vec<AtomicCounters>,
+}
+
+impl ServerCounters {
+    pub(crate) fn new(urls: Vec<String>) -> Self {
+        ServerCounters {
+            servers: urls.iter().map(|_| AtomicCounters::default()).collect(),
+            urls,
+        }
+    }
+
+    pub(crate) fn url(&self, server: usize) -> &str {
+        &self.urls[server]
+    }
+
+    pub(crate) fn record_fetch(&self, server: usize, hits: usize, misses: usize) {
+        let counters = &self.servers[server];
+        counters.hits.fetch_add(hits as u64, Ordering::Relaxed);
+        counters.misses.fetch_add(misses as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_error(&self, server: usize) {
+        self.servers[server].errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The counters by server url.
+    pub(crate) fn snapshot(&self) -> HashMap<String, Counters> {
+        let mut snapshot = HashMap::new();
+        for (url, counters) in self.urls.iter().zip(self.servers.iter()) {
+            let counters = Counters {
+                hits: counters.hits.load(Ordering::Relaxed),
+                misses: counters.misses.load(Ordering::Relaxed),
+                errors: counters.errors.load(Ordering::Relaxed),
+            };
+            // the same url may be added more than once
+            snapshot
+                .entry(url.clone())
+                .or_insert_with(Counters::default)
+                .add(&counters);
+        }
+        snapshot
+    }
+
+    /// The sum of the counters of every server.
+    pub(crate) fn total(&self) -> Counters {
+        let mut total = Counters::default();
+        for counters in self.snapshot().values() {
+            total.add(counters);
+        }
+        total
+    }
+}
+
+/// The latency histograms of every command on every server, see `ClientBuilder::with_metrics`.
+pub(crate) struct Metrics {
+    urls: Vec<String>,
+    servers: Vec<Vec<AtomicHistogram>>,
+}
+
+impl Metrics {
+    pub(crate) fn new(urls: Vec<String>) -> Self {
+        Metrics {
+            servers: urls
+                .iter()
+                .map(|_| COMMANDS.iter().map(|_| AtomicHistogram::new()).collect())
+                .collect(),
+            urls,
+        }
+    }
+
+    pub(crate) fn record(&self, server: usize, command: Command, latency: Duration) {
+        self.servers[server][command as usize].record(latency);
+    }
+
+    /// The histograms of the commands recorded at least once, by server url and command.
+    pub(crate) fn snapshot(&self) -> HashMap<(String, Command), LatencyHistogram> {
+        let mut snapshot = HashMap::new();
+        for (url, histograms) in self.urls.iter().zip(self.servers.iter()) {
+            for (command, histogram) in COMMANDS.iter().zip(histograms) {
+                let histogram = histogram.snapshot();
+                if histogram.count > 0 {
+                    // the same url may be added more than once
+                    snapshot
+                        .entry((url.clone(), *command))
+                        .or_insert_with(LatencyHistogram::default)
+                        .merge(&histogram);
+                }
+            }
+        }
+        snapshot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Command, Counters, Metrics, ServerCounters};
+    use std::time::Duration;
+
+    #[test]
+    fn test_histogram() {
+        let metrics = Metrics::new(vec!["memcache://a".into(), "memcache://b".into()]);
+        for micros in 1..=100 {
+            metrics.record(1, Command::Get, Duration::from_micros(micros));
+        }
+        metrics.record(1, Command::Set, Duration::from_secs(5));
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        let get = &snapshot[&("memcache://b".to_string(), Command::Get)];
+        assert_eq!(get.count(), 100);
+        assert_eq!(get.max(), Duration::from_micros(100));
+        assert_eq!(get.mean(), Duration::from_nanos(50_500));
+        // the median, 50µs, is in the bucket up to 64µs
+        assert_eq!(get.quantile(0.5), Duration::from_micros(64));
+        assert_eq!(get.quantile(0.99), Duration::from_micros(100));
+        assert_eq!(get.quantile(0.0), Duration::from_micros(2));
+
+        let mut merged = get.clone();
+        merged.merge(&snapshot[&("memcache://b".to_string(), Command::Set)]);
+        assert_eq!(merged.count(), 101);
+        assert_eq!(merged.max(), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_counters() {
+        let counters = ServerCounters::new(vec![
+            "memcache://a".into(),
+            "memcache://b".into(),
+            "memcache://a".into(),
+        ]);
+        counters.record_fetch(0, 3, 1);
+        counters.record_fetch(1, 0, 2);
+        counters.record_fetch(2, 1, 0);
+        counters.record_error(1);
+        let snapshot = counters.snapshot();
+        assert_eq!(
+            snapshot["memcache://a"],
+            Counters {
+                hits: 4,
+                misses: 1,
+                errors: 0
+            }
+        );
+        assert_eq!(snapshot["memcache://b"].errors, 1);
+        let total = counters.total();
+        assert_eq!((total.hits, total.misses, total.errors), (4, 3, 1));
+        assert!((total.hit_rate() - 4.0 / 7.0).abs() < f64::EPSILON);
+        assert_eq!(Counters::default().hit_rate(), 0.0);
+    }
+}
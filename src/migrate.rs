@@ -0,0 +1,60 @@
+use crate::client::Client;
+use crate::error::MemcacheError;
+
+/// Copy `keys` from the servers of `src` to the servers of `dst`, with their flags and remaining
+/// TTLs, to warm up a cluster before failing over to it. The values are copied as stored, without
+/// decoding them, except that the values split into chunks by `src` are reassembled and split
+/// again by `dst` if it has chunking enabled, see `ClientBuilder::with_chunking`. Returns how many
+/// keys were copied, the missing keys are skipped.
+///
+/// The remaining TTLs are fetched with meta commands, so `src` must use the ascii protocol and
+/// memcached 1.6 or later. Fails before copying any key if a server of `src` doesn't.
+///
+/// Example:
+///
+/// ```rust
+/// let src = memcache::Client::connect("memcache://localhost:12345?protocol=ascii").unwrap();
+/// let dst = memcache::Client::connect("memcache://localhost:12345").unwrap();
+/// src.set("foo", "bar", 60).unwrap();
+/// assert_eq!(memcache::migrate(&src, &dst, &["foo"]).unwrap(), 1);
+/// # src.flush().unwrap();
+/// ```
+pub fn migrate<K: AsRef<str>>(src: &Client, dst: &Client, keys: &[K]) -> Result<usize, MemcacheError> {
+    src.check_copy_source()?;
+    let mut copied = 0;
+    for key in keys {
+        if src.copy_key(dst, key.as_ref(), false)? {
+            copied += 1;
+        }
+    }
+    Ok(copied)
+}
+
+/// Copy every key stored on the servers of `src` to the servers of `dst`, like `migrate`, listing
+/// the keys with `Client::scan_keys`. The keys are copied as stored on the servers, without the
+/// key encoding of the clients. The keys are listed before their values are copied, as the
+/// listing holds a connection of the pool of each server.
+///
+/// Example:
+///
+/// ```rust
+/// let src = memcache::Client::connect("memcache://localhost:12345?protocol=ascii").unwrap();
+/// let dst = memcache::Client::connect("memcache://localhost:12345").unwrap();
+/// src.set("foo", "bar", 60).unwrap();
+/// assert!(memcache::migrate_all(&src, &dst).unwrap() >= 1);
+/// # src.flush().unwrap();
+/// ```
+pub fn migrate_all(src: &Client, dst: &Client) -> Result<usize, MemcacheError> {
+    src.check_copy_source()?;
+    let keys = src
+        .scan_keys()
+        .map(|metadata| metadata.map(|metadata| metadata.key))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut copied = 0;
+    for key in keys {
+        if src.copy_key(dst, &key, true)? {
+            copied += 1;
+        }
+    }
+    Ok(copied)
+}
@@ -0,0 +1,105 @@
+use std::io::{self, Read};
+use std::sync::mpsc::{self, SyncSender};
+use std::thread;
+
+use crate::client::Client;
+use crate::metrics::Command;
+
+/// How many mirrored commands may wait to be sent, the following ones are dropped.
+const QUEUE_SIZE: usize = 10_000;
+
+/// A command sent to the primary servers, to send to the mirror as well.
+pub(crate) enum Mirrored {
+    /// A `Command::Set`, `Command::Add`, `Command::Replace`, `Command::Append` or
+    /// `Command::Prepend` of an encoded value.
+    Store {
+        command: Command,
+        key: String,
+        data: Vec<u8>,
+        flags: u32,
+        expiration: u32,
+    },
+    /// A set of a key which isn't valid UTF-8, see `Client::set_bytes_key`.
+    SetBytesKey {
+        key: Vec<u8>,
+        data: Vec<u8>,
+        flags: u32,
+        expiration: u32,
+    },
+    Delete(String),
+    DeleteBytesKey(Vec<u8>),
+    Touch(String, u32),
+    Increment(String, u64),
+    Decrement(String, u64),
+    Get(String),
+    Flush,
+    FlushWithDelay(u32),
+}
+
+/// Sends the commands of a `Client` to a second one from a background thread, see
+/// `ClientBuilder::with_mirror`.
+pub(crate) struct Mirror {
+    sender: SyncSender<Mirrored>,
+    read_sample_rate: f64,
+}
+
+impl Mirror {
+    /// Start the thread sending the mirrored commands to `secondary`, which stops once the mirror
+    /// is dropped.
+    pub(crate) fn start(secondary: Client, read_sample_rate: f64) -> io::Result<Self> {
+        let (sender, receiver) = mpsc::sync_channel::<Mirrored>(QUEUE_SIZE);
+        thread::Builder::new().name("memcache-mirror".into()).spawn(move || {
+            for command in receiver {
+                // nobody waits for the mirrored commands, they can only fail silently
+                let _ = secondary.replay(command);
+            }
+        })?;
+        Ok(Mirror {
+            sender,
+            read_sample_rate,
+        })
+    }
+
+    /// Queue `command`, or drop it if the queue is full, so a slow mirror doesn't slow down the
+    /// client.
+    pub(crate) fn send(&self, command: Mirrored) {
+        let _ = self.sender.try_send(command);
+    }
+
+    /// Queue the get of `key` for the sampled part of the reads.
+    pub(crate) fn sample_read(&self, key: &str) {
+        if self.read_sample_rate > 0.0 && rand::random::<f64>() < self.read_sample_rate {
+            self.send(Mirrored::Get(key.to_string()));
+        }
+    }
+}
+
+/// A reader keeping a copy of what it reads for the mirror, see `Client::set_from_reader`.
+pub(crate) struct CopyingReader<R> {
+    reader: R,
+    copy: Option<Vec<u8>>,
+}
+
+impl<R: Read> CopyingReader<R> {
+    /// Read from `reader`, and keep a copy of the data if `copy` is set.
+    pub(crate) fn new(reader: R, copy: bool) -> Self {
+        CopyingReader {
+            reader,
+            copy: if copy { Some(Vec::new()) } else { None },
+        }
+    }
+
+    pub(crate) fn into_copy(self) -> Option<Vec<u8>> {
+        self.copy
+    }
+}
+
+impl<R: Read> Read for CopyingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        if let Some(ref mut copy) = self.copy {
+            copy.extend_from_slice(&buf[..read]);
+        }
+        Ok(read)
+    }
+}
@@ -0,0 +1,327 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use url::Url;
+
+use crate::connection::is_ascii;
+use crate::error::{ClientError, CommandError, MemcacheError};
+use crate::expiration::Expiration;
+use crate::protocol::{parse_response, write_request, BinaryRequest, BinaryResponse, Opcode};
+use crate::value::{FromMemcacheValueExt, ToMemcacheValue};
+
+type Reply = mpsc::Sender<Result<BinaryResponse, MemcacheError>>;
+
+/// A binary protocol connection to a single server, shared by any number of threads which each
+/// have requests in flight at the same time. Every request is tagged with a distinct opaque value,
+/// which the server copies to its response, and a background thread reads the responses and hands
+/// them to the waiting callers, so one connection per server can serve many concurrent requests
+/// without a large connection pool.
+///
+/// The `timeout` url parameter limits how many seconds a request waits for its response. SASL
+/// authentication, TLS and unix sockets are not supported.
+///
+/// Example:
+///
+/// ```rust
+/// let connection = std::sync::Arc::new(memcache::MultiplexedConnection::connect("memcache://localhost:12345").unwrap());
+/// let workers: Vec<_> = (0..4)
+///     .map(|i| {
+///         let connection = connection.clone();
+///         std::thread::spawn(move || {
+///             let key = format!("multiplexed:{}", i);
+///             connection.set(&key, i, 10).unwrap();
+///             assert_eq!(connection.get::<u32>(&key).unwrap(), Some(i));
+///             assert!(connection.delete(&key).unwrap());
+///         })
+///     })
+///     .collect();
+/// for worker in workers {
+///     worker.join().unwrap();
+/// }
+/// ```
+pub struct MultiplexedConnection {
+    shared: Arc<Shared>,
+    stream: TcpStream,
+    timeout: Option<Duration>,
+    reader: Option<JoinHandle<()>>,
+}
+
+struct Shared {
+    writer: Mutex<BufWriter<TcpStream>>,
+    pending: Mutex<Pending>,
+    next_opaque: AtomicU32,
+}
+
+/// The callers waiting for a response, by the opaque of their request, or why the connection was
+/// closed.
+#[derive(Default)]
+struct Pending {
+    waiting: HashMap<u32, Reply>,
+    closed: Option<String>,
+}
+
+impl fmt::Debug for MultiplexedConnection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MultiplexedConnection")
+            .field("stream", &self.stream)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+fn closed_error(reason: &str) -> MemcacheError {
+    io::Error::new(
+        io::ErrorKind::ConnectionAborted,
+        format!("multiplexed connection closed: {}", reason),
+    )
+    .into()
+}
+
+impl MultiplexedConnection {
+    /// Connect to the server with the given url.
+    pub fn connect(url: &str) -> Result<Self, MemcacheError> {
+        let url = Url::parse(url)?;
+        if url.scheme() != "memcache" && url.scheme() != "memcache+tcp" {
+            return Err(MemcacheError::BadURL(
+                "multiplexed connections only support the memcache and memcache+tcp schemes".into(),
+            ));
+        }
+        if is_ascii(&url)? {
+            return Err(MemcacheError::BadURL(
+                "multiplexed connections only support the binary protocol".into(),
+            ));
+        }
+        let timeout = url
+            .query_pairs()
+            .find(|(k, _)| k == "timeout")
+            .and_then(|(_, v)| v.parse::<u64>().ok())
+            .map(Duration::from_secs);
+        let stream = TcpStream::connect(&*url.socket_addrs(|| None)?)?;
+        stream.set_nodelay(true)?;
+        stream.set_write_timeout(timeout)?;
+        let shared = Arc::new(Shared {
+            writer: Mutex::new(BufWriter::new(stream.try_clone()?)),
+            pending: Mutex::new(Pending::default()),
+            next_opaque: AtomicU32::new(0),
+        });
+        let reader = {
+            let shared = shared.clone();
+            let stream = BufReader::new(stream.try_clone()?);
+            thread::Builder::new()
+                .name("memcache-multiplexed-reader".into())
+                .spawn(move || read_responses(&shared, stream))?
+        };
+        Ok(MultiplexedConnection {
+            shared,
+            stream,
+            timeout,
+            reader: Some(reader),
+        })
+    }
+
+    /// Send a raw request and wait for its response. The opaque of the request is replaced, it is
+    /// used to match the response with the request. Quiet opcodes which the server doesn't answer
+    /// wait until the timeout.
+    pub fn request(&self, mut request: BinaryRequest) -> Result<BinaryResponse, MemcacheError> {
+        request.opaque = self.shared.next_opaque.fetch_add(1, Ordering::Relaxed);
+        let (reply, response) = mpsc::channel();
+        {
+            let mut pending = self.shared.pending.lock().unwrap();
+            if let Some(ref reason) = pending.closed {
+                return Err(closed_error(reason));
+            }
+            pending.waiting.insert(request.opaque, reply);
+        }
+        let written = {
+            let mut writer = self.shared.writer.lock().unwrap();
+            write_request(&mut *writer, &request).and_then(|_| Ok(writer.flush()?))
+        };
+        if let Err(e) = written {
+            self.shared.pending.lock().unwrap().waiting.remove(&request.opaque);
+            return Err(e);
+        }
+        let response = match self.timeout {
+            Some(timeout) => response.recv_timeout(timeout),
+            None => response.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match response {
+            Ok(response) => response,
+            Err(RecvTimeoutError::Timeout) => {
+                self.shared.pending.lock().unwrap().waiting.remove(&request.opaque);
+                Err(io::Error::from(io::ErrorKind::TimedOut).into())
+            }
+            Err(RecvTimeoutError::Disconnected) => Err(closed_error("the response was lost")),
+        }
+    }
+
+    /// Get a key, like `Client::get` without the client's transcoders and key rewriting.
+    pub fn get<V: FromMemcacheValueExt>(&self, key: &str) -> Result<Option<V>, MemcacheError> {
+        let response = self.request(BinaryRequest {
+            opcode: Opcode::Get as u8,
+            key: key.as_bytes().to_vec(),
+            ..Default::default()
+        })?;
+        match response.status {
+            0 => {
+                let flags = match response.extras.get(..4) {
+                    Some(flags) => u32::from_be_bytes(flags.try_into().unwrap()),
+                    None => return Err(ClientError::Error(Cow::Borrowed("get response without flags")).into()),
+                };
+                Ok(Some(V::from_memcache_value(response.value, flags, Some(response.cas))?))
+            }
+            status => match CommandError::from(status) {
+                CommandError::KeyNotFound => Ok(None),
+                e => Err(e.into()),
+            },
+        }
+    }
+
+    /// Set a key, like `Client::set` without the client's transcoders and key rewriting.
+    pub fn set<V: ToMemcacheValue<Vec<u8>>>(
+        &self,
+        key: &str,
+        value: V,
+        expiration: impl Into<Expiration>,
+    ) -> Result<(), MemcacheError> {
+        let mut data = Vec::new();
+        value.write_to(&mut data)?;
+        let mut extras = Vec::with_capacity(8);
+        extras.extend_from_slice(&value.get_flags().to_be_bytes());
+        extras.extend_from_slice(&expiration.into().exptime().to_be_bytes());
+        let response = self.request(BinaryRequest {
+            opcode: Opcode::Set as u8,
+            key: key.as_bytes().to_vec(),
+            extras,
+            value: data,
+            ..Default::default()
+        })?;
+        match response.status {
+            0 => Ok(()),
+            status => Err(CommandError::from(status).into()),
+        }
+    }
+
+    /// Delete a key, returning whether it existed.
+    pub fn delete(&self, key: &str) -> Result<bool, MemcacheError> {
+        let response = self.request(BinaryRequest {
+            opcode: Opcode::Delete as u8,
+            key: key.as_bytes().to_vec(),
+            ..Default::default()
+        })?;
+        match response.status {
+            0 => Ok(true),
+            status => match CommandError::from(status) {
+                CommandError::KeyNotFound => Ok(false),
+                e => Err(e.into()),
+            },
+        }
+    }
+}
+
+impl Drop for MultiplexedConnection {
+    fn drop(&mut self) {
+        // unblock the reader thread
+        let _ = self.stream.shutdown(Shutdown::Both);
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+    }
+}
+
+/// Hand the responses to the callers waiting for them, until the connection is closed, and then
+/// fail the requests still waiting.
+fn read_responses(shared: &Shared, mut stream: BufReader<TcpStream>) {
+    let error = loop {
+        let response: BinaryResponse = match parse_response(&mut stream) {
+            Ok(response) => response.into(),
+            Err(e) => break e,
+        };
+        // the caller may have given up waiting after a timeout
+        if let Some(reply) = shared.pending.lock().unwrap().waiting.remove(&response.opaque) {
+            let _ = reply.send(Ok(response));
+        }
+    };
+    let mut pending = shared.pending.lock().unwrap();
+    let reason = error.to_string();
+    for (_, reply) in pending.waiting.drain() {
+        let _ = reply.send(Err(closed_error(&reason)));
+    }
+    pending.closed = Some(reason);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MultiplexedConnection;
+    use crate::protocol::{parse_response, Opcode};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+
+    /// Read a request packet and return its opcode, key and opaque.
+    fn read_request<R: Read>(reader: &mut R) -> (u8, Vec<u8>, u32) {
+        let mut header = [0u8; 24];
+        reader.read_exact(&mut header).unwrap();
+        let key_length = u16::from_be_bytes([header[2], header[3]]) as usize;
+        let body_length = u32::from_be_bytes([header[8], header[9], header[10], header[11]]) as usize;
+        let opaque = u32::from_be_bytes([header[12], header[13], header[14], header[15]]);
+        let mut body = vec![0u8; body_length];
+        reader.read_exact(&mut body).unwrap();
+        let extras_length = header[4] as usize;
+        (
+            header[1],
+            body[extras_length..extras_length + key_length].to_vec(),
+            opaque,
+        )
+    }
+
+    fn write_get_response<W: Write>(writer: &mut W, opaque: u32, value: &[u8]) {
+        let mut packet = vec![0x81, Opcode::Get as u8, 0, 0, 4, 0, 0, 0];
+        packet.extend_from_slice(&(4 + value.len() as u32).to_be_bytes());
+        packet.extend_from_slice(&opaque.to_be_bytes());
+        packet.extend_from_slice(&[0; 8]);
+        packet.extend_from_slice(&[0; 4]);
+        packet.extend_from_slice(value);
+        writer.write_all(&packet).unwrap();
+    }
+
+    #[test]
+    fn test_out_of_order_responses() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("memcache://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let first = read_request(&mut socket);
+            let second = read_request(&mut socket);
+            // answer in the reverse order, with the key as the value
+            write_get_response(&mut socket, second.2, &second.1);
+            write_get_response(&mut socket, first.2, &first.1);
+            assert!(parse_response(&mut socket).is_err());
+        });
+
+        let connection = Arc::new(MultiplexedConnection::connect(&url).unwrap());
+        let clients: Vec<_> = ["foo", "bar"]
+            .iter()
+            .map(|key| {
+                let connection = connection.clone();
+                thread::spawn(move || {
+                    let value: Option<String> = connection.get(key).unwrap();
+                    assert_eq!(value.as_deref(), Some(*key));
+                })
+            })
+            .collect();
+        for client in clients {
+            client.join().unwrap();
+        }
+        drop(connection);
+        server.join().unwrap();
+    }
+}
@@ -1,11 +1,11 @@
 use std::collections::HashMap;
-use std::fmt;
 use std::io::{Read, Write};
 
-use super::ProtocolTrait;
-use crate::client::Stats;
+use super::{GetsCallback, ProtocolTrait, QuietCommand, StoreCommand, StoreResults, StoredValue};
+use crate::buffer_pool;
+use crate::client::{Stats, StoreResult};
 use crate::error::{ClientError, CommandError, MemcacheError, ServerError};
-use crate::stream::Stream;
+use crate::stream::{write_all_vectored, Stream};
 use crate::value::{FromMemcacheValueExt, ToMemcacheValue};
 use std::borrow::Cow;
 
@@ -17,35 +17,66 @@ pub struct Options {
     pub cas: Option<u64>,
 }
 
-#[derive(PartialEq)]
-enum StoreCommand {
-    Cas,
-    Set,
-    Add,
-    Replace,
-    Append,
-    Prepend,
+const END: &'static str = "END\r\n";
+
+/// Reads the lines of responses through a fixed buffer, which caps their length. The buffered
+/// bytes are `buf[pos..filled]`, they are only moved to the start of the buffer when a line
+/// doesn't fit after them.
+struct CappedLineReader<C> {
+    inner: C,
+    pos: usize,
+    filled: usize,
+    buf: [u8; 2048],
 }
 
-const END: &'static str = "END\r\n";
+/// Reject keys with a whitespace or control character, which would be parsed as the end of the key
+/// or of the command by the server.
+fn check_key(key: &str) -> Result<(), MemcacheError> {
+    match key.bytes().find(|byte| byte.is_ascii_control() || *byte == b' ') {
+        Some(byte) => Err(ClientError::InvalidKey(byte).into()),
+        None => Ok(()),
+    }
+}
 
-impl fmt::Display for StoreCommand {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            StoreCommand::Set => write!(f, "set"),
-            StoreCommand::Add => write!(f, "add"),
-            StoreCommand::Replace => write!(f, "replace"),
-            StoreCommand::Append => write!(f, "append"),
-            StoreCommand::Prepend => write!(f, "prepend"),
-            StoreCommand::Cas => write!(f, "cas"),
+pub(crate) fn encode_quiet(buffer: &mut Vec<u8>, command: &QuietCommand) -> Result<(), MemcacheError> {
+    match *command {
+        QuietCommand::Set {
+            key,
+            data,
+            flags,
+            expiration,
+        } => {
+            check_key(key)?;
+            write!(
+                buffer,
+                "set {} {} {} {} noreply\r\n",
+                key,
+                flags,
+                expiration,
+                data.len()
+            )?;
+            buffer.extend_from_slice(data);
+            buffer.extend_from_slice(b"\r\n");
+        }
+        QuietCommand::Delete { key } => {
+            check_key(key)?;
+            write!(buffer, "delete {} noreply\r\n", key)?;
         }
     }
+    Ok(())
 }
 
-struct CappedLineReader<C> {
-    inner: C,
-    filled: usize,
-    buf: [u8; 2048],
+fn check_response_key(response_key: &str, key: &str) -> Result<(), MemcacheError> {
+    if response_key == key {
+        Ok(())
+    } else {
+        Err(ServerError::BadResponse(Cow::Borrowed("key doesn't match in the response")).into())
+    }
+}
+
+/// The ascii protocol can only carry UTF-8 keys, reject others with their first invalid byte.
+fn utf8_key(key: &[u8]) -> Result<&str, MemcacheError> {
+    std::str::from_utf8(key).map_err(|e| ClientError::InvalidKey(key[e.valid_up_to()]).into())
 }
 
 fn get_line(buf: &[u8]) -> Option<usize> {
@@ -63,19 +94,28 @@ impl<C: Read> CappedLineReader<C> {
     fn new(inner: C) -> Self {
         Self {
             inner,
+            pos: 0,
             filled: 0,
             buf: [0x0; 2048],
         }
     }
 
+    fn get_ref(&self) -> &C {
+        &self.inner
+    }
+
     pub(crate) fn get_mut(&mut self) -> &mut C {
         &mut self.inner
     }
 
+    fn buffered(&self) -> &[u8] {
+        &self.buf[self.pos..self.filled]
+    }
+
     fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), MemcacheError> {
-        let min = std::cmp::min(buf.len(), self.filled);
+        let min = std::cmp::min(buf.len(), self.filled - self.pos);
         let (to_fill, rest) = buf.split_at_mut(min);
-        to_fill.copy_from_slice(&self.buf[..min]);
+        to_fill.copy_from_slice(&self.buffered()[..min]);
         self.consume(min);
         if rest.len() != 0 {
             self.inner.read_exact(&mut rest[..])?;
@@ -83,56 +123,166 @@ impl<C: Read> CappedLineReader<C> {
         Ok(())
     }
 
+    /// Copy exactly `length` bytes to `writer`, without buffering them.
+    fn copy_exact<W: Write>(&mut self, length: usize, writer: &mut W) -> Result<(), MemcacheError> {
+        let min = std::cmp::min(length, self.filled - self.pos);
+        writer.write_all(&self.buffered()[..min])?;
+        self.consume(min);
+        let rest = (length - min) as u64;
+        if std::io::copy(&mut (&mut self.inner).take(rest), writer)? != rest {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+        Ok(())
+    }
+
     /// Try to read a CRLF terminated line from the underlying reader.
     /// The length of the line is expected to be <= the length of the
     /// internal buffer, suited for reading headers or short responses.
-    fn read_line<T, F>(&mut self, mut cb: F) -> Result<T, MemcacheError>
+    fn read_line<T, F>(&mut self, cb: F) -> Result<T, MemcacheError>
     where
         F: FnMut(&str) -> Result<T, MemcacheError>,
     {
-        // check if the buf already has a new line
-        if let Some(n) = get_line(&self.buf[..self.filled]) {
-            let result = cb(std::str::from_utf8(&self.buf[..n])?);
-            self.consume(n);
-            return result;
+        match self.read_line_or_eof(cb)? {
+            Some(result) => Ok(result),
+            None => Err(ClientError::Error(Cow::Borrowed("Ascii protocol no line found")))?,
         }
+    }
+
+    /// Same as `read_line`, but returns `None` if the connection was closed by the server.
+    fn read_line_or_eof<T, F>(&mut self, mut cb: F) -> Result<Option<T>, MemcacheError>
+    where
+        F: FnMut(&str) -> Result<T, MemcacheError>,
+    {
+        // the buffer may already hold the line
+        let mut searched: usize = 0;
         loop {
-            let (filled, buf) = self.buf.split_at_mut(self.filled);
-            if buf.len() == 0 {
-                return Err(ClientError::Error(Cow::Borrowed("Ascii protocol response too long")))?;
+            // the line end may be split between two reads, so search from the last searched byte
+            let start = searched.saturating_sub(1);
+            if let Some(n) = get_line(&self.buffered()[start..]) {
+                let n = start + n;
+                let result = cb(std::str::from_utf8(&self.buffered()[..n])?);
+                self.consume(n);
+                return result.map(Some);
+            }
+            searched = self.filled - self.pos;
+            if self.filled == self.buf.len() {
+                if self.pos == 0 {
+                    return Err(ClientError::Error(Cow::Borrowed("Ascii protocol response too long")))?;
+                }
+                self.buf.copy_within(self.pos..self.filled, 0);
+                self.filled -= self.pos;
+                self.pos = 0;
             }
-            let filled = filled.len();
-            let read = self.inner.read(&mut buf[..])?;
+            let read = self.inner.read(&mut self.buf[self.filled..])?;
             if read == 0 {
-                return Err(ClientError::Error(Cow::Borrowed("Ascii protocol no line found")))?;
+                return Ok(None);
             }
             self.filled += read;
-            if let Some(n) = get_line(&buf[..read]) {
-                let result = cb(std::str::from_utf8(&self.buf[..filled + n])?);
-                self.consume(n);
-                return result;
-            }
         }
     }
 
     fn consume(&mut self, amount: usize) {
-        let amount = std::cmp::min(self.filled, amount);
-        self.buf.copy_within(amount..self.filled, 0);
-        self.filled -= amount;
+        self.pos = std::cmp::min(self.filled, self.pos + amount);
+        if self.pos == self.filled {
+            self.pos = 0;
+            self.filled = 0;
+        }
     }
 }
 
 pub struct AsciiProtocol<C: Read + Write + Sized> {
     reader: CappedLineReader<C>,
+    /// Reused to format the command line of storage commands, which is sent along with the value.
+    scratch: Vec<u8>,
+}
+
+/// How many of `keys` can be fetched by a gets command of at most `max_request_size` bytes, at
+/// least one.
+fn gets_batch_len(keys: &[&str], max_request_size: usize) -> usize {
+    // "gets" and the final "\r\n"
+    let mut size = 6;
+    let mut count = 0;
+    for key in keys {
+        size += key.len() + 1;
+        if size > max_request_size {
+            break;
+        }
+        count += 1;
+    }
+    count.max(1)
+}
+
+impl AsciiProtocol<Stream> {
+    /// Fetch `keys` with a single gets command, passing the values to `f` until it fails with the
+    /// error stored in `failed`, and return how many were found.
+    fn gets_batch<V: FromMemcacheValueExt>(
+        &mut self,
+        keys: &[&str],
+        f: &mut GetsCallback<V>,
+        failed: &mut Option<MemcacheError>,
+    ) -> Result<usize, MemcacheError> {
+        // write the keys one by one instead of joining them first, they are buffered anyway
+        self.reader.get_mut().write_all(b"gets")?;
+        for key in keys {
+            write!(self.reader.get_mut(), " {}", key)?;
+        }
+        self.reader.get_mut().write_all(b"\r\n")?;
+        self.reader.get_mut().flush()?;
+
+        let mut found = 0;
+        // there will be atmost keys.len() "VALUE <...>" responses and one END response
+        for _ in 0..=keys.len() {
+            match self.parse_get_response(true)? {
+                Some((key, value)) => {
+                    found += 1;
+                    if failed.is_none() {
+                        *failed = f(key, value).err();
+                    }
+                }
+                None => return Ok(found),
+            }
+        }
+
+        Err(ServerError::BadResponse(Cow::Borrowed("Expected end of gets response")))?
+    }
 }
 
 impl ProtocolTrait for AsciiProtocol<Stream> {
     fn auth(&mut self, username: &str, password: &str) -> Result<(), MemcacheError> {
-        return self.set("auth", format!("{} {}", username, password), 0);
+        // servers started with an authfile reject every command until the client is
+        // authenticated, use that to detect whether authentication is needed at all, so the
+        // credentials are never stored as a regular item on servers without authentication.
+        self.reader.get_mut().write_all(b"version\r\n")?;
+        self.reader.get_mut().flush()?;
+        let requires_auth = self.reader.read_line(|response| {
+            if response.starts_with("VERSION") {
+                Ok(false)
+            } else if response.starts_with("CLIENT_ERROR") {
+                Ok(true)
+            } else {
+                Err(ServerError::BadResponse(Cow::Owned(response.into())))?
+            }
+        })?;
+        if !requires_auth {
+            return Err(ClientError::Error(Cow::Borrowed(
+                "credentials were provided but the server does not require authentication",
+            ))
+            .into());
+        }
+
+        // the ascii authentication is a set command with "<username> <password>" as the value,
+        // the key is ignored by the server
+        match self.set("auth", format!("{} {}", username, password), 0) {
+            Err(MemcacheError::ClientError(ClientError::Error(message))) => {
+                let message = message.trim_start_matches("CLIENT_ERROR ").trim_end_matches("\r\n");
+                Err(CommandError::AuthenticationRequired(Some(message.to_string())).into())
+            }
+            result => result,
+        }
     }
 
     fn version(&mut self) -> Result<String, MemcacheError> {
-        self.reader.get_mut().write(b"version\r\n")?;
+        self.reader.get_mut().write_all(b"version\r\n")?;
         self.reader.get_mut().flush()?;
         self.reader.read_line(|response| {
             let response = MemcacheError::try_from(response)?;
@@ -156,38 +306,97 @@ impl ProtocolTrait for AsciiProtocol<Stream> {
     }
 
     fn get<V: FromMemcacheValueExt>(&mut self, key: &str) -> Result<Option<V>, MemcacheError> {
+        check_key(key)?;
         write!(self.reader.get_mut(), "get {}\r\n", key)?;
 
-        if let Some((k, v)) = self.parse_get_response(false)? {
-            if k != key {
-                Err(ServerError::BadResponse(Cow::Borrowed(
-                    "key doesn't match in the response",
-                )))?
-            } else if self.parse_get_response::<V>(false)?.is_none() {
-                Ok(Some(v))
-            } else {
-                Err(ServerError::BadResponse(Cow::Borrowed("Expected end of get response")))?
-            }
-        } else {
-            Ok(None)
+        let (flags, length) = match self.parse_value_header(false, |k, flags, length, _| {
+            check_response_key(k, key)?;
+            Ok((flags, length))
+        })? {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+        let value = self.read_value(length)?;
+        self.parse_end_response("Expected end of get response")?;
+        Ok(Some(FromMemcacheValueExt::from_memcache_value(value, flags, None)?))
+    }
+
+    fn get_bytes_key<V: FromMemcacheValueExt>(&mut self, key: &[u8]) -> Result<Option<V>, MemcacheError> {
+        self.get(utf8_key(key)?)
+    }
+
+    fn get_with_cas<V: FromMemcacheValueExt>(&mut self, key: &str) -> Result<Option<(V, u64)>, MemcacheError> {
+        check_key(key)?;
+        write!(self.reader.get_mut(), "gets {}\r\n", key)?;
+
+        let (flags, length, cas) = match self.parse_value_header(true, |k, flags, length, cas| {
+            check_response_key(k, key)?;
+            Ok((flags, length, cas.unwrap()))
+        })? {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+        let value = self.read_value(length)?;
+        self.parse_end_response("Expected end of gets response")?;
+        let value = FromMemcacheValueExt::from_memcache_value(value, flags, Some(cas))?;
+        Ok(Some((value, cas)))
+    }
+
+    fn get_into<W: Write>(&mut self, key: &str, writer: &mut W) -> Result<Option<u32>, MemcacheError> {
+        check_key(key)?;
+        write!(self.reader.get_mut(), "get {}\r\n", key)?;
+
+        let (flags, length) = match self.parse_value_header(false, |k, flags, length, _| {
+            check_response_key(k, key)?;
+            Ok((flags, length))
+        })? {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+        self.reader.copy_exact(length, writer)?;
+        let mut end = [0u8; 2];
+        self.reader.read_exact(&mut end)?;
+        if &end != b"\r\n" {
+            return Err(ServerError::BadResponse(Cow::Borrowed("Expected end of get response")).into());
         }
+        self.parse_end_response("Expected end of get response")?;
+        Ok(Some(flags))
     }
 
     fn gets<V: FromMemcacheValueExt>(&mut self, keys: &[&str]) -> Result<HashMap<String, V>, MemcacheError> {
-        write!(self.reader.get_mut(), "gets {}\r\n", keys.join(" "))?;
-
         let mut result: HashMap<String, V> = HashMap::with_capacity(keys.len());
-        // there will be atmost keys.len() "VALUE <...>" responses and one END response
-        for _ in 0..=keys.len() {
-            match self.parse_get_response(true)? {
-                Some((key, value)) => {
-                    result.insert(key, value);
-                }
-                None => return Ok(result),
-            }
-        }
+        self.gets_foreach(keys, &mut |key, value| {
+            result.insert(key, value);
+            Ok(())
+        })?;
+        Ok(result)
+    }
 
-        Err(ServerError::BadResponse(Cow::Borrowed("Expected end of gets response")))?
+    fn gets_foreach<V: FromMemcacheValueExt>(
+        &mut self,
+        keys: &[&str],
+        f: &mut GetsCallback<V>,
+    ) -> Result<usize, MemcacheError> {
+        for key in keys {
+            check_key(key)?;
+        }
+        let mut found = 0;
+        let mut failed = None;
+        let mut remaining = keys;
+        // the requests sent over UDP must fit in a datagram, so the keys may be split in batches
+        let max_request_size = self.reader.get_ref().max_request_size();
+        while !remaining.is_empty() {
+            let (batch, rest) = remaining.split_at(match max_request_size {
+                Some(max_request_size) => gets_batch_len(remaining, max_request_size),
+                None => remaining.len(),
+            });
+            found += self.gets_batch(batch, f, &mut failed)?;
+            remaining = rest;
+        }
+        match failed {
+            Some(e) => Err(e),
+            None => Ok(found),
+        }
     }
 
     fn cas<V: ToMemcacheValue<Stream>>(
@@ -219,12 +428,56 @@ impl ProtocolTrait for AsciiProtocol<Stream> {
         self.store(StoreCommand::Set, key, value, &options).map(|_| ())
     }
 
+    fn set_bytes_key<V: ToMemcacheValue<Stream>>(
+        &mut self,
+        key: &[u8],
+        value: V,
+        expiration: u32,
+    ) -> Result<(), MemcacheError> {
+        self.set(utf8_key(key)?, value, expiration)
+    }
+
+    fn stores<V: ToMemcacheValue<Stream>>(
+        &mut self,
+        command: StoreCommand,
+        entries: Vec<(&str, V, u32)>,
+    ) -> Result<StoreResults, MemcacheError> {
+        let count = entries.len();
+        for (key, _, _) in &entries {
+            check_key(key)?;
+        }
+        for (key, value, expiration) in entries {
+            let options = Options {
+                exptime: expiration,
+                ..Default::default()
+            };
+            self.write_store(command, key, value, &options)?;
+        }
+        self.reader.get_mut().flush()?;
+
+        let mut results = Vec::with_capacity(count);
+        for _ in 0..count {
+            let result = match self.parse_store_response() {
+                Ok(true) => Ok(StoreResult::Stored),
+                Ok(false) => Ok(StoreResult::NotStored),
+                // the connection is out of sync, the other responses can't be read
+                Err(e @ MemcacheError::IOError(_)) => return Err(e),
+                Err(e) => Err(e),
+            };
+            results.push(result);
+        }
+        Ok(results)
+    }
+
     fn add<V: ToMemcacheValue<Stream>>(&mut self, key: &str, value: V, expiration: u32) -> Result<(), MemcacheError> {
         let options = Options {
             exptime: expiration,
             ..Default::default()
         };
-        self.store(StoreCommand::Add, key, value, &options).map(|_| ())
+        match self.store(StoreCommand::Add, key, value, &options)? {
+            true => Ok(()),
+            false => Err(CommandError::KeyExists.into()),
+        }
     }
 
     fn replace<V: ToMemcacheValue<Stream>>(
@@ -237,7 +490,10 @@ impl ProtocolTrait for AsciiProtocol<Stream> {
             exptime: expiration,
             ..Default::default()
         };
-        self.store(StoreCommand::Replace, key, value, &options).map(|_| ())
+        match self.store(StoreCommand::Replace, key, value, &options)? {
+            true => Ok(()),
+            false => Err(CommandError::KeyNotFound.into()),
+        }
     }
 
     fn append<V: ToMemcacheValue<Stream>>(&mut self, key: &str, value: V) -> Result<(), MemcacheError> {
@@ -251,33 +507,117 @@ impl ProtocolTrait for AsciiProtocol<Stream> {
     }
 
     fn delete(&mut self, key: &str) -> Result<bool, MemcacheError> {
+        check_key(key)?;
         write!(self.reader.get_mut(), "delete {}\r\n", key)?;
         self.reader.get_mut().flush()?;
-        self.reader
-            .read_line(|response| match MemcacheError::try_from(response) {
-                Ok(s) => {
-                    if s == "DELETED\r\n" {
-                        Ok(true)
-                    } else {
-                        Err(ServerError::BadResponse(Cow::Owned(s.into())).into())
+        self.parse_delete_response()
+    }
+
+    fn delete_bytes_key(&mut self, key: &[u8]) -> Result<bool, MemcacheError> {
+        self.delete(utf8_key(key)?)
+    }
+
+    fn deletes(&mut self, keys: &[&str]) -> Result<Vec<Result<bool, MemcacheError>>, MemcacheError> {
+        // invalid keys are not sent, and only fail themselves
+        let checked: Vec<_> = keys.iter().map(|key| check_key(key)).collect();
+        for (key, checked) in keys.iter().zip(checked.iter()) {
+            if checked.is_ok() {
+                write!(self.reader.get_mut(), "delete {}\r\n", key)?;
+            }
+        }
+        self.reader.get_mut().flush()?;
+
+        let mut results = Vec::with_capacity(keys.len());
+        for checked in checked {
+            match checked.and_then(|_| self.parse_delete_response()) {
+                // the connection is out of sync, the other responses can't be read
+                Err(e @ MemcacheError::IOError(_)) => return Err(e),
+                result => results.push(result),
+            }
+        }
+        Ok(results)
+    }
+
+    fn deletes_noreply(&mut self, keys: &[&str]) -> Result<(), MemcacheError> {
+        let (last, keys) = match keys.split_last() {
+            Some(split) => split,
+            None => return Ok(()),
+        };
+        for key in keys {
+            check_key(key)?;
+        }
+        check_key(last)?;
+        for key in keys {
+            write!(self.reader.get_mut(), "delete {} noreply\r\n", key)?;
+        }
+        // the response to the last delete tells when the server processed all of them
+        write!(self.reader.get_mut(), "delete {}\r\n", last)?;
+        self.reader.get_mut().flush()?;
+        self.parse_delete_response().map(|_| ())
+    }
+
+    fn write_quiet(&mut self, commands: &[u8], _count: usize) -> Result<(), MemcacheError> {
+        let stream = self.reader.get_mut();
+        stream.write_all(commands)?;
+        // noreply commands are only answered when they fail, the version response tells when the
+        // server processed all of them
+        stream.write_all(b"version\r\n")?;
+        stream.flush()?;
+        let mut result = Ok(());
+        loop {
+            let line = self.reader.read_line(|response| {
+                if response.starts_with("VERSION ") {
+                    return Ok(None);
+                }
+                MemcacheError::try_from(response)?;
+                Ok(Some(ServerError::BadResponse(Cow::Owned(response.into())).into()))
+            });
+            match line {
+                Ok(None) => return result,
+                Err(e @ MemcacheError::IOError(_)) => return Err(e),
+                Ok(Some(e)) | Err(e) => {
+                    if result.is_ok() {
+                        result = Err(e);
                     }
                 }
-                Err(MemcacheError::CommandError(CommandError::KeyNotFound)) => Ok(false),
-                Err(e) => Err(e),
-            })
+            }
+        }
     }
 
     fn increment(&mut self, key: &str, amount: u64) -> Result<u64, MemcacheError> {
+        check_key(key)?;
         write!(self.reader.get_mut(), "incr {} {}\r\n", key, amount)?;
         self.parse_u64_response()
     }
 
+    fn increments(&mut self, entries: &[(&str, u64)]) -> Result<Vec<Result<u64, MemcacheError>>, MemcacheError> {
+        for (key, _) in entries {
+            check_key(key)?;
+        }
+        for (key, amount) in entries {
+            write!(self.reader.get_mut(), "incr {} {}\r\n", key, amount)?;
+        }
+        self.reader.get_mut().flush()?;
+
+        let mut results = Vec::with_capacity(entries.len());
+        for _ in entries {
+            match self.parse_u64_response() {
+                // the connection is out of sync, the other responses can't be read
+                Err(e @ MemcacheError::IOError(_)) => return Err(e),
+                result => results.push(result),
+            }
+        }
+        Ok(results)
+    }
+
     fn decrement(&mut self, key: &str, amount: u64) -> Result<u64, MemcacheError> {
+        check_key(key)?;
         write!(self.reader.get_mut(), "decr {} {}\r\n", key, amount)?;
         self.parse_u64_response()
     }
 
     fn touch(&mut self, key: &str, expiration: u32) -> Result<bool, MemcacheError> {
+        check_key(key)?;
         write!(self.reader.get_mut(), "touch {} {}\r\n", key, expiration)?;
         self.reader.get_mut().flush()?;
         self.reader
@@ -294,8 +634,29 @@ impl ProtocolTrait for AsciiProtocol<Stream> {
             })
     }
 
+    fn exists(&mut self, key: &str) -> Result<bool, MemcacheError> {
+        check_key(key)?;
+        // a meta get without flags only returns whether the item exists, not its value
+        write!(self.reader.get_mut(), "mg {}\r\n", key)?;
+        self.reader.get_mut().flush()?;
+        self.reader
+            .read_line(|response| match MemcacheError::try_from(response)? {
+                "HD\r\n" => Ok(true),
+                "EN\r\n" => Ok(false),
+                s => Err(ServerError::BadResponse(Cow::Owned(s.into())).into()),
+            })
+    }
+
     fn stats(&mut self) -> Result<Stats, MemcacheError> {
-        self.reader.get_mut().write(b"stats\r\n")?;
+        self.stats_with_args("")
+    }
+
+    fn stats_with_args(&mut self, args: &str) -> Result<Stats, MemcacheError> {
+        if args.is_empty() {
+            self.reader.get_mut().write_all(b"stats\r\n")?;
+        } else {
+            write!(self.reader.get_mut(), "stats {}\r\n", args)?;
+        }
         self.reader.get_mut().flush()?;
 
         enum Loop {
@@ -306,20 +667,17 @@ impl ProtocolTrait for AsciiProtocol<Stream> {
         let mut stats: Stats = HashMap::new();
         loop {
             let status = self.reader.read_line(|response| {
-                if response != END {
+                if response == END {
                     return Ok(Loop::Break);
                 }
                 let s = MemcacheError::try_from(response)?;
-                if !s.starts_with("STAT") {
-                    return Err(ServerError::BadResponse(Cow::Owned(s.into())))?;
-                }
-                let stat: Vec<_> = s.trim_end_matches("\r\n").split(" ").collect();
-                if stat.len() < 3 {
-                    return Err(ServerError::BadResponse(Cow::Owned(s.into())).into());
+                let mut stat = s.trim_end_matches("\r\n").splitn(3, ' ');
+                match (stat.next(), stat.next(), stat.next()) {
+                    (Some("STAT"), Some(key), Some(value)) => {
+                        stats.insert(key.into(), value.into());
+                    }
+                    _ => return Err(ServerError::BadResponse(Cow::Owned(s.into())).into()),
                 }
-                let key = stat[1];
-                let value = s.trim_start_matches(format!("STAT {}", key).as_str());
-                stats.insert(key.into(), value.into());
 
                 Ok(Loop::Continue)
             })?;
@@ -329,12 +687,32 @@ impl ProtocolTrait for AsciiProtocol<Stream> {
             }
         }
     }
+
+    fn stats_reset(&mut self) -> Result<(), MemcacheError> {
+        self.reader.get_mut().write_all(b"stats reset\r\n")?;
+        self.reader.get_mut().flush()?;
+        self.reader.read_line(|response| {
+            let response = MemcacheError::try_from(response)?;
+            if response == "RESET\r\n" {
+                Ok(())
+            } else {
+                Err(ServerError::BadResponse(Cow::Owned(response.into())))?
+            }
+        })
+    }
+
+    fn verbosity(&mut self, level: u32) -> Result<(), MemcacheError> {
+        write!(self.reader.get_mut(), "verbosity {}\r\n", level)?;
+        self.reader.get_mut().flush()?;
+        self.parse_ok_response()
+    }
 }
 
 impl AsciiProtocol<Stream> {
     pub(crate) fn new(stream: Stream) -> Self {
         Self {
             reader: CappedLineReader::new(stream),
+            scratch: Vec::new(),
         }
     }
 
@@ -349,6 +727,24 @@ impl AsciiProtocol<Stream> {
         value: V,
         options: &Options,
     ) -> Result<bool, MemcacheError> {
+        check_key(key)?;
+        self.write_store(command, key, value, options)?;
+        self.reader.get_mut().flush()?;
+
+        if options.noreply {
+            return Ok(true);
+        }
+
+        self.parse_store_response()
+    }
+
+    fn write_store<V: ToMemcacheValue<Stream>>(
+        &mut self,
+        command: StoreCommand,
+        key: &str,
+        value: V,
+        options: &Options,
+    ) -> Result<(), MemcacheError> {
         if command == StoreCommand::Cas {
             if options.cas.is_none() {
                 Err(ClientError::Error(Cow::Borrowed(
@@ -357,39 +753,33 @@ impl AsciiProtocol<Stream> {
             }
         }
         let noreply = if options.noreply { " noreply" } else { "" };
-        if options.cas.is_some() {
-            write!(
-                self.reader.get_mut(),
-                "{command} {key} {flags} {exptime} {vlen} {cas}{noreply}\r\n",
-                command = command,
-                key = key,
-                flags = value.get_flags(),
-                exptime = options.exptime,
-                vlen = value.get_length(),
-                cas = options.cas.unwrap(),
-                noreply = noreply
-            )?;
-        } else {
-            write!(
-                self.reader.get_mut(),
-                "{command} {key} {flags} {exptime} {vlen}{noreply}\r\n",
-                command = command,
-                key = key,
-                flags = value.get_flags(),
-                exptime = options.exptime,
-                vlen = value.get_length(),
-                noreply = noreply
-            )?;
+        self.scratch.clear();
+        write!(
+            self.scratch,
+            "{command} {key} {flags} {exptime} {vlen}",
+            command = command,
+            key = key,
+            flags = value.get_flags(),
+            exptime = options.exptime,
+            vlen = value.get_length(),
+        )?;
+        if let Some(cas) = options.cas {
+            write!(self.scratch, " {}", cas)?;
         }
-
-        value.write_to(self.reader.get_mut())?;
-        self.reader.get_mut().write(b"\r\n")?;
-        self.reader.get_mut().flush()?;
-
-        if options.noreply {
-            return Ok(true);
+        write!(self.scratch, "{}\r\n", noreply)?;
+        let stream = self.reader.get_mut();
+        match value.get_bytes() {
+            Some(data) => write_all_vectored(stream, &[&self.scratch, data, b"\r\n"])?,
+            None => {
+                stream.write_all(&self.scratch)?;
+                value.write_to(stream)?;
+                stream.write_all(b"\r\n")?;
+            }
         }
+        Ok(())
+    }
 
+    fn parse_store_response(&mut self) -> Result<bool, MemcacheError> {
         self.reader.read_line(|response| {
             let response = MemcacheError::try_from(response)?;
             match response {
@@ -402,6 +792,144 @@ impl AsciiProtocol<Stream> {
         })
     }
 
+    pub(crate) fn run_command(&mut self, command: &str) -> Result<Vec<String>, MemcacheError> {
+        write!(self.reader.get_mut(), "{}\r\n", command)?;
+        self.reader.get_mut().flush()?;
+
+        let mut lines = Vec::new();
+        loop {
+            let line = self.reader.read_line(|response| {
+                if response == "ERROR\r\n"
+                    || response.starts_with("CLIENT_ERROR")
+                    || response.starts_with("SERVER_ERROR")
+                {
+                    MemcacheError::try_from(response)?;
+                }
+                Ok(response.trim_end_matches("\r\n").to_string())
+            })?;
+            // "VALUE <key> <flags> <bytes> [<cas>]" and "VA <bytes> <flags>*" are followed by a data block
+            let data_length = if line.starts_with("VALUE ") {
+                line.split(' ').nth(3)
+            } else if line.starts_with("VA ") {
+                line.split(' ').nth(1)
+            } else {
+                None
+            };
+            let data_length = match data_length {
+                Some(length) => Some(length.parse::<usize>()?),
+                None => None,
+            };
+            let is_multiline = line.starts_with("VALUE ")
+                || line.starts_with("STAT ")
+                || line.starts_with("ITEM ")
+                || line.starts_with("PREFIX ")
+                || line.starts_with("key=");
+            lines.push(line);
+            if let Some(length) = data_length {
+                let mut value = vec![0u8; length + 2];
+                self.reader.read_exact(value.as_mut_slice())?;
+                if &value[length..] != b"\r\n" {
+                    return Err(ServerError::BadResponse(Cow::Owned(String::from_utf8(value)?)))?;
+                }
+                value.truncate(length);
+                lines.push(String::from_utf8_lossy(&value).into_owned());
+            }
+            if !is_multiline {
+                return Ok(lines);
+            }
+        }
+    }
+
+    /// Fetch the value of `key` with its flags and its remaining TTL in seconds, or `None` for
+    /// the TTL if it never expires, with a meta get.
+    pub(crate) fn get_with_ttl(&mut self, key: &str) -> Result<Option<StoredValue>, MemcacheError> {
+        check_key(key)?;
+        write!(self.reader.get_mut(), "mg {} v f t\r\n", key)?;
+        self.reader.get_mut().flush()?;
+        let header = self.reader.read_line(|response| {
+            let response = MemcacheError::try_from(response)?;
+            if response == "EN\r\n" {
+                return Ok(None);
+            }
+            let bad_response = || ServerError::BadResponse(Cow::Owned(response.into()));
+            let mut parts = response.trim_end_matches("\r\n").split(' ');
+            if parts.next() != Some("VA") {
+                return Err(bad_response().into());
+            }
+            let length: usize = parts.next().ok_or_else(bad_response)?.parse()?;
+            let (mut flags, mut ttl) = (0, None);
+            for part in parts {
+                if let Some(value) = part.strip_prefix('f') {
+                    flags = value.parse()?;
+                } else if let Some(value) = part.strip_prefix('t') {
+                    ttl = if value == "-1" { None } else { Some(value.parse()?) };
+                }
+            }
+            Ok(Some((length, flags, ttl)))
+        })?;
+        match header {
+            Some((length, flags, ttl)) => Ok(Some((self.read_value(length)?, flags, ttl))),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) fn start_metadump(&mut self) -> Result<(), MemcacheError> {
+        self.reader.get_mut().write_all(b"lru_crawler metadump all\r\n")?;
+        self.reader.get_mut().flush()?;
+        Ok(())
+    }
+
+    /// Read the next `key=...` line of a `lru_crawler metadump` response, or `None` after the last.
+    pub(crate) fn read_metadump_line(&mut self) -> Result<Option<String>, MemcacheError> {
+        self.reader.read_line(|response| {
+            if response.starts_with("key=") {
+                return Ok(Some(response.trim_end_matches("\r\n").to_string()));
+            }
+            let response = MemcacheError::try_from(response)?;
+            if response == END {
+                Ok(None)
+            } else {
+                // e.g. "BUSY currently processing crawler request" if another dump is running
+                Err(ServerError::BadResponse(Cow::Owned(response.into())))?
+            }
+        })
+    }
+
+    pub(crate) fn start_watch(&mut self, filters: &str) -> Result<(), MemcacheError> {
+        write!(self.reader.get_mut(), "watch {}\r\n", filters)?;
+        self.reader.get_mut().flush()?;
+        self.parse_ok_response()
+    }
+
+    /// Read the next log line of a `watch` stream, or `None` if the server closed the connection.
+    pub(crate) fn read_watch_line(&mut self) -> Result<Option<String>, MemcacheError> {
+        self.reader
+            .read_line_or_eof(|response| Ok(response.trim_end_matches("\r\n").to_string()))
+    }
+
+    pub(crate) fn shutdown(&mut self, graceful: bool) -> Result<(), MemcacheError> {
+        let graceful = if graceful { " graceful" } else { "" };
+        write!(self.reader.get_mut(), "shutdown{}\r\n", graceful)?;
+        self.reader.get_mut().flush()?;
+        // the server closes the connection when shutting down
+        let response = self.reader.read_line_or_eof(|response| {
+            let response = MemcacheError::try_from(response)?;
+            if response == "OK\r\n" {
+                Ok(())
+            } else if response.starts_with("ERROR") {
+                // e.g. "ERROR: shutdown not enabled" if the server was not started with -A
+                Err(ServerError::Error(response.trim_end_matches("\r\n").to_string()))?
+            } else {
+                Err(ServerError::BadResponse(Cow::Owned(response.into())))?
+            }
+        });
+        match response {
+            Ok(_) => Ok(()),
+            Err(MemcacheError::IOError(ref e)) if e.kind() == std::io::ErrorKind::ConnectionReset => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
     fn parse_ok_response(&mut self) -> Result<(), MemcacheError> {
         self.reader.read_line(|response| {
             let response = MemcacheError::try_from(response)?;
@@ -413,11 +941,13 @@ impl AsciiProtocol<Stream> {
         })
     }
 
-    fn parse_get_response<V: FromMemcacheValueExt>(
-        &mut self,
-        has_cas: bool,
-    ) -> Result<Option<(String, V)>, MemcacheError> {
-        let result = self.reader.read_line(|buf| {
+    /// Parse a `VALUE` line and pass its key, flags, length and cas unique to `f`, which gets the
+    /// key borrowed from the read buffer. Returns `None` at the end of the response.
+    fn parse_value_header<T, F>(&mut self, has_cas: bool, mut f: F) -> Result<Option<T>, MemcacheError>
+    where
+        F: FnMut(&str, u32, usize, Option<u64>) -> Result<T, MemcacheError>,
+    {
+        self.reader.read_line(|buf| {
             let buf = MemcacheError::try_from(buf)?;
             if buf == END {
                 return Ok(None);
@@ -439,26 +969,67 @@ impl AsciiProtocol<Stream> {
             if header.next().is_some() {
                 return Err(ServerError::BadResponse(Cow::Owned(buf.into())))?;
             }
-            Ok(Some((key.to_string(), flags, length, cas)))
+            f(key, flags, length, cas).map(Some)
+        })
+    }
+
+    fn parse_end_response(&mut self, message: &'static str) -> Result<(), MemcacheError> {
+        self.reader.read_line(|response| {
+            if MemcacheError::try_from(response)? == END {
+                Ok(())
+            } else {
+                Err(ServerError::BadResponse(Cow::Borrowed(message)))?
+            }
+        })
+    }
+
+    fn read_value(&mut self, length: usize) -> Result<Vec<u8>, MemcacheError> {
+        // read the value and its trailing \r\n separately, so the buffer is allocated with the
+        // exact size of the value and can be handed out as is, e.g. as `bytes::Bytes`
+        let mut value = buffer_pool::take(length);
+        self.reader.read_exact(value.as_mut_slice())?;
+        let mut end = [0u8; 2];
+        self.reader.read_exact(&mut end)?;
+        if &end != b"\r\n" {
+            return Err(ServerError::BadResponse(Cow::Borrowed(
+                "Expected \\r\\n after the value",
+            )))?;
+        }
+        Ok(value)
+    }
+
+    fn parse_get_response<V: FromMemcacheValueExt>(
+        &mut self,
+        has_cas: bool,
+    ) -> Result<Option<(String, V)>, MemcacheError> {
+        let header = self.parse_value_header(has_cas, |key, flags, length, cas| {
+            Ok((key.to_string(), flags, length, cas))
         })?;
-        match result {
+        match header {
             Some((key, flags, length, cas)) => {
-                let mut value = vec![0u8; length + 2];
-                self.reader.read_exact(value.as_mut_slice())?;
-                if &value[length..] != b"\r\n" {
-                    return Err(ServerError::BadResponse(Cow::Owned(String::from_utf8(value)?)))?;
-                }
-                // remove the trailing \r\n
-                value.pop();
-                value.pop();
-                value.shrink_to_fit();
+                let value = self.read_value(length)?;
                 let value = FromMemcacheValueExt::from_memcache_value(value, flags, cas)?;
-                Ok(Some((key.to_string(), value)))
+                Ok(Some((key, value)))
             }
             None => Ok(None),
         }
     }
 
+    fn parse_delete_response(&mut self) -> Result<bool, MemcacheError> {
+        self.reader
+            .read_line(|response| match MemcacheError::try_from(response) {
+                Ok(s) => {
+                    if s == "DELETED\r\n" {
+                        Ok(true)
+                    } else {
+                        Err(ServerError::BadResponse(Cow::Owned(s.into())).into())
+                    }
+                }
+                Err(MemcacheError::CommandError(CommandError::KeyNotFound)) => Ok(false),
+                Err(e) => Err(e),
+            })
+    }
+
     fn parse_u64_response(&mut self) -> Result<u64, MemcacheError> {
         self.reader.read_line(|response| {
             let s = MemcacheError::try_from(response)?;
@@ -466,3 +1037,83 @@ impl AsciiProtocol<Stream> {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_quiet, gets_batch_len, CappedLineReader};
+    use crate::protocol::QuietCommand;
+    use std::io::{self, Read};
+
+    /// Returns at most 5 bytes per read, to split lines between reads.
+    struct Chunked<'a>(&'a [u8]);
+
+    impl Read for Chunked<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let length = buf.len().min(self.0.len()).min(5);
+            buf[..length].copy_from_slice(&self.0[..length]);
+            self.0 = &self.0[length..];
+            Ok(length)
+        }
+    }
+
+    #[test]
+    fn test_read_lines() {
+        let mut reader = CappedLineReader::new(Chunked(b"VALUE foo 0 3\r\nbar\r\nEND\r\n"));
+        let header = reader.read_line(|line| Ok(line.to_string())).unwrap();
+        assert_eq!(header, "VALUE foo 0 3\r\n");
+        let mut value = [0u8; 5];
+        reader.read_exact(&mut value).unwrap();
+        assert_eq!(&value, b"bar\r\n");
+        assert_eq!(reader.read_line(|line| Ok(line.to_string())).unwrap(), "END\r\n");
+        assert!(reader.read_line_or_eof(|_| Ok(())).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_split_line_end() {
+        // the reads end between the "\r" and the "\n"
+        let mut reader = CappedLineReader::new(Chunked(b"STAT\r\nEND\r\n"));
+        assert_eq!(reader.read_line(|line| Ok(line.to_string())).unwrap(), "STAT\r\n");
+        assert_eq!(reader.read_line(|line| Ok(line.to_string())).unwrap(), "END\r\n");
+    }
+
+    #[test]
+    fn test_compact_buffer() {
+        // the second line only fits once the first one is moved out of the buffer
+        let mut response = vec![b'a'; 1500];
+        response.extend_from_slice(b"\r\n");
+        response.extend_from_slice(&[b'b'; 1500]);
+        response.extend_from_slice(b"\r\n");
+        let mut reader = CappedLineReader::new(&response[..]);
+        assert_eq!(reader.read_line(|line| Ok(line.len())).unwrap(), 1502);
+        assert_eq!(reader.read_line(|line| Ok(line.len())).unwrap(), 1502);
+
+        let long = vec![b'a'; 4096];
+        assert!(CappedLineReader::new(&long[..]).read_line(|_| Ok(())).is_err());
+    }
+
+    #[test]
+    fn test_encode_quiet() {
+        let mut commands = Vec::new();
+        let set = QuietCommand::Set {
+            key: "foo",
+            data: b"bar",
+            flags: 1,
+            expiration: 10,
+        };
+        encode_quiet(&mut commands, &set).unwrap();
+        encode_quiet(&mut commands, &QuietCommand::Delete { key: "baz" }).unwrap();
+        assert_eq!(commands, b"set foo 1 10 3 noreply\r\nbar\r\ndelete baz noreply\r\n");
+        assert!(encode_quiet(&mut commands, &QuietCommand::Delete { key: "a b" }).is_err());
+    }
+
+    #[test]
+    fn test_gets_batch_len() {
+        let keys = ["foo", "bar", "baz"];
+        // "gets foo bar\r\n"
+        assert_eq!(gets_batch_len(&keys, 14), 2);
+        assert_eq!(gets_batch_len(&keys, 13), 1);
+        assert_eq!(gets_batch_len(&keys, 1400), 3);
+        // a key which doesn't fit is sent on its own, which fails
+        assert_eq!(gets_batch_len(&keys, 5), 1);
+    }
+}
@@ -1,11 +1,13 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::io::Write;
 
-use super::ProtocolTrait;
+use super::{GetsCallback, ProtocolTrait, QuietCommand, StoreCommand, StoreResults};
 use crate::client::Stats;
-use crate::error::MemcacheError;
-use crate::protocol::binary_packet::{self, Magic, Opcode, PacketHeader};
-use crate::stream::Stream;
+use crate::error::{ClientError, CommandError, MemcacheError};
+use crate::protocol::binary_packet::{self, BinaryRequest, BinaryResponse, Magic, Opcode, PacketHeader, SaslStatus};
+use crate::protocol::sasl;
+use crate::stream::{write_all_vectored, Stream};
 use crate::value::{FromMemcacheValueExt, ToMemcacheValue};
 use byteorder::{BigEndian, WriteBytesExt};
 
@@ -13,21 +15,46 @@ pub struct BinaryProtocol {
     pub stream: Stream,
 }
 
+pub(crate) fn encode_quiet(buffer: &mut Vec<u8>, command: &QuietCommand) -> Result<(), MemcacheError> {
+    match *command {
+        QuietCommand::Set {
+            key,
+            data,
+            flags,
+            expiration,
+        } => {
+            let request_header = PacketHeader {
+                magic: Magic::Request as u8,
+                opcode: Opcode::SetQ as u8,
+                key_length: key.len() as u16,
+                extras_length: 8,
+                total_body_length: (8 + key.len() + data.len()) as u32,
+                ..Default::default()
+            };
+            request_header.write(buffer)?;
+            buffer.write_u32::<BigEndian>(flags)?;
+            buffer.write_u32::<BigEndian>(expiration)?;
+            buffer.extend_from_slice(key.as_bytes());
+            buffer.extend_from_slice(data);
+        }
+        QuietCommand::Delete { key } => {
+            let request_header = PacketHeader {
+                magic: Magic::Request as u8,
+                opcode: Opcode::DeleteQ as u8,
+                key_length: key.len() as u16,
+                total_body_length: key.len() as u32,
+                ..Default::default()
+            };
+            request_header.write(buffer)?;
+            buffer.extend_from_slice(key.as_bytes());
+        }
+    }
+    Ok(())
+}
+
 impl ProtocolTrait for BinaryProtocol {
     fn auth(&mut self, username: &str, password: &str) -> Result<(), MemcacheError> {
-        let key = "PLAIN";
-        let request_header = PacketHeader {
-            magic: Magic::Request as u8,
-            opcode: Opcode::StartAuth as u8,
-            key_length: key.len() as u16,
-            total_body_length: (key.len() + username.len() + password.len() + 2) as u32,
-            ..Default::default()
-        };
-        request_header.write(&mut self.stream)?;
-        self.stream.write_all(key.as_bytes())?;
-        write!(&mut self.stream, "\x00{}\x00{}", username, password)?;
-        self.stream.flush()?;
-        binary_packet::parse_start_auth_response(&mut self.stream).map(|_| ())
+        self.auth_with_mechanism(username, password, None)
     }
 
     fn version(&mut self) -> Result<String, MemcacheError> {
@@ -68,6 +95,38 @@ impl ProtocolTrait for BinaryProtocol {
     }
 
     fn get<V: FromMemcacheValueExt>(&mut self, key: &str) -> Result<Option<V>, MemcacheError> {
+        self.get_bytes_key(key.as_bytes())
+    }
+
+    fn get_bytes_key<V: FromMemcacheValueExt>(&mut self, key: &[u8]) -> Result<Option<V>, MemcacheError> {
+        let request_header = PacketHeader {
+            magic: Magic::Request as u8,
+            opcode: Opcode::Get as u8,
+            key_length: key.len() as u16,
+            total_body_length: key.len() as u32,
+            ..Default::default()
+        };
+        request_header.write(&mut self.stream)?;
+        self.stream.write_all(key)?;
+        self.stream.flush()?;
+        binary_packet::parse_get_response(&mut self.stream)
+    }
+
+    fn get_with_cas<V: FromMemcacheValueExt>(&mut self, key: &str) -> Result<Option<(V, u64)>, MemcacheError> {
+        let request_header = PacketHeader {
+            magic: Magic::Request as u8,
+            opcode: Opcode::Get as u8,
+            key_length: key.len() as u16,
+            total_body_length: key.len() as u32,
+            ..Default::default()
+        };
+        request_header.write(&mut self.stream)?;
+        self.stream.write_all(key.as_bytes())?;
+        self.stream.flush()?;
+        binary_packet::parse_get_with_cas_response(&mut self.stream)
+    }
+
+    fn get_into<W: Write>(&mut self, key: &str, writer: &mut W) -> Result<Option<u32>, MemcacheError> {
         let request_header = PacketHeader {
             magic: Magic::Request as u8,
             opcode: Opcode::Get as u8,
@@ -78,10 +137,26 @@ impl ProtocolTrait for BinaryProtocol {
         request_header.write(&mut self.stream)?;
         self.stream.write_all(key.as_bytes())?;
         self.stream.flush()?;
-        return binary_packet::parse_get_response(&mut self.stream);
+        binary_packet::parse_get_into_response(&mut self.stream, writer)
     }
 
     fn gets<V: FromMemcacheValueExt>(&mut self, keys: &[&str]) -> Result<HashMap<String, V>, MemcacheError> {
+        let mut result = HashMap::with_capacity(keys.len());
+        self.gets_foreach(keys, &mut |key, value| {
+            result.insert(key, value);
+            Ok(())
+        })?;
+        Ok(result)
+    }
+
+    fn gets_foreach<V: FromMemcacheValueExt>(
+        &mut self,
+        keys: &[&str],
+        f: &mut GetsCallback<V>,
+    ) -> Result<usize, MemcacheError> {
+        if let Stream::Udp(_) = self.stream {
+            return self.gets_one_by_one(keys, f);
+        }
         for key in keys {
             let request_header = PacketHeader {
                 magic: Magic::Request as u8,
@@ -99,7 +174,36 @@ impl ProtocolTrait for BinaryProtocol {
             ..Default::default()
         };
         noop_request_header.write(&mut self.stream)?;
-        return binary_packet::parse_gets_response(&mut self.stream, keys.len());
+        return binary_packet::parse_gets_response(&mut self.stream, keys.len(), f);
+    }
+
+    fn stores<V: ToMemcacheValue<Stream>>(
+        &mut self,
+        command: StoreCommand,
+        entries: Vec<(&str, V, u32)>,
+    ) -> Result<StoreResults, MemcacheError> {
+        let opcode = match command {
+            StoreCommand::Set => Opcode::SetQ,
+            StoreCommand::Add => Opcode::AddQ,
+            StoreCommand::Replace => Opcode::ReplaceQ,
+            StoreCommand::Append => Opcode::AppendQ,
+            StoreCommand::Prepend => Opcode::PrependQ,
+            StoreCommand::Cas => {
+                return Err(ClientError::Error(Cow::Borrowed("cas can't be batched")).into());
+            }
+        };
+        let count = entries.len();
+        for (index, (key, value, expiration)) in entries.into_iter().enumerate() {
+            self.write_request(opcode, key.as_bytes(), value, expiration, None, index as u32)?;
+        }
+        let noop_request_header = PacketHeader {
+            magic: Magic::Request as u8,
+            opcode: Opcode::Noop as u8,
+            ..Default::default()
+        };
+        noop_request_header.write(&mut self.stream)?;
+        self.stream.flush()?;
+        binary_packet::parse_quiet_store_responses(&mut self.stream, count)
     }
 
     fn cas<V: ToMemcacheValue<Stream>>(
@@ -109,16 +213,25 @@ impl ProtocolTrait for BinaryProtocol {
         expiration: u32,
         cas: u64,
     ) -> Result<bool, MemcacheError> {
-        self.send_request(Opcode::Set, key, value, expiration, Some(cas))?;
+        self.send_request(Opcode::Set, key.as_bytes(), value, expiration, Some(cas))?;
         binary_packet::parse_cas_response(&mut self.stream)
     }
 
     fn set<V: ToMemcacheValue<Stream>>(&mut self, key: &str, value: V, expiration: u32) -> Result<(), MemcacheError> {
-        return self.store(Opcode::Set, key, value, expiration, None);
+        return self.store(Opcode::Set, key.as_bytes(), value, expiration, None);
+    }
+
+    fn set_bytes_key<V: ToMemcacheValue<Stream>>(
+        &mut self,
+        key: &[u8],
+        value: V,
+        expiration: u32,
+    ) -> Result<(), MemcacheError> {
+        self.store(Opcode::Set, key, value, expiration, None)
     }
 
     fn add<V: ToMemcacheValue<Stream>>(&mut self, key: &str, value: V, expiration: u32) -> Result<(), MemcacheError> {
-        return self.store(Opcode::Add, key, value, expiration, None);
+        return self.store(Opcode::Add, key.as_bytes(), value, expiration, None);
     }
 
     fn replace<V: ToMemcacheValue<Stream>>(
@@ -127,7 +240,7 @@ impl ProtocolTrait for BinaryProtocol {
         value: V,
         expiration: u32,
     ) -> Result<(), MemcacheError> {
-        return self.store(Opcode::Replace, key, value, expiration, None);
+        return self.store(Opcode::Replace, key.as_bytes(), value, expiration, None);
     }
 
     fn append<V: ToMemcacheValue<Stream>>(&mut self, key: &str, value: V) -> Result<(), MemcacheError> {
@@ -161,6 +274,10 @@ impl ProtocolTrait for BinaryProtocol {
     }
 
     fn delete(&mut self, key: &str) -> Result<bool, MemcacheError> {
+        self.delete_bytes_key(key.as_bytes())
+    }
+
+    fn delete_bytes_key(&mut self, key: &[u8]) -> Result<bool, MemcacheError> {
         let request_header = PacketHeader {
             magic: Magic::Request as u8,
             opcode: Opcode::Delete as u8,
@@ -169,9 +286,69 @@ impl ProtocolTrait for BinaryProtocol {
             ..Default::default()
         };
         request_header.write(&mut self.stream)?;
-        self.stream.write_all(key.as_bytes())?;
+        self.stream.write_all(key)?;
+        self.stream.flush()?;
+        binary_packet::parse_delete_response(&mut self.stream)
+    }
+
+    fn deletes(&mut self, keys: &[&str]) -> Result<Vec<Result<bool, MemcacheError>>, MemcacheError> {
+        for key in keys {
+            let request_header = PacketHeader {
+                magic: Magic::Request as u8,
+                opcode: Opcode::Delete as u8,
+                key_length: key.len() as u16,
+                total_body_length: key.len() as u32,
+                ..Default::default()
+            };
+            request_header.write(&mut self.stream)?;
+            self.stream.write_all(key.as_bytes())?;
+        }
+        self.stream.flush()?;
+
+        let mut results = Vec::with_capacity(keys.len());
+        for _ in keys {
+            match binary_packet::parse_delete_response(&mut self.stream) {
+                // the connection is out of sync, the other responses can't be read
+                Err(e @ MemcacheError::IOError(_)) => return Err(e),
+                result => results.push(result),
+            }
+        }
+        Ok(results)
+    }
+
+    fn deletes_noreply(&mut self, keys: &[&str]) -> Result<(), MemcacheError> {
+        for key in keys {
+            let request_header = PacketHeader {
+                magic: Magic::Request as u8,
+                opcode: Opcode::DeleteQ as u8,
+                key_length: key.len() as u16,
+                total_body_length: key.len() as u32,
+                ..Default::default()
+            };
+            request_header.write(&mut self.stream)?;
+            self.stream.write_all(key.as_bytes())?;
+        }
+        let noop_request_header = PacketHeader {
+            magic: Magic::Request as u8,
+            opcode: Opcode::Noop as u8,
+            ..Default::default()
+        };
+        noop_request_header.write(&mut self.stream)?;
+        self.stream.flush()?;
+        binary_packet::parse_quiet_delete_responses(&mut self.stream, keys.len())
+    }
+
+    fn write_quiet(&mut self, commands: &[u8], count: usize) -> Result<(), MemcacheError> {
+        self.stream.write_all(commands)?;
+        let noop_request_header = PacketHeader {
+            magic: Magic::Request as u8,
+            opcode: Opcode::Noop as u8,
+            ..Default::default()
+        };
+        noop_request_header.write(&mut self.stream)?;
         self.stream.flush()?;
-        return binary_packet::parse_delete_response(&mut self.stream);
+        // only the failed commands are answered, and deleting a missing key is not a failure
+        binary_packet::parse_quiet_delete_responses(&mut self.stream, count)
     }
 
     fn increment(&mut self, key: &str, amount: u64) -> Result<u64, MemcacheError> {
@@ -197,6 +374,40 @@ impl ProtocolTrait for BinaryProtocol {
         return binary_packet::parse_counter_response(&mut self.stream);
     }
 
+    fn increments(&mut self, entries: &[(&str, u64)]) -> Result<Vec<Result<u64, MemcacheError>>, MemcacheError> {
+        for (key, amount) in entries {
+            let request_header = PacketHeader {
+                magic: Magic::Request as u8,
+                opcode: Opcode::Increment as u8,
+                key_length: key.len() as u16,
+                extras_length: 20,
+                total_body_length: (20 + key.len()) as u32,
+                ..Default::default()
+            };
+            // don't create missing counters, like the ascii protocol
+            let extras = binary_packet::CounterExtras {
+                amount: *amount,
+                initial_value: 0,
+                expiration: 0xffff_ffff,
+            };
+            request_header.write(&mut self.stream)?;
+            self.stream.write_u64::<BigEndian>(extras.amount)?;
+            self.stream.write_u64::<BigEndian>(extras.initial_value)?;
+            self.stream.write_u32::<BigEndian>(extras.expiration)?;
+            self.stream.write_all(key.as_bytes())?;
+        }
+        self.stream.flush()?;
+
+        let mut results = Vec::with_capacity(entries.len());
+        for _ in entries {
+            match binary_packet::parse_counter_response(&mut self.stream) {
+                Err(e @ MemcacheError::IOError(_)) => return Err(e),
+                result => results.push(result),
+            }
+        }
+        Ok(results)
+    }
+
     fn decrement(&mut self, key: &str, amount: u64) -> Result<u64, MemcacheError> {
         let request_header = PacketHeader {
             magic: Magic::Request as u8,
@@ -236,53 +447,202 @@ impl ProtocolTrait for BinaryProtocol {
         return binary_packet::parse_touch_response(&mut self.stream);
     }
 
+    fn exists(&mut self, key: &str) -> Result<bool, MemcacheError> {
+        // the binary protocol can't fetch an item without its value, at least don't buffer it
+        Ok(self.get_into(key, &mut std::io::sink())?.is_some())
+    }
+
     fn stats(&mut self) -> Result<Stats, MemcacheError> {
+        self.stats_with_args("")
+    }
+
+    fn stats_with_args(&mut self, args: &str) -> Result<Stats, MemcacheError> {
         let request_header = PacketHeader {
             magic: Magic::Request as u8,
             opcode: Opcode::Stat as u8,
+            key_length: args.len() as u16,
+            total_body_length: args.len() as u32,
             ..Default::default()
         };
         request_header.write(&mut self.stream)?;
+        self.stream.write_all(args.as_bytes())?;
         self.stream.flush()?;
         let stats_info = binary_packet::parse_stats_response(&mut self.stream)?;
         return Ok(stats_info);
     }
+
+    fn stats_reset(&mut self) -> Result<(), MemcacheError> {
+        let key = "reset";
+        let request_header = PacketHeader {
+            magic: Magic::Request as u8,
+            opcode: Opcode::Stat as u8,
+            key_length: key.len() as u16,
+            total_body_length: key.len() as u32,
+            ..Default::default()
+        };
+        request_header.write(&mut self.stream)?;
+        self.stream.write_all(key.as_bytes())?;
+        self.stream.flush()?;
+        binary_packet::parse_stats_response(&mut self.stream).map(|_| ())
+    }
+
+    fn verbosity(&mut self, level: u32) -> Result<(), MemcacheError> {
+        let request_header = PacketHeader {
+            magic: Magic::Request as u8,
+            opcode: Opcode::Verbosity as u8,
+            extras_length: 4,
+            total_body_length: 4,
+            ..Default::default()
+        };
+        request_header.write(&mut self.stream)?;
+        self.stream.write_u32::<BigEndian>(level)?;
+        self.stream.flush()?;
+        binary_packet::parse_response(&mut self.stream)?.err().map(|_| ())
+    }
 }
 
 impl BinaryProtocol {
+    /// Fetch `keys` with a get request each, for UDP streams: a batch of quiet gets followed by a
+    /// noop isn't answered by a single response which could be reassembled.
+    fn gets_one_by_one<V: FromMemcacheValueExt>(
+        &mut self,
+        keys: &[&str],
+        f: &mut GetsCallback<V>,
+    ) -> Result<usize, MemcacheError> {
+        let mut found = 0;
+        for key in keys {
+            if let Some((value, _)) = self.get_with_cas(key)? {
+                found += 1;
+                f(key.to_string(), value)?;
+            }
+        }
+        Ok(found)
+    }
+
+    /// Authenticate with SASL, using `mechanism` if given or the most secure mechanism
+    /// offered by the server otherwise.
+    pub(crate) fn auth_with_mechanism(
+        &mut self,
+        username: &str,
+        password: &str,
+        mechanism: Option<&str>,
+    ) -> Result<(), MemcacheError> {
+        let mechanisms = match self.list_sasl_mechanisms() {
+            Ok(mechanisms) => mechanisms,
+            // servers which can't list their mechanisms still understand PLAIN
+            Err(MemcacheError::CommandError(CommandError::Unknown(_))) => vec!["PLAIN".to_string()],
+            Err(e) => return Err(e),
+        };
+        let mut mechanism = sasl::select(&mechanisms, mechanism, username, password)?;
+        let data = mechanism.start()?;
+        let mut status = self.send_sasl_request(Opcode::SaslAuth, mechanism.name(), &data)?;
+        loop {
+            match status {
+                SaslStatus::Complete(data) => return mechanism.finish(&data),
+                SaslStatus::Continue(challenge) => {
+                    let data = mechanism.step(&challenge)?;
+                    status = self.send_sasl_request(Opcode::SaslStep, mechanism.name(), &data)?;
+                }
+            }
+        }
+    }
+
+    fn list_sasl_mechanisms(&mut self) -> Result<Vec<String>, MemcacheError> {
+        let request_header = PacketHeader {
+            magic: Magic::Request as u8,
+            opcode: Opcode::SaslListMechs as u8,
+            ..Default::default()
+        };
+        request_header.write(&mut self.stream)?;
+        self.stream.flush()?;
+        binary_packet::parse_sasl_list_mechs_response(&mut self.stream)
+    }
+
+    fn send_sasl_request(&mut self, opcode: Opcode, mechanism: &str, data: &[u8]) -> Result<SaslStatus, MemcacheError> {
+        let request_header = PacketHeader {
+            magic: Magic::Request as u8,
+            opcode: opcode as u8,
+            key_length: mechanism.len() as u16,
+            total_body_length: (mechanism.len() + data.len()) as u32,
+            ..Default::default()
+        };
+        request_header.write(&mut self.stream)?;
+        self.stream.write_all(mechanism.as_bytes())?;
+        self.stream.write_all(data)?;
+        self.stream.flush()?;
+        binary_packet::parse_sasl_response(&mut self.stream)
+    }
+
+    pub(crate) fn run_command(&mut self, request: &BinaryRequest) -> Result<BinaryResponse, MemcacheError> {
+        binary_packet::write_request(&mut self.stream, request)?;
+        self.stream.flush()?;
+        Ok(binary_packet::parse_response(&mut self.stream)?.into())
+    }
+
     fn send_request<V: ToMemcacheValue<Stream>>(
         &mut self,
         opcode: Opcode,
-        key: &str,
+        key: &[u8],
+        value: V,
+        expiration: u32,
+        cas: Option<u64>,
+    ) -> Result<(), MemcacheError> {
+        self.write_request(opcode, key, value, expiration, cas, 0)?;
+        self.stream.flush().map_err(Into::into)
+    }
+
+    /// Write a store request without flushing it, for pipelining. `opaque` is copied to the
+    /// response, to match it with the request.
+    fn write_request<V: ToMemcacheValue<Stream>>(
+        &mut self,
+        opcode: Opcode,
+        key: &[u8],
         value: V,
         expiration: u32,
         cas: Option<u64>,
+        opaque: u32,
     ) -> Result<(), MemcacheError> {
+        // append and prepend requests have no flags and expiration
+        let extras_length = match opcode {
+            Opcode::Append | Opcode::Prepend | Opcode::AppendQ | Opcode::PrependQ => 0,
+            _ => 8,
+        };
         let request_header = PacketHeader {
             magic: Magic::Request as u8,
             opcode: opcode as u8,
             key_length: key.len() as u16,
-            extras_length: 8,
-            total_body_length: (8 + key.len() + value.get_length()) as u32,
+            extras_length,
+            total_body_length: (usize::from(extras_length) + key.len() + value.get_length()) as u32,
+            opaque,
             cas: cas.unwrap_or(0),
             ..Default::default()
         };
-        let extras = binary_packet::StoreExtras {
-            flags: value.get_flags(),
-            expiration,
-        };
-        request_header.write(&mut self.stream)?;
-        self.stream.write_u32::<BigEndian>(extras.flags)?;
-        self.stream.write_u32::<BigEndian>(extras.expiration)?;
-        self.stream.write_all(key.as_bytes())?;
-        value.write_to(&mut self.stream)?;
-        self.stream.flush().map_err(Into::into)
+        // the header, extras and key are small, write them along with the value at once
+        let mut head = Vec::with_capacity(24 + usize::from(extras_length) + key.len());
+        request_header.write(&mut head)?;
+        if extras_length > 0 {
+            let extras = binary_packet::StoreExtras {
+                flags: value.get_flags(),
+                expiration,
+            };
+            head.write_u32::<BigEndian>(extras.flags)?;
+            head.write_u32::<BigEndian>(extras.expiration)?;
+        }
+        head.extend_from_slice(key);
+        match value.get_bytes() {
+            Some(data) => write_all_vectored(&mut self.stream, &[&head, data])?,
+            None => {
+                self.stream.write_all(&head)?;
+                value.write_to(&mut self.stream)?;
+            }
+        }
+        Ok(())
     }
 
     fn store<V: ToMemcacheValue<Stream>>(
         &mut self,
         opcode: Opcode,
-        key: &str,
+        key: &[u8],
         value: V,
         expiration: u32,
         cas: Option<u64>,
@@ -1,4 +1,7 @@
+use crate::buffer_pool;
+use crate::client::StoreResult;
 use crate::error::{CommandError, MemcacheError, ServerError};
+use crate::protocol::{GetsCallback, StoreResults};
 use crate::value::FromMemcacheValueExt;
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use std::borrow::Cow;
@@ -6,8 +9,11 @@ use std::collections::HashMap;
 use std::io::{self, Cursor};
 
 const OK_STATUS: u16 = 0x0;
+const AUTH_ERROR_STATUS: u16 = 0x20;
+const AUTH_CONTINUE_STATUS: u16 = 0x21;
 
 #[allow(dead_code)]
+#[derive(Clone, Copy)]
 pub enum Opcode {
     Get = 0x00,
     Set = 0x01,
@@ -23,8 +29,17 @@ pub enum Opcode {
     GetKQ = 0x0d,
     Append = 0x0e,
     Prepend = 0x0f,
+    SetQ = 0x11,
+    AddQ = 0x12,
+    ReplaceQ = 0x13,
+    DeleteQ = 0x14,
+    AppendQ = 0x19,
+    PrependQ = 0x1a,
+    Verbosity = 0x1b,
     Touch = 0x1c,
-    StartAuth = 0x21,
+    SaslListMechs = 0x20,
+    SaslAuth = 0x21,
+    SaslStep = 0x22,
 }
 
 pub enum Magic {
@@ -92,6 +107,64 @@ impl PacketHeader {
     }
 }
 
+/// A raw binary protocol request packet, see `Connection::run_binary_command`.
+#[derive(Debug, Default, Clone)]
+pub struct BinaryRequest {
+    pub opcode: u8,
+    pub key: Vec<u8>,
+    pub extras: Vec<u8>,
+    pub value: Vec<u8>,
+    pub opaque: u32,
+    pub cas: u64,
+}
+
+/// A raw binary protocol response packet, see `Connection::run_binary_command`.
+#[derive(Debug, Clone)]
+pub struct BinaryResponse {
+    pub opcode: u8,
+    pub status: u16,
+    pub data_type: u8,
+    pub opaque: u32,
+    pub cas: u64,
+    pub key: Vec<u8>,
+    pub extras: Vec<u8>,
+    pub value: Vec<u8>,
+}
+
+/// Write a raw request packet, without flushing it.
+pub(crate) fn write_request<W: io::Write>(writer: &mut W, request: &BinaryRequest) -> Result<(), MemcacheError> {
+    let request_header = PacketHeader {
+        magic: Magic::Request as u8,
+        opcode: request.opcode,
+        key_length: request.key.len() as u16,
+        extras_length: request.extras.len() as u8,
+        total_body_length: (request.extras.len() + request.key.len() + request.value.len()) as u32,
+        opaque: request.opaque,
+        cas: request.cas,
+        ..Default::default()
+    };
+    request_header.write(writer)?;
+    writer.write_all(&request.extras)?;
+    writer.write_all(&request.key)?;
+    writer.write_all(&request.value)?;
+    Ok(())
+}
+
+impl From<Response> for BinaryResponse {
+    fn from(response: Response) -> Self {
+        BinaryResponse {
+            opcode: response.header.opcode,
+            status: response.header.vbucket_id_or_status,
+            data_type: response.header.data_type,
+            opaque: response.header.opaque,
+            cas: response.header.cas,
+            key: response.key,
+            extras: response.extras,
+            value: response.value,
+        }
+    }
+}
+
 pub struct Response {
     header: PacketHeader,
     key: Vec<u8>,
@@ -119,8 +192,9 @@ pub fn parse_response<R: io::Read>(reader: &mut R) -> Result<Response, MemcacheE
     reader.read_exact(key.as_mut_slice())?;
 
     // TODO: return error if total_body_length < extras_length + key_length
-    let mut value =
-        vec![0x0; (header.total_body_length - u32::from(header.key_length) - u32::from(header.extras_length)) as usize];
+    let mut value = buffer_pool::take(
+        (header.total_body_length - u32::from(header.key_length) - u32::from(header.extras_length)) as usize,
+    );
     reader.read_exact(value.as_mut_slice())?;
 
     Ok(Response {
@@ -147,28 +221,68 @@ pub fn parse_version_response<R: io::Read>(reader: &mut R) -> Result<String, Mem
 }
 
 pub fn parse_get_response<R: io::Read, V: FromMemcacheValueExt>(reader: &mut R) -> Result<Option<V>, MemcacheError> {
+    Ok(parse_get_with_cas_response(reader)?.map(|(value, _)| value))
+}
+
+/// Same as `parse_get_response`, but also returns the cas unique of the value.
+pub fn parse_get_with_cas_response<R: io::Read, V: FromMemcacheValueExt>(
+    reader: &mut R,
+) -> Result<Option<(V, u64)>, MemcacheError> {
     match parse_response(reader)?.err() {
         Ok(Response {
             header, extras, value, ..
         }) => {
             let flags = Cursor::new(extras).read_u32::<BigEndian>()?;
-            Ok(Some(FromMemcacheValueExt::from_memcache_value(
-                value,
-                flags,
-                Some(header.cas),
-            )?))
+            let value = FromMemcacheValueExt::from_memcache_value(value, flags, Some(header.cas))?;
+            Ok(Some((value, header.cas)))
         }
         Err(MemcacheError::CommandError(CommandError::KeyNotFound)) => Ok(None),
         Err(e) => Err(e),
     }
 }
 
+/// Same as `parse_get_response`, but copies the value to `writer` and returns its flags.
+pub fn parse_get_into_response<R: io::Read, W: io::Write>(
+    reader: &mut R,
+    writer: &mut W,
+) -> Result<Option<u32>, MemcacheError> {
+    let header = PacketHeader::read(reader)?;
+    let mut extras = vec![0x0; header.extras_length as usize];
+    reader.read_exact(extras.as_mut_slice())?;
+    let mut key = vec![0x0; header.key_length as usize];
+    reader.read_exact(key.as_mut_slice())?;
+    let length = (header.total_body_length - u32::from(header.key_length) - u32::from(header.extras_length)) as u64;
+    if header.vbucket_id_or_status != OK_STATUS {
+        let mut value = vec![0x0; length as usize];
+        reader.read_exact(value.as_mut_slice())?;
+        let response = Response {
+            header,
+            key,
+            extras,
+            value,
+        };
+        return match response.err() {
+            Err(MemcacheError::CommandError(CommandError::KeyNotFound)) => Ok(None),
+            Err(e) => Err(e),
+            Ok(_) => unreachable!(),
+        };
+    }
+    if io::copy(&mut io::Read::take(&mut *reader, length), writer)? != length {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(Some(Cursor::new(extras).read_u32::<BigEndian>()?))
+}
+
+/// Parse the responses of quiet get requests followed by a noop request, passing the values to `f`,
+/// and return how many were found.
 pub fn parse_gets_response<R: io::Read, V: FromMemcacheValueExt>(
     reader: &mut R,
     max_responses: usize,
-) -> Result<HashMap<String, V>, MemcacheError> {
-    let mut result = HashMap::new();
-    for _ in 0..=max_responses {
+    f: &mut GetsCallback<V>,
+) -> Result<usize, MemcacheError> {
+    let mut failed = None;
+    // every response before the noop one is a value
+    for found in 0..=max_responses {
         let Response {
             header,
             key,
@@ -176,18 +290,78 @@ pub fn parse_gets_response<R: io::Read, V: FromMemcacheValueExt>(
             value,
         } = parse_response(reader)?.err()?;
         if header.opcode == Opcode::Noop as u8 {
-            return Ok(result);
+            return match failed {
+                Some(e) => Err(e),
+                None => Ok(found),
+            };
         }
         let flags = Cursor::new(extras).read_u32::<BigEndian>()?;
         let key = String::from_utf8(key)?;
-        result.insert(
-            key,
-            FromMemcacheValueExt::from_memcache_value(value, flags, Some(header.cas))?,
-        );
+        if failed.is_none() {
+            failed = f(
+                key,
+                FromMemcacheValueExt::from_memcache_value(value, flags, Some(header.cas))?,
+            )
+            .err();
+        }
     }
     Err(ServerError::BadResponse(Cow::Borrowed("Expected end of gets response")))?
 }
 
+/// Parse the responses of quiet store requests, sent with their index as opaque, followed by a
+/// noop request. Only the requests which failed get a response.
+pub fn parse_quiet_store_responses<R: io::Read>(reader: &mut R, count: usize) -> Result<StoreResults, MemcacheError> {
+    let mut results: StoreResults = (0..count).map(|_| Ok(StoreResult::Stored)).collect();
+    for _ in 0..=count {
+        let response = parse_response(reader)?;
+        if response.header.opcode == Opcode::Noop as u8 {
+            return Ok(results);
+        }
+        let index = response.header.opaque as usize;
+        if index >= count {
+            Err(ServerError::BadResponse(Cow::Borrowed(
+                "Unexpected opaque in store response",
+            )))?
+        }
+        results[index] = match response.err() {
+            Ok(_) => Ok(StoreResult::Stored),
+            // 0x5 is the status of items not stored by append and prepend
+            Err(MemcacheError::CommandError(e))
+                if e == CommandError::KeyExists || e == CommandError::KeyNotFound || e == CommandError::Unknown(5) =>
+            {
+                Ok(StoreResult::NotStored)
+            }
+            Err(e) => Err(e),
+        };
+    }
+    Err(ServerError::BadResponse(Cow::Borrowed(
+        "Expected end of store responses",
+    )))?
+}
+
+/// Parse the responses of quiet delete requests followed by a noop request. Only the requests
+/// which failed get a response, missing keys are not errors.
+pub fn parse_quiet_delete_responses<R: io::Read>(reader: &mut R, count: usize) -> Result<(), MemcacheError> {
+    let mut result = Ok(());
+    for _ in 0..=count {
+        let response = parse_response(reader)?;
+        if response.header.opcode == Opcode::Noop as u8 {
+            return result;
+        }
+        match response.err() {
+            Ok(_) | Err(MemcacheError::CommandError(CommandError::KeyNotFound)) => {}
+            Err(e) => {
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+    }
+    Err(ServerError::BadResponse(Cow::Borrowed(
+        "Expected end of delete responses",
+    )))?
+}
+
 pub fn parse_delete_response<R: io::Read>(reader: &mut R) -> Result<bool, MemcacheError> {
     match parse_response(reader)?.err() {
         Ok(_) => Ok(true),
@@ -223,6 +397,31 @@ pub fn parse_stats_response<R: io::Read>(reader: &mut R) -> Result<HashMap<Strin
     Ok(result)
 }
 
-pub fn parse_start_auth_response<R: io::Read>(reader: &mut R) -> Result<bool, MemcacheError> {
-    parse_response(reader)?.err().map(|_| true)
+pub enum SaslStatus {
+    /// Authentication succeeded, with the final data sent by the server.
+    Complete(Vec<u8>),
+    /// The server sent a challenge which should be answered with a SASL STEP request.
+    Continue(Vec<u8>),
+}
+
+pub fn parse_sasl_list_mechs_response<R: io::Read>(reader: &mut R) -> Result<Vec<String>, MemcacheError> {
+    let Response { value, .. } = parse_response(reader)?.err()?;
+    Ok(String::from_utf8(value)?
+        .split_whitespace()
+        .map(|mechanism| mechanism.to_string())
+        .collect())
+}
+
+pub fn parse_sasl_response<R: io::Read>(reader: &mut R) -> Result<SaslStatus, MemcacheError> {
+    let Response { header, value, .. } = parse_response(reader)?;
+    match header.vbucket_id_or_status {
+        OK_STATUS => Ok(SaslStatus::Complete(value)),
+        AUTH_CONTINUE_STATUS => Ok(SaslStatus::Continue(value)),
+        AUTH_ERROR_STATUS => {
+            let message = String::from_utf8_lossy(&value).into_owned();
+            let message = if message.is_empty() { None } else { Some(message) };
+            Err(CommandError::AuthenticationRequired(message))?
+        }
+        status => Err(CommandError::from(status))?,
+    }
 }
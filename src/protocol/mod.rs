@@ -1,15 +1,77 @@
 mod ascii;
 mod binary;
 mod binary_packet;
+mod sasl;
 
-use crate::client::Stats;
+use crate::client::{Stats, StoreResult};
 use crate::error::MemcacheError;
 pub(crate) use crate::protocol::ascii::AsciiProtocol;
 pub(crate) use crate::protocol::binary::BinaryProtocol;
+pub(crate) use crate::protocol::binary_packet::{parse_response, write_request, Opcode};
+pub use crate::protocol::binary_packet::{BinaryRequest, BinaryResponse};
 use crate::stream::Stream;
 use crate::value::{FromMemcacheValueExt, ToMemcacheValue};
 use enum_dispatch::enum_dispatch;
 use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum StoreCommand {
+    Cas,
+    Set,
+    Add,
+    Replace,
+    Append,
+    Prepend,
+}
+
+impl fmt::Display for StoreCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StoreCommand::Set => write!(f, "set"),
+            StoreCommand::Add => write!(f, "add"),
+            StoreCommand::Replace => write!(f, "replace"),
+            StoreCommand::Append => write!(f, "append"),
+            StoreCommand::Prepend => write!(f, "prepend"),
+            StoreCommand::Cas => write!(f, "cas"),
+        }
+    }
+}
+
+/// The outcome of each entry of a batched store, in the order of the entries.
+pub(crate) type StoreResults = Vec<Result<StoreResult, MemcacheError>>;
+
+/// A value with its flags and its remaining TTL in seconds, or `None` if it never expires.
+pub(crate) type StoredValue = (Vec<u8>, u32, Option<u32>);
+
+/// Receives the values of `ProtocolTrait::gets_foreach` as they are parsed. Once it failed, the
+/// remaining values are read and discarded, and the error is returned.
+pub(crate) type GetsCallback<'a, V> = dyn FnMut(String, V) -> Result<(), MemcacheError> + 'a;
+
+/// A mutation whose response is only sent by the server if it fails, encoded ahead of time with
+/// `encode_quiet` and sent with `ProtocolTrait::write_quiet`.
+pub(crate) enum QuietCommand<'a> {
+    Set {
+        key: &'a str,
+        data: &'a [u8],
+        flags: u32,
+        expiration: u32,
+    },
+    Delete {
+        key: &'a str,
+    },
+}
+
+/// Append `command` to `buffer`, as a noreply command for the ascii protocol or a quiet request
+/// for the binary protocol.
+pub(crate) fn encode_quiet(buffer: &mut Vec<u8>, ascii: bool, command: &QuietCommand) -> Result<(), MemcacheError> {
+    if ascii {
+        ascii::encode_quiet(buffer, command)
+    } else {
+        binary::encode_quiet(buffer, command)
+    }
+}
 
 #[enum_dispatch]
 pub enum Protocol {
@@ -24,8 +86,29 @@ pub trait ProtocolTrait {
     fn flush(&mut self) -> Result<(), MemcacheError>;
     fn flush_with_delay(&mut self, delay: u32) -> Result<(), MemcacheError>;
     fn get<V: FromMemcacheValueExt>(&mut self, key: &str) -> Result<Option<V>, MemcacheError>;
+    fn get_bytes_key<V: FromMemcacheValueExt>(&mut self, key: &[u8]) -> Result<Option<V>, MemcacheError>;
+    fn get_with_cas<V: FromMemcacheValueExt>(&mut self, key: &str) -> Result<Option<(V, u64)>, MemcacheError>;
+    fn get_into<W: Write>(&mut self, key: &str, writer: &mut W) -> Result<Option<u32>, MemcacheError>;
     fn gets<V: FromMemcacheValueExt>(&mut self, keys: &[&str]) -> Result<HashMap<String, V>, MemcacheError>;
+    /// Fetch `keys` like `gets`, passing the values found to `f` as they are parsed, and return
+    /// how many were found.
+    fn gets_foreach<V: FromMemcacheValueExt>(
+        &mut self,
+        keys: &[&str],
+        f: &mut GetsCallback<V>,
+    ) -> Result<usize, MemcacheError>;
     fn set<V: ToMemcacheValue<Stream>>(&mut self, key: &str, value: V, expiration: u32) -> Result<(), MemcacheError>;
+    fn set_bytes_key<V: ToMemcacheValue<Stream>>(
+        &mut self,
+        key: &[u8],
+        value: V,
+        expiration: u32,
+    ) -> Result<(), MemcacheError>;
+    fn stores<V: ToMemcacheValue<Stream>>(
+        &mut self,
+        command: StoreCommand,
+        entries: Vec<(&str, V, u32)>,
+    ) -> Result<StoreResults, MemcacheError>;
     fn cas<V: ToMemcacheValue<Stream>>(
         &mut self,
         key: &str,
@@ -43,8 +126,19 @@ pub trait ProtocolTrait {
     fn append<V: ToMemcacheValue<Stream>>(&mut self, key: &str, value: V) -> Result<(), MemcacheError>;
     fn prepend<V: ToMemcacheValue<Stream>>(&mut self, key: &str, value: V) -> Result<(), MemcacheError>;
     fn delete(&mut self, key: &str) -> Result<bool, MemcacheError>;
+    fn delete_bytes_key(&mut self, key: &[u8]) -> Result<bool, MemcacheError>;
+    fn deletes(&mut self, keys: &[&str]) -> Result<Vec<Result<bool, MemcacheError>>, MemcacheError>;
+    fn deletes_noreply(&mut self, keys: &[&str]) -> Result<(), MemcacheError>;
+    /// Send `count` commands encoded by `encode_quiet`, and wait until the server processed them.
+    /// Returns the first error reported by the server.
+    fn write_quiet(&mut self, commands: &[u8], count: usize) -> Result<(), MemcacheError>;
     fn increment(&mut self, key: &str, amount: u64) -> Result<u64, MemcacheError>;
+    fn increments(&mut self, entries: &[(&str, u64)]) -> Result<Vec<Result<u64, MemcacheError>>, MemcacheError>;
     fn decrement(&mut self, key: &str, amount: u64) -> Result<u64, MemcacheError>;
     fn touch(&mut self, key: &str, expiration: u32) -> Result<bool, MemcacheError>;
+    fn exists(&mut self, key: &str) -> Result<bool, MemcacheError>;
     fn stats(&mut self) -> Result<Stats, MemcacheError>;
+    fn stats_with_args(&mut self, args: &str) -> Result<Stats, MemcacheError>;
+    fn stats_reset(&mut self) -> Result<(), MemcacheError>;
+    fn verbosity(&mut self, level: u32) -> Result<(), MemcacheError>;
 }
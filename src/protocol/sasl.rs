@@ -0,0 +1,365 @@
+#[cfg(feature = "scram")]
+use crate::error::CommandError;
+use crate::error::{ClientError, MemcacheError};
+#[cfg(feature = "scram")]
+use base64::engine::general_purpose::STANDARD as BASE64;
+#[cfg(feature = "scram")]
+use base64::Engine;
+#[cfg(feature = "scram")]
+use hmac::{Hmac, Mac};
+#[cfg(feature = "scram")]
+use sha1::Sha1;
+#[cfg(feature = "scram")]
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+
+/// Mechanisms supported by this crate, from the most to the least preferred.
+#[cfg(feature = "scram")]
+const SUPPORTED: &[&str] = &["SCRAM-SHA-256", "SCRAM-SHA-1", "PLAIN"];
+#[cfg(not(feature = "scram"))]
+const SUPPORTED: &[&str] = &["PLAIN"];
+
+/// A SASL mechanism used to authenticate binary protocol connections.
+pub(crate) trait Mechanism {
+    /// The mechanism name as listed by the server.
+    fn name(&self) -> &'static str;
+
+    /// The initial client response, sent with the SASL AUTH request.
+    fn start(&mut self) -> Result<Vec<u8>, MemcacheError>;
+
+    /// The response to a server challenge, sent with a SASL STEP request.
+    fn step(&mut self, challenge: &[u8]) -> Result<Vec<u8>, MemcacheError>;
+
+    /// Verify the data sent by the server along with a successful authentication.
+    fn finish(&mut self, _data: &[u8]) -> Result<(), MemcacheError> {
+        Ok(())
+    }
+}
+
+/// The PLAIN mechanism, described in RFC 4616.
+pub(crate) struct Plain {
+    username: String,
+    password: String,
+}
+
+impl Mechanism for Plain {
+    fn name(&self) -> &'static str {
+        "PLAIN"
+    }
+
+    fn start(&mut self) -> Result<Vec<u8>, MemcacheError> {
+        Ok(format!("\x00{}\x00{}", self.username, self.password).into_bytes())
+    }
+
+    fn step(&mut self, _challenge: &[u8]) -> Result<Vec<u8>, MemcacheError> {
+        Err(ClientError::Error(Cow::Borrowed(
+            "unexpected SASL challenge for the PLAIN mechanism",
+        )))?
+    }
+}
+
+#[cfg(feature = "scram")]
+#[derive(Clone, Copy)]
+enum ScramHash {
+    Sha1,
+    Sha256,
+}
+
+#[cfg(feature = "scram")]
+impl ScramHash {
+    fn hash(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            ScramHash::Sha1 => Sha1::digest(data).to_vec(),
+            ScramHash::Sha256 => Sha256::digest(data).to_vec(),
+        }
+    }
+
+    fn hmac(self, key: &[u8], data: &[u8]) -> Vec<u8> {
+        match self {
+            ScramHash::Sha1 => {
+                let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any size");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+            ScramHash::Sha256 => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+        }
+    }
+
+    /// The `Hi` function from RFC 5802, which is PBKDF2 with HMAC as the pseudorandom function.
+    fn hi(self, password: &[u8], salt: &[u8], iterations: u32) -> Vec<u8> {
+        let mut block = salt.to_vec();
+        block.extend_from_slice(&[0, 0, 0, 1]);
+        let mut u = self.hmac(password, &block);
+        let mut result = u.clone();
+        for _ in 1..iterations {
+            u = self.hmac(password, &u);
+            for (r, u) in result.iter_mut().zip(u.iter()) {
+                *r ^= u;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(feature = "scram")]
+enum ScramState {
+    Initial,
+    ClientFirstSent { client_first_bare: String },
+    ClientFinalSent { server_signature: Vec<u8> },
+    Verified,
+}
+
+/// The SCRAM-SHA-1 and SCRAM-SHA-256 mechanisms, described in RFC 5802 and RFC 7677.
+#[cfg(feature = "scram")]
+pub(crate) struct Scram {
+    hash: ScramHash,
+    username: String,
+    password: String,
+    nonce: String,
+    state: ScramState,
+}
+
+#[cfg(feature = "scram")]
+fn scram_error(message: &'static str) -> MemcacheError {
+    ClientError::Error(Cow::Borrowed(message)).into()
+}
+
+#[cfg(feature = "scram")]
+impl Scram {
+    fn new(hash: ScramHash, username: &str, password: &str, nonce: String) -> Self {
+        Scram {
+            hash,
+            username: username.replace('=', "=3D").replace(',', "=2C"),
+            password: password.to_string(),
+            nonce,
+            state: ScramState::Initial,
+        }
+    }
+
+    fn client_final(&mut self, client_first_bare: &str, server_first: &str) -> Result<Vec<u8>, MemcacheError> {
+        let mut nonce = None;
+        let mut salt = None;
+        let mut iterations = None;
+        for attribute in server_first.split(',') {
+            if let Some(value) = attribute.strip_prefix("r=") {
+                nonce = Some(value);
+            } else if let Some(value) = attribute.strip_prefix("s=") {
+                salt = Some(BASE64.decode(value).map_err(|_| scram_error("invalid SCRAM salt"))?);
+            } else if let Some(value) = attribute.strip_prefix("i=") {
+                iterations = Some(value.parse::<u32>()?);
+            }
+        }
+        let (nonce, salt, iterations) = match (nonce, salt, iterations) {
+            (Some(nonce), Some(salt), Some(iterations)) if iterations > 0 => (nonce, salt, iterations),
+            _ => return Err(scram_error("invalid SCRAM server first message")),
+        };
+        if !nonce.starts_with(&self.nonce) {
+            return Err(scram_error("SCRAM server nonce doesn't match the client nonce"));
+        }
+
+        let salted_password = self.hash.hi(self.password.as_bytes(), &salt, iterations);
+        let client_key = self.hash.hmac(&salted_password, b"Client Key");
+        let stored_key = self.hash.hash(&client_key);
+        // "biws" is the base64 encoded GS2 header "n,,"
+        let client_final_without_proof = format!("c=biws,r={}", nonce);
+        let auth_message = format!("{},{},{}", client_first_bare, server_first, client_final_without_proof);
+        let client_signature = self.hash.hmac(&stored_key, auth_message.as_bytes());
+        let proof: Vec<u8> = client_key
+            .iter()
+            .zip(client_signature.iter())
+            .map(|(key, signature)| key ^ signature)
+            .collect();
+        let server_key = self.hash.hmac(&salted_password, b"Server Key");
+        self.state = ScramState::ClientFinalSent {
+            server_signature: self.hash.hmac(&server_key, auth_message.as_bytes()),
+        };
+        Ok(format!("{},p={}", client_final_without_proof, BASE64.encode(proof)).into_bytes())
+    }
+
+    fn verify(&mut self, server_final: &str, server_signature: &[u8]) -> Result<(), MemcacheError> {
+        if let Some(error) = server_final.strip_prefix("e=") {
+            return Err(CommandError::AuthenticationRequired(Some(error.to_string())).into());
+        }
+        match server_final.strip_prefix("v=").map(|v| BASE64.decode(v)) {
+            Some(Ok(ref signature)) if signature.as_slice() == server_signature => {
+                self.state = ScramState::Verified;
+                Ok(())
+            }
+            _ => Err(scram_error("SCRAM server signature verification failed")),
+        }
+    }
+}
+
+#[cfg(feature = "scram")]
+impl Mechanism for Scram {
+    fn name(&self) -> &'static str {
+        match self.hash {
+            ScramHash::Sha1 => "SCRAM-SHA-1",
+            ScramHash::Sha256 => "SCRAM-SHA-256",
+        }
+    }
+
+    fn start(&mut self) -> Result<Vec<u8>, MemcacheError> {
+        let client_first_bare = format!("n={},r={}", self.username, self.nonce);
+        let client_first = format!("n,,{}", client_first_bare);
+        self.state = ScramState::ClientFirstSent { client_first_bare };
+        Ok(client_first.into_bytes())
+    }
+
+    fn step(&mut self, challenge: &[u8]) -> Result<Vec<u8>, MemcacheError> {
+        let challenge = std::str::from_utf8(challenge)?;
+        match std::mem::replace(&mut self.state, ScramState::Initial) {
+            ScramState::ClientFirstSent { client_first_bare } => self.client_final(&client_first_bare, challenge),
+            // some servers send the server final message as another challenge
+            ScramState::ClientFinalSent { server_signature } => {
+                self.verify(challenge, &server_signature)?;
+                Ok(Vec::new())
+            }
+            _ => Err(scram_error("unexpected SASL challenge for the SCRAM mechanism")),
+        }
+    }
+
+    fn finish(&mut self, data: &[u8]) -> Result<(), MemcacheError> {
+        match std::mem::replace(&mut self.state, ScramState::Initial) {
+            ScramState::Verified => Ok(()),
+            ScramState::ClientFinalSent { server_signature } => {
+                self.verify(std::str::from_utf8(data)?, &server_signature)
+            }
+            _ => Err(scram_error("SCRAM authentication finished unexpectedly")),
+        }
+    }
+}
+
+fn create(name: &str, username: &str, password: &str) -> Box<dyn Mechanism> {
+    match name {
+        #[cfg(feature = "scram")]
+        "SCRAM-SHA-256" | "SCRAM-SHA-1" => {
+            let hash = if name == "SCRAM-SHA-1" {
+                ScramHash::Sha1
+            } else {
+                ScramHash::Sha256
+            };
+            let nonce: String = std::iter::repeat_with(rand::random::<u8>)
+                .map(|b| (b'a' + b % 26) as char)
+                .take(24)
+                .collect();
+            Box::new(Scram::new(hash, username, password, nonce))
+        }
+        _ => Box::new(Plain {
+            username: username.to_string(),
+            password: password.to_string(),
+        }),
+    }
+}
+
+/// Select the mechanism to authenticate with from the ones offered by the server. If `requested`
+/// is given, only that mechanism is considered, otherwise the most secure supported one is used.
+pub(crate) fn select(
+    offered: &[String],
+    requested: Option<&str>,
+    username: &str,
+    password: &str,
+) -> Result<Box<dyn Mechanism>, MemcacheError> {
+    if let Some(requested) = requested {
+        let requested = requested.to_uppercase();
+        if !SUPPORTED.contains(&requested.as_str()) {
+            return Err(ClientError::Error(Cow::Owned(format!("unsupported SASL mechanism: {}", requested))).into());
+        }
+        if !offered.contains(&requested) {
+            return Err(ClientError::Error(Cow::Owned(format!(
+                "SASL mechanism {} is not offered by the server, available mechanisms: {}",
+                requested,
+                offered.join(" ")
+            )))
+            .into());
+        }
+        return Ok(create(&requested, username, password));
+    }
+    match SUPPORTED
+        .iter()
+        .find(|name| offered.iter().any(|mechanism| mechanism == *name))
+    {
+        Some(name) => Ok(create(name, username, password)),
+        None => Err(ClientError::Error(Cow::Owned(format!(
+            "none of the SASL mechanisms offered by the server are supported: {}",
+            offered.join(" ")
+        ))))?,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::select;
+
+    #[test]
+    fn test_plain() {
+        let mut mechanism = select(&["CRAM-MD5".into(), "PLAIN".into()], None, "user", "pass").unwrap();
+        assert_eq!(mechanism.name(), "PLAIN");
+        assert_eq!(mechanism.start().unwrap(), b"\x00user\x00pass");
+        assert!(mechanism.step(b"challenge").is_err());
+    }
+
+    #[test]
+    fn test_unsupported_mechanisms() {
+        assert!(select(&["CRAM-MD5".into()], None, "user", "pass").is_err());
+        assert!(select(&["CRAM-MD5".into()], Some("cram-md5"), "user", "pass").is_err());
+        assert!(select(&["PLAIN".into()], Some("scram-sha-1"), "user", "pass").is_err());
+    }
+
+    #[cfg(feature = "scram")]
+    #[test]
+    fn test_scram_sha1() {
+        // test vector from RFC 5802 section 5
+        use super::{Mechanism, Scram, ScramHash};
+        let mut mechanism = Scram::new(ScramHash::Sha1, "user", "pencil", "fyko+d2lbbFgONRv9qkxdawL".into());
+        assert_eq!(mechanism.name(), "SCRAM-SHA-1");
+        assert_eq!(
+            mechanism.start().unwrap(),
+            b"n,,n=user,r=fyko+d2lbbFgONRv9qkxdawL".to_vec()
+        );
+        let client_final = mechanism
+            .step(b"r=fyko+d2lbbFgONRv9qkxdawL3rfcNHYJY1ZVvWVs7j,s=QSXCR+Q6sek8bf92,i=4096")
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(client_final).unwrap(),
+            "c=biws,r=fyko+d2lbbFgONRv9qkxdawL3rfcNHYJY1ZVvWVs7j,p=v0X8v3Bz2T0CJGbJQyF0X+HI4Ts="
+        );
+        mechanism.finish(b"v=rmF9pqV8S7suAoZWja4dJRkFsKQ=").unwrap();
+    }
+
+    #[cfg(feature = "scram")]
+    #[test]
+    fn test_scram_sha256() {
+        // test vector from RFC 7677 section 3
+        use super::{Mechanism, Scram, ScramHash};
+        let mut mechanism = Scram::new(ScramHash::Sha256, "user", "pencil", "rOprNGfwEbeRWgbNEkqO".into());
+        assert_eq!(mechanism.name(), "SCRAM-SHA-256");
+        mechanism.start().unwrap();
+        let client_final = mechanism
+            .step(b"r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096")
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(client_final).unwrap(),
+            "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="
+        );
+        mechanism
+            .finish(b"v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=")
+            .unwrap();
+    }
+
+    #[cfg(feature = "scram")]
+    #[test]
+    fn test_scram_bad_server_signature() {
+        use super::{Mechanism, Scram, ScramHash};
+        let mut mechanism = Scram::new(ScramHash::Sha1, "user", "pencil", "fyko+d2lbbFgONRv9qkxdawL".into());
+        mechanism.start().unwrap();
+        mechanism
+            .step(b"r=fyko+d2lbbFgONRv9qkxdawL3rfcNHYJY1ZVvWVs7j,s=QSXCR+Q6sek8bf92,i=4096")
+            .unwrap();
+        assert!(mechanism.finish(b"v=AAAAAAAAAAAAAAAAAAAAAAAAAAA=").is_err());
+    }
+}
@@ -0,0 +1,114 @@
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::client::Client;
+use crate::error::{CommandError, MemcacheError};
+
+/// Limits the number of requests per key to `limit` per window, with counters stored in
+/// memcached, so the quota is shared by every process using the same servers.
+///
+/// Requests are counted in a bucket per window, stored under `<prefix><key>:<window number>` and
+/// expiring with it. By default the windows are fixed, which allows bursts of up to twice the limit
+/// around the start of a window. A sliding window weights the count of the previous window by how
+/// much of it still overlaps the sliding window, at the cost of fetching it too.
+///
+/// Rejected requests are counted as well, so a client which keeps retrying stays limited. Counters
+/// are not transcoded, so they can't be used with transcoders which change all values.
+///
+/// Example:
+///
+/// ```rust
+/// use std::time::Duration;
+///
+/// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+/// let limiter = memcache::RateLimiter::new(&client, "requests:", 2, Duration::from_secs(60)).with_sliding_window();
+/// assert!(limiter.try_acquire("alice").unwrap());
+/// assert!(limiter.try_acquire("alice").unwrap());
+/// assert!(!limiter.try_acquire("alice").unwrap());
+/// assert!(limiter.try_acquire("bob").unwrap());
+/// # client.flush().unwrap();
+/// ```
+pub struct RateLimiter<'a> {
+    client: &'a Client,
+    prefix: String,
+    limit: u64,
+    window: Duration,
+    sliding: bool,
+}
+
+impl fmt::Debug for RateLimiter<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RateLimiter")
+            .field("prefix", &self.prefix)
+            .field("limit", &self.limit)
+            .field("window", &self.window)
+            .field("sliding", &self.sliding)
+            .finish()
+    }
+}
+
+impl<'a> RateLimiter<'a> {
+    /// Allow `limit` requests per key in each fixed `window`.
+    ///
+    /// Panics if `window` is shorter than a second, the resolution of memcached expirations.
+    pub fn new(client: &'a Client, prefix: &str, limit: u64, window: Duration) -> Self {
+        assert!(window.as_secs() >= 1, "the rate limit window must be at least a second");
+        RateLimiter {
+            client,
+            prefix: prefix.to_string(),
+            limit,
+            window,
+            sliding: false,
+        }
+    }
+
+    /// Use a sliding window instead of fixed windows.
+    pub fn with_sliding_window(mut self) -> Self {
+        self.sliding = true;
+        self
+    }
+
+    /// Count a request for `key`, and return whether it is within the limit.
+    pub fn try_acquire(&self, key: &str) -> Result<bool, MemcacheError> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let window = self.window.as_millis();
+        let bucket = now.as_millis() / window;
+        if !self.sliding {
+            return Ok(self.count(key, bucket)? <= self.limit);
+        }
+        let count = self.count(key, bucket)?;
+        let previous: u64 = self
+            .client
+            .get(self.bucket_key(key, bucket.saturating_sub(1)))?
+            .unwrap_or(0);
+        // the part of the previous window still covered by the sliding window
+        let overlap = 1.0 - (now.as_millis() % window) as f64 / window as f64;
+        Ok(previous as f64 * overlap + count as f64 <= self.limit as f64)
+    }
+
+    fn bucket_key(&self, key: &str, bucket: u128) -> String {
+        format!("{}{}:{}", self.prefix, key, bucket)
+    }
+
+    /// Increment the counter of the bucket, creating it if it's missing.
+    fn count(&self, key: &str, bucket: u128) -> Result<u64, MemcacheError> {
+        let bucket_key = self.bucket_key(key, bucket);
+        // a sliding window still reads the bucket during the next window
+        let windows = if self.sliding { 2 } else { 1 };
+        let ttl = self.window.as_secs() as u32 * windows + 1;
+        loop {
+            // `increments` never creates missing counters, unlike `increment` with the binary protocol
+            match self.client.increments(&[(bucket_key.as_str(), 1)])?.remove(&bucket_key) {
+                Some(Err(ref e)) if matches!(e.inner(), MemcacheError::CommandError(CommandError::KeyNotFound)) => {}
+                Some(result) => return result,
+                None => unreachable!(),
+            }
+            match self.client.add(&bucket_key, 1u64, ttl) {
+                Ok(()) => return Ok(1),
+                // created concurrently, increment it
+                Err(ref e) if matches!(e.inner(), MemcacheError::CommandError(CommandError::KeyExists)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
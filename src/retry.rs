@@ -0,0 +1,145 @@
+use std::time::Duration;
+
+use crate::error::{ErrorClass, MemcacheError};
+use crate::metrics::Command;
+
+/// When `Client` sends a command again after it failed with a transient error, like a connection
+/// reset by a restarting server, see `ClientBuilder::with_retry_policy`.
+///
+/// The delay before every retry doubles from the initial backoff up to the maximum backoff, and
+/// with jitter, which is enabled by default, a random delay up to it is used instead, so clients
+/// failing at the same time don't retry at the same time. By default connection errors and
+/// timeouts of the commands fetching keys are retried, other commands may be applied twice if the
+/// failure happened after the server received them, so only idempotent ones should be added.
+///
+/// Retries apply to the commands sent for a single key, and to each request of a multi-get.
+///
+/// Example:
+///
+/// ```rust
+/// use std::time::Duration;
+///
+/// let policy = memcache::RetryPolicy::new(3)
+///     .with_backoff(Duration::from_millis(5), Duration::from_millis(100))
+///     .with_commands(&[memcache::Command::Get, memcache::Command::Gets, memcache::Command::Touch]);
+/// let client = memcache::Client::builder()
+///     .add_server("memcache://localhost:12345")
+///     .with_retry_policy(policy)
+///     .build()
+///     .unwrap();
+/// client.set("foo", "bar", 10).unwrap();
+/// assert_eq!(client.get::<String>("foo").unwrap(), Some("bar".to_string()));
+/// # client.flush().unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    jitter: bool,
+    errors: Vec<ErrorClass>,
+    commands: Vec<Command>,
+}
+
+impl RetryPolicy {
+    /// Send a command at most `max_attempts` times, retrying connection errors and timeouts of
+    /// `Command::Get` and `Command::Gets` after a backoff from 10ms up to 1s, with jitter.
+    pub fn new(max_attempts: u32) -> Self {
+        RetryPolicy {
+            max_attempts,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+            jitter: true,
+            errors: vec![ErrorClass::Connection, ErrorClass::Timeout],
+            commands: vec![Command::Get, Command::Gets],
+        }
+    }
+
+    /// Wait `initial` before the first retry, and twice as long before every following retry, up
+    /// to `max`.
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Whether to wait a random delay up to the backoff instead of the backoff itself.
+    pub fn with_jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Set the classes of errors which are retried.
+    pub fn with_errors(mut self, errors: &[ErrorClass]) -> Self {
+        self.errors = errors.to_vec();
+        self
+    }
+
+    /// Set the commands which are retried.
+    pub fn with_commands(mut self, commands: &[Command]) -> Self {
+        self.commands = commands.to_vec();
+        self
+    }
+
+    /// How long to wait before sending `command` again after its `attempt`, counted from 1, failed
+    /// with `error`, or `None` if it isn't retried.
+    pub(crate) fn backoff(&self, command: Command, error: &MemcacheError, attempt: u32) -> Option<Duration> {
+        if attempt >= self.max_attempts || !self.commands.contains(&command) {
+            return None;
+        }
+        match error.class() {
+            Some(class) if self.errors.contains(&class) => (),
+            _ => return None,
+        }
+        let backoff = 2u32
+            .checked_pow(attempt - 1)
+            .and_then(|factor| self.initial_backoff.checked_mul(factor))
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff));
+        Some(if self.jitter {
+            backoff.mul_f64(rand::random::<f64>())
+        } else {
+            backoff
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RetryPolicy;
+    use crate::error::{CommandError, ErrorClass, MemcacheError, ServerError};
+    use crate::metrics::Command;
+    use std::io;
+    use std::time::Duration;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::new(4)
+            .with_backoff(Duration::from_millis(10), Duration::from_millis(25))
+            .with_jitter(false);
+        let reset = MemcacheError::from(io::Error::from(io::ErrorKind::ConnectionReset));
+        assert_eq!(policy.backoff(Command::Get, &reset, 1), Some(Duration::from_millis(10)));
+        assert_eq!(
+            policy.backoff(Command::Gets, &reset, 2),
+            Some(Duration::from_millis(20))
+        );
+        assert_eq!(policy.backoff(Command::Get, &reset, 3), Some(Duration::from_millis(25)));
+        // the attempts are exhausted
+        assert_eq!(policy.backoff(Command::Get, &reset, 4), None);
+        // not a retried command
+        assert_eq!(policy.backoff(Command::Set, &reset, 1), None);
+        // not transient
+        assert_eq!(policy.backoff(Command::Get, &CommandError::KeyExists.into(), 1), None);
+        let server_error = MemcacheError::from(ServerError::Error("SERVER_ERROR out of memory".into()));
+        assert_eq!(policy.backoff(Command::Get, &server_error, 1), None);
+        let policy = policy.with_errors(&[ErrorClass::Server]);
+        assert_eq!(
+            policy.backoff(Command::Get, &server_error, 1),
+            Some(Duration::from_millis(10))
+        );
+
+        let policy = RetryPolicy::new(100).with_backoff(Duration::from_millis(10), Duration::from_millis(50));
+        for attempt in 1..100 {
+            assert!(policy.backoff(Command::Get, &reset, attempt).unwrap() <= Duration::from_millis(50));
+        }
+    }
+}
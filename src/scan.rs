@@ -0,0 +1,235 @@
+use std::borrow::Cow;
+use std::slice;
+
+use r2d2::{Pool, PooledConnection};
+
+use crate::connection::ConnectionManager;
+use crate::error::{MemcacheError, ServerError};
+
+/// Metadata of a key stored on a server, as reported by `lru_crawler metadump`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyMetadata {
+    /// The key, with the server's URL encoding decoded.
+    pub key: String,
+    /// The unix timestamp when the item expires, or `None` if it never expires.
+    pub expiration: Option<u64>,
+    /// The unix timestamp of the last access to the item.
+    pub last_access: u64,
+    /// The CAS value of the item.
+    pub cas: u64,
+    /// Whether the item was fetched since it was stored.
+    pub fetched: bool,
+    /// The slab class the item is stored in.
+    pub slab_class: u32,
+    /// The total size of the item in bytes, including the key and the item header.
+    pub size: u32,
+}
+
+impl KeyMetadata {
+    /// Parse a line like `key=foo exp=-1 la=1614000000 cas=2 fetch=no cls=1 size=63`.
+    pub(crate) fn parse(line: &str) -> Result<Self, MemcacheError> {
+        let bad_response = || ServerError::BadResponse(Cow::Owned(line.to_string()));
+        let mut key = None;
+        let mut expiration = None;
+        let mut last_access = 0;
+        let mut cas = 0;
+        let mut fetched = false;
+        let mut slab_class = 0;
+        let mut size = 0;
+        for field in line.split(' ') {
+            let mut parts = field.splitn(2, '=');
+            let (name, value) = match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) => (name, value),
+                _ => return Err(bad_response().into()),
+            };
+            match name {
+                "key" => key = Some(decode_key(value).ok_or_else(bad_response)?),
+                "exp" => expiration = if value == "-1" { None } else { Some(value.parse()?) },
+                "la" => last_access = value.parse()?,
+                "cas" => cas = value.parse()?,
+                "fetch" => fetched = value == "yes",
+                "cls" => slab_class = value.parse()?,
+                "size" => size = value.parse()?,
+                // newer servers may report more fields
+                _ => {}
+            }
+        }
+        Ok(KeyMetadata {
+            key: key.ok_or_else(bad_response)?,
+            expiration,
+            last_access,
+            cas,
+            fetched,
+            slab_class,
+            size,
+        })
+    }
+}
+
+/// A key listed by `stats cachedump`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedumpItem {
+    pub key: String,
+    /// The size of the item's value in bytes.
+    pub size: u32,
+    /// The unix timestamp when the item expires. Servers report the time they started for items
+    /// which never expire.
+    pub expiration: u64,
+}
+
+impl CachedumpItem {
+    /// Parse a line like `ITEM foo [3 b; 1614003600 s]`.
+    pub(crate) fn parse(line: &str) -> Result<Self, MemcacheError> {
+        let bad_response = || ServerError::BadResponse(Cow::Owned(line.to_string()));
+        let mut parts = line.split(' ');
+        match (
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+        ) {
+            (Some("ITEM"), Some(key), Some(size), Some("b;"), Some(expiration), Some("s]")) => Ok(CachedumpItem {
+                key: key.to_string(),
+                size: size.strip_prefix('[').ok_or_else(bad_response)?.parse()?,
+                expiration: expiration.parse()?,
+            }),
+            _ => Err(bad_response())?,
+        }
+    }
+}
+
+/// Decode the `%XX` escapes used by memcached for keys in metadump and log output.
+pub(crate) fn decode_key(key: &str) -> Option<String> {
+    let bytes = key.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// An iterator over the metadata of all keys stored on the servers of a client, created by
+/// `Client::scan_keys`.
+///
+/// The keys are read from the servers one at a time while iterating. A pooled connection is
+/// held for the server which is currently scanned, dropping the iterator before the scan has
+/// finished reads the rest of the server's dump so the connection can be reused.
+pub struct KeyScan<'a> {
+    pools: slice::Iter<'a, Pool<ConnectionManager>>,
+    current: Option<PooledConnection<ConnectionManager>>,
+}
+
+impl<'a> KeyScan<'a> {
+    pub(crate) fn new(pools: &'a [Pool<ConnectionManager>]) -> Self {
+        KeyScan {
+            pools: pools.iter(),
+            current: None,
+        }
+    }
+
+    fn next_server(&mut self) -> Option<Result<(), MemcacheError>> {
+        let pool = self.pools.next()?;
+        let result = pool.get().map_err(MemcacheError::from).and_then(|mut connection| {
+            connection.start_metadump()?;
+            self.current = Some(connection);
+            Ok(())
+        });
+        Some(result)
+    }
+}
+
+impl<'a> Iterator for KeyScan<'a> {
+    type Item = Result<KeyMetadata, MemcacheError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let connection = match self.current {
+                Some(ref mut connection) => connection,
+                None => match self.next_server()? {
+                    Ok(()) => continue,
+                    Err(e) => return Some(Err(e)),
+                },
+            };
+            match connection.read_metadump_line() {
+                Ok(Some(line)) => return Some(KeyMetadata::parse(&line)),
+                Ok(None) => self.current = None,
+                Err(e) => {
+                    self.current = None;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Drop for KeyScan<'a> {
+    fn drop(&mut self) {
+        if let Some(ref mut connection) = self.current {
+            while let Ok(Some(_)) = connection.read_metadump_line() {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_key, CachedumpItem, KeyMetadata};
+
+    #[test]
+    fn test_decode_key() {
+        assert_eq!(decode_key("foo").unwrap(), "foo");
+        assert_eq!(decode_key("foo%20bar%2Fbaz").unwrap(), "foo bar/baz");
+        assert_eq!(decode_key("%E4%BD%A0%E5%A5%BD").unwrap(), "你好");
+        assert!(decode_key("foo%2").is_none());
+        assert!(decode_key("foo%zz").is_none());
+    }
+
+    #[test]
+    fn test_parse_key_metadata() {
+        let metadata = KeyMetadata::parse("key=foo%3Abar exp=-1 la=1614000000 cas=2 fetch=no cls=1 size=63").unwrap();
+        assert_eq!(
+            metadata,
+            KeyMetadata {
+                key: "foo:bar".to_string(),
+                expiration: None,
+                last_access: 1614000000,
+                cas: 2,
+                fetched: false,
+                slab_class: 1,
+                size: 63,
+            }
+        );
+
+        let metadata =
+            KeyMetadata::parse("key=baz exp=1614003600 la=1614000000 cas=3 fetch=yes cls=2 size=120 flags=0").unwrap();
+        assert_eq!(metadata.expiration, Some(1614003600));
+        assert!(metadata.fetched);
+        assert_eq!(metadata.slab_class, 2);
+
+        assert!(KeyMetadata::parse("exp=-1 la=1614000000").is_err());
+        assert!(KeyMetadata::parse("key=foo exp=never").is_err());
+    }
+
+    #[test]
+    fn test_parse_cachedump_item() {
+        assert_eq!(
+            CachedumpItem::parse("ITEM foo [3 b; 1614003600 s]").unwrap(),
+            CachedumpItem {
+                key: "foo".to_string(),
+                size: 3,
+                expiration: 1614003600,
+            }
+        );
+        assert!(CachedumpItem::parse("ITEM foo [3 b;").is_err());
+        assert!(CachedumpItem::parse("ITEM foo 3 b; 1614003600 s]").is_err());
+    }
+}
@@ -0,0 +1,171 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt;
+
+use crate::error::{ClientError, MemcacheError};
+use crate::value::Transcoder;
+
+type Migration = Box<dyn Fn(Vec<u8>) -> Result<Vec<u8>, MemcacheError> + Send + Sync>;
+
+/// A `Transcoder` which stamps values with a schema version, and upgrades values written with an
+/// older version when they are fetched, so the layout of cached values can evolve without
+/// flushing the servers.
+///
+/// The version is stored as a 2 bytes header, and values with a header are marked with a flag
+/// bit, `0x2000_0000` by default. Values without it, e.g. written before versioning was enabled,
+/// are version 0. A value is upgraded by applying the migrations registered with
+/// `with_migration` for each version up to the current one, and fetching values which can't be
+/// upgraded, or were written by a newer version, fails with a `ClientError`.
+///
+/// Migrations transform the serialized value, so add this transcoder before any other, like
+/// `Compression`.
+///
+/// Example:
+///
+/// ```rust
+/// // version 1 renamed the `name` field of a JSON value to `username`
+/// let schema = memcache::SchemaVersion::new(1).with_migration(0, |value| {
+///     let value = String::from_utf8(value)?;
+///     Ok(value.replace("\"name\":", "\"username\":").into_bytes())
+/// });
+/// let client = memcache::Client::builder()
+///     .add_server("memcache://localhost:12345")
+///     .with_transcoder(schema)
+///     .build()
+///     .unwrap();
+/// let old = memcache::Client::connect("memcache://localhost:12345").unwrap();
+/// old.set("user", r#"{"name":"alice"}"#, 0).unwrap();
+/// let value: String = client.get("user").unwrap().unwrap();
+/// assert_eq!(value, r#"{"username":"alice"}"#);
+/// ```
+pub struct SchemaVersion {
+    version: u16,
+    flag: u32,
+    migrations: HashMap<u16, Migration>,
+}
+
+impl fmt::Debug for SchemaVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SchemaVersion")
+            .field("version", &self.version)
+            .field("flag", &self.flag)
+            .finish()
+    }
+}
+
+impl SchemaVersion {
+    const DEFAULT_FLAG: u32 = 0x2000_0000;
+
+    /// Stamp stored values with `version`.
+    pub fn new(version: u16) -> Self {
+        SchemaVersion {
+            version,
+            flag: Self::DEFAULT_FLAG,
+            migrations: HashMap::new(),
+        }
+    }
+
+    /// Register the upgrade of values from version `from` to version `from + 1`.
+    pub fn with_migration<F>(mut self, from: u16, migration: F) -> Self
+    where
+        F: Fn(Vec<u8>) -> Result<Vec<u8>, MemcacheError> + Send + Sync + 'static,
+    {
+        self.migrations.insert(from, Box::new(migration));
+        self
+    }
+
+    /// Set the flag bit which marks values with a version header.
+    ///
+    /// Panics if `flag` doesn't have exactly one bit set.
+    pub fn with_flag(mut self, flag: u32) -> Self {
+        assert_eq!(flag.count_ones(), 1, "the schema version flag must be a single bit");
+        self.flag = flag;
+        self
+    }
+
+    fn upgrade(&self, mut value: Vec<u8>, mut version: u16) -> Result<Vec<u8>, MemcacheError> {
+        if version > self.version {
+            return Err(ClientError::Error(Cow::Owned(format!(
+                "value has schema version {}, newer than the current version {}",
+                version, self.version
+            )))
+            .into());
+        }
+        while version < self.version {
+            let migration = self.migrations.get(&version).ok_or_else(|| {
+                ClientError::Error(Cow::Owned(format!("no migration from schema version {}", version)))
+            })?;
+            value = migration(value)?;
+            version += 1;
+        }
+        Ok(value)
+    }
+}
+
+impl Transcoder for SchemaVersion {
+    fn encode(&self, value: Vec<u8>, flags: u32) -> Result<(Vec<u8>, u32), MemcacheError> {
+        let mut encoded = Vec::with_capacity(value.len() + 2);
+        encoded.extend_from_slice(&self.version.to_be_bytes());
+        encoded.extend_from_slice(&value);
+        Ok((encoded, flags | self.flag))
+    }
+
+    fn decode(&self, mut value: Vec<u8>, flags: u32) -> Result<(Vec<u8>, u32), MemcacheError> {
+        if flags & self.flag == 0 {
+            return Ok((self.upgrade(value, 0)?, flags));
+        }
+        if value.len() < 2 {
+            return Err(ClientError::Error(Cow::Borrowed("value is missing its schema version")).into());
+        }
+        let version = u16::from_be_bytes(value[..2].try_into().unwrap());
+        value.drain(..2);
+        Ok((self.upgrade(value, version)?, flags & !self.flag))
+    }
+
+    fn reserved_flags(&self) -> u32 {
+        self.flag
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SchemaVersion;
+    use crate::value::Transcoder;
+
+    fn schema() -> SchemaVersion {
+        SchemaVersion::new(2)
+            .with_migration(0, |mut value| {
+                value.push(b'1');
+                Ok(value)
+            })
+            .with_migration(1, |mut value| {
+                value.push(b'2');
+                Ok(value)
+            })
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let (encoded, flags) = schema().encode(b"foo".to_vec(), 0x2).unwrap();
+        assert_eq!(encoded, b"\x00\x02foo");
+        assert_eq!(flags, 0x2000_0002);
+        assert_eq!(schema().decode(encoded, flags).unwrap(), (b"foo".to_vec(), 0x2));
+    }
+
+    #[test]
+    fn test_migrations() {
+        // unversioned values are version 0
+        assert_eq!(schema().decode(b"foo".to_vec(), 0).unwrap(), (b"foo12".to_vec(), 0));
+        let (encoded, flags) = SchemaVersion::new(1).encode(b"foo".to_vec(), 0).unwrap();
+        assert_eq!(schema().decode(encoded, flags).unwrap(), (b"foo2".to_vec(), 0));
+    }
+
+    #[test]
+    fn test_unsupported_versions() {
+        let (encoded, flags) = SchemaVersion::new(3).encode(b"foo".to_vec(), 0).unwrap();
+        assert!(schema().decode(encoded, flags).is_err());
+        assert!(SchemaVersion::new(1).decode(b"foo".to_vec(), 0).is_err());
+        assert!(schema().decode(b"f".to_vec(), 0x2000_0000).is_err());
+    }
+}
@@ -1,47 +1,169 @@
+pub(crate) mod socket;
 mod udp_stream;
 
-use std::io::{self, Read, Write};
+use std::io::{self, BufReader, BufWriter, IoSlice, Read, Write};
 use std::net::TcpStream;
 #[cfg(unix)]
 use std::os::unix::net::UnixStream;
 use std::time::Duration;
 
-pub(crate) use self::udp_stream::UdpStream;
+pub(crate) use self::udp_stream::{UdpOptions, UdpStream};
 use crate::error::MemcacheError;
 
 #[cfg(feature = "tls")]
 use openssl::ssl::SslStream;
 
+/// The capacity of the read and write buffers of connected streams when it's not configured, the
+/// default of `BufReader` and `BufWriter`.
+pub(crate) const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
+
+/// The connection to a server. Reads and writes of connected streams are buffered, so a command
+/// written in several pieces is sent with a single syscall when it is flushed, or before reading
+/// its response, and responses with many small values are read with few syscalls. UDP streams
+/// buffer the datagram of a request on their own. Values are serialized in `Memory` by the
+/// clients which transform them before sending them.
 pub enum Stream {
-    Tcp(TcpStream),
+    Tcp(Buffered<TcpStream>),
     Udp(UdpStream),
     #[cfg(unix)]
-    Unix(UnixStream),
+    Unix(Buffered<UnixStream>),
     #[cfg(feature = "tls")]
-    Tls(SslStream<TcpStream>),
+    Tls(Buffered<SslStream<TcpStream>>),
+    Memory(Vec<u8>),
+}
+
+pub type Buffered<S> = BufReader<FlushOnRead<S>>;
+
+/// Wrap a connected stream in read and write buffers of the given capacities.
+pub(crate) fn buffered<S: Read + Write>(stream: S, read_capacity: usize, write_capacity: usize) -> Buffered<S> {
+    BufReader::with_capacity(
+        read_capacity,
+        FlushOnRead(BufWriter::with_capacity(write_capacity, stream)),
+    )
+}
+
+/// A write buffered stream, which sends the buffered writes before reading, as the response
+/// depends on them.
+pub struct FlushOnRead<S: Write>(BufWriter<S>);
+
+impl<S: Write> FlushOnRead<S> {
+    fn get_ref(&self) -> &S {
+        self.0.get_ref()
+    }
+}
+
+impl<S: Read + Write> Read for FlushOnRead<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.0.buffer().is_empty() {
+            self.0.flush()?;
+        }
+        self.0.get_mut().read(buf)
+    }
+}
+
+impl<S: Write> Write for FlushOnRead<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+        self.0.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Write all the buffers, with a single vectored write if the stream accepts them at once. Buffers
+/// larger than the write buffer of the stream are written without being copied.
+pub(crate) fn write_all_vectored<W: Write>(writer: &mut W, bufs: &[&[u8]]) -> io::Result<()> {
+    let mut bufs: Vec<&[u8]> = bufs.iter().copied().filter(|buf| !buf.is_empty()).collect();
+    while !bufs.is_empty() {
+        let slices: Vec<IoSlice> = bufs.iter().map(|buf| IoSlice::new(buf)).collect();
+        let mut written = match writer.write_vectored(&slices) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(written) => written,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        // drop the buffers which were written entirely, and the written part of the next one
+        let mut consumed = 0;
+        while consumed < bufs.len() && written >= bufs[consumed].len() {
+            written -= bufs[consumed].len();
+            consumed += 1;
+        }
+        bufs.drain(..consumed);
+        if let Some(first) = bufs.first_mut() {
+            *first = &first[written..];
+        }
+    }
+    Ok(())
 }
 
 impl Stream {
+    /// The largest request which can be sent at once, for UDP streams whose requests must fit in a
+    /// datagram.
+    pub(crate) fn max_request_size(&self) -> Option<usize> {
+        match self {
+            Stream::Udp(ref stream) => Some(stream.max_request_size()),
+            _ => None,
+        }
+    }
+
+    /// The bytes written to a stream in memory, `None` for the connected streams.
+    pub(crate) fn into_memory(self) -> Option<Vec<u8>> {
+        match self {
+            Stream::Memory(data) => Some(data),
+            _ => None,
+        }
+    }
+
+    /// The read and write timeouts of the socket.
+    pub(super) fn timeouts(&self) -> Result<(Option<Duration>, Option<Duration>), MemcacheError> {
+        let timeouts = match self {
+            Stream::Tcp(ref conn) => {
+                let socket = conn.get_ref().get_ref();
+                (socket.read_timeout()?, socket.write_timeout()?)
+            }
+            #[cfg(unix)]
+            Stream::Unix(ref conn) => {
+                let socket = conn.get_ref().get_ref();
+                (socket.read_timeout()?, socket.write_timeout()?)
+            }
+            #[cfg(feature = "tls")]
+            Stream::Tls(ref stream) => {
+                let socket = stream.get_ref().get_ref().get_ref();
+                (socket.read_timeout()?, socket.write_timeout()?)
+            }
+            Stream::Udp(ref conn) => conn.timeouts()?,
+            Stream::Memory(_) => (None, None),
+        };
+        Ok(timeouts)
+    }
+
     pub(super) fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<(), MemcacheError> {
         match self {
-            Stream::Tcp(ref conn) => conn.set_read_timeout(timeout)?,
+            Stream::Tcp(ref conn) => conn.get_ref().get_ref().set_read_timeout(timeout)?,
             #[cfg(unix)]
-            Stream::Unix(ref conn) => conn.set_read_timeout(timeout)?,
+            Stream::Unix(ref conn) => conn.get_ref().get_ref().set_read_timeout(timeout)?,
             #[cfg(feature = "tls")]
-            Stream::Tls(ref stream) => stream.get_ref().set_read_timeout(timeout)?,
+            Stream::Tls(ref stream) => stream.get_ref().get_ref().get_ref().set_read_timeout(timeout)?,
             Stream::Udp(ref conn) => conn.set_read_timeout(timeout)?,
+            Stream::Memory(_) => (),
         }
         Ok(())
     }
 
     pub(super) fn set_write_timeout(&mut self, timeout: Option<Duration>) -> Result<(), MemcacheError> {
         match self {
-            Stream::Tcp(ref conn) => conn.set_write_timeout(timeout)?,
+            Stream::Tcp(ref conn) => conn.get_ref().get_ref().set_write_timeout(timeout)?,
             #[cfg(unix)]
-            Stream::Unix(ref conn) => conn.set_write_timeout(timeout)?,
+            Stream::Unix(ref conn) => conn.get_ref().get_ref().set_write_timeout(timeout)?,
             #[cfg(feature = "tls")]
-            Stream::Tls(ref stream) => stream.get_ref().set_write_timeout(timeout)?,
+            Stream::Tls(ref stream) => stream.get_ref().get_ref().get_ref().set_write_timeout(timeout)?,
             Stream::Udp(ref conn) => conn.set_write_timeout(timeout)?,
+            Stream::Memory(_) => (),
         }
         Ok(())
     }
@@ -56,6 +178,8 @@ impl Read for Stream {
             Stream::Unix(ref mut stream) => stream.read(buf),
             #[cfg(feature = "tls")]
             Stream::Tls(ref mut stream) => stream.read(buf),
+            // nothing is ever read from memory
+            Stream::Memory(_) => Ok(0),
         }
     }
 }
@@ -63,23 +187,76 @@ impl Read for Stream {
 impl Write for Stream {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         match self {
-            Stream::Tcp(ref mut stream) => stream.write(buf),
+            Stream::Tcp(ref mut stream) => stream.get_mut().write(buf),
             Stream::Udp(ref mut stream) => stream.write(buf),
             #[cfg(unix)]
-            Stream::Unix(ref mut stream) => stream.write(buf),
+            Stream::Unix(ref mut stream) => stream.get_mut().write(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(ref mut stream) => stream.get_mut().write(buf),
+            Stream::Memory(ref mut data) => data.write(buf),
+        }
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(ref mut stream) => stream.get_mut().write_vectored(bufs),
+            Stream::Udp(ref mut stream) => stream.write_vectored(bufs),
+            #[cfg(unix)]
+            Stream::Unix(ref mut stream) => stream.get_mut().write_vectored(bufs),
             #[cfg(feature = "tls")]
-            Stream::Tls(ref mut stream) => stream.write(buf),
+            Stream::Tls(ref mut stream) => stream.get_mut().write_vectored(bufs),
+            Stream::Memory(ref mut data) => data.write_vectored(bufs),
         }
     }
 
     fn flush(&mut self) -> io::Result<()> {
         match self {
-            Stream::Tcp(ref mut stream) => stream.flush(),
+            Stream::Tcp(ref mut stream) => stream.get_mut().flush(),
             Stream::Udp(ref mut stream) => stream.flush(),
             #[cfg(unix)]
-            Stream::Unix(ref mut stream) => stream.flush(),
+            Stream::Unix(ref mut stream) => stream.get_mut().flush(),
             #[cfg(feature = "tls")]
-            Stream::Tls(ref mut stream) => stream.flush(),
+            Stream::Tls(ref mut stream) => stream.get_mut().flush(),
+            Stream::Memory(_) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_all_vectored;
+    use std::io::{self, IoSlice, Write};
+
+    /// Accepts at most 3 bytes per write, to exercise partial vectored writes.
+    struct Trickle(Vec<u8>);
+
+    impl Write for Trickle {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let length = buf.len().min(3);
+            self.0.extend_from_slice(&buf[..length]);
+            Ok(length)
+        }
+
+        fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+            let mut written = 0;
+            for buf in bufs {
+                written += self.write(&buf[..buf.len().min(3 - written)])?;
+                if written == 3 {
+                    break;
+                }
+            }
+            Ok(written)
         }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_all_vectored() {
+        let mut writer = Trickle(Vec::new());
+        write_all_vectored(&mut writer, &[b"set foo", b"", b"bar", b"\r\n"]).unwrap();
+        assert_eq!(writer.0, b"set foobar\r\n");
     }
 }
@@ -0,0 +1,150 @@
+//! Socket options which `std::net::TcpStream` doesn't expose.
+
+use socket2::SockRef;
+use std::io;
+use std::net::TcpStream;
+use std::time::Duration;
+
+fn buffer_size(size: usize) -> io::Result<usize> {
+    use std::convert::TryFrom;
+    i32::try_from(size)
+        .map(|_| size)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "socket option is too large"))
+}
+
+/// Set the size of the kernel receive buffer of the socket, `SO_RCVBUF`.
+pub(crate) fn set_recv_buffer_size(stream: &TcpStream, size: usize) -> io::Result<()> {
+    SockRef::from(stream).set_recv_buffer_size(buffer_size(size)?)
+}
+
+/// Set the size of the kernel send buffer of the socket, `SO_SNDBUF`.
+pub(crate) fn set_send_buffer_size(stream: &TcpStream, size: usize) -> io::Result<()> {
+    SockRef::from(stream).set_send_buffer_size(buffer_size(size)?)
+}
+
+/// The TCP keepalive probes of a connection: the idle time before the first probe, and the time
+/// between probes, or the system defaults if they are `None`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct Keepalive {
+    pub(crate) idle: Option<Duration>,
+    pub(crate) interval: Option<Duration>,
+}
+
+/// Enable TCP keepalive probes, `SO_KEEPALIVE`, with the timings of `keepalive`.
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "macos",
+    target_os = "ios"
+))]
+pub(crate) fn set_keepalive(stream: &TcpStream, keepalive: Keepalive) -> io::Result<()> {
+    let mut params = socket2::TcpKeepalive::new();
+    if let Some(idle) = keepalive.idle {
+        params = params.with_time(seconds(idle));
+    }
+    if let Some(interval) = keepalive.interval {
+        params = params.with_interval(seconds(interval));
+    }
+    SockRef::from(stream).set_tcp_keepalive(&params)
+}
+
+/// Keepalive timings are set in whole seconds, at least one.
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "macos",
+    target_os = "ios"
+))]
+fn seconds(duration: Duration) -> Duration {
+    let seconds = duration.as_secs() + u64::from(duration.subsec_nanos() > 0);
+    Duration::from_secs(seconds.max(1))
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "macos",
+    target_os = "ios"
+)))]
+pub(crate) fn set_keepalive(_stream: &TcpStream, _keepalive: Keepalive) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "TCP keepalive options are not supported on this platform",
+    ))
+}
+
+/// Set how long data may stay unacknowledged before the connection is closed, `TCP_USER_TIMEOUT`,
+/// in milliseconds.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn set_user_timeout(stream: &TcpStream, timeout: Duration) -> io::Result<()> {
+    // a timeout of zero would restore the system default
+    let timeout = timeout.max(Duration::from_millis(1));
+    SockRef::from(stream).set_tcp_user_timeout(Some(timeout))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) fn set_user_timeout(_stream: &TcpStream, _timeout: Duration) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "TCP_USER_TIMEOUT is only supported on Linux",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{set_recv_buffer_size, set_send_buffer_size};
+    use socket2::SockRef;
+    use std::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn test_buffer_sizes() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        set_recv_buffer_size(&stream, 256 * 1024).unwrap();
+        set_send_buffer_size(&stream, 256 * 1024).unwrap();
+        // the kernel may double the size for its bookkeeping, or cap it
+        let socket = SockRef::from(&stream);
+        assert!(socket.recv_buffer_size().unwrap() >= 64 * 1024);
+        assert!(socket.send_buffer_size().unwrap() >= 64 * 1024);
+        assert!(set_recv_buffer_size(&stream, usize::MAX).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_keepalive() {
+        use super::{set_keepalive, Keepalive};
+        use std::time::Duration;
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let socket = SockRef::from(&stream);
+        assert!(!socket.keepalive().unwrap());
+        let keepalive = Keepalive {
+            idle: Some(Duration::from_secs(30)),
+            interval: Some(Duration::from_millis(2500)),
+        };
+        set_keepalive(&stream, keepalive).unwrap();
+        assert!(socket.keepalive().unwrap());
+        assert_eq!(socket.keepalive_time().unwrap(), Duration::from_secs(30));
+        assert_eq!(socket.keepalive_interval().unwrap(), Duration::from_secs(3));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_user_timeout() {
+        use super::set_user_timeout;
+        use std::time::Duration;
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        set_user_timeout(&stream, Duration::from_secs(5)).unwrap();
+        assert_eq!(
+            SockRef::from(&stream).tcp_user_timeout().unwrap(),
+            Some(Duration::from_secs(5))
+        );
+    }
+}
@@ -1,33 +1,111 @@
 use crate::error::MemcacheError;
 use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
 use rand;
-use std::collections::HashMap;
 use std::io;
 use std::io::{Error, ErrorKind, Read, Write};
-use std::net::UdpSocket;
-use std::time::Duration;
+use std::net::{TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
 use std::u16;
 use url::Url;
 
+/// The largest payload of an UDP datagram.
+const MAX_DATAGRAM_SIZE: usize = 65507;
+
+/// The size of the frame header starting every datagram.
+const HEADER_SIZE: usize = 8;
+
+/// How a `UdpStream` sends the requests and waits for the responses, see
+/// `ClientBuilder::with_udp_retransmission` and `ClientBuilder::with_udp_max_datagram_size`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct UdpOptions {
+    /// How long to wait for a whole response before sending the request again, or failing. The
+    /// read timeout of the socket is used if it's not set.
+    pub(crate) request_timeout: Option<Duration>,
+    /// How many times a request is sent again after a request timeout.
+    pub(crate) retransmits: u32,
+    /// The largest datagram sent, frame header included, the largest UDP payload if not set.
+    pub(crate) max_datagram_size: Option<usize>,
+    /// Whether the requests which don't fit in a datagram are sent over TCP to the same address,
+    /// instead of failing.
+    pub(crate) tcp_fallback: bool,
+}
+
 pub struct UdpStream {
     socket: UdpSocket,
+    options: UdpOptions,
+    /// The connection used for the requests which don't fit in a datagram, if enabled, connected
+    /// by the first one.
+    tcp_stream: Option<TcpStream>,
+    /// Whether the response of the last request is read from `tcp_stream`.
+    reading_tcp: bool,
     read_buf: Vec<u8>,
     write_buf: Vec<u8>,
+    /// The buffer receiving the datagrams, allocated by the first request.
+    recv_buf: Vec<u8>,
     request_id: u16,
 }
 
+/// The payloads of the datagrams of a response, by their sequence number.
+#[derive(Default)]
+struct Datagrams {
+    payloads: Vec<Option<Vec<u8>>>,
+    missing: usize,
+}
+
+impl Datagrams {
+    /// Add the payload of the datagram `sequence_no` of `total_datagrams`, and return whether every
+    /// datagram of the response was received. Duplicated datagrams are ignored.
+    fn insert(&mut self, sequence_no: u16, total_datagrams: u16, payload: &[u8]) -> io::Result<bool> {
+        if sequence_no >= total_datagrams {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Invalid UDP datagram sequence number",
+            ));
+        }
+        if self.payloads.is_empty() {
+            self.payloads = vec![None; total_datagrams as usize];
+            self.missing = total_datagrams as usize;
+        } else if self.payloads.len() != total_datagrams as usize {
+            return Err(Error::new(ErrorKind::InvalidData, "Inconsistent UDP datagram count"));
+        }
+        let slot = &mut self.payloads[sequence_no as usize];
+        if slot.is_none() {
+            *slot = Some(payload.to_vec());
+            self.missing -= 1;
+        }
+        Ok(self.missing == 0)
+    }
+
+    fn into_payload(self) -> Vec<u8> {
+        self.payloads.into_iter().flatten().flatten().collect()
+    }
+}
+
 impl UdpStream {
-    pub fn new(addr: &Url) -> Result<Self, MemcacheError> {
+    pub(crate) fn new(addr: &Url, options: UdpOptions) -> Result<Self, MemcacheError> {
         let socket = UdpSocket::bind("0.0.0.0:0")?;
         socket.connect(&*addr.socket_addrs(|| None)?)?;
         return Ok(UdpStream {
             socket,
+            options,
+            tcp_stream: None,
+            reading_tcp: false,
             read_buf: Vec::new(),
             write_buf: Vec::new(),
+            recv_buf: Vec::new(),
             request_id: rand::random::<u16>(),
         });
     }
 
+    /// The size of the largest request which fits in a datagram.
+    pub(crate) fn max_request_size(&self) -> usize {
+        self.options.max_datagram_size.unwrap_or(MAX_DATAGRAM_SIZE) - HEADER_SIZE
+    }
+
+    pub(crate) fn timeouts(&self) -> Result<(Option<Duration>, Option<Duration>), MemcacheError> {
+        Ok((self.socket.read_timeout()?, self.socket.write_timeout()?))
+    }
+
     pub(crate) fn set_read_timeout(&self, duration: Option<Duration>) -> Result<(), MemcacheError> {
         Ok(self.socket.set_read_timeout(duration)?)
     }
@@ -39,6 +117,11 @@ impl UdpStream {
 
 impl Read for UdpStream {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.reading_tcp {
+            if let Some(ref mut tcp_stream) = self.tcp_stream {
+                return tcp_stream.read(buf);
+            }
+        }
         let mut buf_len = buf.len();
         if buf_len > self.read_buf.len() {
             buf_len = self.read_buf.len();
@@ -56,54 +139,235 @@ impl Write for UdpStream {
     }
 
     fn flush(&mut self) -> io::Result<()> {
+        self.reading_tcp = false;
+        if self.write_buf.len() > self.max_request_size() {
+            let result = if self.options.tcp_fallback {
+                self.send_over_tcp()
+            } else {
+                Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "request of {} bytes doesn't fit in an UDP datagram of {} bytes",
+                        self.write_buf.len(),
+                        self.max_request_size() + HEADER_SIZE
+                    ),
+                ))
+            };
+            self.write_buf.clear();
+            return result;
+        }
         // udp header is 8 bytes in the begining of each datagram
         let mut udp_header: Vec<u8> = Vec::new();
 
-        udp_header.write_u16::<BigEndian>(self.request_id)?; // request id to uniquely identify response for this request
+        udp_header.write_u16::<BigEndian>(0)?; // request id to uniquely identify response for this request, set by send_request
         udp_header.write_u16::<BigEndian>(0)?; // 0 indicates this is the first datagram for this request
         udp_header.write_u16::<BigEndian>(1)?; // total datagrams in this request (requests can only be 1 datagram long)
         udp_header.write_u16::<BigEndian>(0)?; // reserved bytes
         self.write_buf.splice(0..0, udp_header.iter().cloned());
-        self.socket.send(self.write_buf.as_slice())?;
+        let result = self.send_request();
         self.write_buf.clear(); // clear the buffer for the next command
+        result
+    }
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut
+}
+
+impl UdpStream {
+    /// Send the request in `write_buf` over TCP, whose response is then read from the TCP stream.
+    fn send_over_tcp(&mut self) -> io::Result<()> {
+        if self.tcp_stream.is_none() {
+            let tcp_stream = TcpStream::connect(self.socket.peer_addr()?)?;
+            tcp_stream.set_read_timeout(self.socket.read_timeout()?)?;
+            tcp_stream.set_write_timeout(self.socket.write_timeout()?)?;
+            tcp_stream.set_nodelay(true)?;
+            self.tcp_stream = Some(tcp_stream);
+        }
+        let tcp_stream = self.tcp_stream.as_mut().unwrap();
+        if let Err(e) = tcp_stream.write_all(&self.write_buf) {
+            // the server may be waiting for the rest of the request
+            self.tcp_stream = None;
+            return Err(e);
+        }
+        self.reading_tcp = true;
+        Ok(())
+    }
 
-        let mut response_datagrams: HashMap<u16, Vec<u8>> = HashMap::new();
-        let mut total_datagrams;
-        let mut remaining_datagrams = 0;
+    /// Send the request in `write_buf` and receive its response, sending it again when the
+    /// response isn't received in time, up to the configured number of retransmits.
+    fn send_request(&mut self) -> io::Result<()> {
+        let mut retransmits = 0;
+        loop {
+            // every attempt gets its own request id, so the datagrams answering the previous
+            // attempts, or the previous requests, are discarded
+            let request_id = self.request_id;
+            self.request_id = (self.request_id % (u16::MAX)) + 1;
+            BigEndian::write_u16(&mut self.write_buf[0..2], request_id);
+            self.socket.send(self.write_buf.as_slice())?;
+            match self.receive(request_id) {
+                Err(ref e) if is_timeout(e) && retransmits < self.options.retransmits => retransmits += 1,
+                result => return result,
+            }
+        }
+    }
+
+    /// Receive the response of the request `request_id` into `read_buf`.
+    fn receive(&mut self, request_id: u16) -> io::Result<()> {
+        let deadline = match self.options.request_timeout {
+            Some(timeout) => Instant::now() + timeout,
+            None => return self.receive_datagrams(request_id, None),
+        };
+        // the read timeout is shortened to the request deadline while receiving, and restored
+        // afterwards, even if receiving fails
+        let read_timeout = self.socket.read_timeout()?;
+        let result = self.receive_datagrams(request_id, Some(deadline));
+        self.socket.set_read_timeout(read_timeout)?;
+        result
+    }
+
+    fn receive_datagrams(&mut self, request_id: u16, deadline: Option<Instant>) -> io::Result<()> {
+        // for large values the response spans several datagrams, which may arrive out of order
+        let mut datagrams = Datagrams::default();
         self.read_buf.clear();
+        if self.recv_buf.is_empty() {
+            self.recv_buf = vec![0; MAX_DATAGRAM_SIZE];
+        }
         loop {
-            // for large values, response can span multiple datagrams, so gather them all
-            let mut buf: [u8; 1400] = [0; 1400]; // memcache udp response payload can not be longer than 1400 bytes
-            let bytes_read = self.socket.recv(&mut buf)?;
-            if bytes_read < 8 {
+            if let Some(deadline) = deadline {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(Error::from(ErrorKind::TimedOut));
+                }
+                self.socket.set_read_timeout(Some(deadline - now))?;
+            }
+            let bytes_read = self.socket.recv(&mut self.recv_buf)?;
+            if bytes_read < HEADER_SIZE {
                 // make an error here to avoid panic below
                 return Err(Error::new(ErrorKind::Other, "Invalid UDP header received"));
             }
-
-            let request_id = BigEndian::read_u16(&buf[0..]);
-            if self.request_id != request_id {
-                // ideally this shouldn't happen as we wait to read out response before sending another request
+            let buf = &self.recv_buf[..bytes_read];
+            if BigEndian::read_u16(&buf[0..]) != request_id {
+                // a late or duplicated response to a previous request
                 continue;
             }
             let sequence_no = BigEndian::read_u16(&buf[2..]);
-            total_datagrams = BigEndian::read_u16(&buf[4..]);
-            if remaining_datagrams == 0 {
-                remaining_datagrams = total_datagrams;
-            }
-
-            let mut v: Vec<u8> = Vec::new();
-            v.extend_from_slice(&buf[8..bytes_read]);
-            response_datagrams.insert(sequence_no, v);
-            remaining_datagrams -= 1;
-            if remaining_datagrams == 0 {
-                break;
+            let total_datagrams = BigEndian::read_u16(&buf[4..]);
+            if datagrams.insert(sequence_no, total_datagrams, &buf[HEADER_SIZE..])? {
+                self.read_buf = datagrams.into_payload();
+                return Ok(());
             }
         }
-        for i in 0..total_datagrams {
-            self.read_buf.append(&mut (response_datagrams[&i].clone()));
-        }
+    }
+}
 
-        self.request_id = (self.request_id % (u16::MAX)) + 1;
-        Ok(())
+#[cfg(test)]
+mod tests {
+    use super::Datagrams;
+
+    #[test]
+    fn test_reassembly() {
+        let mut datagrams = Datagrams::default();
+        assert!(!datagrams.insert(2, 3, b"baz").unwrap());
+        assert!(!datagrams.insert(0, 3, b"foo").unwrap());
+        // duplicated datagrams don't count
+        assert!(!datagrams.insert(2, 3, b"baz").unwrap());
+        assert!(datagrams.insert(1, 3, b"bar").unwrap());
+        assert_eq!(datagrams.into_payload(), b"foobarbaz");
+
+        let mut datagrams = Datagrams::default();
+        assert!(datagrams.insert(0, 1, b"foo").unwrap());
+        assert_eq!(datagrams.into_payload(), b"foo");
+    }
+
+    #[test]
+    fn test_oversized_request() {
+        use super::{UdpOptions, UdpStream};
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+        use std::thread;
+        use url::Url;
+
+        // the TCP fallback connects to the port of the UDP socket
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("memcache+udp://{}", listener.local_addr().unwrap())).unwrap();
+        let server = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut request = [0; 20];
+            socket.read_exact(&mut request).unwrap();
+            assert_eq!(&request, &[b'x'; 20]);
+            socket.write_all(b"STORED\r\n").unwrap();
+        });
+
+        let options = UdpOptions {
+            max_datagram_size: Some(24),
+            ..Default::default()
+        };
+        let mut stream = UdpStream::new(&url, options).unwrap();
+        stream.write_all(&[b'x'; 20]).unwrap();
+        assert!(stream.flush().is_err());
+
+        let options = UdpOptions {
+            tcp_fallback: true,
+            ..options
+        };
+        let mut stream = UdpStream::new(&url, options).unwrap();
+        stream.write_all(&[b'x'; 20]).unwrap();
+        stream.flush().unwrap();
+        let mut response = [0; 8];
+        stream.read_exact(&mut response).unwrap();
+        assert_eq!(&response, b"STORED\r\n");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_request_timeout() {
+        use super::{UdpOptions, UdpStream};
+        use std::io::{Read, Write};
+        use std::net::UdpSocket;
+        use std::thread;
+        use std::time::Duration;
+        use url::Url;
+
+        // a server which doesn't answer the first request
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("memcache+udp://{}", socket.local_addr().unwrap())).unwrap();
+        let server = thread::spawn(move || {
+            let mut request = [0; 64];
+            socket.recv_from(&mut request).unwrap();
+            let (_, client) = socket.recv_from(&mut request).unwrap();
+            let mut response = request[0..8].to_vec();
+            response[4..6].copy_from_slice(&[0, 1]);
+            response.extend_from_slice(b"STORED\r\n");
+            socket.send_to(&response, client).unwrap();
+        });
+
+        let options = UdpOptions {
+            request_timeout: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let mut stream = UdpStream::new(&url, options).unwrap();
+        let read_timeout = Some(Duration::from_secs(5));
+        stream.set_read_timeout(read_timeout).unwrap();
+        stream.write_all(b"foo").unwrap();
+        assert!(stream.flush().is_err());
+        assert_eq!(stream.timeouts().unwrap().0, read_timeout);
+
+        stream.write_all(b"foo").unwrap();
+        stream.flush().unwrap();
+        let mut response = [0; 8];
+        stream.read_exact(&mut response).unwrap();
+        assert_eq!(&response, b"STORED\r\n");
+        assert_eq!(stream.timeouts().unwrap().0, read_timeout);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_invalid_datagrams() {
+        assert!(Datagrams::default().insert(0, 0, b"foo").is_err());
+        assert!(Datagrams::default().insert(2, 2, b"foo").is_err());
+        let mut datagrams = Datagrams::default();
+        datagrams.insert(0, 2, b"foo").unwrap();
+        assert!(datagrams.insert(1, 3, b"bar").is_err());
     }
 }
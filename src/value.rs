@@ -1,11 +1,86 @@
+use crate::error::ClientError;
 use crate::error::MemcacheError;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::convert::TryFrom;
 use std::io;
-use std::io::Write;
+use std::io::{Read, Write};
+use std::mem;
+use std::net::IpAddr;
+use std::num::{
+    NonZeroI128, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI8, NonZeroU128, NonZeroU16, NonZeroU32, NonZeroU64,
+    NonZeroU8,
+};
 use std::str;
 use std::str::FromStr;
+use std::sync::Arc;
 
 pub enum Flags {
     Bytes = 0,
+    /// Set for values serialized by `Bincode`, high enough to not collide with the low flag
+    /// bits used by other clients.
+    #[cfg(feature = "bincode")]
+    Bincode = 0x100,
+    /// Set for values serialized by `MsgPack`.
+    #[cfg(feature = "msgpack")]
+    MsgPack = 0x200,
+}
+
+impl Flags {
+    /// The flag bits set by the serialization wrappers enabled by the crate features.
+    pub(crate) fn serialization_mask() -> u32 {
+        #[allow(unused_mut)]
+        let mut mask = 0;
+        #[cfg(feature = "bincode")]
+        {
+            mask |= Flags::Bincode as u32;
+        }
+        #[cfg(feature = "msgpack")]
+        {
+            mask |= Flags::MsgPack as u32;
+        }
+        mask
+    }
+}
+
+/// Which flag bits are used by what, as configured on a client, see `Client::flag_layout`.
+/// The client refuses to be built with overlapping transcoder, chunking and application bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FlagLayout {
+    /// The bits declared by `ClientBuilder::with_application_flags`.
+    pub application: u32,
+    /// The bits set by the client's transcoders, see `Transcoder::reserved_flags`.
+    pub transcoders: u32,
+    /// The bits set by the serialization wrappers like `Bincode` and `MsgPack`, depending on the
+    /// enabled features.
+    pub serialization: u32,
+    /// The bit marking chunked values, if chunking is enabled.
+    pub chunking: u32,
+    /// The bit marking cached misses, if negative caching is enabled.
+    pub negative_caching: u32,
+}
+
+impl FlagLayout {
+    /// All the bits reserved by the client, which applications must not use.
+    pub fn reserved(&self) -> u32 {
+        self.transcoders | self.serialization | self.chunking | self.negative_caching
+    }
+}
+
+/// The type of a value before it was serialized, for transcoders which store some types
+/// differently, like the ones compatible with other clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueKind {
+    /// Arbitrary bytes.
+    Bytes,
+    /// An UTF-8 string.
+    Text,
+    /// A boolean, serialized as `true` or `false`.
+    Bool,
+    /// An integer with the given size in bytes, serialized as decimal text.
+    Integer(usize),
+    /// A floating point number with the given size in bytes, serialized as decimal text.
+    Float(usize),
 }
 
 /// determine how the value is serialize to memcache
@@ -13,6 +88,17 @@ pub trait ToMemcacheValue<W: Write> {
     fn get_flags(&self) -> u32;
     fn get_length(&self) -> usize;
     fn write_to(&self, stream: &mut W) -> io::Result<()>;
+
+    /// The type of the value, passed to the client's `Transcoder`.
+    fn get_kind(&self) -> ValueKind {
+        ValueKind::Bytes
+    }
+
+    /// The serialized value, if it is already in memory as is, so it can be sent along with the
+    /// command in a single vectored write instead of being written separately.
+    fn get_bytes(&self) -> Option<&[u8]> {
+        None
+    }
 }
 
 impl<'a, W: Write> ToMemcacheValue<W> for &'a [u8] {
@@ -30,6 +116,10 @@ impl<'a, W: Write> ToMemcacheValue<W> for &'a [u8] {
             Err(e) => Err(e),
         }
     }
+
+    fn get_bytes(&self) -> Option<&[u8]> {
+        Some(self)
+    }
 }
 
 impl<'a, W: Write> ToMemcacheValue<W> for &'a String {
@@ -44,6 +134,52 @@ impl<'a, W: Write> ToMemcacheValue<W> for &'a String {
     fn write_to(&self, stream: &mut W) -> io::Result<()> {
         ToMemcacheValue::<W>::write_to(*self, stream)
     }
+
+    fn get_kind(&self) -> ValueKind {
+        ToMemcacheValue::<W>::get_kind(*self)
+    }
+
+    fn get_bytes(&self) -> Option<&[u8]> {
+        ToMemcacheValue::<W>::get_bytes(*self)
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl<W: Write> ToMemcacheValue<W> for bytes::Bytes {
+    fn get_flags(&self) -> u32 {
+        Flags::Bytes as u32
+    }
+
+    fn get_length(&self) -> usize {
+        self.len()
+    }
+
+    fn write_to(&self, stream: &mut W) -> io::Result<()> {
+        stream.write_all(self)
+    }
+
+    fn get_bytes(&self) -> Option<&[u8]> {
+        Some(self)
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl<W: Write> ToMemcacheValue<W> for &bytes::Bytes {
+    fn get_flags(&self) -> u32 {
+        ToMemcacheValue::<W>::get_flags(*self)
+    }
+
+    fn get_length(&self) -> usize {
+        ToMemcacheValue::<W>::get_length(*self)
+    }
+
+    fn write_to(&self, stream: &mut W) -> io::Result<()> {
+        ToMemcacheValue::<W>::write_to(*self, stream)
+    }
+
+    fn get_bytes(&self) -> Option<&[u8]> {
+        ToMemcacheValue::<W>::get_bytes(*self)
+    }
 }
 
 impl<W: Write> ToMemcacheValue<W> for String {
@@ -61,6 +197,14 @@ impl<W: Write> ToMemcacheValue<W> for String {
             Err(e) => Err(e),
         }
     }
+
+    fn get_kind(&self) -> ValueKind {
+        ValueKind::Text
+    }
+
+    fn get_bytes(&self) -> Option<&[u8]> {
+        Some(str::as_bytes(self))
+    }
 }
 
 impl<'a, W: Write> ToMemcacheValue<W> for &'a str {
@@ -78,10 +222,18 @@ impl<'a, W: Write> ToMemcacheValue<W> for &'a str {
             Err(e) => Err(e),
         }
     }
+
+    fn get_kind(&self) -> ValueKind {
+        ValueKind::Text
+    }
+
+    fn get_bytes(&self) -> Option<&[u8]> {
+        Some(str::as_bytes(self))
+    }
 }
 
 macro_rules! impl_to_memcache_value_for_number {
-    ($ty:ident) => {
+    ($ty:ident, $kind:expr) => {
         impl<W: Write> ToMemcacheValue<W> for $ty {
             fn get_flags(&self) -> u32 {
                 return Flags::Bytes as u32;
@@ -97,37 +249,402 @@ macro_rules! impl_to_memcache_value_for_number {
                     Err(e) => Err(e),
                 }
             }
+
+            fn get_kind(&self) -> ValueKind {
+                $kind
+            }
         }
     };
 }
 
-impl_to_memcache_value_for_number!(bool);
-impl_to_memcache_value_for_number!(u8);
-impl_to_memcache_value_for_number!(u16);
-impl_to_memcache_value_for_number!(u32);
-impl_to_memcache_value_for_number!(u64);
-impl_to_memcache_value_for_number!(i8);
-impl_to_memcache_value_for_number!(i16);
-impl_to_memcache_value_for_number!(i32);
-impl_to_memcache_value_for_number!(i64);
-impl_to_memcache_value_for_number!(f32);
-impl_to_memcache_value_for_number!(f64);
+impl_to_memcache_value_for_number!(bool, ValueKind::Bool);
+impl_to_memcache_value_for_number!(u8, ValueKind::Integer(1));
+impl_to_memcache_value_for_number!(u16, ValueKind::Integer(2));
+impl_to_memcache_value_for_number!(u32, ValueKind::Integer(4));
+impl_to_memcache_value_for_number!(u64, ValueKind::Integer(8));
+impl_to_memcache_value_for_number!(u128, ValueKind::Integer(16));
+impl_to_memcache_value_for_number!(i8, ValueKind::Integer(1));
+impl_to_memcache_value_for_number!(i16, ValueKind::Integer(2));
+impl_to_memcache_value_for_number!(i32, ValueKind::Integer(4));
+impl_to_memcache_value_for_number!(i64, ValueKind::Integer(8));
+impl_to_memcache_value_for_number!(i128, ValueKind::Integer(16));
+impl_to_memcache_value_for_number!(NonZeroU8, ValueKind::Integer(1));
+impl_to_memcache_value_for_number!(NonZeroU16, ValueKind::Integer(2));
+impl_to_memcache_value_for_number!(NonZeroU32, ValueKind::Integer(4));
+impl_to_memcache_value_for_number!(NonZeroU64, ValueKind::Integer(8));
+impl_to_memcache_value_for_number!(NonZeroU128, ValueKind::Integer(16));
+impl_to_memcache_value_for_number!(NonZeroI8, ValueKind::Integer(1));
+impl_to_memcache_value_for_number!(NonZeroI16, ValueKind::Integer(2));
+impl_to_memcache_value_for_number!(NonZeroI32, ValueKind::Integer(4));
+impl_to_memcache_value_for_number!(NonZeroI64, ValueKind::Integer(8));
+impl_to_memcache_value_for_number!(NonZeroI128, ValueKind::Integer(16));
+impl_to_memcache_value_for_number!(f32, ValueKind::Float(4));
+impl_to_memcache_value_for_number!(f64, ValueKind::Float(8));
+
+/// Stored as its 16 bytes. Only available with the `uuid` feature.
+#[cfg(feature = "uuid")]
+impl<W: Write> ToMemcacheValue<W> for uuid::Uuid {
+    fn get_flags(&self) -> u32 {
+        Flags::Bytes as u32
+    }
+
+    fn get_length(&self) -> usize {
+        self.as_bytes().len()
+    }
+
+    fn write_to(&self, stream: &mut W) -> io::Result<()> {
+        stream.write_all(self.as_bytes())
+    }
+}
+
+/// Stored in RFC 3339 format with nanoseconds, e.g. `2021-06-01T12:00:00.000000000Z`, which
+/// sorts chronologically. Only available with the `chrono` feature.
+#[cfg(feature = "chrono")]
+impl<W: Write> ToMemcacheValue<W> for chrono::DateTime<chrono::Utc> {
+    fn get_flags(&self) -> u32 {
+        Flags::Bytes as u32
+    }
+
+    fn get_length(&self) -> usize {
+        self.to_rfc3339_opts(chrono::SecondsFormat::Nanos, true).len()
+    }
+
+    fn write_to(&self, stream: &mut W) -> io::Result<()> {
+        stream.write_all(self.to_rfc3339_opts(chrono::SecondsFormat::Nanos, true).as_bytes())
+    }
+
+    fn get_kind(&self) -> ValueKind {
+        ValueKind::Text
+    }
+}
+
+/// Stored as its 4 or 16 octets, see `FromMemcacheValue` for how it's parsed.
+impl<W: Write> ToMemcacheValue<W> for IpAddr {
+    fn get_flags(&self) -> u32 {
+        Flags::Bytes as u32
+    }
+
+    fn get_length(&self) -> usize {
+        match self {
+            IpAddr::V4(_) => 4,
+            IpAddr::V6(_) => 16,
+        }
+    }
+
+    fn write_to(&self, stream: &mut W) -> io::Result<()> {
+        match self {
+            IpAddr::V4(ip) => stream.write_all(&ip.octets()),
+            IpAddr::V6(ip) => stream.write_all(&ip.octets()),
+        }
+    }
+}
+
+/// Wrapper to store any `serde::Serialize` value as JSON, and to fetch any
+/// `serde::de::DeserializeOwned` value stored as JSON. Only available with the `serde_json`
+/// feature.
+///
+/// Example:
+///
+/// ```rust
+/// # #[cfg(feature = "serde_json")]
+/// # {
+/// use memcache::Json;
+/// use std::collections::HashMap;
+///
+/// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+/// let mut scores = HashMap::new();
+/// scores.insert("alice".to_string(), 42);
+/// client.set("scores", Json(&scores), 0).unwrap();
+/// let Json(fetched): Json<HashMap<String, i32>> = client.get("scores").unwrap().unwrap();
+/// assert_eq!(fetched, scores);
+/// # }
+/// ```
+#[cfg(feature = "serde_json")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Json<T>(pub T);
+
+#[cfg(feature = "serde_json")]
+impl<W: Write, T: serde::Serialize> ToMemcacheValue<W> for Json<T> {
+    fn get_flags(&self) -> u32 {
+        Flags::Bytes as u32
+    }
+
+    fn get_length(&self) -> usize {
+        // serde_json can't compute the length without serializing
+        serde_json::to_vec(&self.0).map(|data| data.len()).unwrap_or(0)
+    }
+
+    fn write_to(&self, stream: &mut W) -> io::Result<()> {
+        serde_json::to_writer(stream, &self.0)?;
+        Ok(())
+    }
+
+    fn get_kind(&self) -> ValueKind {
+        ValueKind::Text
+    }
+}
+
+/// Wrapper to store any `serde::Serialize` value with bincode, and to fetch any
+/// `serde::de::DeserializeOwned` value stored with bincode. Values are stored with a dedicated
+/// flag bit, fetching a value without it is an error. Only available with the `bincode` feature.
+///
+/// Example:
+///
+/// ```rust
+/// # #[cfg(feature = "bincode")]
+/// # {
+/// use memcache::Bincode;
+///
+/// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+/// client.set("point", Bincode((1u32, -2i64)), 0).unwrap();
+/// let Bincode(point): Bincode<(u32, i64)> = client.get("point").unwrap().unwrap();
+/// assert_eq!(point, (1, -2));
+/// # }
+/// ```
+#[cfg(feature = "bincode")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Bincode<T>(pub T);
+
+#[cfg(feature = "bincode")]
+impl<W: Write, T: serde::Serialize> ToMemcacheValue<W> for Bincode<T> {
+    fn get_flags(&self) -> u32 {
+        Flags::Bincode as u32
+    }
+
+    fn get_length(&self) -> usize {
+        bincode::serialized_size(&self.0).unwrap_or(0) as usize
+    }
+
+    fn write_to(&self, stream: &mut W) -> io::Result<()> {
+        bincode::serialize_into(stream, &self.0).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Wrapper to store any `serde::Serialize` value as MessagePack, and to fetch any
+/// `serde::de::DeserializeOwned` value stored as MessagePack. Structs are serialized as maps
+/// with their field names, so other languages can read them. Values are stored with a dedicated
+/// flag bit, fetching a value without it is an error. Only available with the `msgpack` feature.
+///
+/// Example:
+///
+/// ```rust
+/// # #[cfg(feature = "msgpack")]
+/// # {
+/// use memcache::MsgPack;
+///
+/// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+/// client.set("tags", MsgPack(vec!["a", "b"]), 0).unwrap();
+/// let MsgPack(tags): MsgPack<Vec<String>> = client.get("tags").unwrap().unwrap();
+/// assert_eq!(tags, vec!["a", "b"]);
+/// # }
+/// ```
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MsgPack<T>(pub T);
+
+#[cfg(feature = "msgpack")]
+impl<W: Write, T: serde::Serialize> ToMemcacheValue<W> for MsgPack<T> {
+    fn get_flags(&self) -> u32 {
+        Flags::MsgPack as u32
+    }
+
+    fn get_length(&self) -> usize {
+        rmp_serde::to_vec_named(&self.0).map(|data| data.len()).unwrap_or(0)
+    }
+
+    fn write_to(&self, stream: &mut W) -> io::Result<()> {
+        rmp_serde::encode::write_named(stream, &self.0).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// A value serialized by `ToMemcacheValue` and encoded by the client's `Transcoder`.
+pub(crate) struct EncodedValue {
+    pub(crate) data: Vec<u8>,
+    pub(crate) flags: u32,
+}
+
+impl<W: Write> ToMemcacheValue<W> for EncodedValue {
+    fn get_flags(&self) -> u32 {
+        self.flags
+    }
+
+    fn get_length(&self) -> usize {
+        self.data.len()
+    }
+
+    fn write_to(&self, stream: &mut W) -> io::Result<()> {
+        stream.write_all(&self.data)
+    }
+
+    fn get_bytes(&self) -> Option<&[u8]> {
+        Some(&self.data)
+    }
+}
+
+/// Bytes stored with the flags given by the caller.
+pub(crate) struct FlaggedValue<'a> {
+    pub(crate) data: &'a [u8],
+    pub(crate) flags: u32,
+}
+
+impl<W: Write> ToMemcacheValue<W> for FlaggedValue<'_> {
+    fn get_flags(&self) -> u32 {
+        self.flags
+    }
+
+    fn get_length(&self) -> usize {
+        self.data.len()
+    }
+
+    fn write_to(&self, stream: &mut W) -> io::Result<()> {
+        stream.write_all(self.data)
+    }
+
+    fn get_bytes(&self) -> Option<&[u8]> {
+        Some(self.data)
+    }
+}
+
+/// The value of a single key store, streamed as serialized by `ToMemcacheValue`, or encoded by a
+/// client which transforms the values.
+pub(crate) enum StoreValue<'a, V> {
+    Streamed(&'a V),
+    Encoded(FlaggedValue<'a>),
+}
+
+impl<'a, V> StoreValue<'a, V> {
+    pub(crate) fn new(value: &'a V, encoded: Option<&'a EncodedValue>) -> Self {
+        match encoded {
+            Some(encoded) => StoreValue::Encoded(FlaggedValue {
+                data: &encoded.data,
+                flags: encoded.flags,
+            }),
+            None => StoreValue::Streamed(value),
+        }
+    }
+}
+
+impl<W: Write, V: ToMemcacheValue<W>> ToMemcacheValue<W> for StoreValue<'_, V> {
+    fn get_flags(&self) -> u32 {
+        match self {
+            StoreValue::Streamed(value) => value.get_flags(),
+            StoreValue::Encoded(value) => ToMemcacheValue::<W>::get_flags(value),
+        }
+    }
+
+    fn get_length(&self) -> usize {
+        match self {
+            StoreValue::Streamed(value) => value.get_length(),
+            StoreValue::Encoded(value) => ToMemcacheValue::<W>::get_length(value),
+        }
+    }
+
+    fn write_to(&self, stream: &mut W) -> io::Result<()> {
+        match self {
+            StoreValue::Streamed(value) => value.write_to(stream),
+            StoreValue::Encoded(value) => value.write_to(stream),
+        }
+    }
+
+    fn get_kind(&self) -> ValueKind {
+        match self {
+            StoreValue::Streamed(value) => value.get_kind(),
+            StoreValue::Encoded(value) => ToMemcacheValue::<W>::get_kind(value),
+        }
+    }
+
+    fn get_bytes(&self) -> Option<&[u8]> {
+        match self {
+            StoreValue::Streamed(value) => value.get_bytes(),
+            StoreValue::Encoded(value) => ToMemcacheValue::<W>::get_bytes(value),
+        }
+    }
+}
+
+/// A value streamed from a reader, which must provide exactly `length` bytes.
+pub(crate) struct ReaderValue<R> {
+    reader: RefCell<R>,
+    length: usize,
+}
+
+impl<R: Read> ReaderValue<R> {
+    pub(crate) fn new(reader: R, length: usize) -> Self {
+        ReaderValue {
+            reader: RefCell::new(reader),
+            length,
+        }
+    }
+}
+
+impl<R: Read, W: Write> ToMemcacheValue<W> for ReaderValue<R> {
+    fn get_flags(&self) -> u32 {
+        Flags::Bytes as u32
+    }
+
+    fn get_length(&self) -> usize {
+        self.length
+    }
+
+    fn write_to(&self, stream: &mut W) -> io::Result<()> {
+        let mut reader = self.reader.borrow_mut();
+        let copied = io::copy(&mut (&mut *reader).take(self.length as u64), stream)?;
+        if copied < self.length as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "reader ended before the announced length",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A fetched value with its metadata, see `Client::get_value`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Value<T> {
+    pub value: T,
+    /// The flags of the value as stored, before being decoded by the client's transcoder.
+    pub flags: u32,
+    /// The cas unique of the value, to pass to `Client::cas`.
+    pub cas: Option<u64>,
+    pub key: String,
+}
 
 type MemcacheValue<T> = Result<T, MemcacheError>;
 
 /// determine how the value is unserialize to memcache
 pub trait FromMemcacheValue: Sized {
     fn from_memcache_value(_: Vec<u8>, _: u32) -> MemcacheValue<Self>;
+
+    /// Decode the value from a borrowed buffer, for types which don't keep the buffer, so it can
+    /// be reused for the next values with `ClientBuilder::with_value_buffer_pool`. Returns `None`
+    /// for types which take over the buffer, the default.
+    fn from_memcache_slice(_: &[u8], _: u32) -> Option<MemcacheValue<Self>> {
+        None
+    }
 }
 
 pub trait FromMemcacheValueExt: Sized {
     fn from_memcache_value(value: Vec<u8>, flags: u32, cas: Option<u64>) -> MemcacheValue<Self>;
+
+    /// Same as `FromMemcacheValue::from_memcache_slice`.
+    fn from_memcache_slice(_value: &[u8], _flags: u32, _cas: Option<u64>) -> Option<MemcacheValue<Self>> {
+        None
+    }
 }
 
 impl<V: FromMemcacheValue> FromMemcacheValueExt for V {
     fn from_memcache_value(value: Vec<u8>, flags: u32, _cas: Option<u64>) -> MemcacheValue<Self> {
         FromMemcacheValue::from_memcache_value(value, flags)
     }
+
+    fn from_memcache_slice(value: &[u8], flags: u32, _cas: Option<u64>) -> Option<MemcacheValue<Self>> {
+        FromMemcacheValue::from_memcache_slice(value, flags)
+    }
+}
+
+/// `from_memcache_value` of the types which implement `from_memcache_slice`.
+fn from_owned_slice<V: FromMemcacheValue>(value: Vec<u8>, flags: u32) -> MemcacheValue<V> {
+    V::from_memcache_slice(&value, flags).expect("the type decodes from slices")
 }
 
 impl FromMemcacheValueExt for (Vec<u8>, u32, Option<u64>) {
@@ -148,31 +665,436 @@ impl FromMemcacheValue for Vec<u8> {
     }
 }
 
+/// Takes over the buffer the value was read into, without copying it.
+#[cfg(feature = "bytes")]
+impl FromMemcacheValue for bytes::Bytes {
+    fn from_memcache_value(value: Vec<u8>, _: u32) -> MemcacheValue<Self> {
+        Ok(bytes::Bytes::from(value))
+    }
+}
+
+#[cfg(feature = "serde_json")]
+impl<T: serde::de::DeserializeOwned> FromMemcacheValue for Json<T> {
+    fn from_memcache_value(value: Vec<u8>, flags: u32) -> MemcacheValue<Self> {
+        from_owned_slice(value, flags)
+    }
+
+    fn from_memcache_slice(value: &[u8], _: u32) -> Option<MemcacheValue<Self>> {
+        Some(serde_json::from_slice(value).map(Json).map_err(MemcacheError::from))
+    }
+}
+
+#[cfg(any(feature = "bincode", feature = "msgpack"))]
+fn check_serialization_flag(flags: u32, flag: Flags, name: &'static str) -> MemcacheValue<()> {
+    if flags & flag as u32 == 0 {
+        return Err(ClientError::Error(Cow::Owned(format!(
+            "value with flags {:#x} was not serialized with {}",
+            flags, name
+        )))
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(feature = "bincode")]
+impl<T: serde::de::DeserializeOwned> FromMemcacheValue for Bincode<T> {
+    fn from_memcache_value(value: Vec<u8>, flags: u32) -> MemcacheValue<Self> {
+        from_owned_slice(value, flags)
+    }
+
+    fn from_memcache_slice(value: &[u8], flags: u32) -> Option<MemcacheValue<Self>> {
+        Some(
+            check_serialization_flag(flags, Flags::Bincode, "bincode")
+                .and_then(|_| Ok(Bincode(bincode::deserialize(value)?))),
+        )
+    }
+}
+
+#[cfg(feature = "msgpack")]
+impl<T: serde::de::DeserializeOwned> FromMemcacheValue for MsgPack<T> {
+    fn from_memcache_value(value: Vec<u8>, flags: u32) -> MemcacheValue<Self> {
+        from_owned_slice(value, flags)
+    }
+
+    fn from_memcache_slice(value: &[u8], flags: u32) -> Option<MemcacheValue<Self>> {
+        Some(
+            check_serialization_flag(flags, Flags::MsgPack, "msgpack")
+                .and_then(|_| Ok(MsgPack(rmp_serde::from_slice(value)?))),
+        )
+    }
+}
+
 impl FromMemcacheValue for String {
     fn from_memcache_value(value: Vec<u8>, _: u32) -> MemcacheValue<Self> {
         return Ok(String::from_utf8(value)?);
     }
 }
 
+impl FromMemcacheValue for Cow<'static, str> {
+    fn from_memcache_value(value: Vec<u8>, _: u32) -> MemcacheValue<Self> {
+        Ok(Cow::Owned(String::from_utf8(value)?))
+    }
+}
+
+impl FromMemcacheValue for Box<[u8]> {
+    fn from_memcache_value(value: Vec<u8>, _: u32) -> MemcacheValue<Self> {
+        Ok(value.into_boxed_slice())
+    }
+}
+
+impl FromMemcacheValue for Arc<[u8]> {
+    fn from_memcache_value(value: Vec<u8>, _: u32) -> MemcacheValue<Self> {
+        Ok(Arc::from(value))
+    }
+}
+
+/// Parsed from its 16 bytes, or from its textual representation.
+#[cfg(feature = "uuid")]
+impl FromMemcacheValue for uuid::Uuid {
+    fn from_memcache_value(value: Vec<u8>, flags: u32) -> MemcacheValue<Self> {
+        from_owned_slice(value, flags)
+    }
+
+    fn from_memcache_slice(value: &[u8], _: u32) -> Option<MemcacheValue<Self>> {
+        if let Ok(uuid) = uuid::Uuid::from_slice(value) {
+            return Some(Ok(uuid));
+        }
+        Some(uuid::Uuid::try_parse_ascii(value).map_err(|e| ClientError::Error(Cow::Owned(e.to_string())).into()))
+    }
+}
+
+/// Parsed from RFC 3339 format, with any offset.
+#[cfg(feature = "chrono")]
+impl FromMemcacheValue for chrono::DateTime<chrono::Utc> {
+    fn from_memcache_value(value: Vec<u8>, flags: u32) -> MemcacheValue<Self> {
+        from_owned_slice(value, flags)
+    }
+
+    fn from_memcache_slice(value: &[u8], _: u32) -> Option<MemcacheValue<Self>> {
+        let s = match str::from_utf8(value) {
+            Ok(s) => s,
+            Err(e) => return Some(Err(e.into())),
+        };
+        match chrono::DateTime::parse_from_rfc3339(s) {
+            Ok(datetime) => Some(Ok(datetime.with_timezone(&chrono::Utc))),
+            Err(e) => Some(Err(ClientError::Error(Cow::Owned(e.to_string())).into())),
+        }
+    }
+}
+
+/// Parsed from its textual representation like numbers, or else from its 4 or 16 octets.
+impl FromMemcacheValue for IpAddr {
+    fn from_memcache_value(value: Vec<u8>, flags: u32) -> MemcacheValue<Self> {
+        from_owned_slice(value, flags)
+    }
+
+    fn from_memcache_slice(value: &[u8], _: u32) -> Option<MemcacheValue<Self>> {
+        if let Some(ip) = str::from_utf8(value).ok().and_then(|s| IpAddr::from_str(s).ok()) {
+            return Some(Ok(ip));
+        }
+        if let Ok(octets) = <[u8; 4]>::try_from(value) {
+            return Some(Ok(IpAddr::from(octets)));
+        }
+        if let Ok(octets) = <[u8; 16]>::try_from(value) {
+            return Some(Ok(IpAddr::from(octets)));
+        }
+        Some(Err(
+            ClientError::Error(Cow::Borrowed("value is not an ip address")).into()
+        ))
+    }
+}
+
+impl FromMemcacheValue for bool {
+    fn from_memcache_value(value: Vec<u8>, flags: u32) -> MemcacheValue<Self> {
+        from_owned_slice(value, flags)
+    }
+
+    fn from_memcache_slice(value: &[u8], _: u32) -> Option<MemcacheValue<Self>> {
+        Some(match str::from_utf8(value) {
+            Ok(s) => Self::from_str(s).map_err(MemcacheError::from),
+            Err(e) => Err(e.into()),
+        })
+    }
+}
+
+/// Wrapper to fetch a number stored as a big-endian binary number of the size of its type, like
+/// Java clients store them, instead of its textual representation. Fetching a value of another
+/// size is an error.
+///
+/// Example:
+///
+/// ```rust
+/// use memcache::BinaryNumber;
+///
+/// let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+/// client.set("counter", &[0u8, 0, 1, 0][..], 0).unwrap();
+/// let BinaryNumber(counter): BinaryNumber<u32> = client.get("counter").unwrap().unwrap();
+/// assert_eq!(counter, 256);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BinaryNumber<T>(pub T);
+
+/// Numbers are parsed from their textual representation, as stored by this crate and most other
+/// clients, see `BinaryNumber` for the binary representation.
 macro_rules! impl_from_memcache_value_for_number {
     ($ty:ident) => {
         impl FromMemcacheValue for $ty {
-            fn from_memcache_value(value: Vec<u8>, _: u32) -> MemcacheValue<Self> {
-                let s: String = FromMemcacheValue::from_memcache_value(value, 0)?;
-                Ok(Self::from_str(s.as_str())?)
+            fn from_memcache_value(value: Vec<u8>, flags: u32) -> MemcacheValue<Self> {
+                from_owned_slice(value, flags)
+            }
+
+            fn from_memcache_slice(value: &[u8], _: u32) -> Option<MemcacheValue<Self>> {
+                Some(match str::from_utf8(value) {
+                    Ok(s) => Self::from_str(s).map_err(MemcacheError::from),
+                    Err(e) => Err(e.into()),
+                })
+            }
+        }
+
+        impl FromMemcacheValue for BinaryNumber<$ty> {
+            fn from_memcache_value(value: Vec<u8>, flags: u32) -> MemcacheValue<Self> {
+                from_owned_slice(value, flags)
+            }
+
+            fn from_memcache_slice(value: &[u8], _: u32) -> Option<MemcacheValue<Self>> {
+                Some(match <[u8; mem::size_of::<$ty>()]>::try_from(value) {
+                    Ok(bytes) => Ok(BinaryNumber($ty::from_be_bytes(bytes))),
+                    Err(_) => {
+                        Err(ClientError::Error(Cow::Borrowed("value doesn't have the size of the number")).into())
+                    }
+                })
             }
         }
     };
 }
 
-impl_from_memcache_value_for_number!(bool);
 impl_from_memcache_value_for_number!(u8);
 impl_from_memcache_value_for_number!(u16);
 impl_from_memcache_value_for_number!(u32);
 impl_from_memcache_value_for_number!(u64);
+impl_from_memcache_value_for_number!(u128);
 impl_from_memcache_value_for_number!(i8);
 impl_from_memcache_value_for_number!(i16);
 impl_from_memcache_value_for_number!(i32);
 impl_from_memcache_value_for_number!(i64);
+impl_from_memcache_value_for_number!(i128);
 impl_from_memcache_value_for_number!(f32);
 impl_from_memcache_value_for_number!(f64);
+
+macro_rules! impl_from_memcache_value_for_non_zero {
+    ($ty:ident, $inner:ident) => {
+        impl FromMemcacheValue for $ty {
+            fn from_memcache_value(value: Vec<u8>, flags: u32) -> MemcacheValue<Self> {
+                from_owned_slice(value, flags)
+            }
+
+            fn from_memcache_slice(value: &[u8], flags: u32) -> Option<MemcacheValue<Self>> {
+                let number = <$inner as FromMemcacheValue>::from_memcache_slice(value, flags)?;
+                Some(number.and_then(|number| {
+                    $ty::new(number).ok_or_else(|| ClientError::Error(Cow::Borrowed("value is zero")).into())
+                }))
+            }
+        }
+    };
+}
+
+impl_from_memcache_value_for_non_zero!(NonZeroU8, u8);
+impl_from_memcache_value_for_non_zero!(NonZeroU16, u16);
+impl_from_memcache_value_for_non_zero!(NonZeroU32, u32);
+impl_from_memcache_value_for_non_zero!(NonZeroU64, u64);
+impl_from_memcache_value_for_non_zero!(NonZeroU128, u128);
+impl_from_memcache_value_for_non_zero!(NonZeroI8, i8);
+impl_from_memcache_value_for_non_zero!(NonZeroI16, i16);
+impl_from_memcache_value_for_non_zero!(NonZeroI32, i32);
+impl_from_memcache_value_for_non_zero!(NonZeroI64, i64);
+impl_from_memcache_value_for_non_zero!(NonZeroI128, i128);
+
+/// Transforms values and flags on their way to and from the servers, e.g. to compress values or
+/// to follow the flag conventions of other clients sharing the cache.
+///
+/// Values are serialized with `ToMemcacheValue` before being encoded, and decoded before being
+/// deserialized with `FromMemcacheValue`. `append` and `prepend` bypass the transcoder, as their
+/// data is concatenated to an already encoded value by the server.
+///
+/// Example:
+///
+/// ```rust
+/// struct Reversed;
+///
+/// impl memcache::Transcoder for Reversed {
+///     fn encode(&self, mut value: Vec<u8>, flags: u32) -> Result<(Vec<u8>, u32), memcache::MemcacheError> {
+///         value.reverse();
+///         Ok((value, flags))
+///     }
+///
+///     fn decode(&self, mut value: Vec<u8>, flags: u32) -> Result<(Vec<u8>, u32), memcache::MemcacheError> {
+///         value.reverse();
+///         Ok((value, flags))
+///     }
+/// }
+///
+/// let client = memcache::Client::builder()
+///     .add_server("memcache://localhost:12345")
+///     .with_transcoder(Reversed)
+///     .build()
+///     .unwrap();
+/// client.set("foo", "bar", 0).unwrap();
+/// let value: String = client.get("foo").unwrap().unwrap();
+/// assert_eq!(value, "bar");
+/// ```
+pub trait Transcoder: Send + Sync {
+    /// Encode a serialized value and its flags before it is sent to the server.
+    fn encode(&self, value: Vec<u8>, flags: u32) -> Result<(Vec<u8>, u32), MemcacheError>;
+
+    /// Same as `encode`, with the type of the value before it was serialized. Called by the
+    /// client instead of `encode`, override it to store some types differently.
+    fn encode_kind(&self, value: Vec<u8>, flags: u32, _kind: ValueKind) -> Result<(Vec<u8>, u32), MemcacheError> {
+        self.encode(value, flags)
+    }
+
+    /// Decode a value and its flags as received from the server, before it is deserialized.
+    fn decode(&self, value: Vec<u8>, flags: u32) -> Result<(Vec<u8>, u32), MemcacheError>;
+
+    /// The flag bits this transcoder sets on the values it encodes, which must not be used by
+    /// other transcoders or the application. Checked when the client is built.
+    fn reserved_flags(&self) -> u32 {
+        0
+    }
+}
+
+/// Applies multiple transcoders, in order when encoding and in reverse order when decoding.
+pub(crate) struct TranscoderChain(pub(crate) Vec<Arc<dyn Transcoder>>);
+
+impl Transcoder for TranscoderChain {
+    fn encode(&self, value: Vec<u8>, flags: u32) -> Result<(Vec<u8>, u32), MemcacheError> {
+        self.encode_kind(value, flags, ValueKind::Bytes)
+    }
+
+    fn encode_kind(&self, value: Vec<u8>, flags: u32, kind: ValueKind) -> Result<(Vec<u8>, u32), MemcacheError> {
+        let mut transcoders = self.0.iter();
+        let (mut value, mut flags) = match transcoders.next() {
+            Some(transcoder) => transcoder.encode_kind(value, flags, kind)?,
+            None => (value, flags),
+        };
+        for transcoder in transcoders {
+            let encoded = transcoder.encode(value, flags)?;
+            value = encoded.0;
+            flags = encoded.1;
+        }
+        Ok((value, flags))
+    }
+
+    fn decode(&self, value: Vec<u8>, flags: u32) -> Result<(Vec<u8>, u32), MemcacheError> {
+        let (mut value, mut flags) = (value, flags);
+        for transcoder in self.0.iter().rev() {
+            let decoded = transcoder.decode(value, flags)?;
+            value = decoded.0;
+            flags = decoded.1;
+        }
+        Ok((value, flags))
+    }
+
+    fn reserved_flags(&self) -> u32 {
+        self.0
+            .iter()
+            .fold(0, |flags, transcoder| flags | transcoder.reserved_flags())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BinaryNumber, FromMemcacheValue, ToMemcacheValue};
+    use std::borrow::Cow;
+    use std::net::IpAddr;
+    use std::num::{NonZeroI32, NonZeroU64};
+    use std::sync::Arc;
+
+    fn encode<V: ToMemcacheValue<Vec<u8>>>(value: V) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        value.write_to(&mut encoded).unwrap();
+        assert_eq!(encoded.len(), value.get_length());
+        encoded
+    }
+
+    #[test]
+    fn test_owned_types() {
+        let value: Box<[u8]> = FromMemcacheValue::from_memcache_value(b"foo".to_vec(), 0).unwrap();
+        assert_eq!(&*value, b"foo");
+        let value: Arc<[u8]> = FromMemcacheValue::from_memcache_value(b"foo".to_vec(), 0).unwrap();
+        assert_eq!(&*value, b"foo");
+        let value: Cow<'static, str> = FromMemcacheValue::from_memcache_value(b"foo".to_vec(), 0).unwrap();
+        assert_eq!(value, "foo");
+    }
+
+    #[test]
+    fn test_numbers() {
+        let value: u64 = FromMemcacheValue::from_memcache_value(b"42".to_vec(), 0).unwrap();
+        assert_eq!(value, 42);
+        let value: u16 = FromMemcacheValue::from_memcache_value(b"42".to_vec(), 0).unwrap();
+        assert_eq!(value, 42);
+        assert!(<u64 as FromMemcacheValue>::from_memcache_value(b"foo".to_vec(), 0).is_err());
+        // values of the size of the number are still parsed as text
+        assert!(<u16 as FromMemcacheValue>::from_memcache_value(b"-1".to_vec(), 0).is_err());
+        assert!(<u8 as FromMemcacheValue>::from_memcache_value(b"x".to_vec(), 0).is_err());
+
+        let BinaryNumber(value): BinaryNumber<u32> =
+            FromMemcacheValue::from_memcache_value(vec![0, 0, 1, 0], 0).unwrap();
+        assert_eq!(value, 256);
+        let BinaryNumber(value): BinaryNumber<i16> =
+            FromMemcacheValue::from_memcache_value(vec![0xff, 0xfe], 0).unwrap();
+        assert_eq!(value, -2);
+        let BinaryNumber(value): BinaryNumber<f64> =
+            FromMemcacheValue::from_memcache_value(1.5f64.to_be_bytes().to_vec(), 0).unwrap();
+        assert_eq!(value, 1.5);
+        assert!(<BinaryNumber<u16> as FromMemcacheValue>::from_memcache_value(b"42".to_vec(), 0).is_ok());
+        assert!(<BinaryNumber<u32> as FromMemcacheValue>::from_memcache_value(b"42".to_vec(), 0).is_err());
+    }
+
+    #[test]
+    fn test_ip_addr() {
+        for ip in &["127.0.0.1", "::1", "fe80::1234:5678:9abc"] {
+            let ip: IpAddr = ip.parse().unwrap();
+            let encoded = encode(ip);
+            assert_eq!(encoded.len(), if ip.is_ipv4() { 4 } else { 16 });
+            assert_eq!(IpAddr::from_memcache_value(encoded, 0).unwrap(), ip);
+        }
+        let ip = IpAddr::from_memcache_value(b"10.0.0.1".to_vec(), 0).unwrap();
+        assert_eq!(ip, IpAddr::from([10, 0, 0, 1]));
+        assert!(IpAddr::from_memcache_value(b"foo".to_vec(), 0).is_err());
+    }
+
+    #[cfg(feature = "uuid")]
+    #[test]
+    fn test_uuid() {
+        let uuid = uuid::Uuid::parse_str("67e55044-10b1-426f-9247-bb680e5fe0c8").unwrap();
+        let encoded = encode(uuid);
+        assert_eq!(encoded.len(), 16);
+        assert_eq!(uuid::Uuid::from_memcache_value(encoded, 0).unwrap(), uuid);
+        let parsed = uuid::Uuid::from_memcache_value(uuid.to_string().into_bytes(), 0).unwrap();
+        assert_eq!(parsed, uuid);
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_chrono() {
+        use chrono::TimeZone;
+        let datetime = chrono::Utc.timestamp_opt(1_622_548_800, 42).unwrap();
+        let encoded = encode(datetime);
+        assert_eq!(encoded, b"2021-06-01T12:00:00.000000042Z");
+        assert_eq!(chrono::DateTime::from_memcache_value(encoded, 0).unwrap(), datetime);
+        let parsed = chrono::DateTime::from_memcache_value(b"2021-06-01T14:00:00+02:00".to_vec(), 0).unwrap();
+        assert_eq!(parsed, chrono::Utc.timestamp_opt(1_622_548_800, 0).unwrap());
+    }
+
+    #[test]
+    fn test_wide_and_non_zero() {
+        let value = u128::MAX;
+        assert_eq!(u128::from_memcache_value(encode(value), 0).unwrap(), value);
+        let value = i128::MIN;
+        assert_eq!(i128::from_memcache_value(encode(value), 0).unwrap(), value);
+        let value = NonZeroU64::new(42).unwrap();
+        assert_eq!(encode(value), b"42");
+        assert_eq!(NonZeroU64::from_memcache_value(b"42".to_vec(), 0).unwrap(), value);
+        assert!(NonZeroI32::from_memcache_value(b"0".to_vec(), 0).is_err());
+    }
+}
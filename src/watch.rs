@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::MemcacheError;
+use crate::protocol::AsciiProtocol;
+use crate::scan::decode_key;
+use crate::stream::Stream;
+
+/// The kinds of log lines which can be streamed with `Connection::watch`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchFilter {
+    /// Item fetches, like `get` and `touch`.
+    Fetchers,
+    /// Item stores, like `set` and `append`.
+    Mutations,
+    /// Items evicted from the cache to free memory.
+    Evictions,
+}
+
+impl WatchFilter {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            WatchFilter::Fetchers => "fetchers",
+            WatchFilter::Mutations => "mutations",
+            WatchFilter::Evictions => "evictions",
+        }
+    }
+}
+
+/// The type of a `WatchEvent`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchEventKind {
+    /// An item was fetched, logged for `WatchFilter::Fetchers`.
+    Fetch,
+    /// An item was stored, logged for `WatchFilter::Mutations`.
+    Store,
+    /// An item was evicted, logged for `WatchFilter::Evictions`.
+    Eviction,
+    /// Any other type of log line, with the type reported by the server.
+    Other(String),
+}
+
+/// A log line streamed by `Connection::watch`, like
+/// `ts=1614000000.123456 gid=1 type=item_get key=foo status=found clsid=1 cfd=20`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatchEvent {
+    /// The time the event was logged at.
+    pub timestamp: Option<SystemTime>,
+    pub kind: WatchEventKind,
+    /// The key of the item, with the server's URL encoding decoded.
+    pub key: Option<String>,
+    /// The status of the operation, like `found`, `not_found` or `stored`.
+    pub status: Option<String>,
+    /// All fields of the log line, including the ones above as reported by the server.
+    pub fields: HashMap<String, String>,
+}
+
+impl WatchEvent {
+    pub(crate) fn parse(line: &str) -> Self {
+        let fields: HashMap<String, String> = line
+            .split(' ')
+            .filter_map(|field| {
+                let mut parts = field.splitn(2, '=');
+                Some((parts.next()?.to_string(), parts.next()?.to_string()))
+            })
+            .collect();
+        let kind = match fields.get("type").map(String::as_str) {
+            Some("item_get") => WatchEventKind::Fetch,
+            Some("item_store") => WatchEventKind::Store,
+            Some("eviction") => WatchEventKind::Eviction,
+            Some(kind) => WatchEventKind::Other(kind.to_string()),
+            // e.g. "[skipped: 12]" if the server dropped log lines because the client was too slow
+            None => WatchEventKind::Other(line.to_string()),
+        };
+        WatchEvent {
+            timestamp: fields.get("ts").and_then(|ts| parse_timestamp(ts)),
+            kind,
+            key: fields
+                .get("key")
+                .map(|key| decode_key(key).unwrap_or_else(|| key.clone())),
+            status: fields.get("status").cloned(),
+            fields,
+        }
+    }
+}
+
+/// Parse a timestamp like `1614000000.123456`.
+fn parse_timestamp(ts: &str) -> Option<SystemTime> {
+    let mut parts = ts.splitn(2, '.');
+    let secs = parts.next()?.parse().ok()?;
+    let micros = match parts.next() {
+        Some(micros) => micros.parse().ok()?,
+        None => 0,
+    };
+    Some(UNIX_EPOCH + Duration::from_secs(secs) + Duration::from_micros(micros))
+}
+
+/// An iterator over the log lines streamed by a server, created by `Connection::watch`.
+/// The iteration ends when the server closes the connection.
+pub struct Watch<'a> {
+    protocol: &'a mut AsciiProtocol<Stream>,
+}
+
+impl<'a> Watch<'a> {
+    pub(crate) fn new(protocol: &'a mut AsciiProtocol<Stream>) -> Self {
+        Watch { protocol }
+    }
+}
+
+impl<'a> Iterator for Watch<'a> {
+    type Item = Result<WatchEvent, MemcacheError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.protocol.read_watch_line() {
+            Ok(Some(line)) => Some(Ok(WatchEvent::parse(&line))),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WatchEvent, WatchEventKind};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_parse_watch_event() {
+        let event =
+            WatchEvent::parse("ts=1614000000.123456 gid=1 type=item_get key=foo%20bar status=found clsid=1 cfd=20");
+        assert_eq!(event.kind, WatchEventKind::Fetch);
+        assert_eq!(event.key.as_deref(), Some("foo bar"));
+        assert_eq!(event.status.as_deref(), Some("found"));
+        assert_eq!(event.fields["clsid"], "1");
+        assert_eq!(
+            event.timestamp,
+            Some(UNIX_EPOCH + Duration::from_secs(1614000000) + Duration::from_micros(123456))
+        );
+
+        let event = WatchEvent::parse("ts=1614000000.5 gid=2 type=item_store key=foo status=stored cmd=set ttl=0");
+        assert_eq!(event.kind, WatchEventKind::Store);
+        assert_eq!(event.fields["cmd"], "set");
+
+        let event = WatchEvent::parse("ts=1614000000.5 gid=3 type=eviction key=foo fetch=no ttl=-1 la=10 clsid=1");
+        assert_eq!(event.kind, WatchEventKind::Eviction);
+        assert_eq!(event.status, None);
+
+        let event = WatchEvent::parse("[skipped: 12]");
+        assert_eq!(event.kind, WatchEventKind::Other("[skipped: 12]".to_string()));
+        assert_eq!(event.timestamp, None);
+        assert_eq!(event.key, None);
+    }
+}
@@ -0,0 +1,138 @@
+use std::panic;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::thread;
+
+/// Limits how many of the requests which `Client` sends to several servers at once run at a time,
+/// across the clones of the client, see `ClientBuilder::with_worker_pool`.
+pub(crate) struct WorkerPool {
+    threads: usize,
+    /// How many jobs are running.
+    busy: Mutex<usize>,
+    available: Condvar,
+}
+
+/// A running job, which frees its thread of the pool when dropped, even if the job panicked.
+struct Permit<'a>(&'a WorkerPool);
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        *self.0.busy() -= 1;
+        self.0.available.notify_one();
+    }
+}
+
+impl WorkerPool {
+    pub(crate) fn new(threads: usize) -> Self {
+        WorkerPool {
+            threads: threads.max(1),
+            busy: Mutex::new(0),
+            available: Condvar::new(),
+        }
+    }
+
+    fn busy(&self) -> MutexGuard<'_, usize> {
+        // the lock is never held while running a job, so it can't be poisoned by one
+        self.busy.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Wait until fewer than `threads` jobs are running.
+    fn acquire(&self) -> Permit<'_> {
+        let mut busy = self.busy();
+        while *busy >= self.threads {
+            busy = self.available.wait(busy).unwrap_or_else(|e| e.into_inner());
+        }
+        *busy += 1;
+        Permit(self)
+    }
+
+    /// Run `jobs` on at most `threads` threads at a time and return their results in order, once
+    /// all of them finished. If a job panicked, the panic is resumed after the other jobs finished.
+    pub(crate) fn run<'a, T: Send + 'a>(&self, jobs: Vec<Box<dyn FnOnce() -> T + Send + 'a>>) -> Vec<T> {
+        let threads = self.threads.min(jobs.len());
+        let results = Mutex::new((0..jobs.len()).map(|_| None).collect::<Vec<_>>());
+        let jobs = Mutex::new(jobs.into_iter().enumerate());
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|_| {
+                    scope.spawn(|| loop {
+                        let next = jobs.lock().unwrap_or_else(|e| e.into_inner()).next();
+                        let (index, job) = match next {
+                            Some(next) => next,
+                            None => return,
+                        };
+                        let _permit = self.acquire();
+                        let result = job();
+                        results.lock().unwrap_or_else(|e| e.into_inner())[index] = Some(result);
+                    })
+                })
+                .collect();
+            // the scope waits for the other workers before resuming a panic
+            for worker in workers {
+                if let Err(e) = worker.join() {
+                    panic::resume_unwind(e);
+                }
+            }
+        });
+        results
+            .into_inner()
+            .unwrap_or_else(|e| e.into_inner())
+            .into_iter()
+            .map(|result| result.expect("every job ran"))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WorkerPool;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Barrier;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_run() {
+        let pool = WorkerPool::new(3);
+        let values = [1, 2, 3];
+        // the jobs wait for each other, so they must run concurrently
+        let barrier = Barrier::new(3);
+        let jobs: Vec<Box<dyn FnOnce() -> i32 + Send + '_>> = values
+            .iter()
+            .map(|value| -> Box<dyn FnOnce() -> i32 + Send + '_> {
+                let barrier = &barrier;
+                Box::new(move || {
+                    barrier.wait();
+                    value * 2
+                })
+            })
+            .collect();
+        assert_eq!(pool.run(jobs), vec![2, 4, 6]);
+        assert!(pool.run(Vec::<Box<dyn FnOnce() -> i32 + Send>>::new()).is_empty());
+
+        // no more jobs than the pool has threads run at a time
+        let pool = WorkerPool::new(2);
+        let (running, most) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        let jobs: Vec<Box<dyn FnOnce() + Send + '_>> = (0..6)
+            .map(|_| -> Box<dyn FnOnce() + Send + '_> {
+                let (running, most) = (&running, &most);
+                Box::new(move || {
+                    most.fetch_max(running.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(10));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        pool.run(jobs);
+        assert_eq!(most.into_inner(), 2);
+    }
+
+    #[test]
+    fn test_panic() {
+        let pool = WorkerPool::new(1);
+        let jobs: Vec<Box<dyn FnOnce() -> i32 + Send>> = vec![Box::new(|| panic!("failed")), Box::new(|| 1)];
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| pool.run(jobs))).is_err());
+        // the thread of the panicked job is freed
+        assert_eq!(pool.run(vec![Box::new(|| 2)]), vec![2]);
+    }
+}
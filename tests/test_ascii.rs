@@ -45,5 +45,62 @@ fn test_ascii() {
     assert_eq!(client.increment("ascii_counter", 100).unwrap(), 103);
     assert_eq!(client.decrement("ascii_counter", 3).unwrap(), 100);
 
-    client.stats().unwrap();
+    let stats = client.stats().unwrap();
+    assert!(stats[0].1.contains_key("pid"));
+    let stats = client.stats_items().unwrap();
+    assert!(!stats[0].1.classes.is_empty());
+    let stats = client.stats_slabs().unwrap();
+    assert!(stats[0].1.totals.contains_key("active_slabs"));
+    client.stats_sizes().unwrap();
+
+    let keys: Vec<_> = client.scan_keys().map(|metadata| metadata.unwrap().key).collect();
+    assert!(keys.contains(&"ascii_foo".to_string()));
+    // dropping a scan early leaves the connection usable
+    assert!(client.scan_keys().next().is_some());
+    client.version().unwrap();
+
+    let mut connection = client.get_connections().unwrap().remove(0);
+    let class = client.stats_items().unwrap()[0]
+        .1
+        .classes
+        .keys()
+        .cloned()
+        .min()
+        .unwrap();
+    let items = connection.stats_cachedump(class, 0).unwrap();
+    assert!(!items.is_empty());
+    client.stats_reset().unwrap();
+
+    client.verbosity(1).unwrap();
+    client.verbosity(0).unwrap();
+}
+
+#[test]
+fn test_ascii_raw_command() {
+    let client = memcache::Client::connect("memcache://localhost:12345?protocol=ascii").unwrap();
+    let mut connection = client.get_connections().unwrap().remove(0);
+
+    let response = connection.run_ascii_command("version").unwrap();
+    assert_eq!(response.len(), 1);
+    assert!(response[0].starts_with("VERSION "));
+
+    let response = connection.run_ascii_command("set ascii_raw 0 0 3\r\nbar").unwrap();
+    assert_eq!(response, vec!["STORED"]);
+    let response = connection.run_ascii_command("get ascii_raw").unwrap();
+    assert_eq!(response, vec!["VALUE ascii_raw 0 3", "bar", "END"]);
+
+    assert!(connection.run_ascii_command("no_such_command").is_err());
+}
+
+#[test]
+fn test_ascii_watch() {
+    let client = memcache::Client::connect("memcache://localhost:12345?protocol=ascii").unwrap();
+    let mut connection = client.get_connections().unwrap().remove(0);
+    let mut events = connection.watch(&[memcache::WatchFilter::Mutations]).unwrap();
+
+    let other = memcache::Client::connect("memcache://localhost:12345?protocol=ascii").unwrap();
+    other.set("ascii_watched", "value", 0).unwrap();
+    let event = events.next().unwrap().unwrap();
+    assert_eq!(event.kind, memcache::WatchEventKind::Store);
+    assert_eq!(event.key.as_deref(), Some("ascii_watched"));
 }
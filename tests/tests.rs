@@ -3,7 +3,9 @@ extern crate rand;
 
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
+use std::collections::HashMap;
 use std::iter;
+use std::sync::Arc;
 use std::thread;
 use std::thread::JoinHandle;
 use std::time;
@@ -146,9 +148,10 @@ fn udp_test() {
     assert_eq!(client.touch("foooo", 123).unwrap(), false);
     assert_eq!(client.touch("fooo", 12345).unwrap(), true);
 
-    // gets is not supported for udp
-    let value: Result<std::collections::HashMap<String, String>, _> = client.gets(&["foo", "fooo"]);
-    assert_eq!(value.is_ok(), false);
+    let values: std::collections::HashMap<String, String> = client.gets(&["foo", "fooo", "foooo"]).unwrap();
+    assert_eq!(values.len(), 2);
+    assert_eq!(values["foo"], "barbazbar");
+    assert_eq!(values["fooo"], "0");
 
     let mut keys: Vec<String> = Vec::new();
     for _ in 0..1000 {
@@ -250,3 +253,1551 @@ fn test_cas() {
         client.flush().unwrap();
     }
 }
+
+#[test]
+fn test_binary_raw_command() {
+    use memcache::{BinaryRequest, Client};
+    let client = Client::connect("memcache://localhost:12345").unwrap();
+    let mut connection = client.get_connections().unwrap().remove(0);
+
+    let request = BinaryRequest {
+        opcode: 0x0b, // version
+        opaque: 42,
+        ..Default::default()
+    };
+    let response = connection.run_binary_command(&request).unwrap();
+    assert_eq!(response.opcode, 0x0b);
+    assert_eq!(response.status, 0);
+    assert_eq!(response.opaque, 42);
+    assert!(!response.value.is_empty());
+
+    let request = BinaryRequest {
+        opcode: 0x00, // get
+        key: b"binary_raw_not_exists".to_vec(),
+        ..Default::default()
+    };
+    let response = connection.run_binary_command(&request).unwrap();
+    assert_eq!(response.status, 0x1);
+}
+
+#[test]
+fn test_builder() {
+    use memcache::Client;
+    assert!(Client::builder().build().is_err());
+
+    let client = Client::builder()
+        .add_server(vec!["memcache://localhost:12345", "memcache://localhost:12346"])
+        .with_pool_size(2)
+        .build()
+        .unwrap();
+    assert_eq!(client.version().unwrap().len(), 2);
+}
+
+#[test]
+fn test_transcoder() {
+    struct Marked;
+
+    impl memcache::Transcoder for Marked {
+        fn encode(&self, mut value: Vec<u8>, flags: u32) -> Result<(Vec<u8>, u32), memcache::MemcacheError> {
+            value.insert(0, b'!');
+            Ok((value, flags | 0x8000))
+        }
+
+        fn decode(&self, mut value: Vec<u8>, flags: u32) -> Result<(Vec<u8>, u32), memcache::MemcacheError> {
+            if flags & 0x8000 != 0 {
+                value.remove(0);
+            }
+            Ok((value, flags & !0x8000))
+        }
+    }
+
+    let client = memcache::Client::builder()
+        .add_server("memcache://localhost:12345")
+        .with_transcoder(Marked)
+        .build()
+        .unwrap();
+    let plain = memcache::Client::connect("memcache://localhost:12345").unwrap();
+
+    let key = gen_random_key();
+    client.set(key.as_str(), "bar", 0).unwrap();
+    let value: Option<String> = client.get(key.as_str()).unwrap();
+    assert_eq!(value, Some("bar".into()));
+    let value: Option<(Vec<u8>, u32)> = plain.get(key.as_str()).unwrap();
+    assert_eq!(value, Some((b"!bar".to_vec(), 0x8000)));
+
+    let values: std::collections::HashMap<String, String> = client.gets(&[key.as_str()]).unwrap();
+    assert_eq!(values[&key], "bar");
+}
+
+#[cfg(feature = "serde_json")]
+#[test]
+fn test_json() {
+    use memcache::Json;
+    use std::collections::HashMap;
+
+    let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    let key = gen_random_key();
+    let mut value = HashMap::new();
+    value.insert("answer".to_string(), vec![4, 2]);
+    client.set(key.as_str(), Json(&value), 0).unwrap();
+    let raw: String = client.get(key.as_str()).unwrap().unwrap();
+    assert_eq!(raw, r#"{"answer":[4,2]}"#);
+    let fetched: Option<Json<HashMap<String, Vec<i32>>>> = client.get(key.as_str()).unwrap();
+    assert_eq!(fetched, Some(Json(value)));
+
+    let result: Result<Option<Json<Vec<i32>>>, _> = client.get(key.as_str());
+    assert!(result.is_err());
+}
+
+#[cfg(all(feature = "bincode", feature = "msgpack"))]
+#[test]
+fn test_bincode_msgpack() {
+    use memcache::{Bincode, MsgPack};
+
+    let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    let key = gen_random_key();
+    client.set(key.as_str(), Bincode(vec![1u64, 2, 3]), 0).unwrap();
+    let value: Option<Bincode<Vec<u64>>> = client.get(key.as_str()).unwrap();
+    assert_eq!(value, Some(Bincode(vec![1, 2, 3])));
+    // the flags tell the encodings apart
+    let result: Result<Option<MsgPack<Vec<u64>>>, _> = client.get(key.as_str());
+    assert!(result.is_err());
+
+    client.set(key.as_str(), MsgPack(("foo", 42)), 0).unwrap();
+    let value: Option<MsgPack<(String, i32)>> = client.get(key.as_str()).unwrap();
+    assert_eq!(value, Some(MsgPack(("foo".to_string(), 42))));
+    let result: Result<Option<Bincode<(String, i32)>>, _> = client.get(key.as_str());
+    assert!(result.is_err());
+}
+
+#[cfg(all(feature = "derive", feature = "serde_json"))]
+#[test]
+fn test_derive() {
+    use memcache::MemcacheValue;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, MemcacheValue)]
+    struct UserId(u64);
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize, MemcacheValue)]
+    #[memcache(json)]
+    struct User {
+        name: String,
+        age: u8,
+    }
+
+    let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    let key = gen_random_key();
+    client.set(key.as_str(), UserId(42), 0).unwrap();
+    let value: Option<UserId> = client.get(key.as_str()).unwrap();
+    assert_eq!(value, Some(UserId(42)));
+    let value: Option<u64> = client.get(key.as_str()).unwrap();
+    assert_eq!(value, Some(42));
+
+    let user = User {
+        name: "alice".into(),
+        age: 42,
+    };
+    client.set(key.as_str(), &user, 0).unwrap();
+    let value: Option<User> = client.get(key.as_str()).unwrap();
+    assert_eq!(value, Some(user));
+}
+
+#[test]
+fn test_python_compat() {
+    let client = memcache::Client::builder()
+        .add_server("memcache://localhost:12345")
+        .with_transcoder(memcache::PythonCompat::python_memcached())
+        .build()
+        .unwrap();
+    let plain = memcache::Client::connect("memcache://localhost:12345").unwrap();
+
+    let key = gen_random_key();
+    client.set(key.as_str(), "foo", 0).unwrap();
+    let value: Option<(Vec<u8>, u32)> = plain.get(key.as_str()).unwrap();
+    assert_eq!(value, Some((b"foo".to_vec(), 16)));
+    let value: Option<String> = client.get(key.as_str()).unwrap();
+    assert_eq!(value, Some("foo".into()));
+
+    client.set(key.as_str(), 42u64, 0).unwrap();
+    let value: Option<(Vec<u8>, u32)> = plain.get(key.as_str()).unwrap();
+    assert_eq!(value, Some((b"42".to_vec(), 2)));
+    let value: Option<u64> = client.get(key.as_str()).unwrap();
+    assert_eq!(value, Some(42));
+}
+
+#[test]
+fn test_chunking() {
+    let client = memcache::Client::builder()
+        .add_server("memcache://localhost:12345")
+        .with_chunking(100)
+        .build()
+        .unwrap();
+    let plain = memcache::Client::connect("memcache://localhost:12345").unwrap();
+
+    let key = gen_random_key();
+    let value = "foo".repeat(100);
+    client.set(key.as_str(), value.as_str(), 0).unwrap();
+    let fetched: Option<String> = client.get(key.as_str()).unwrap();
+    assert_eq!(fetched, Some(value.clone()));
+    let fetched: HashMap<String, String> = client.gets(&[key.as_str()]).unwrap();
+    assert_eq!(fetched[&key], value);
+
+    let manifest: String = plain.get(key.as_str()).unwrap().unwrap();
+    let id: u32 = manifest.split(' ').next().unwrap().parse().unwrap();
+    assert!(plain.delete(&format!("{}:chunk:{:08x}:1", key, id)).unwrap());
+    let fetched: Option<String> = client.get(key.as_str()).unwrap();
+    assert_eq!(fetched, None);
+    let fetched: HashMap<String, String> = client.gets(&[key.as_str()]).unwrap();
+    assert!(fetched.is_empty());
+}
+
+#[test]
+fn test_chunking_percent_encoding() {
+    let client = memcache::Client::builder()
+        .add_server("memcache://localhost:12345")
+        .with_chunking(100)
+        .with_key_percent_encoding()
+        .build()
+        .unwrap();
+
+    let key = format!("{} 100%", gen_random_key());
+    let value = "foo".repeat(100);
+    client.set(key.as_str(), value.as_str(), 0).unwrap();
+    let fetched: Option<String> = client.get(key.as_str()).unwrap();
+    assert_eq!(fetched, Some(value.clone()));
+    let fetched: memcache::Value<String> = client.get_value(key.as_str()).unwrap().unwrap();
+    assert_eq!(fetched.value, value);
+    let fetched: HashMap<String, String> = client.gets(&[key.as_str()]).unwrap();
+    assert_eq!(fetched[&key], value);
+    let fetched: Vec<Option<String>> = client.get_multi_ordered(&[key.as_str()]).unwrap();
+    assert_eq!(fetched, vec![Some(value.clone())]);
+    let mut fetched = Vec::new();
+    client
+        .gets_foreach(&[key.as_str()], |_, value: String| fetched.push(value))
+        .unwrap();
+    assert_eq!(fetched, vec![value]);
+}
+
+#[test]
+fn test_set_from_reader() {
+    let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+
+    let key = gen_random_key();
+    let data: Vec<u8> = (0..300 * 1024).map(|i| i as u8).collect();
+    client.set_from_reader(key.as_str(), &data[..], data.len(), 0).unwrap();
+    let value: Option<Vec<u8>> = client.get(key.as_str()).unwrap();
+    assert_eq!(value, Some(data.clone()));
+
+    // a reader shorter than the announced length fails without storing anything
+    let short = gen_random_key();
+    assert!(client.set_from_reader(short.as_str(), &data[..10], 20, 0).is_err());
+    let value: Option<Vec<u8>> = client.get(short.as_str()).unwrap();
+    assert_eq!(value, None);
+}
+
+#[test]
+fn test_get_into_writer() {
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let client = memcache::Client::connect(*url).unwrap();
+
+        let key = gen_random_key();
+        let data: Vec<u8> = (0..300 * 1024).map(|i| i as u8).collect();
+        client.set(key.as_str(), &data[..], 0).unwrap();
+        let mut buf = Vec::new();
+        assert_eq!(client.get_into_writer(key.as_str(), &mut buf).unwrap(), Some(0));
+        assert_eq!(buf, data);
+
+        let mut buf = Vec::new();
+        assert_eq!(client.get_into_writer(&gen_random_key(), &mut buf).unwrap(), None);
+        assert!(buf.is_empty());
+    }
+}
+
+#[cfg(feature = "checksum")]
+#[test]
+fn test_checksum() {
+    let client = memcache::Client::builder()
+        .add_server("memcache://localhost:12345")
+        .with_transcoder(memcache::Checksum::crc32())
+        .build()
+        .unwrap();
+    let plain = memcache::Client::connect("memcache://localhost:12345").unwrap();
+
+    let key = gen_random_key();
+    client.set(key.as_str(), "foo", 0).unwrap();
+    let value: Option<String> = client.get(key.as_str()).unwrap();
+    assert_eq!(value, Some("foo".into()));
+
+    // a partial overwrite by another client corrupts the value
+    plain.append(key.as_str(), "bar").unwrap();
+    match client.get::<String>(key.as_str()) {
+        Err(memcache::MemcacheError::ServerError(memcache::ServerError::ChecksumMismatch)) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[cfg(feature = "bytes")]
+#[test]
+fn test_bytes() {
+    let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+
+    let key = gen_random_key();
+    let value = bytes::Bytes::from_static(b"foo bar");
+    client.set(key.as_str(), &value, 0).unwrap();
+    let fetched: Option<bytes::Bytes> = client.get(key.as_str()).unwrap();
+    assert_eq!(fetched, Some(value));
+}
+
+#[test]
+fn test_flags() {
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let client = memcache::Client::builder()
+            .add_server(*url)
+            .with_transcoder(memcache::PythonCompat::python_memcached())
+            .build()
+            .unwrap();
+
+        let key = gen_random_key();
+        client.set_with_flags(key.as_str(), b"foo", 0, 0xdead_beef).unwrap();
+        let value = client.get_with_flags(key.as_str()).unwrap();
+        assert_eq!(value, Some((b"foo".to_vec(), 0xdead_beef)));
+        assert_eq!(client.get_with_flags(&gen_random_key()).unwrap(), None);
+    }
+}
+
+#[test]
+fn test_value() {
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let client = memcache::Client::connect(*url).unwrap();
+
+        let key = gen_random_key();
+        client.set(key.as_str(), 42u64, 0).unwrap();
+        let value: memcache::Value<u64> = client.get_value(key.as_str()).unwrap().unwrap();
+        assert_eq!(value.value, 42);
+        assert_eq!(value.key, key);
+        assert_eq!(value.flags, 0);
+        assert!(client.cas(key.as_str(), 43u64, 0, value.cas.unwrap()).unwrap());
+        assert!(!client.cas(key.as_str(), 44u64, 0, value.cas.unwrap()).unwrap());
+
+        let values: HashMap<String, memcache::Value<u64>> = client.gets_values(&[key.as_str(), "missing"]).unwrap();
+        assert_eq!(values.len(), 1);
+        assert_eq!(values[&key].value, 43);
+    }
+}
+
+#[test]
+fn test_item_size_max() {
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let client = memcache::Client::connect(*url).unwrap();
+        let limit = client.get_connections().unwrap()[0].item_size_max().unwrap();
+
+        let value = vec![0u8; limit + 1];
+        match client.set(gen_random_key().as_str(), &value[..], 0) {
+            Err(memcache::MemcacheError::ClientError(memcache::ClientError::ValueTooLarge { length, limit: max })) => {
+                assert_eq!(length, limit + 1);
+                assert_eq!(max, limit);
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+        // the connection is still usable, as nothing was sent
+        client.set(gen_random_key().as_str(), "foo", 0).unwrap();
+    }
+}
+
+#[test]
+fn test_decode_failures_as_misses() {
+    let client = memcache::Client::builder()
+        .add_server("memcache://localhost:12345")
+        .with_decode_failures_as_misses(true)
+        .build()
+        .unwrap();
+    let strict = memcache::Client::connect("memcache://localhost:12345").unwrap();
+
+    let key = gen_random_key();
+    client.set(key.as_str(), "not a number", 0).unwrap();
+    assert!(strict.get::<u64>(key.as_str()).is_err());
+    assert_eq!(client.get::<u64>(key.as_str()).unwrap(), None);
+    assert!(client.gets::<u64>(&[key.as_str()]).unwrap().is_empty());
+    assert_eq!(
+        client.get_raw(key.as_str()).unwrap(),
+        Some((b"not a number".to_vec(), 0))
+    );
+}
+
+#[test]
+fn test_get_or_set() {
+    let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+
+    let key = gen_random_key();
+    let mut calls = 0;
+    let value: u64 = client
+        .get_or_set(key.as_str(), 0, || {
+            calls += 1;
+            42
+        })
+        .unwrap();
+    assert_eq!(value, 42);
+    let value: u64 = client.get_or_set(key.as_str(), 0, || 43).unwrap();
+    assert_eq!(value, 42);
+    assert_eq!(calls, 1);
+
+    let key = gen_random_key();
+    let value: String = client.get_or_add(key.as_str(), 0, || "foo".to_string()).unwrap();
+    assert_eq!(value, "foo");
+    let value: Option<String> = client.get(key.as_str()).unwrap();
+    assert_eq!(value, Some("foo".into()));
+}
+
+#[test]
+fn test_update() {
+    let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+
+    let key = gen_random_key();
+    let value: u64 = client
+        .update(key.as_str(), 0, |current: Option<u64>| current.unwrap_or(0) + 1)
+        .unwrap();
+    assert_eq!(value, 1);
+
+    // a concurrent modification between the fetch and the store is retried
+    let mut calls = 0;
+    let value: u64 = client
+        .update(key.as_str(), 0, |current: Option<u64>| {
+            calls += 1;
+            if calls == 1 {
+                client.set(key.as_str(), 10, 0).unwrap();
+            }
+            current.unwrap() + 1
+        })
+        .unwrap();
+    assert_eq!(value, 11);
+    assert_eq!(calls, 2);
+    let value: Option<u64> = client.get(key.as_str()).unwrap();
+    assert_eq!(value, Some(11));
+
+    let client = memcache::Client::builder()
+        .add_server("memcache://localhost:12345")
+        .with_cas_retries(0)
+        .build()
+        .unwrap();
+    let result = client.update(key.as_str(), 0, |current: Option<u64>| {
+        client.set(key.as_str(), 0, 0).unwrap();
+        current.unwrap() + 1
+    });
+    match result {
+        Err(memcache::MemcacheError::CommandError(memcache::CommandError::KeyExists)) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn test_add_existing_key() {
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let client = memcache::Client::connect(*url).unwrap();
+
+        let key = gen_random_key();
+        client.add(key.as_str(), "foo", 0).unwrap();
+        match client.add(key.as_str(), "bar", 0) {
+            Err(memcache::MemcacheError::CommandError(memcache::CommandError::KeyExists)) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+        let value: Option<String> = client.get(key.as_str()).unwrap();
+        assert_eq!(value, Some("foo".into()));
+    }
+}
+
+#[test]
+fn test_replace_missing_key() {
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let client = memcache::Client::connect(*url).unwrap();
+
+        let key = gen_random_key();
+        match client.replace(key.as_str(), "foo", 0) {
+            Err(memcache::MemcacheError::CommandError(memcache::CommandError::KeyNotFound)) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+        let value: Option<String> = client.get(key.as_str()).unwrap();
+        assert_eq!(value, None);
+    }
+}
+
+#[test]
+fn test_get_with_cas() {
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let client = memcache::Client::connect(*url).unwrap();
+
+        let key = gen_random_key();
+        let value: Option<(String, u64)> = client.get_with_cas(key.as_str()).unwrap();
+        assert_eq!(value, None);
+        client.set(key.as_str(), "foo", 0).unwrap();
+        let (value, cas): (String, u64) = client.get_with_cas(key.as_str()).unwrap().unwrap();
+        assert_eq!(value, "foo");
+        assert!(client.cas(key.as_str(), "bar", 0, cas).unwrap());
+        assert!(!client.cas(key.as_str(), "baz", 0, cas).unwrap());
+    }
+}
+
+#[test]
+fn test_store_result() {
+    use memcache::StoreResult;
+
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let client = memcache::Client::connect(*url).unwrap();
+
+        let key = gen_random_key();
+        assert_eq!(
+            client.try_replace(key.as_str(), "foo", 0).unwrap(),
+            StoreResult::NotStored
+        );
+        assert_eq!(client.try_add(key.as_str(), "foo", 0).unwrap(), StoreResult::Stored);
+        assert_eq!(client.try_add(key.as_str(), "bar", 0).unwrap(), StoreResult::NotStored);
+        assert_eq!(client.try_replace(key.as_str(), "baz", 0).unwrap(), StoreResult::Stored);
+        let value: Option<String> = client.get(key.as_str()).unwrap();
+        assert_eq!(value, Some("baz".into()));
+    }
+}
+
+#[test]
+fn test_sets() {
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let client = memcache::Client::connect(*url).unwrap();
+
+        let keys: Vec<String> = (0..3).map(|_| gen_random_key()).collect();
+        let results = client
+            .sets(&[
+                (keys[0].as_str(), 1u64, 0),
+                (keys[1].as_str(), 2u64, 1),
+                (keys[2].as_str(), 3u64, 0),
+            ])
+            .unwrap();
+        assert_eq!(results.len(), 3);
+        assert!(results.values().all(|result| result.is_ok()));
+        let values: HashMap<String, u64> = client
+            .gets(&[keys[0].as_str(), keys[1].as_str(), keys[2].as_str()])
+            .unwrap();
+        assert_eq!(values.len(), 3);
+        assert_eq!(values[&keys[1]], 2);
+
+        thread::sleep(time::Duration::from_secs(2));
+        let values: HashMap<String, u64> = client
+            .gets(&[keys[0].as_str(), keys[1].as_str(), keys[2].as_str()])
+            .unwrap();
+        assert_eq!(values.len(), 2);
+        assert!(!values.contains_key(&keys[1]));
+
+        // rejected entries don't prevent the others from being stored
+        let long_key = "a".repeat(251);
+        let large_value = "a".repeat(2 * 1024 * 1024);
+        let results = client
+            .sets(&[
+                (long_key.as_str(), "foo", 0),
+                (keys[1].as_str(), large_value.as_str(), 0),
+                (keys[2].as_str(), "bar", 0),
+            ])
+            .unwrap();
+        assert!(results[&long_key].is_err());
+        assert!(results[&keys[1]].is_err());
+        assert_eq!(results[&keys[2]].as_ref().unwrap(), &memcache::StoreResult::Stored);
+        let value: Option<u64> = client.get(keys[0].as_str()).unwrap();
+        assert_eq!(value, Some(1));
+    }
+}
+
+#[test]
+fn test_adds() {
+    use memcache::StoreResult;
+
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let client = memcache::Client::connect(*url).unwrap();
+
+        let keys: Vec<String> = (0..3).map(|_| gen_random_key()).collect();
+        client.set(keys[1].as_str(), "existing", 0).unwrap();
+        let entries: Vec<(&str, &str)> = keys.iter().map(|key| (key.as_str(), "new")).collect();
+        let results = client.adds(&entries, 0).unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[&keys[0]].as_ref().unwrap(), &StoreResult::Stored);
+        assert_eq!(results[&keys[1]].as_ref().unwrap(), &StoreResult::NotStored);
+        assert_eq!(results[&keys[2]].as_ref().unwrap(), &StoreResult::Stored);
+        let value: Option<String> = client.get(keys[1].as_str()).unwrap();
+        assert_eq!(value, Some("existing".into()));
+    }
+}
+
+#[test]
+fn test_bulk_stores() {
+    use memcache::StoreResult;
+
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let client = memcache::Client::connect(*url).unwrap();
+
+        let (existing, missing) = (gen_random_key(), gen_random_key());
+        client.set(existing.as_str(), "b", 0).unwrap();
+
+        let results = client
+            .replaces(&[(existing.as_str(), "c"), (missing.as_str(), "c")], 0)
+            .unwrap();
+        assert_eq!(results[&existing].as_ref().unwrap(), &StoreResult::Stored);
+        assert_eq!(results[&missing].as_ref().unwrap(), &StoreResult::NotStored);
+
+        let results = client
+            .appends(&[(existing.as_str(), "d"), (missing.as_str(), "d")])
+            .unwrap();
+        assert_eq!(results[&existing].as_ref().unwrap(), &StoreResult::Stored);
+        assert_eq!(results[&missing].as_ref().unwrap(), &StoreResult::NotStored);
+
+        let results = client
+            .prepends(&[(existing.as_str(), "a"), (missing.as_str(), "a")])
+            .unwrap();
+        assert_eq!(results[&existing].as_ref().unwrap(), &StoreResult::Stored);
+        assert_eq!(results[&missing].as_ref().unwrap(), &StoreResult::NotStored);
+
+        let value: Option<String> = client.get(existing.as_str()).unwrap();
+        assert_eq!(value, Some("acd".into()));
+        let value: Option<String> = client.get(missing.as_str()).unwrap();
+        assert_eq!(value, None);
+    }
+}
+
+#[test]
+fn test_increments() {
+    use memcache::{CommandError, MemcacheError};
+
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let client = memcache::Client::connect(*url).unwrap();
+
+        let (first, second, missing) = (gen_random_key(), gen_random_key(), gen_random_key());
+        client.set(first.as_str(), 1, 0).unwrap();
+        client.set(second.as_str(), 10, 0).unwrap();
+        let results = client
+            .increments(&[(first.as_str(), 2), (second.as_str(), 5), (missing.as_str(), 1)])
+            .unwrap();
+        assert_eq!(results[&first].as_ref().unwrap(), &3);
+        assert_eq!(results[&second].as_ref().unwrap(), &15);
+        match results[&missing] {
+            Err(MemcacheError::CommandError(CommandError::KeyNotFound)) => {}
+            ref result => panic!("unexpected result: {:?}", result),
+        }
+        let value: Option<u64> = client.get(missing.as_str()).unwrap();
+        assert_eq!(value, None);
+    }
+}
+
+#[test]
+fn test_deletes() {
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let client = memcache::Client::connect(*url).unwrap();
+
+        let keys: Vec<String> = (0..3).map(|_| gen_random_key()).collect();
+        client.set(keys[0].as_str(), "foo", 0).unwrap();
+        client.set(keys[2].as_str(), "foo", 0).unwrap();
+        let results = client.deletes(keys.iter()).unwrap();
+        assert_eq!(results.len(), 3);
+        assert!(results[&keys[0]]);
+        assert!(!results[&keys[1]]);
+        assert!(results[&keys[2]]);
+        let values: HashMap<String, String> = client.gets(&[keys[0].as_str(), keys[2].as_str()]).unwrap();
+        assert!(values.is_empty());
+    }
+}
+
+#[test]
+fn test_deletes_partial() {
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let client = memcache::Client::connect(*url).unwrap();
+
+        let keys: Vec<String> = (0..2).map(|_| gen_random_key()).collect();
+        client.set(keys[0].as_str(), "foo", 0).unwrap();
+        let too_long = "a".repeat(251);
+        let result = client.deletes_partial([keys[0].as_str(), keys[1].as_str(), too_long.as_str()]);
+        assert!(!result.is_complete());
+        assert_eq!(result.deleted.len(), 2);
+        assert!(result.deleted[&keys[0]]);
+        assert!(!result.deleted[&keys[1]]);
+        assert_eq!(result.failed_keys().collect::<Vec<_>>(), vec![too_long.as_str()]);
+        match result.errors[0].1 {
+            memcache::MemcacheError::ClientError(memcache::ClientError::KeyTooLong) => (),
+            ref e => panic!("unexpected error: {}", e),
+        }
+        assert!(client.deletes([keys[0].as_str(), too_long.as_str()]).is_err());
+
+        // the errors are sorted by key, whatever the order of the servers
+        let other_too_long = "b".repeat(251);
+        let result = client.deletes_partial([other_too_long.as_str(), too_long.as_str()]);
+        assert_eq!(
+            result.failed_keys().collect::<Vec<_>>(),
+            vec![too_long.as_str(), other_too_long.as_str()]
+        );
+    }
+}
+
+#[test]
+fn test_multi_get_batches() {
+    for concurrency in &[None, Some(1), Some(4)] {
+        let mut builder = memcache::Client::builder()
+            .add_server("memcache://localhost:12345")
+            .add_server("memcache://localhost:12345?protocol=ascii")
+            .with_pool_size(4)
+            .with_multi_get_batch_size(3);
+        if let Some(concurrency) = *concurrency {
+            builder = builder.with_multi_get_concurrency(concurrency);
+        }
+        let client = builder.build().unwrap();
+
+        let keys: Vec<String> = (0..20).map(|_| gen_random_key()).collect();
+        for (i, key) in keys.iter().enumerate() {
+            client.set(key.as_str(), i as u64, 0).unwrap();
+        }
+        let mut query: Vec<&str> = keys.iter().map(String::as_str).collect();
+        query.push("missing");
+        let values: HashMap<String, u64> = client.gets(&query).unwrap();
+        assert_eq!(values.len(), 20);
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(values[key], i as u64);
+        }
+    }
+}
+
+#[test]
+fn test_get_multi_ordered() {
+    let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+
+    let keys: Vec<String> = (0..3).map(|_| gen_random_key()).collect();
+    client.set(keys[0].as_str(), 1u64, 0).unwrap();
+    client.set(keys[2].as_str(), 3u64, 0).unwrap();
+    let values: Vec<Option<u64>> = client
+        .get_multi_ordered(&[keys[2].as_str(), keys[1].as_str(), keys[0].as_str(), keys[2].as_str()])
+        .unwrap();
+    assert_eq!(values, vec![Some(3), None, Some(1), Some(3)]);
+}
+
+#[test]
+fn test_generic_keys() {
+    let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+
+    let keys: Vec<String> = (0..3).map(|_| gen_random_key()).collect();
+    client.set(&keys[0], 1u64, 0).unwrap();
+    client.set(keys[1].clone(), 2u64, 0).unwrap();
+    let value: Option<u64> = client.get(&keys[0]).unwrap();
+    assert_eq!(value, Some(1));
+
+    let values: HashMap<String, u64> = client.gets(&keys).unwrap();
+    assert_eq!(values.len(), 2);
+
+    let values: HashMap<&String, u64> = client.gets_keyed(&keys).unwrap();
+    assert_eq!(values.len(), 2);
+    assert_eq!(values[&keys[0]], 1);
+    assert_eq!(values[&keys[1]], 2);
+    assert!(!values.contains_key(&keys[2]));
+}
+
+#[cfg(feature = "key-hashing")]
+#[test]
+fn test_long_key_hashing() {
+    let client = memcache::Client::builder()
+        .add_server("memcache://localhost:12345")
+        .with_long_key_hashing("hashed:")
+        .with_chunking(4)
+        .build()
+        .unwrap();
+
+    let long_key = "a".repeat(300) + &gen_random_key();
+    client.set(long_key.as_str(), "foo bar baz", 0).unwrap();
+    let value: Option<String> = client.get(long_key.as_str()).unwrap();
+    assert_eq!(value, Some("foo bar baz".into()));
+
+    let values: HashMap<String, String> = client.gets(&[long_key.as_str(), "missing"]).unwrap();
+    assert_eq!(values[&long_key], "foo bar baz");
+
+    let results = client.deletes([long_key.as_str()]).unwrap();
+    assert!(results[&long_key]);
+    let value: Option<String> = client.get(long_key.as_str()).unwrap();
+    assert_eq!(value, None);
+
+    let plain = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    assert!(plain.get::<String>(long_key.as_str()).is_err());
+}
+
+#[test]
+fn test_invalid_keys() {
+    use memcache::{ClientError, MemcacheError};
+
+    let client = memcache::Client::connect("memcache://localhost:12345?protocol=ascii").unwrap();
+    for key in &["foo bar", "foo\r\nflush_all", "foo\0"] {
+        match client.set(*key, "bar", 0) {
+            Err(MemcacheError::ClientError(ClientError::InvalidKey(_))) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert!(client.get::<String>(*key).is_err());
+        assert!(client.gets::<String>(&["foo", *key]).is_err());
+    }
+    // the connection is still usable
+    client.set("foo", "bar", 0).unwrap();
+
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let client = memcache::Client::builder()
+            .add_server(*url)
+            .with_key_percent_encoding()
+            .build()
+            .unwrap();
+        let key = format!("{} with spaces\r\n", gen_random_key());
+        client.set(key.as_str(), "baz", 0).unwrap();
+        let value: Option<String> = client.get(key.as_str()).unwrap();
+        assert_eq!(value, Some("baz".into()));
+        let values: HashMap<String, String> = client.gets(&[key.as_str()]).unwrap();
+        assert_eq!(values[&key], "baz");
+        assert!(client.delete(key.as_str()).unwrap());
+    }
+}
+
+#[test]
+fn test_bytes_keys() {
+    use memcache::{ClientError, MemcacheError};
+
+    let client = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    let mut key = gen_random_key().into_bytes();
+    key.extend_from_slice(&[0xff, 0x00, b' ', 0xc3]);
+    client.set_bytes_key(&key, "bar", 0).unwrap();
+    let value: Option<String> = client.get_bytes_key(&key).unwrap();
+    assert_eq!(value, Some("bar".into()));
+    assert!(client.delete_bytes_key(&key).unwrap());
+    let value: Option<String> = client.get_bytes_key(&key).unwrap();
+    assert_eq!(value, None);
+
+    // valid UTF-8 keys are regular keys
+    let utf8_key = gen_random_key();
+    client.set_bytes_key(utf8_key.as_bytes(), "baz", 0).unwrap();
+    let value: Option<String> = client.get(utf8_key.as_str()).unwrap();
+    assert_eq!(value, Some("baz".into()));
+
+    let client = memcache::Client::connect("memcache://localhost:12345?protocol=ascii").unwrap();
+    match client.set_bytes_key(&[b'a', 0xff], "bar", 0) {
+        Err(MemcacheError::ClientError(ClientError::InvalidKey(0xff))) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn test_expiration_types() {
+    use std::time::{Duration, SystemTime};
+
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let client = memcache::Client::connect(*url).unwrap();
+        let key = gen_random_key();
+        client
+            .set(key.as_str(), "bar", Duration::from_secs(60 * 60 * 24 * 60))
+            .unwrap();
+        let value: Option<String> = client.get(key.as_str()).unwrap();
+        assert_eq!(value, Some("bar".into()));
+
+        assert!(client.touch(&key, SystemTime::now() - Duration::from_secs(10)).unwrap());
+        let value: Option<String> = client.get(key.as_str()).unwrap();
+        assert_eq!(value, None);
+    }
+}
+
+#[test]
+fn test_exists() {
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let client = memcache::Client::connect(*url).unwrap();
+        let key = gen_random_key();
+        assert!(!client.exists(&key).unwrap());
+        client.set(key.as_str(), "bar".repeat(1000), 0).unwrap();
+        assert!(client.exists(&key).unwrap());
+        client.delete(&key).unwrap();
+        assert!(!client.exists(&key).unwrap());
+    }
+}
+
+#[test]
+fn test_lock() {
+    use memcache::MemcacheLock;
+
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        // a held lock isn't an error when the errors carry their context either
+        let with_context = memcache::Client::builder()
+            .add_server(*url)
+            .with_error_context(true)
+            .build()
+            .unwrap();
+        for client in &[memcache::Client::connect(*url).unwrap(), with_context] {
+            let key = gen_random_key();
+            let lock = MemcacheLock::try_lock(client, &key, 10).unwrap().unwrap();
+            assert!(MemcacheLock::try_lock(client, &key, 10).unwrap().is_none());
+            assert!(lock.extend().unwrap());
+
+            // a lock taken over by another owner can't be extended or released
+            client.set(key.as_str(), "other owner", 10).unwrap();
+            assert!(!lock.extend().unwrap());
+            assert!(!lock.unlock().unwrap());
+            client.delete(&key).unwrap();
+
+            {
+                let guard = MemcacheLock::try_lock(client, &key, 10).unwrap().unwrap().guard();
+                assert_eq!(guard.key(), key);
+                assert!(MemcacheLock::try_lock(client, &key, 10).unwrap().is_none());
+            }
+            let lock = MemcacheLock::try_lock(client, &key, 10).unwrap().unwrap();
+            assert!(lock.unlock().unwrap());
+            assert!(!client.exists(&key).unwrap());
+        }
+    }
+}
+
+#[test]
+fn test_rate_limiter() {
+    use memcache::RateLimiter;
+
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let with_context = memcache::Client::builder()
+            .add_server(*url)
+            .with_error_context(true)
+            .build()
+            .unwrap();
+        for client in &[memcache::Client::connect(*url).unwrap(), with_context] {
+            let prefix = gen_random_key();
+            let fixed = RateLimiter::new(client, &prefix, 3, time::Duration::from_secs(3600));
+            let sliding = RateLimiter::new(
+                client,
+                &format!("{}:sliding", prefix),
+                3,
+                time::Duration::from_secs(3600),
+            )
+            .with_sliding_window();
+            for limiter in &[fixed, sliding] {
+                for _ in 0..3 {
+                    assert!(limiter.try_acquire("alice").unwrap());
+                }
+                assert!(!limiter.try_acquire("alice").unwrap());
+                assert!(limiter.try_acquire("bob").unwrap());
+            }
+        }
+    }
+}
+
+#[test]
+fn test_get_or_set_xfetch() {
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let client = memcache::Client::connect(*url).unwrap();
+        let key = gen_random_key();
+        let value: String = client.get_or_set_xfetch(&key, 60, 1.0, || "foo".to_string()).unwrap();
+        assert_eq!(value, "foo");
+        // a fast computation far from its expiration is never refreshed early
+        let value: String = client.get_or_set_xfetch(&key, 60, 1.0, || unreachable!()).unwrap();
+        assert_eq!(value, "foo");
+
+        // a slow computation about to expire is always refreshed early
+        let value: String = client
+            .get_or_set_xfetch(&key, 1, 1e9, || {
+                thread::sleep(time::Duration::from_millis(10));
+                "bar".to_string()
+            })
+            .unwrap();
+        assert_eq!(value, "bar");
+        let value: String = client.get_or_set_xfetch(&key, 60, 1e9, || "baz".to_string()).unwrap();
+        assert_eq!(value, "baz");
+    }
+}
+
+#[test]
+fn test_negative_caching() {
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let client = memcache::Client::builder()
+            .add_server(*url)
+            .with_negative_caching(10)
+            .build()
+            .unwrap();
+        let key = gen_random_key();
+        let value: Option<String> = client.get_or_set_optional(&key, 60, || None).unwrap();
+        assert_eq!(value, None);
+        let value: Option<String> = client.get_or_set_optional(&key, 60, || unreachable!()).unwrap();
+        assert_eq!(value, None);
+        // cached misses are missing for the other fetch methods
+        assert_eq!(client.get::<String>(key.as_str()).unwrap(), None);
+
+        client.delete(&key).unwrap();
+        let value: Option<String> = client.get_or_set_optional(&key, 60, || Some("bar".into())).unwrap();
+        assert_eq!(value, Some("bar".into()));
+        assert_eq!(client.get::<String>(key.as_str()).unwrap(), Some("bar".into()));
+
+        // without negative caching, misses are not stored
+        let client = memcache::Client::connect(*url).unwrap();
+        let key = gen_random_key();
+        let value: Option<String> = client.get_or_set_optional(&key, 60, || None).unwrap();
+        assert_eq!(value, None);
+        assert!(!client.exists(&key).unwrap());
+    }
+}
+
+#[test]
+fn test_value_buffer_pool() {
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let client = memcache::Client::builder()
+            .add_server(*url)
+            .with_value_buffer_pool(4, 1024)
+            .build()
+            .unwrap();
+        let key = gen_random_key();
+        client.set(&key, 42u64, 0).unwrap();
+        for _ in 0..10 {
+            assert_eq!(client.get::<u64>(&key).unwrap(), Some(42));
+            // values keeping their buffer aren't affected by the recycled ones
+            assert_eq!(client.get::<String>(&key).unwrap(), Some("42".into()));
+        }
+        let keys = vec![key.clone(), gen_random_key()];
+        let values: HashMap<String, u64> = client.gets(&keys).unwrap();
+        assert_eq!(values.get(&key), Some(&42));
+    }
+}
+
+#[test]
+fn test_broadcast() {
+    let client = memcache::Client::builder()
+        .add_server("memcache://localhost:12345")
+        .add_server("memcache://localhost:12345?protocol=ascii")
+        .build()
+        .unwrap();
+    let versions = client.version().unwrap();
+    assert_eq!(versions.len(), 2);
+    // the results are in the order of the servers
+    assert_eq!(versions[0].0, "memcache://localhost:12345");
+    assert_eq!(versions[1].0, "memcache://localhost:12345?protocol=ascii");
+    assert_eq!(client.stats().unwrap().len(), 2);
+    let versions_each = client.version_each();
+    assert_eq!(versions_each[0].0, versions[0].0);
+    assert_eq!(versions_each[1].1.as_ref().unwrap(), &versions[1].1);
+    assert!(client.stats_each().iter().all(|(_, stats)| stats.is_ok()));
+    client.stats_reset().unwrap();
+    client.flush().unwrap();
+}
+
+#[test]
+fn test_multiplexed_connection() {
+    let connection = Arc::new(memcache::MultiplexedConnection::connect("memcache://localhost:12345").unwrap());
+    let workers: Vec<_> = (0..8)
+        .map(|i| {
+            let connection = connection.clone();
+            thread::spawn(move || {
+                for j in 0..50u64 {
+                    let key = gen_random_key();
+                    connection.set(&key, i * 1000 + j, 60).unwrap();
+                    assert_eq!(connection.get::<u64>(&key).unwrap(), Some(i * 1000 + j));
+                    assert!(connection.delete(&key).unwrap());
+                    assert_eq!(connection.get::<u64>(&key).unwrap(), None);
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    assert!(memcache::MultiplexedConnection::connect("memcache://localhost:12345?protocol=ascii").is_err());
+}
+
+#[test]
+fn test_get_coalescing() {
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let client = memcache::Client::builder()
+            .add_server(*url)
+            .with_pool_size(4)
+            .with_get_coalescing()
+            .build()
+            .unwrap();
+        let key = gen_random_key();
+        client.set(&key, "bar", 60).unwrap();
+        let workers: Vec<_> = (0..8)
+            .map(|_| {
+                let (client, key) = (client.clone(), key.clone());
+                thread::spawn(move || {
+                    for _ in 0..20 {
+                        assert_eq!(client.get::<String>(&key).unwrap(), Some("bar".into()));
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        client.delete(&key).unwrap();
+        assert_eq!(client.get::<String>(&key).unwrap(), None);
+    }
+}
+
+#[test]
+fn test_deletes_noreply() {
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let client = memcache::Client::builder()
+            .add_server(*url)
+            .with_multi_get_batch_size(7)
+            .build()
+            .unwrap();
+        let keys: Vec<String> = (0..20).map(|_| gen_random_key()).collect();
+        for key in keys.iter().step_by(2) {
+            client.set(key.as_str(), "bar", 0).unwrap();
+        }
+        client.deletes_noreply(&keys).unwrap();
+        let values: HashMap<String, String> = client.gets(&keys).unwrap();
+        assert!(values.is_empty());
+        // the connection is still in sync
+        client.set(keys[0].as_str(), "baz", 0).unwrap();
+        assert_eq!(client.get::<String>(keys[0].as_str()).unwrap(), Some("baz".into()));
+        client.deletes_noreply(&[] as &[&str]).unwrap();
+    }
+}
+
+#[test]
+fn test_deferred_writes() {
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let client = memcache::Client::connect(*url).unwrap();
+        let key = gen_random_key();
+        client.set_noreply(key.as_str(), "bar", 0).unwrap();
+        assert_eq!(client.get::<String>(key.as_str()).unwrap(), Some("bar".into()));
+        client.delete_noreply(key.as_str()).unwrap();
+        assert_eq!(client.get::<String>(key.as_str()).unwrap(), None);
+
+        let client = memcache::Client::builder()
+            .add_server(*url)
+            .with_deferred_writes(time::Duration::from_millis(50), 1024)
+            .build()
+            .unwrap();
+        let keys: Vec<String> = (0..10).map(|_| gen_random_key()).collect();
+        for key in keys.iter() {
+            client.set_noreply(key.as_str(), 42, 0).unwrap();
+        }
+        client.flush_deferred_writes().unwrap();
+        let values: HashMap<String, u32> = client.gets(&keys).unwrap();
+        assert_eq!(values.len(), keys.len());
+
+        // written once they waited for the delay
+        client.delete_noreply(keys[0].as_str()).unwrap();
+        thread::sleep(time::Duration::from_millis(500));
+        assert_eq!(client.get::<u32>(keys[0].as_str()).unwrap(), None);
+
+        // written as soon as the buffer is full
+        let value = "a".repeat(2048);
+        client.set_noreply(keys[1].as_str(), value.as_str(), 0).unwrap();
+        assert_eq!(client.get::<String>(keys[1].as_str()).unwrap(), Some(value));
+    }
+}
+
+#[test]
+fn test_worker_pool() {
+    let client = memcache::Client::builder()
+        .add_server("memcache://localhost:12345")
+        .add_server("memcache://localhost:12345?protocol=ascii")
+        .with_worker_pool(1)
+        .with_multi_get_batch_size(3)
+        .build()
+        .unwrap();
+    let versions = client.version().unwrap();
+    assert_eq!(versions[0].0, "memcache://localhost:12345");
+    assert_eq!(versions[1].0, "memcache://localhost:12345?protocol=ascii");
+    let keys: Vec<String> = (0..20).map(|_| gen_random_key()).collect();
+    for key in keys.iter() {
+        client.set(key.as_str(), key.as_str(), 0).unwrap();
+    }
+    let values: HashMap<String, String> = client.gets(&keys).unwrap();
+    assert_eq!(values.len(), keys.len());
+    assert!(values.iter().all(|(key, value)| key == value));
+}
+
+#[test]
+fn test_metrics() {
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let client = memcache::Client::builder()
+            .add_server(*url)
+            .with_metrics()
+            .build()
+            .unwrap();
+        assert!(client.metrics().is_empty());
+        let key = gen_random_key();
+        client.set(key.as_str(), "bar", 0).unwrap();
+        for _ in 0..10 {
+            assert_eq!(client.get::<String>(key.as_str()).unwrap(), Some("bar".into()));
+        }
+        let _: HashMap<String, String> = client.gets(&[key.as_str()]).unwrap();
+        let metrics = client.metrics();
+        assert_eq!(metrics.len(), 3);
+        let get = &metrics[&(url.to_string(), memcache::Command::Get)];
+        assert_eq!(get.count(), 10);
+        assert!(get.quantile(0.5) <= get.quantile(0.99));
+        assert!(get.quantile(0.99) <= get.max());
+        assert_eq!(metrics[&(url.to_string(), memcache::Command::Set)].count(), 1);
+        assert_eq!(metrics[&(url.to_string(), memcache::Command::Gets)].count(), 1);
+    }
+}
+
+#[test]
+fn test_slow_operation_hook() {
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let operations = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = operations.clone();
+        let client = memcache::Client::builder()
+            .add_server(*url)
+            .with_slow_operation_hook(time::Duration::from_secs(0), move |operation| {
+                recorded.lock().unwrap().push((
+                    operation.command,
+                    operation.key.map(String::from),
+                    operation.server.to_string(),
+                ));
+            })
+            .build()
+            .unwrap();
+        let key = gen_random_key();
+        client.set(key.as_str(), "bar", 0).unwrap();
+        let _: HashMap<String, String> = client.gets(&[key.as_str()]).unwrap();
+        let operations = operations.lock().unwrap();
+        assert_eq!(
+            *operations,
+            vec![
+                (memcache::Command::Set, Some(key.clone()), url.to_string()),
+                (memcache::Command::Gets, None, url.to_string()),
+            ]
+        );
+    }
+}
+
+#[test]
+fn test_counters() {
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let client = memcache::Client::connect(*url).unwrap();
+        assert_eq!(client.counters(), memcache::Counters::default());
+        let key = gen_random_key();
+        let missing = gen_random_key();
+        client.set(key.as_str(), "bar", 0).unwrap();
+        assert_eq!(client.get::<String>(key.as_str()).unwrap(), Some("bar".into()));
+        assert_eq!(client.get::<String>(missing.as_str()).unwrap(), None);
+        let _: HashMap<String, String> = client.gets(&[key.as_str(), missing.as_str()]).unwrap();
+        let counters = client.counters();
+        assert_eq!((counters.hits, counters.misses, counters.errors), (2, 2, 0));
+        assert_eq!(client.server_counters()[*url], counters);
+    }
+}
+
+#[test]
+fn test_gets_foreach() {
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let client = memcache::Client::builder()
+            .add_server(*url)
+            .with_multi_get_batch_size(2)
+            .build()
+            .unwrap();
+        let keys: Vec<String> = (0..5).map(|_| gen_random_key()).collect();
+        for (i, key) in keys.iter().enumerate().skip(1) {
+            client.set(key.as_str(), i as u32, 0).unwrap();
+        }
+        let mut values = HashMap::new();
+        client
+            .gets_foreach(&keys, |key, value: u32| {
+                values.insert(key.to_string(), value);
+            })
+            .unwrap();
+        assert_eq!(values.len(), 4);
+        for (i, key) in keys.iter().enumerate().skip(1) {
+            assert_eq!(values[key], i as u32);
+        }
+    }
+}
+
+#[test]
+fn test_get_with_timeout() {
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let client = memcache::Client::builder()
+            .add_server(*url)
+            .with_pool_size(1)
+            .build()
+            .unwrap();
+        let key = gen_random_key();
+        client.set(key.as_str(), "bar", 0).unwrap();
+        let timeout = time::Duration::from_millis(500);
+        let value: Option<String> = client.get_with_timeout(key.as_str(), timeout).unwrap();
+        assert_eq!(value, Some("bar".into()));
+        assert_eq!(
+            client.get_with_timeout::<String>(gen_random_key(), timeout).unwrap(),
+            None
+        );
+        // the pooled connection is reused with its own timeouts
+        assert_eq!(client.get::<String>(key.as_str()).unwrap(), Some("bar".into()));
+    }
+}
+
+#[test]
+fn test_gets_with_timeout() {
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let client = memcache::Client::connect(*url).unwrap();
+        let key = gen_random_key();
+        client.set(key.as_str(), "bar", 0).unwrap();
+        let keys = [key.as_str(), "missing"];
+        let values: HashMap<String, String> = client.gets_with_timeout(&keys, time::Duration::from_secs(1)).unwrap();
+        assert_eq!(values[&key], "bar");
+
+        // nothing is sent once the deadline is reached
+        let error = client
+            .gets_with_timeout::<String>(&keys, time::Duration::from_secs(0))
+            .unwrap_err();
+        assert!(error.partial.is_empty());
+        match error.error {
+            memcache::MemcacheError::ClientError(memcache::ClientError::DeadlineExceeded) => (),
+            e => panic!("unexpected error: {}", e),
+        }
+        assert!(client
+            .get_with_timeout::<String>(key.as_str(), time::Duration::from_secs(0))
+            .is_err());
+    }
+}
+
+#[test]
+fn test_error_context() {
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        for &include_keys in &[true, false] {
+            let client = memcache::Client::builder()
+                .add_server(*url)
+                .with_error_context(include_keys)
+                .build()
+                .unwrap();
+            let key = gen_random_key();
+            let error = client.replace(key.as_str(), "bar", 0).unwrap_err();
+            match error {
+                memcache::MemcacheError::ContextError(ref context) => {
+                    assert_eq!(context.server, *url);
+                    assert_eq!(context.command, memcache::Command::Replace);
+                    assert_eq!(context.key, if include_keys { Some(key.clone()) } else { None });
+                }
+                ref e => panic!("unexpected error: {}", e),
+            }
+            match error.inner() {
+                memcache::MemcacheError::CommandError(memcache::CommandError::KeyNotFound) => (),
+                e => panic!("unexpected error: {}", e),
+            }
+            assert_eq!(
+                client.try_replace(key.as_str(), "bar", 0).unwrap(),
+                memcache::StoreResult::NotStored
+            );
+        }
+    }
+}
+
+#[test]
+fn test_gets_partial() {
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let client = memcache::Client::connect(*url).unwrap();
+        let key = gen_random_key();
+        client.set(key.as_str(), "bar", 0).unwrap();
+        let result = client.gets_partial::<String>(&[key.as_str(), "missing"]).unwrap();
+        assert!(result.is_complete());
+        assert_eq!(result.values.len(), 1);
+        assert_eq!(result.values[&key], "bar");
+    }
+}
+
+#[test]
+fn test_get_or_stale() {
+    for url in &[
+        "memcache://localhost:12345",
+        "memcache://localhost:12345?protocol=ascii",
+    ] {
+        let client = memcache::Client::builder()
+            .add_server(*url)
+            .with_stale_fallback(10)
+            .build()
+            .unwrap();
+        let key = gen_random_key();
+        assert_eq!(client.get_or_stale::<String>(key.as_str()).unwrap(), None);
+        client.set(key.as_str(), "bar", 0).unwrap();
+        let value = client.get_or_stale::<String>(key.as_str()).unwrap().unwrap();
+        assert!(!value.is_stale());
+        assert_eq!(value.into_inner(), "bar");
+    }
+}
+
+#[test]
+fn test_mirror() {
+    // the secondary stores the keys percent-encoded, to tell its keys apart on the same server
+    let secondary = memcache::Client::builder()
+        .add_server("memcache://localhost:12345?protocol=ascii")
+        .with_key_percent_encoding()
+        .build()
+        .unwrap();
+    let client = memcache::Client::builder()
+        .add_server("memcache://localhost:12345")
+        .with_mirror(secondary, 1.0)
+        .build()
+        .unwrap();
+    // wait for the mirrored value of `key` to be `expected`
+    let mirrored = |key: &str, expected: Option<&str>| {
+        let mirrored_key = format!("{}25", key);
+        let mut value = None;
+        for _ in 0..100 {
+            value = client.get::<String>(mirrored_key.as_str()).unwrap();
+            if value.as_deref() == expected {
+                break;
+            }
+            thread::sleep(time::Duration::from_millis(10));
+        }
+        assert_eq!(value.as_deref(), expected);
+    };
+    let key = format!("{}%", gen_random_key());
+    client.set(key.as_str(), "bar", 0).unwrap();
+    mirrored(&key, Some("bar"));
+    client.append(key.as_str(), "baz").unwrap();
+    mirrored(&key, Some("barbaz"));
+    let cas = client.get_value::<String>(key.as_str()).unwrap().unwrap().cas.unwrap();
+    assert!(client.cas(key.as_str(), "qux", 0, cas).unwrap());
+    mirrored(&key, Some("qux"));
+
+    let other_key = format!("{}%", gen_random_key());
+    client.sets(&[(other_key.as_str(), "foo", 0)]).unwrap();
+    mirrored(&other_key, Some("foo"));
+    client.set_noreply(other_key.as_str(), "bar", 0).unwrap();
+    mirrored(&other_key, Some("bar"));
+    client.deletes([key.as_str(), other_key.as_str()]).unwrap();
+    mirrored(&key, None);
+    mirrored(&other_key, None);
+}
+
+#[test]
+fn test_migrate() {
+    let src = memcache::Client::connect("memcache://localhost:12345?protocol=ascii").unwrap();
+    // the destination stores the keys percent-encoded, to tell its keys apart on the same server
+    let dst = memcache::Client::builder()
+        .add_server("memcache://localhost:12345")
+        .with_key_percent_encoding()
+        .build()
+        .unwrap();
+    let key = format!("{}%", gen_random_key());
+    let copied_key = format!("{}25", key);
+    src.set_with_flags(key.as_str(), b"bar", 100, 0x42).unwrap();
+    assert_eq!(memcache::migrate(&src, &dst, &[key.as_str(), "missing"]).unwrap(), 1);
+    assert_eq!(
+        src.get_with_flags(copied_key.as_str()).unwrap(),
+        Some((b"bar".to_vec(), 0x42))
+    );
+    let mut connection = src.get_connections().unwrap().remove(0);
+    let response = connection.run_ascii_command(&format!("mg {} t", copied_key)).unwrap();
+    let ttl: u32 = response[0].split(' ').nth(1).unwrap()[1..].parse().unwrap();
+    assert!(ttl > 0 && ttl <= 100);
+    drop(connection);
+    src.delete(key.as_str()).unwrap();
+    src.delete(copied_key.as_str()).unwrap();
+
+    // the chunked values are reassembled, and split again by the destination
+    let src = memcache::Client::builder()
+        .add_server("memcache://localhost:12345?protocol=ascii")
+        .with_chunking(100)
+        .build()
+        .unwrap();
+    let dst = memcache::Client::builder()
+        .add_server("memcache://localhost:12345")
+        .with_key_percent_encoding()
+        .with_chunking(200)
+        .build()
+        .unwrap();
+    let value = "foo".repeat(100);
+    src.set(key.as_str(), value.as_str(), 100).unwrap();
+    assert_eq!(memcache::migrate(&src, &dst, &[key.as_str()]).unwrap(), 1);
+    assert_eq!(dst.get::<String>(key.as_str()).unwrap(), Some(value));
+    src.delete(key.as_str()).unwrap();
+    dst.delete(key.as_str()).unwrap();
+
+    // the binary protocol can't fetch the remaining TTLs
+    let binary = memcache::Client::connect("memcache://localhost:12345").unwrap();
+    assert!(memcache::migrate(&binary, &dst, &[key.as_str()]).is_err());
+}